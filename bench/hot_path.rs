@@ -0,0 +1,169 @@
+// bench/hot_path.rs
+//
+// 事件热路径基准：解析内核消息 → 构造设备 → 筛选候选规则并匹配 → 合并执行计划，
+// 不创建节点也不执行 RUN。开始计时之前先做分配审计，打印每类事件平均的内存分配次数，
+// 便于在 PR 中比较改动前后：cargo bench --bench hot_path [--features small-vec]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use rust_udev::device::UEventDevice;
+use rust_udev::monitor::parse_uevent;
+use rust_udev::plan::{apply_rule, ExecutionPlan};
+use rust_udev::rules::parser::parse_rules_str;
+use rust_udev::rules::ruleset::RuleSet;
+
+// 审计时统计 alloc 和 realloc 的次数
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const AUDIT_ROUNDS: usize = 1000;
+
+// 每组规则覆盖常见的写法：SUBSYSTEM/KERNEL 通配、ENV 条件、DEVPATH 前缀，
+// 赋值只有 SYMLINK、TAG 和 ENV，匹配时不会读写文件
+fn bench_rules() -> String {
+    let mut rules = String::new();
+    for i in 0..40 {
+        rules.push_str(&format!(
+            "SUBSYSTEM==\"block\", KERNEL==\"sd*[0-9]\", ENV{{ID_BUS}}==\"usb\", SYMLINK+=\"disk/bench/{i}-%k\", TAG+=\"bench{i}\"\n\
+             SUBSYSTEM==\"block\", ENV{{DEVTYPE}}==\"disk\", KERNEL==\"nvme{i}n*\", SYMLINK+=\"nvme/{i}\"\n\
+             SUBSYSTEM==\"tty\", KERNEL==\"ttyUSB*\", ENV{{ID_VENDOR_ID}}==\"1a86\", SYMLINK+=\"serial/bench-{i}\", ENV{{BENCH_{i}}}=\"1\"\n\
+             DEVPATH==\"/devices/platform/bench{i}/*\", ENV{{BENCH_PLATFORM}}=\"{i}\"\n\
+             SUBSYSTEM==\"net\", ACTION==\"add\", ENV{{INTERFACE}}==\"eth{i}\", TAG+=\"net{i}\"\n"
+        ));
+    }
+    rules
+}
+
+fn uevent(fields: &[&str]) -> Vec<u8> {
+    let mut msg = Vec::new();
+    for field in fields {
+        msg.extend_from_slice(field.as_bytes());
+        msg.push(0);
+    }
+    msg
+}
+
+fn events() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        (
+            "block_add",
+            uevent(&[
+                "add@/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0/host0/target0:0:0/0:0:0:0/block/sda/sda1",
+                "ACTION=add",
+                "DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0/host0/target0:0:0/0:0:0:0/block/sda/sda1",
+                "SUBSYSTEM=block",
+                "MAJOR=8",
+                "MINOR=1",
+                "DEVNAME=sda1",
+                "DEVTYPE=partition",
+                "DISKSEQ=9",
+                "PARTN=1",
+                "ID_BUS=usb",
+                "SEQNUM=4242",
+            ]),
+        ),
+        (
+            "tty_add",
+            uevent(&[
+                "add@/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/ttyUSB0/tty/ttyUSB0",
+                "ACTION=add",
+                "DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/ttyUSB0/tty/ttyUSB0",
+                "SUBSYSTEM=tty",
+                "MAJOR=188",
+                "MINOR=0",
+                "DEVNAME=ttyUSB0",
+                "ID_VENDOR_ID=1a86",
+                "SEQNUM=4243",
+            ]),
+        ),
+        (
+            "net_add",
+            uevent(&[
+                "add@/devices/virtual/net/eth3",
+                "ACTION=add",
+                "DEVPATH=/devices/virtual/net/eth3",
+                "SUBSYSTEM=net",
+                "INTERFACE=eth3",
+                "IFINDEX=7",
+                "SEQNUM=4244",
+            ]),
+        ),
+    ]
+}
+
+// 与 process_event 中的规则循环相同，只是不分发到线程池，也不执行计划
+fn handle(msg: &[u8], rules: &RuleSet) -> ExecutionPlan {
+    let mut device = UEventDevice::from_event(parse_uevent(msg)).expect("event without DEVPATH");
+    let mut plan = ExecutionPlan::new(&device);
+    let mut symbols = rules.prepare(&device);
+    for index in rules.candidate_indices(device.devpath(), device.subsystem()) {
+        if rules.evaluate(index, &symbols, &mut device).is_err() {
+            continue;
+        }
+        let rule = &rules.rules()[index];
+        apply_rule(rule, &mut device);
+        plan.merge(rule, &device);
+        symbols = rules.prepare(&device);
+    }
+    plan
+}
+
+fn audit(rules: &RuleSet) {
+    for (name, msg) in events() {
+        // 先跑一次，线程局部的缓冲区已经分配好
+        black_box(handle(&msg, rules));
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..AUDIT_ROUNDS {
+            black_box(handle(&msg, rules));
+        }
+        let count = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!("allocation audit: {:<10} {:.1} allocations/event", name, count as f64 / AUDIT_ROUNDS as f64);
+    }
+}
+
+fn hot_path(c: &mut Criterion) {
+    let rules = RuleSet::new(parse_rules_str(&bench_rules()));
+    audit(&rules);
+
+    let events = events();
+    let (_, block) = &events[0];
+    c.bench_function("parse_uevent", |b| b.iter(|| parse_uevent(black_box(block))));
+
+    let device = UEventDevice::from_event(parse_uevent(block)).unwrap();
+    c.bench_function("candidate_indices", |b| {
+        b.iter(|| rules.candidate_indices(black_box(device.devpath()), black_box(device.subsystem())))
+    });
+
+    let mut group = c.benchmark_group("receive_match_dispatch");
+    for (name, msg) in &events {
+        group.bench_function(*name, |b| b.iter(|| handle(black_box(msg), &rules)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hot_path);
+criterion_main!(benches);
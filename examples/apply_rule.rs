@@ -0,0 +1,46 @@
+// examples/apply_rule.rs
+//
+// 在代码中构造规则和设备，计算规则会产生的执行计划（不会真正创建节点或执行命令）
+// cargo run --example apply_rule
+
+use std::collections::HashMap;
+
+use rust_udev::prelude::*;
+
+fn main() {
+    let event: HashMap<String, String> = [
+        ("ACTION", "add"),
+        ("SUBSYSTEM", "tty"),
+        ("DEVPATH", "/devices/pci0000:00/usb1/1-1/1-1:1.0/ttyUSB0/tty/ttyUSB0"),
+        ("DEVNAME", "ttyUSB0"),
+        ("MAJOR", "188"),
+        ("MINOR", "0"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+
+    let device = UEventDevice::from_event(event).expect("valid event");
+
+    let rule = Rule {
+        action: Some("add".to_string()),
+        subsystem: Some("tty".to_string()),
+        group: Some("dialout".to_string()),
+        mode: Some("0660".to_string()),
+        symlink: vec!["serial-%k".to_string()],
+        tag_add: vec!["uaccess".to_string()],
+        ..Rule::default()
+    };
+
+    let plan = plan_actions(&device, &RuleSet::new(vec![rule]));
+    if plan.trace.matched() == 0 {
+        println!("Rule does not match {}", device.devpath().display());
+        return;
+    }
+
+    println!("node:     {:?}", plan.node);
+    println!("symlinks: {:?}", plan.symlinks);
+    println!("group:    {:?}", plan.group);
+    println!("mode:     {:?}", plan.mode);
+    println!("tags:     {:?}", plan.tags);
+}
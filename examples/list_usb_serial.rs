@@ -0,0 +1,50 @@
+// examples/list_usb_serial.rs
+//
+// 列出系统中的 USB 串口适配器（ttyUSB*/ttyACM*）
+// cargo run --example list_usb_serial
+
+use std::fs;
+
+use rust_udev::device::UEventDevice;
+
+fn main() {
+    let entries = match fs::read_dir("/sys/class/tty") {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Cannot read /sys/class/tty: {}", e);
+            return;
+        }
+    };
+
+    let mut found = 0;
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with("ttyUSB") && !name.starts_with("ttyACM") {
+            continue;
+        }
+
+        let syspath = entry.path();
+        let Some(device) = UEventDevice::from_syspath(&syspath) else {
+            continue;
+        };
+
+        // 驱动挂在 device/driver 下，uevent 里不一定有
+        let driver = fs::read_link(syspath.join("device/driver"))
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "unknown".to_string());
+
+        println!(
+            "/dev/{:<10} major={} minor={} driver={}",
+            name,
+            device.major().map_or("?".to_string(), |major| major.to_string()),
+            device.minor().map_or("?".to_string(), |minor| minor.to_string()),
+            driver
+        );
+        found += 1;
+    }
+
+    if found == 0 {
+        println!("No USB serial adapters found.");
+    }
+}
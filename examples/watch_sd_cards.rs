@@ -0,0 +1,46 @@
+// examples/watch_sd_cards.rs
+//
+// 监听 SD 卡（mmcblk*）的插入和拔出
+// cargo run --example watch_sd_cards
+
+use std::io;
+use std::os::fd::AsRawFd;
+
+use nix::poll::{poll, PollFd, PollFlags};
+use rust_udev::prelude::*;
+
+fn main() -> io::Result<()> {
+    let monitor = UEventMonitor::new()?;
+    let poll_fd = PollFd::new(monitor.as_raw_fd(), PollFlags::POLLIN);
+
+    println!("Waiting for SD card events, press Ctrl-C to quit...");
+
+    loop {
+        if poll(&mut [poll_fd], -1).map_err(io::Error::from)? == 0 {
+            continue;
+        }
+
+        let event = match monitor.receive_event() {
+            Ok(event) => event,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        };
+
+        let Some(device) = UEventDevice::from_event(event) else {
+            continue;
+        };
+
+        let is_sd_card = device.is_block_device()
+            && device.devtype() == Some("disk")
+            && device.kernel().is_some_and(|k| k.starts_with("mmcblk"));
+        if !is_sd_card {
+            continue;
+        }
+
+        match device.action() {
+            DeviceAction::Add => println!("SD card inserted: {}", device.devnode().unwrap_or("?")),
+            DeviceAction::Remove => println!("SD card removed: {}", device.devnode().unwrap_or("?")),
+            other => println!("SD card event {:?}: {}", other, device.devpath().display()),
+        }
+    }
+}
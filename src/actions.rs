@@ -1,98 +1,612 @@
 /// src/actions.rs
 use nix::sys::stat::{makedev, mknod, Mode, SFlag};
 use std::fs;
-use std::os::unix::fs::{symlink, PermissionsExt};
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::io::Write;
+use std::os::unix::fs::{symlink, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::collections::{BTreeSet, HashMap};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use log::*;
 use users::{get_group_by_name, get_user_by_name};
 
 use crate::device::UEventDevice;
+use crate::plan::ExecutionPlan;
 use crate::rules::matcher::Rule;
+use crate::symlink_db::{DeviceLinks, SymlinkDb};
 
-/// 替换字符串中的变量，比如 $DEVNAME、$ACTION
+/// 设备节点和符号链接的默认根目录，udev.conf 的 dev_root= 或命令行 --dev-root 可以修改
+pub const DEV_ROOT: &str = "/dev";
+
+/// 规则没有给出 MODE 时新建节点的权限
+pub const DEFAULT_NODE_MODE: &str = "0660";
+
+/// 替换字符串中的格式符，比如 %k、$kernel、%s{size}、$env{ID_SERIAL}
+///
+/// 单次扫描整个字符串：%% 和 $$ 输出字面的 % 和 $，替换结果不会被再次展开。
+/// $env{KEY}、${KEY} 和 %E{KEY} 的属性不存在时替换为空字符串；
+/// 其它无法识别或当前没有值的格式符原样保留。
 pub fn substitute_vars(input: &str, device: &UEventDevice) -> String {
-    let mut result = input.to_string();
+    substitute_vars_escaped(input, device, |value| value)
+}
 
-    let devnum_str = device.devnum().map(|n| n.to_string());
-    let major_str = device.major().map(|n| n.to_string());
-    let minor_str = device.minor().map(|n| n.to_string());
-    let devtype = device.devtype();
-    let kernel = device.kernel();
-    let devnode = device.devnode();
-    let devpath_str = device.devpath().to_str();
-    let subsystem = Some(device.subsystem());
+/// 与 substitute_vars 相同，但每段替换出的值先经过 escape，规则中的字面部分原样保留；
+/// SYMLINK 和 NAME 用它转义设备提供的值，值中的 '/' 不会变成路径分隔符
+pub fn substitute_vars_escaped<F>(input: &str, device: &UEventDevice, escape: F) -> String
+where
+    F: Fn(String) -> String,
+{
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
 
-    let vars: Vec<(char, Option<&str>)> = vec![
-        ('k', kernel),
-        ('n', devnode),
-        ('p', devpath_str),
-        ('c', devtype),
-        ('t', devtype),
-        ('d', devnum_str.as_deref()),
-        ('s', subsystem),
-        ('m', major_str.as_deref()),
-        ('r', minor_str.as_deref()),
-    ];
+    while let Some(pos) = rest.find(['%', '$']) {
+        result.push_str(&rest[..pos]);
+        let marker = char::from(rest.as_bytes()[pos]);
+        let after = &rest[pos + 1..];
 
-    for (var_char, val_opt) in vars {
-        if let Some(val) = val_opt {
-            let pattern = format!("%{}", var_char);
-            result = result.replace(&pattern, val);
+        // %% 和 $$ 是字面字符，不是替换出的值
+        if after.starts_with(marker) {
+            result.push(marker);
+            rest = &after[1..];
+            continue;
         }
-    }
 
-    for (key, val) in device.properties() {
-        let pattern = format!("${{{}}}", key);
-        result = result.replace(&pattern, val);
+        let expanded = parse_format(marker, after)
+            .and_then(|(format, arg, consumed)| Some((expand(format, arg, device)?, consumed)));
+
+        match expanded {
+            Some((value, consumed)) => {
+                result.push_str(&escape(value));
+                rest = &after[consumed..];
+            }
+            None => {
+                result.push(marker);
+                rest = after;
+            }
+        }
     }
 
+    result.push_str(rest);
     result
 }
 
+/// 找出 substitute_vars 不认识的 %x / $name 格式符，按出现顺序返回；
+/// 用于检查规则，不关心设备上是否真的有对应的值
+pub fn unknown_substitutions(input: &str) -> Vec<String> {
+    let mut unknown = Vec::new();
+    let mut rest = input;
+
+    while let Some(pos) = rest.find(['%', '$']) {
+        let marker = char::from(rest.as_bytes()[pos]);
+        let after = &rest[pos + 1..];
+
+        if after.starts_with(marker) {
+            rest = &after[1..];
+            continue;
+        }
+
+        match parse_format(marker, after) {
+            Some((_, _, consumed)) => rest = &after[consumed..],
+            None => {
+                let name: String = after
+                    .chars()
+                    .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                    .collect();
+                let name = if name.is_empty() {
+                    after.chars().next().map(String::from).unwrap_or_default()
+                } else {
+                    name
+                };
+                unknown.push(format!("{}{}", marker, name));
+                rest = after;
+            }
+        }
+    }
+
+    unknown
+}
+
+// 格式符取的值；Attr、Env 和 ProgramField 带 {参数}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Kernel,
+    Number,
+    Devpath,
+    Devnode,
+    Driver,
+    ParentKernel,
+    ParentNode,
+    Major,
+    Minor,
+    Devtype,
+    Subsystem,
+    DevRoot,
+    SysRoot,
+    Links,
+    Name,
+    ProgramResult,
+    ProgramField,
+    Attr,
+    Env,
+}
+
+impl Format {
+    fn braced(self) -> bool {
+        matches!(self, Format::ProgramField | Format::Attr | Format::Env)
+    }
+}
+
+// 所有格式符：标记、% 之后的单个字符或 $ 之后的小写名字、取值方式，与 udev 相同。${KEY} 的名字为空
+const FORMATS: &[(char, &str, Format)] = &[
+    ('%', "k", Format::Kernel),
+    ('%', "n", Format::Number),
+    ('%', "N", Format::Devnode),
+    ('%', "p", Format::Devpath),
+    ('%', "b", Format::ParentKernel),
+    ('%', "P", Format::ParentNode),
+    ('%', "t", Format::Devtype),
+    ('%', "d", Format::Driver),
+    ('%', "s", Format::Subsystem),
+    ('%', "M", Format::Major),
+    ('%', "m", Format::Minor),
+    ('%', "r", Format::DevRoot),
+    ('%', "S", Format::SysRoot),
+    ('%', "c", Format::ProgramResult),
+    ('%', "c", Format::ProgramField),
+    ('%', "s", Format::Attr),
+    ('%', "E", Format::Env),
+    ('$', "kernel", Format::Kernel),
+    ('$', "number", Format::Number),
+    ('$', "devpath", Format::Devpath),
+    ('$', "devnode", Format::Devnode),
+    ('$', "driver", Format::Driver),
+    ('$', "id", Format::ParentKernel),
+    ('$', "parent", Format::ParentNode),
+    ('$', "major", Format::Major),
+    ('$', "minor", Format::Minor),
+    ('$', "root", Format::DevRoot),
+    ('$', "sys", Format::SysRoot),
+    ('$', "links", Format::Links),
+    ('$', "name", Format::Name),
+    ('$', "result", Format::ProgramResult),
+    ('$', "result", Format::ProgramField),
+    ('$', "attr", Format::Attr),
+    ('$', "sysfs", Format::Attr),
+    ('$', "env", Format::Env),
+    ('$', "", Format::Env),
+];
+
+// 解析标记之后的格式符，返回取值方式、{参数} 和消耗的字节数；名字后面有 {参数} 时优先取带参数的格式
+fn parse_format(marker: char, input: &str) -> Option<(Format, Option<&str>, usize)> {
+    let name_len = match marker {
+        '%' => input.chars().next()?.len_utf8(),
+        _ => input.find(|c: char| !c.is_ascii_lowercase()).unwrap_or(input.len()),
+    };
+    let name = &input[..name_len];
+    let formats = || {
+        FORMATS
+            .iter()
+            .filter(move |(m, n, _)| *m == marker && *n == name)
+            .map(|(_, _, format)| *format)
+    };
+
+    if let Some(arg) = braced_arg(&input[name_len..]) {
+        if let Some(format) = formats().find(|format| format.braced()) {
+            return Some((format, Some(arg), name_len + arg.len() + 2));
+        }
+    }
+    formats().find(|format| !format.braced()).map(|format| (format, None, name_len))
+}
+
+// 按格式符从设备取值，当前没有值时为 None
+fn expand(format: Format, arg: Option<&str>, device: &UEventDevice) -> Option<String> {
+    let arg = arg.unwrap_or_default();
+    match format {
+        Format::Kernel => device.kernel().map(str::to_string),
+        Format::Number => kernel_number(device),
+        Format::Devpath => device.devpath().to_str().map(str::to_string),
+        Format::Devnode => device.devnode().map(|name| device.dev_root().join(name).display().to_string()),
+        Format::Driver => device.driver().map(str::to_string),
+        Format::ParentKernel => parent_kernel(device),
+        Format::ParentNode => device.parent().and_then(|parent| parent.devnode().map(str::to_string)),
+        Format::Major => device.major().map(|n| n.to_string()),
+        Format::Minor => device.minor().map(|n| n.to_string()),
+        Format::Devtype => device.devtype().map(str::to_string),
+        Format::Subsystem => Some(device.subsystem().to_string()),
+        Format::DevRoot => Some(device.dev_root().display().to_string()),
+        Format::SysRoot => Some(device.sys_root().display().to_string()),
+        Format::Links => Some(device.devlinks().iter().map(String::as_str).collect::<Vec<_>>().join(" ")),
+        // 之前的规则没有用 NAME= 改名时就是内核名
+        Format::Name => device.name().or(device.kernel()).map(str::to_string),
+        Format::ProgramResult => device.program_result().map(str::to_string),
+        Format::ProgramField => program_field(device, arg),
+        Format::Attr => sysattr(device, arg),
+        Format::Env => Some(property(device, arg).unwrap_or_default()),
+    }
+}
+
+// 形如 {arg} 的参数，返回花括号内的内容
+fn braced_arg(input: &str) -> Option<&str> {
+    let inner = input.strip_prefix('{')?;
+    inner.find('}').map(|end| &inner[..end])
+}
+
+fn property(device: &UEventDevice, key: &str) -> Option<String> {
+    device.property(key).map(str::to_string)
+}
+
+// 优先使用匹配 ATTR{} 时缓存的值
+fn sysattr(device: &UEventDevice, attr: &str) -> Option<String> {
+    if let Some(value) = device.sysattrs().get(attr) {
+        return Some(value.clone());
+    }
+    fs::read_to_string(device.syspath().join(attr))
+        .ok()
+        .map(|s| s.trim_end().to_string())
+}
+
+// 父设备的内核名，即 devpath 的上一级目录名
+fn parent_kernel(device: &UEventDevice) -> Option<String> {
+    device
+        .devpath()
+        .parent()?
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(str::to_string)
+}
+
+// 内核名末尾的数字，比如 sda1 -> 1、ttyUSB0 -> 0
+fn kernel_number(device: &UEventDevice) -> Option<String> {
+    let kernel = device.kernel()?;
+    let digits = kernel.len() - kernel.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    (digits > 0).then(|| kernel[kernel.len() - digits..].to_string())
+}
+
+/// 处理 %c{N} 与 %c{N+}：取 PROGRAM 输出中第 N 个（或从第 N 个起全部）空白分隔的字段
+fn program_field(device: &UEventDevice, spec: &str) -> Option<String> {
+    let program_result = device.program_result()?;
+    let fields: Vec<&str> = program_result.split_whitespace().collect();
+
+    let (num, to_end) = match spec.strip_suffix('+') {
+        Some(n) => (n, true),
+        None => (spec, false),
+    };
+
+    match num.parse::<usize>() {
+        Ok(n) if n >= 1 => Some(if to_end {
+            fields.get(n - 1..).map(|f| f.join(" ")).unwrap_or_default()
+        } else {
+            fields.get(n - 1).copied().unwrap_or("").to_string()
+        }),
+        _ => {
+            warn!("Invalid program result index '%c{{{}}}'", spec);
+            None
+        }
+    }
+}
+
+/// 把不安全的字符替换为 '_'：保留字母数字、"#+-.:=@_" 以及 keep 中的字符
+pub fn replace_unsafe_chars(value: &str, keep: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "#+-.:=@_".contains(c) || keep.contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// 执行 PROGRAM 命令，退出码为 0 时返回其标准输出（去掉末尾换行）；
+/// 超过 timeout（即 event_timeout）时杀死命令，返回 TimedOut 错误
+pub fn run_program(program: &str, device: &UEventDevice, timeout: Duration) -> std::io::Result<Option<String>> {
+    let cmd = substitute_vars(program, device);
+    debug!("Executing PROGRAM: {}", cmd);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&cmd)
+        .envs(device.properties())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    // 输出在另一个线程中读取，命令写满管道时也不会卡住
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        std::io::Read::read_to_end(&mut stdout, &mut output).map(|_| output)
+    });
+
+    let status = wait_with_timeout(&mut child, timeout)?;
+    let Some(status) = status else {
+        warn!("PROGRAM '{}' killed after {:?}", cmd, timeout);
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("PROGRAM '{}' timed out", cmd),
+        ));
+    };
+    let output = reader.join().unwrap_or_else(|_| Ok(Vec::new()))?;
+
+    if status.success() {
+        let stdout = String::from_utf8_lossy(&output);
+        Ok(Some(stdout.trim_end_matches('\n').to_string()))
+    } else {
+        debug!("PROGRAM '{}' exited with {}", cmd, status);
+        Ok(None)
+    }
+}
+
+// 等待子进程退出，超时返回 None；轮询间隔从 1ms 逐渐加长，短命令几乎没有额外延迟
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> std::io::Result<Option<std::process::ExitStatus>> {
+    const MAX_INTERVAL: Duration = Duration::from_millis(50);
+    let deadline = Instant::now() + timeout;
+    let mut interval = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(interval.min(deadline - now));
+        interval = (interval * 2).min(MAX_INTERVAL);
+    }
+}
+
+/// 解析 key=value 形式的输出并写入设备属性，值两侧的引号会被去掉
+pub fn import_properties(content: &str, device: &mut UEventDevice) -> usize {
+    let mut imported = 0;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim();
+            if key.is_empty() {
+                continue;
+            }
+            let value = value.trim().trim_matches('"').trim_matches('\'');
+            debug!("Importing property {}={}", key, value);
+            device.set_property(key, value);
+            imported += 1;
+        }
+    }
+
+    imported
+}
+
+/// IMPORT{program}：执行命令并把其输出的 key=value 行导入设备属性
+pub fn import_program(program: &str, device: &mut UEventDevice, timeout: Duration) -> std::io::Result<bool> {
+    match run_program(program, device, timeout)? {
+        Some(output) => {
+            let count = import_properties(&output, device);
+            info!("Imported {} properties from program '{}'", count, program);
+            Ok(true)
+        }
+        None => {
+            warn!("IMPORT{{program}} '{}' failed, nothing imported", program);
+            Ok(false)
+        }
+    }
+}
+
+/// IMPORT{file}：从磁盘上的 key=value 文件导入设备属性
+pub fn import_file(path: &str, device: &mut UEventDevice) -> std::io::Result<usize> {
+    let path = substitute_vars(path, device);
+    let content = fs::read_to_string(&path)?;
+    let count = import_properties(&content, device);
+    info!("Imported {} properties from file '{}'", count, path);
+    Ok(count)
+}
+
+/// IMPORT{cmdline}：从 /proc/cmdline 导入同名参数，无值的参数记为 "1"
+pub fn import_cmdline(name: &str, device: &mut UEventDevice) -> std::io::Result<bool> {
+    let cmdline = fs::read_to_string("/proc/cmdline")?;
+
+    // 与内核一致，后出现的参数覆盖前面的
+    let value = cmdline
+        .split_whitespace()
+        .rev()
+        .find_map(|param| match param.split_once('=') {
+            Some((key, value)) if key == name => Some(value.trim_matches('"')),
+            None if param == name => Some("1"),
+            _ => None,
+        });
+
+    match value {
+        Some(value) => {
+            debug!("Importing cmdline property {}={}", name, value);
+            device.set_property(name, value);
+            Ok(true)
+        }
+        None => {
+            debug!("Kernel command line has no parameter '{}'", name);
+            Ok(false)
+        }
+    }
+}
+
+/// ATTR{key}="value"：把替换后的值写入设备的 sysfs 属性
+pub fn write_sysattr(attr: &str, value: &str, device: &UEventDevice) -> std::io::Result<()> {
+    // 只允许写设备自身目录下的属性
+    if Path::new(attr).is_absolute() || attr.split('/').any(|c| c == "..") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("attribute '{}' is outside the device directory", attr),
+        ));
+    }
+
+    let attr_path = device.syspath().join(attr);
+    let value = substitute_vars(value, device);
+    debug!("Writing '{}' to {:?}", value, attr_path);
+
+    // sysfs 属性必须已经存在，不创建新文件
+    let mut file = fs::OpenOptions::new().write(true).open(&attr_path)?;
+    file.write_all(value.as_bytes())?;
+    info!("Set sysfs attribute {:?} to '{}'", attr_path, value);
+    Ok(())
+}
+
+// disk 和 partition 是块设备，其余按字符设备处理
+fn device_node_type(device: &UEventDevice) -> SFlag {
+    match device.devtype() {
+        Some("disk") | Some("partition") => SFlag::S_IFBLK,
+        _ => SFlag::S_IFCHR,
+    }
+}
+
+/// 内核模块按需加载所需的设备节点列表，格式为 "模块名 节点名 c10:229"
+fn modules_devname_path() -> Option<PathBuf> {
+    let uts = nix::sys::utsname::uname().ok()?;
+    Some(Path::new("/lib/modules").join(uts.release()).join("modules.devname"))
+}
+
+// 在 modules.devname 中查找节点的类型和设备号
+fn lookup_devname(name: &str) -> Option<(SFlag, u32, u32)> {
+    let content = fs::read_to_string(modules_devname_path()?).ok()?;
+
+    content.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let (_module, devname, spec) = (fields.next()?, fields.next()?, fields.next()?);
+        if line.starts_with('#') || devname != name {
+            return None;
+        }
+
+        let sflag = match spec.chars().next()? {
+            'c' => SFlag::S_IFCHR,
+            'b' => SFlag::S_IFBLK,
+            _ => return None,
+        };
+        let (major, minor) = spec[1..].split_once(':')?;
+        Some((sflag, major.parse().ok()?, minor.parse().ok()?))
+    })
+}
+
+/// OPTIONS+="static_node=name"：在任何 uevent 到达之前创建节点（设备号取自 modules.devname），
+/// 并应用规则中的 OWNER/GROUP/MODE；节点已存在时只更新权限
+pub fn create_static_node(root: &Path, name: &str, rule: &Rule, resolve: ResolveNames) -> std::io::Result<()> {
+    let path = root.join(name);
+
+    if path.symlink_metadata().is_err() {
+        let Some((sflag, major, minor)) = lookup_devname(name) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("'{}' is not listed in modules.devname", name),
+            ));
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mode = Mode::from_bits(0o600).unwrap_or(Mode::empty());
+        mknod(&path, sflag, mode, makedev(major.into(), minor.into()))?;
+        info!("Created static node {:?} ({}:{})", path, major, minor);
+        if rule.mode.is_none() {
+            apply_mode(&path, &Some(DEFAULT_NODE_MODE.to_string()))?;
+        }
+    }
+
+    apply_mode(&path, &rule.mode)?;
+    apply_owner(&path, &rule.owner, resolve)?;
+    apply_group(&path, &rule.group, resolve)?;
+    Ok(())
+}
+
+/// 创建设备节点并设置 MODE、OWNER、GROUP、XATTR 和 SECLABEL
+///
+/// 节点先以 0600 创建在同一目录下的临时名字上，权限和标签都设置好后才改名为最终的名字并同步目录，
+/// 所以通过设备名或之后创建的符号链接打开节点的进程，看到的已经是规则给出的权限；没有 MODE 时为 0660。
+/// 节点已经存在时（devtmpfs 已经创建了它，或重启后的 coldplug）只能就地更新，内核创建的节点在更新之前
+/// 保持它自己的权限，只有之后创建的符号链接能保证指向已经设置好的节点
 pub fn create_device_node(
+    root: &Path,
     devname: &str,
     device: &UEventDevice,
-    rule: &Rule,
+    plan: &ExecutionPlan,
 ) -> std::io::Result<()> {
     let major = device.major().unwrap_or(0);
     let minor = device.minor().unwrap_or(0);
 
-    let sflag = match device.devtype() {
-        Some("disk") | Some("partition") => SFlag::S_IFBLK,
-        _ => SFlag::S_IFCHR,
-    };
+    let sflag = device_node_type(device);
 
-    let test_dev_root = "/home/rust_udev/testdev";
-    let full_path = format!("{}/{}", test_dev_root, devname);
-    let path = Path::new(&full_path);
+    let path = root.join(devname);
+    let parent = path.parent().unwrap_or(root);
+    fs::create_dir_all(parent)?;
 
-    fs::create_dir_all(path.parent().unwrap_or(Path::new("/dev"))).unwrap();
+    if path.symlink_metadata().is_ok() {
+        info!("Device node already exists: {}", devname);
+        apply_node_metadata(&path, root, plan);
+        return Ok(());
+    }
 
-    let mode = Mode::from_bits(0o660).unwrap_or(Mode::empty());
+    let tmp_path = temp_path(&path);
+    let mode = Mode::from_bits(0o600).unwrap_or(Mode::empty());
+    mknod(&tmp_path, sflag, mode, makedev(major.into(), minor.into()))?;
 
-    match mknod(path, sflag, mode, makedev(major.into(), minor.into())) {
-        Ok(_) => info!("Created device node: {}", devname),
-        Err(e) => {
-            if e.to_string().contains("File exists") {
-                info!("Device node already exists: {}", devname);
-            } else {
-                error!("Failed to create device node {}: {}", devname, e);
-            }
+    if plan.mode.is_none() {
+        if let Err(e) = apply_mode(&tmp_path, &Some(DEFAULT_NODE_MODE.to_string())) {
+            warn!("Failed to apply mode to {:?}: {}", tmp_path, e);
         }
     }
-
-    let _ = apply_mode(path, &rule.mode);
-    let _ = apply_owner(path, &rule.owner);
-    let _ = apply_group(path, &rule.group);
+    apply_node_metadata(&tmp_path, root, plan);
+    if let Err(e) = fs::rename(&tmp_path, &path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    fs::File::open(parent)?.sync_all()?;
+    info!("Created device node: {}", devname);
 
     Ok(())
 }
 
+// 同一目录下唯一的临时名字：并发处理的事件可能同时替换同一个节点或链接
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let parent = path.parent().unwrap_or(Path::new("/"));
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let id = NEXT.fetch_add(1, Ordering::Relaxed);
+    parent.join(format!(".{}.udev-tmp-{}-{}", file_name, std::process::id(), id))
+}
+
+// 各项互不影响，失败时记录日志并继续；节点至少保持创建时的 0600
+fn apply_node_metadata(path: &Path, root: &Path, plan: &ExecutionPlan) {
+    let mode = match (&plan.mode, &plan.group) {
+        (None, Some(_)) => Some("0660".to_string()),
+        (mode, _) => mode.clone(),
+    };
+    if let Err(e) = apply_mode(path, &mode) {
+        warn!("Failed to apply mode to {:?}: {}", path, e);
+    }
+    if let Err(e) = apply_owner(path, &plan.owner, plan.resolve_names) {
+        warn!("Failed to apply owner to {:?}: {}", path, e);
+    }
+    if let Err(e) = apply_group(path, &plan.group, plan.resolve_names) {
+        warn!("Failed to apply group to {:?}: {}", path, e);
+    }
+    if let Err(e) = apply_xattrs(path, &plan.xattrs) {
+        warn!("Failed to apply xattrs to {:?}: {}", path, e);
+    }
+    if let Err(e) = apply_seclabel(path, root, &BTreeSet::new(), &plan.seclabel) {
+        warn!("Failed to apply SELinux label to {:?}: {}", path, e);
+    }
+}
+
 pub fn apply_mode(dev_path: &Path, mode: &Option<String>) -> std::io::Result<()> {
     if let Some(mode_str) = mode {
-        let mode_val = u32::from_str_radix(mode_str, 8)
+        let mode_val = u32::from_str_radix(mode_str.trim(), 8)
             .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid mode"))?;
         info!("Applying mode {} to {:?}", mode_str, dev_path);
         fs::set_permissions(dev_path, fs::Permissions::from_mode(mode_val))?;
@@ -102,13 +616,64 @@ pub fn apply_mode(dev_path: &Path, mode: &Option<String>) -> std::io::Result<()>
     Ok(())
 }
 
-pub fn apply_owner(dev_path: &Path, owner: &Option<String>) -> std::io::Result<()> {
+/// 何时把 OWNER/GROUP 中的用户名、组名解析为数字 ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResolveNames {
+    /// 加载规则时解析并缓存，事件处理时不再查询 NSS；含变量替换的值仍在事件时解析
+    #[default]
+    Early,
+    /// 每个事件都重新解析
+    Late,
+    /// 不解析名字，只接受数字 ID
+    Never,
+}
+
+impl ResolveNames {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "early" => Some(ResolveNames::Early),
+            "late" => Some(ResolveNames::Late),
+            "never" => Some(ResolveNames::Never),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResolveNames::Early => "early",
+            ResolveNames::Late => "late",
+            ResolveNames::Never => "never",
+        }
+    }
+}
+
+/// OWNER 的值可以是用户名或数字 UID；resolve_names=never 时只接受数字
+pub fn resolve_uid(owner: &str, mode: ResolveNames) -> Option<u32> {
+    let owner = owner.trim();
+    match owner.parse() {
+        Ok(uid) => Some(uid),
+        Err(_) if mode == ResolveNames::Never => None,
+        Err(_) => get_user_by_name(owner).map(|user| user.uid()),
+    }
+}
+
+/// GROUP 的值可以是组名或数字 GID；resolve_names=never 时只接受数字
+pub fn resolve_gid(group: &str, mode: ResolveNames) -> Option<u32> {
+    let group = group.trim();
+    match group.parse() {
+        Ok(gid) => Some(gid),
+        Err(_) if mode == ResolveNames::Never => None,
+        Err(_) => get_group_by_name(group).map(|group| group.gid()),
+    }
+}
+
+pub fn apply_owner(dev_path: &Path, owner: &Option<String>, mode: ResolveNames) -> std::io::Result<()> {
     if let Some(owner_name) = owner {
-        if let Some(user) = get_user_by_name(owner_name) {
+        if let Some(uid) = resolve_uid(owner_name, mode) {
             info!("Applying owner {} to {:?}", owner_name, dev_path);
-            nix::unistd::chown(dev_path, Some(user.uid().into()), None)?;
+            nix::unistd::chown(dev_path, Some(uid.into()), None)?;
         } else {
-            warn!("User '{}' not found", owner_name);
+            warn!("User '{}' not found (resolve_names={})", owner_name, mode.as_str());
         }
     } else {
         info!("No owner specified for {:?}", dev_path);
@@ -116,13 +681,13 @@ pub fn apply_owner(dev_path: &Path, owner: &Option<String>) -> std::io::Result<(
     Ok(())
 }
 
-pub fn apply_group(dev_path: &Path, group: &Option<String>) -> std::io::Result<()> {
+pub fn apply_group(dev_path: &Path, group: &Option<String>, mode: ResolveNames) -> std::io::Result<()> {
     if let Some(group_name) = group {
-        if let Some(group) = get_group_by_name(group_name) {
+        if let Some(gid) = resolve_gid(group_name, mode) {
             info!("Applying group {} to {:?}", group_name, dev_path);
-            nix::unistd::chown(dev_path, None, Some(group.gid().into()))?;
+            nix::unistd::chown(dev_path, None, Some(gid.into()))?;
         } else {
-            warn!("Group '{}' not found", group_name);
+            warn!("Group '{}' not found (resolve_names={})", group_name, mode.as_str());
         }
     } else {
         info!("No group specified for {:?}", dev_path);
@@ -130,23 +695,98 @@ pub fn apply_group(dev_path: &Path, group: &Option<String>) -> std::io::Result<(
     Ok(())
 }
 
-pub fn create_symlinks(
+/// 写入 XATTR{} 设置的扩展属性；属性随节点一起删除，无需清理
+///
+/// 某一项失败时记录下来并继续写其余的属性，返回最后一个错误。
+pub fn apply_xattrs(dev_path: &Path, xattrs: &[(String, String)]) -> std::io::Result<()> {
+    let mut result = Ok(());
+    for (name, value) in xattrs {
+        info!("Setting xattr {}={} on {:?}", name, value, dev_path);
+        if let Err(e) = crate::xattr::set(dev_path, name, value) {
+            warn!("Failed to set xattr {} on {:?}: {}", name, dev_path, e);
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// 按 SECLABEL{selinux} 标记设备节点以及指向它的符号链接；节点重新创建或 change 事件时再次调用即可恢复标签
+pub fn apply_seclabel(
     dev_path: &Path,
-    symlinks: &[String],
-    device: &UEventDevice,
+    root: &Path,
+    symlinks: &BTreeSet<String>,
+    label: &Option<String>,
 ) -> std::io::Result<()> {
+    let Some(label) = label else {
+        return Ok(());
+    };
+    crate::selinux::set_label(dev_path, label)?;
+
     for link in symlinks {
-        info!("Creating symlink for: {}", link);
-        let substituted = substitute_vars(link, device);
-        info!("Substituted symlink path: {}", substituted);
-        let link_path = PathBuf::from("/home/rust_udev/testdev").join(substituted);
+        let link_path = root.join(link);
+        // 链接可能属于优先级更高的其它设备
+        if fs::read_link(&link_path).is_ok_and(|target| target == dev_path) {
+            crate::selinux::set_label(&link_path, label)?;
+        }
+    }
+    Ok(())
+}
+
+/// 在符号链接数据库中声明设备的链接（链接名已在合并执行计划时完成替换），
+/// 只有当前设备优先级最高时才把链接指向它。被冲突策略拒绝或创建失败的链接从设备中删除，
+/// 加了序号的链接以新名字记入设备；单个链接失败时继续处理其余链接，返回第一个错误
+pub fn create_symlinks(
+    dev_path: &Path,
+    root: &Path,
+    device: &mut UEventDevice,
+    priority: i32,
+    db: &SymlinkDb,
+) -> std::io::Result<()> {
+    let mut result = Ok(());
+    for link in device.devlinks().clone() {
+        let link_path = root.join(&link);
 
-        if link_path.exists() {
-            fs::remove_file(&link_path)?;
+        device.remove_devlink(&link);
+        let Some((link_path, target)) = db.claim(&link_path, device.devpath(), dev_path, priority) else {
+            continue;
+        };
+        let claimed = link_path
+            .strip_prefix(root)
+            .map_or(link.clone(), |claimed| claimed.to_string_lossy().into_owned());
+        if target != dev_path {
+            info!(
+                "Symlink {:?} stays with higher-priority {:?} (priority {})",
+                link_path, target, priority
+            );
+            device.add_devlink(&claimed);
+            continue;
         }
 
         info!("Creating symlink {:?} -> {:?}", link_path, dev_path);
-        symlink(dev_path, link_path)?;
+        match point_symlink(&link_path, dev_path) {
+            Ok(()) => device.add_devlink(&claimed),
+            Err(e) => {
+                warn!("Failed to create symlink {:?}: {}", link_path, e);
+                db.unclaim(&link_path, device.devpath());
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+    result
+}
+
+// 覆盖已有的链接（包括悬空的链接）：先在同一目录下以临时名字创建，再 rename 覆盖，替换期间链接始终存在
+fn point_symlink(link_path: &Path, target: &Path) -> std::io::Result<()> {
+    let parent = link_path.parent().unwrap_or(Path::new("/"));
+    fs::create_dir_all(parent)?;
+
+    let tmp_path = temp_path(link_path);
+    symlink(target, &tmp_path)?;
+    if let Err(e) = fs::rename(&tmp_path, link_path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
     }
     Ok(())
 }
@@ -162,7 +802,98 @@ pub fn remove_device_node(dev_path: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
-pub fn remove_symlinks(dev_path: &Path, symlink_dir: &Path) -> std::io::Result<()> {
+/// remove 事件的兜底清理，用于守护进程没有见过 add 的设备（比如重启前添加的设备）：
+/// record 是上次运行写出的节点和链接记录，只删除其中 major:minor 与事件相同的节点，
+/// 以及记录中仍指向该节点的符号链接；单项失败时记录下来继续，返回删除的条目数
+pub fn remove_stale_artifacts(device: &UEventDevice, record: &DeviceLinks) -> usize {
+    let (Some(node), Some(major), Some(minor)) = (&record.node, device.major(), device.minor()) else {
+        return 0;
+    };
+    let devnum = makedev(major.into(), minor.into());
+    let want_block = device_node_type(device) == SFlag::S_IFBLK;
+
+    // 节点已经不在时（devtmpfs 删掉了）链接仍然可能残留
+    let stale_node = match node.symlink_metadata() {
+        Ok(meta) => {
+            let file_type = meta.file_type();
+            let is_node = if want_block {
+                file_type.is_block_device()
+            } else {
+                file_type.is_char_device()
+            };
+            if !is_node || meta.rdev() != devnum {
+                debug!("{:?} now belongs to another device, leaving it", node);
+                return 0;
+            }
+            true
+        }
+        Err(_) => false,
+    };
+
+    let mut removed = 0;
+    for link in &record.links {
+        let points_to_node = fs::read_link(&link.link)
+            .is_ok_and(|target| normalize_path(&link.link.parent().unwrap_or(&link.link).join(target)) == *node);
+        if !points_to_node {
+            continue;
+        }
+        info!("Removing stale symlink {:?} -> {:?}", link.link, node);
+        match fs::remove_file(&link.link) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!("Failed to remove stale symlink {:?}: {}", link.link, e),
+        }
+    }
+
+    if stale_node {
+        info!("Removing stale device node {:?} ({}:{})", node, major, minor);
+        match fs::remove_file(node) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!("Failed to remove stale device node {:?}: {}", node, e),
+        }
+    }
+    removed
+}
+
+// 按字面处理 . 和 ..，不访问文件系统（节点可能已经不存在）
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// 撤销设备的链接声明：仍有其它设备声明的链接改指向剩余优先级最高的设备，
+/// 其余指向该设备节点的链接被删除
+pub fn remove_symlinks(
+    dev_path: &Path,
+    symlink_dir: &Path,
+    device: &UEventDevice,
+    db: &SymlinkDb,
+) -> std::io::Result<()> {
+    for (link_path, target) in db.release(device.devpath()) {
+        match target {
+            Some(target) => {
+                info!("Repointing symlink {:?} -> {:?}", link_path, target);
+                point_symlink(&link_path, &target)?;
+            }
+            None => {
+                info!("Removing symlink {:?}", link_path);
+                if let Err(e) = fs::remove_file(&link_path) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
     let dev_path_canon = dev_path.canonicalize()?;
     debug!("Scanning for symlinks pointing to {:?}", dev_path_canon);
 
@@ -199,26 +930,126 @@ pub fn remove_symlinks(dev_path: &Path, symlink_dir: &Path) -> std::io::Result<(
     Ok(())
 }
 
-pub fn run_commands(commands: &Vec<String>, device: &UEventDevice) -> std::io::Result<()> {
-    let envs = device.properties();
+/// 在后台启动一条已完成变量替换的 RUN 命令，不等待它结束；由 Reaper 回收
+pub fn spawn_command(command: &str, envs: &HashMap<String, String>) -> std::io::Result<Child> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(envs)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+}
 
-    for cmd in commands {
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .envs(envs)
-            .output()?;
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
 
-        if !output.status.success() {
-            eprintln!("Command failed: {}", cmd);
-            eprintln!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-        } else {
-            println!(
-                "Command output: {}",
-                String::from_utf8_lossy(&output.stdout)
-            );
+    use super::*;
+    use crate::symlink_db::LinkRecord;
+
+    fn removed_device() -> UEventDevice {
+        let properties = HashMap::from([
+            ("ACTION".to_string(), "remove".to_string()),
+            ("DEVPATH".to_string(), "/devices/virtual/block/loop7".to_string()),
+            ("SUBSYSTEM".to_string(), "block".to_string()),
+            ("DEVNAME".to_string(), "loop7".to_string()),
+            ("MAJOR".to_string(), "7".to_string()),
+            ("MINOR".to_string(), "7".to_string()),
+        ]);
+        UEventDevice::from_event(properties).unwrap()
+    }
+
+    fn record(node: &Path, links: &[&Path]) -> DeviceLinks {
+        DeviceLinks {
+            node: Some(node.to_path_buf()),
+            links: links
+                .iter()
+                .map(|link| LinkRecord {
+                    link: link.to_path_buf(),
+                    priority: 0,
+                    active: true,
+                })
+                .collect(),
         }
     }
 
-    Ok(())
+    #[test]
+    fn stale_cleanup_only_touches_recorded_links_to_the_node() {
+        let dir = std::env::temp_dir().join(format!("rust_udev-stale-{}", std::process::id()));
+        fs::create_dir_all(dir.join("disk")).unwrap();
+        let node = dir.join("loop7");
+        let own = dir.join("disk/own");
+        let relative = dir.join("disk/relative");
+        let foreign = dir.join("disk/foreign");
+        let unlisted = dir.join("disk/unlisted");
+        symlink(&node, &own).unwrap();
+        symlink("../loop7", &relative).unwrap();
+        symlink(dir.join("loop8"), &foreign).unwrap();
+        symlink(&node, &unlisted).unwrap();
+
+        let removed = remove_stale_artifacts(&removed_device(), &record(&node, &[&own, &relative, &foreign]));
+        let exists = |path: &Path| path.symlink_metadata().is_ok();
+        let remaining = [exists(&own), exists(&relative), exists(&foreign), exists(&unlisted)];
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(removed, 2);
+        assert_eq!(remaining, [false, false, true, true]);
+    }
+
+    #[test]
+    fn temp_paths_are_unique_in_the_same_directory() {
+        let first = temp_path(Path::new("/dev/disk/by-id/usb-x"));
+        let second = temp_path(Path::new("/dev/disk/by-id/usb-x"));
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(Path::new("/dev/disk/by-id")));
+        assert!(first.file_name().unwrap().to_string_lossy().starts_with(".usb-x.udev-tmp-"));
+    }
+
+    #[test]
+    fn stale_cleanup_leaves_a_reused_node_alone() {
+        let dir = std::env::temp_dir().join(format!("rust_udev-reused-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let node = dir.join("loop7");
+        let link = dir.join("link");
+        fs::write(&node, "").unwrap();
+        symlink(&node, &link).unwrap();
+
+        let removed = remove_stale_artifacts(&removed_device(), &record(&node, &[&link]));
+        let remaining = (node.exists(), link.symlink_metadata().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(removed, 0);
+        assert_eq!(remaining, (true, true));
+    }
+
+    #[test]
+    fn substitutions_expand_short_and_long_formats() {
+        let mut device = removed_device();
+        device.set_property("ID_SERIAL", "disk-1");
+        let value = substitute_vars("%k $kernel %n $number %E{ID_SERIAL} $env{ID_SERIAL} ${MISSING}|%% $$", &device);
+        assert_eq!(value, "loop7 loop7 7 7 disk-1 disk-1 |% $");
+    }
+
+    #[test]
+    fn substitutions_follow_udev_names() {
+        let mut device = removed_device();
+        device.set_dev_root(Path::new("/tmp/dev"));
+        device.add_devlink("disk/by-id/a");
+        device.add_devlink("disk/by-id/b");
+        let value = substitute_vars("%N $devnode %M:%m $major:$minor %r $root %S $sys", &device);
+        assert_eq!(value, "/tmp/dev/loop7 /tmp/dev/loop7 7:7 7:7 /tmp/dev /tmp/dev /sys /sys");
+        assert_eq!(substitute_vars("$links|$name", &device), "disk/by-id/a disk/by-id/b|loop7");
+
+        device.set_name(Some("disk/renamed".to_string()));
+        device.set_property("DRIVER", "loop");
+        assert_eq!(substitute_vars("$name %d $driver", &device), "disk/renamed loop loop");
+    }
+
+    #[test]
+    fn unknown_substitutions_use_the_same_formats() {
+        assert!(unknown_substitutions("%k $result $result{1} %c{2+} $attr{size} ${ID} %% $$").is_empty());
+        assert!(unknown_substitutions("%N %P $parent %r %S $links $name").is_empty());
+        assert_eq!(unknown_substitutions("%q $kernal $"), vec!["%q", "$kernal", "$"]);
+    }
 }
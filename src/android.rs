@@ -0,0 +1,10 @@
+// src/android.rs
+
+use crate::profile::Profile;
+
+/// Android 厂商 USB ID 和 adb/fastboot 接口表，以及按其中的 ID_ANDROID 授权访问 Android 设备的规则
+pub const ANDROID: Profile = Profile {
+    name: "<51-android.rules>",
+    rules: include_str!("../rules/51-android.rules"),
+    hwdb: include_str!("../hwdb.d/20-android.hwdb"),
+};
@@ -0,0 +1,393 @@
+// src/builtins/blkid.rs
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::{encode_string, Builtin, BuiltinOptions};
+use crate::device::UEventDevice;
+use crate::transliterate::{replace_chars, replace_whitespace};
+
+/// 简化的 blkid：从设备节点读取 FAT 和 NTFS 的卷标与序列号，导出 ID_FS_TYPE、ID_FS_UUID、
+/// ID_FS_LABEL 和 ID_FS_LABEL_ENC，供 by-label、by-uuid 链接使用
+///
+/// 卷标不是 UTF-8：FAT 根目录卷标项和引导扇区中是 OEM 代码页（按 Linux vfat 默认的 CP437 转换），
+/// NTFS 的 $Volume 中是 UTF-16LE。ID_FS_LABEL 中空白和不安全字符替换为 '_'，
+/// ID_FS_LABEL_ENC 则像 udev 一样用 \xNN 转义，可以无损地用在链接名中。
+/// 参数可以给出设备节点，默认为 DEVNAME。
+pub struct Blkid;
+
+// 读取的最大长度：引导扇区以及 NTFS 的一个 MFT 记录
+const SECTOR_SIZE: usize = 512;
+const MAX_MFT_RECORD: usize = 4096;
+// FAT 根目录最多读取的字节数
+const MAX_ROOT_DIR: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Probe {
+    fs_type: &'static str,
+    uuid: Option<String>,
+    label: Option<String>,
+}
+
+impl Builtin for Blkid {
+    fn name(&self) -> &'static str {
+        "blkid"
+    }
+
+    fn run(
+        &self,
+        device: &UEventDevice,
+        args: &[&str],
+        _options: &BuiltinOptions,
+    ) -> io::Result<Vec<(String, String)>> {
+        let node = match args.first() {
+            Some(node) => PathBuf::from(node),
+            None => device_node(device)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "device has no DEVNAME"))?,
+        };
+
+        let mut file = File::open(&node)?;
+        let Some(probe) = probe(&mut file)? else {
+            return Ok(Vec::new());
+        };
+
+        let mut props = vec![
+            ("ID_FS_TYPE".to_string(), probe.fs_type.to_string()),
+            ("ID_FS_USAGE".to_string(), "filesystem".to_string()),
+        ];
+        if let Some(uuid) = probe.uuid {
+            props.push(("ID_FS_UUID".to_string(), safe_string(&uuid)));
+            props.push(("ID_FS_UUID_ENC".to_string(), encode_string(&uuid)));
+        }
+        if let Some(label) = probe.label {
+            props.push(("ID_FS_LABEL".to_string(), safe_string(&label)));
+            props.push(("ID_FS_LABEL_ENC".to_string(), encode_string(&label)));
+        }
+        Ok(props)
+    }
+}
+
+// 与 udev 的 blkid 相同：空白换成 '_'，不安全的字节换成 '_'，合法的多字节 UTF-8 字符保留
+fn safe_string(value: &str) -> String {
+    replace_chars(&replace_whitespace(value), "")
+}
+
+// DEVNAME 通常是相对名字；守护进程在设备根目录下创建节点，设备根目录不是 /dev 时再找 /dev
+fn device_node(device: &UEventDevice) -> Option<PathBuf> {
+    let name = device.devnode()?;
+    if Path::new(name).is_absolute() {
+        return Some(PathBuf::from(name));
+    }
+    [device.dev_root(), Path::new("/dev")]
+        .iter()
+        .map(|root| root.join(name))
+        .find(|path| path.exists())
+}
+
+fn probe<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Probe>> {
+    let mut boot = [0u8; SECTOR_SIZE];
+    reader.seek(SeekFrom::Start(0))?;
+    if reader.read(&mut boot)? < SECTOR_SIZE {
+        return Ok(None);
+    }
+
+    if &boot[3..11] == b"NTFS    " {
+        return probe_ntfs(reader, &boot);
+    }
+    if boot[510..512] == [0x55, 0xAA] {
+        return probe_fat(reader, &boot);
+    }
+    Ok(None)
+}
+
+// FAT32 的扩展 BPB 在 0x40，FAT12/16 在 0x24；卷标 11 字节，空格填充。
+// 格式化之后改过的卷标只写在根目录中，引导扇区里的可能是旧的，所以根目录优先
+fn probe_fat<R: Read + Seek>(reader: &mut R, boot: &[u8]) -> io::Result<Option<Probe>> {
+    let fat32 = &boot[0x52..0x5A] == b"FAT32   ";
+    let (serial_at, label_at) = if fat32 {
+        (0x43, 0x47)
+    } else if &boot[0x36..0x39] == b"FAT" {
+        (0x27, 0x2B)
+    } else {
+        return Ok(None);
+    };
+
+    let serial = u32::from_le_bytes(boot[serial_at..serial_at + 4].try_into().unwrap());
+    let label = match root_dir_label(reader, boot, fat32)? {
+        Some(label) => Some(label),
+        None => fat_label(&boot[label_at..label_at + 11]),
+    };
+    Ok(Some(Probe {
+        fs_type: "vfat",
+        uuid: Some(format!("{:04X}-{:04X}", serial >> 16, serial & 0xFFFF)),
+        label,
+    }))
+}
+
+fn fat_label(raw: &[u8]) -> Option<String> {
+    let label = decode_cp437(raw);
+    let label = label.trim_end();
+    (!label.is_empty() && label != "NO NAME").then(|| label.to_string())
+}
+
+// 根目录中属性为 0x08 的项是卷标；FAT12/16 的根目录紧跟在 FAT 之后，
+// FAT32 的根目录是普通的簇链，只读第一个簇
+fn root_dir_label<R: Read + Seek>(reader: &mut R, boot: &[u8], fat32: bool) -> io::Result<Option<String>> {
+    let u16_at = |at: usize| u16::from_le_bytes([boot[at], boot[at + 1]]) as u64;
+    let u32_at = |at: usize| u32::from_le_bytes(boot[at..at + 4].try_into().unwrap()) as u64;
+    let sector_size = u16_at(0x0B);
+    let cluster_sectors = boot[0x0D] as u64;
+    let reserved = u16_at(0x0E);
+    let fats = boot[0x10] as u64;
+
+    let (start, len) = if fat32 {
+        let data = reserved + fats * u32_at(0x24);
+        let cluster = u32_at(0x2C);
+        if cluster < 2 {
+            return Ok(None);
+        }
+        (data + (cluster - 2) * cluster_sectors, cluster_sectors * sector_size)
+    } else {
+        (reserved + fats * u16_at(0x16), u16_at(0x11) * 32)
+    };
+    if sector_size == 0 || len == 0 || len as usize > MAX_ROOT_DIR {
+        return Ok(None);
+    }
+
+    let mut dir = vec![0u8; len as usize];
+    reader.seek(SeekFrom::Start(start * sector_size))?;
+    reader.read_exact(&mut dir)?;
+    for entry in dir.chunks_exact(32) {
+        match entry[0] {
+            0x00 => break,
+            0xE5 => continue,
+            // 长文件名项的属性是 0x0F；卷标项有 0x08 而没有目录位 0x10
+            _ if entry[11] & 0x0F != 0x0F && entry[11] & 0x18 == 0x08 => {
+                let mut raw = [0u8; 11];
+                raw.copy_from_slice(&entry[..11]);
+                // 0x05 表示第一个字节其实是 0xE5
+                if raw[0] == 0x05 {
+                    raw[0] = 0xE5;
+                }
+                return Ok(fat_label(&raw));
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+// 卷标是 MFT 第 3 个记录（$Volume）中的 VOLUME_NAME（0x60）属性
+fn probe_ntfs<R: Read + Seek>(reader: &mut R, boot: &[u8]) -> io::Result<Option<Probe>> {
+    let sector_size = u16::from_le_bytes([boot[0x0B], boot[0x0C]]) as u64;
+    let cluster_size = sector_size * boot[0x0D] as u64;
+    let mft_cluster = u64::from_le_bytes(boot[0x30..0x38].try_into().unwrap());
+    // 为负数时记录大小是 2 的 -n 次方字节
+    let record_size = match boot[0x40] as i8 {
+        n if n < 0 => 1u64 << (-n as u32),
+        n => n as u64 * cluster_size,
+    };
+    let serial = u64::from_le_bytes(boot[0x48..0x50].try_into().unwrap());
+    let mut probe = Probe {
+        fs_type: "ntfs",
+        uuid: Some(format!("{:016X}", serial)),
+        label: None,
+    };
+
+    if sector_size == 0 || cluster_size == 0 || record_size as usize > MAX_MFT_RECORD || record_size < sector_size {
+        return Ok(Some(probe));
+    }
+    let mut record = vec![0u8; record_size as usize];
+    let offset = mft_cluster
+        .checked_mul(cluster_size)
+        .and_then(|start| start.checked_add(3 * record_size));
+    let Some(offset) = offset else {
+        return Ok(Some(probe));
+    };
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut record)?;
+
+    if &record[0..4] == b"FILE" && apply_fixups(&mut record, sector_size as usize) {
+        probe.label = volume_name(&record);
+    }
+    Ok(Some(probe))
+}
+
+// 每个扇区最后两个字节被替换成了更新序列号，原值保存在更新序列数组中
+fn apply_fixups(record: &mut [u8], sector_size: usize) -> bool {
+    let usa_offset = u16::from_le_bytes([record[4], record[5]]) as usize;
+    let usa_count = u16::from_le_bytes([record[6], record[7]]) as usize;
+    if usa_count == 0 || usa_offset + usa_count * 2 > record.len() {
+        return false;
+    }
+    for i in 1..usa_count {
+        let end = i * sector_size;
+        let fixup = usa_offset + i * 2;
+        if end > record.len() || record[end - 2..end] != record[usa_offset..usa_offset + 2] {
+            return false;
+        }
+        record.copy_within(fixup..fixup + 2, end - 2);
+    }
+    true
+}
+
+fn volume_name(record: &[u8]) -> Option<String> {
+    let mut offset = u16::from_le_bytes([record[0x14], record[0x15]]) as usize;
+    while offset + 0x18 <= record.len() {
+        let kind = u32::from_le_bytes(record[offset..offset + 4].try_into().ok()?);
+        let length = u32::from_le_bytes(record[offset + 4..offset + 8].try_into().ok()?) as usize;
+        if kind == 0xFFFF_FFFF || length == 0 {
+            return None;
+        }
+        // 只处理常驻属性
+        if kind == 0x60 && record[offset + 8] == 0 {
+            let value_len = u32::from_le_bytes(record[offset + 0x10..offset + 0x14].try_into().ok()?) as usize;
+            let value_at = offset + u16::from_le_bytes([record[offset + 0x14], record[offset + 0x15]]) as usize;
+            let value = record.get(value_at..value_at + value_len)?;
+            let units: Vec<u16> = value.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+            let name = String::from_utf16_lossy(&units);
+            return (!name.is_empty()).then_some(name);
+        }
+        offset += length;
+    }
+    None
+}
+
+// CP437 的高半部分；低半部分与 ASCII 相同
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// 把 OEM 代码页 437 的字节转换为字符串
+pub fn decode_cp437(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| if b < 0x80 { b as char } else { CP437_HIGH[(b - 0x80) as usize] })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn put(image: &mut [u8], at: usize, bytes: &[u8]) {
+        image[at..at + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // FAT16：1 个保留扇区、2 个各 1 扇区的 FAT，根目录从第 3 个扇区开始，16 项
+    fn fat16(boot_label: &[u8; 11], root_entries: &[(&[u8; 11], u8)]) -> Cursor<Vec<u8>> {
+        let mut image = vec![0u8; 8 * SECTOR_SIZE];
+        put(&mut image, 0x0B, &512u16.to_le_bytes());
+        image[0x0D] = 1;
+        put(&mut image, 0x0E, &1u16.to_le_bytes());
+        image[0x10] = 2;
+        put(&mut image, 0x11, &16u16.to_le_bytes());
+        put(&mut image, 0x16, &1u16.to_le_bytes());
+        put(&mut image, 0x27, &0x1234_ABCDu32.to_le_bytes());
+        put(&mut image, 0x2B, boot_label);
+        put(&mut image, 0x36, b"FAT16   ");
+        put(&mut image, 510, &[0x55, 0xAA]);
+        for (i, (name, attr)) in root_entries.iter().enumerate() {
+            let at = 3 * SECTOR_SIZE + i * 32;
+            put(&mut image, at, &name[..]);
+            image[at + 11] = *attr;
+        }
+        Cursor::new(image)
+    }
+
+    #[test]
+    fn fat_root_dir_label_wins_over_boot_sector() {
+        let mut deleted = *b"OLDLABEL   ";
+        deleted[0] = 0xE5;
+        let mut image = fat16(
+            b"BOOTLABEL  ",
+            &[(&deleted, 0x08), (b"LFN-ENTRY  ", 0x0F), (b"README  TXT", 0x20), (b"NEW \x82T\x90    ", 0x28)],
+        );
+        let probe = probe(&mut image).unwrap().unwrap();
+        assert_eq!(probe.fs_type, "vfat");
+        assert_eq!(probe.uuid.as_deref(), Some("1234-ABCD"));
+        assert_eq!(probe.label.as_deref(), Some("NEW éTÉ"));
+    }
+
+    #[test]
+    fn fat_falls_back_to_boot_sector_label() {
+        let mut image = fat16(b"BOOTLABEL  ", &[(b"README  TXT", 0x20)]);
+        assert_eq!(probe(&mut image).unwrap().unwrap().label.as_deref(), Some("BOOTLABEL"));
+
+        let mut image = fat16(b"NO NAME    ", &[]);
+        assert_eq!(probe(&mut image).unwrap().unwrap().label, None);
+    }
+
+    #[test]
+    fn fat32_reads_the_root_cluster() {
+        // 32 个保留扇区、1 个 1 扇区的 FAT，每簇 2 扇区，根目录在第 3 簇
+        let mut image = vec![0u8; 40 * SECTOR_SIZE];
+        put(&mut image, 0x0B, &512u16.to_le_bytes());
+        image[0x0D] = 2;
+        put(&mut image, 0x0E, &32u16.to_le_bytes());
+        image[0x10] = 1;
+        put(&mut image, 0x24, &1u32.to_le_bytes());
+        put(&mut image, 0x2C, &3u32.to_le_bytes());
+        put(&mut image, 0x43, &0xDEAD_BEEFu32.to_le_bytes());
+        put(&mut image, 0x47, b"NO NAME    ");
+        put(&mut image, 0x52, b"FAT32   ");
+        put(&mut image, 510, &[0x55, 0xAA]);
+        let root = (32 + 1 + 2) * SECTOR_SIZE;
+        put(&mut image, root, b"USB STICK  ");
+        image[root + 11] = 0x08;
+
+        let probe = probe(&mut Cursor::new(image)).unwrap().unwrap();
+        assert_eq!(probe.uuid.as_deref(), Some("DEAD-BEEF"));
+        assert_eq!(probe.label.as_deref(), Some("USB STICK"));
+    }
+
+    #[test]
+    fn ntfs_label_comes_from_the_volume_record() {
+        // 每簇 1 扇区，MFT 在第 2 簇，记录大小 2^10 字节，$Volume 在 2*512 + 3*1024
+        let mut image = vec![0u8; 8 * 1024];
+        put(&mut image, 3, b"NTFS    ");
+        put(&mut image, 0x0B, &512u16.to_le_bytes());
+        image[0x0D] = 1;
+        put(&mut image, 0x30, &2u64.to_le_bytes());
+        image[0x40] = (-10i8) as u8;
+        put(&mut image, 0x48, &0x0123_4567_89AB_CDEFu64.to_le_bytes());
+
+        let mut record = vec![0u8; 1024];
+        put(&mut record, 0, b"FILE");
+        put(&mut record, 4, &0x30u16.to_le_bytes());
+        put(&mut record, 6, &3u16.to_le_bytes());
+        put(&mut record, 0x14, &0x38u16.to_le_bytes());
+        // 更新序列号 0x0001，每个扇区的最后两个字节原本是 0
+        put(&mut record, 0x30, &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        put(&mut record, 510, &[0x01, 0x00]);
+        put(&mut record, 1022, &[0x01, 0x00]);
+        put(&mut record, 0x38, &0x60u32.to_le_bytes());
+        put(&mut record, 0x3C, &0x28u32.to_le_bytes());
+        put(&mut record, 0x48, &10u32.to_le_bytes());
+        put(&mut record, 0x4C, &0x18u16.to_le_bytes());
+        let name: Vec<u8> = "Daten".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        put(&mut record, 0x50, &name);
+        put(&mut record, 0x60, &0xFFFF_FFFFu32.to_le_bytes());
+        put(&mut image, 2 * 512 + 3 * 1024, &record);
+
+        let probe = probe(&mut Cursor::new(image)).unwrap().unwrap();
+        assert_eq!(probe.fs_type, "ntfs");
+        assert_eq!(probe.uuid.as_deref(), Some("0123456789ABCDEF"));
+        assert_eq!(probe.label.as_deref(), Some("Daten"));
+    }
+
+    #[test]
+    fn labels_are_made_safe_like_udev() {
+        assert_eq!(safe_string("  My Disk/été  "), "My_Disk_été");
+    }
+}
@@ -0,0 +1,112 @@
+// src/builtins/hwdb.rs
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use log::*;
+
+use super::{Builtin, BuiltinOptions};
+use crate::device::UEventDevice;
+use crate::hwdb::{Hwdb, HWDB_DIRS};
+use crate::profile;
+
+// 内置数据在前，/usr/lib 和 /etc 中的文件可以覆盖它们
+static DATABASE: LazyLock<Hwdb> = LazyLock::new(|| {
+    let mut hwdb = embedded();
+    match Hwdb::load(&HWDB_DIRS) {
+        Ok(files) => hwdb.extend(files),
+        Err(e) => warn!("Failed to load hwdb files: {}", e),
+    }
+    debug!("Loaded {} hwdb entries", hwdb.len());
+    hwdb
+});
+
+// 随可选功能内置的数据表
+fn embedded() -> Hwdb {
+    let mut hwdb = Hwdb::new();
+    for profile in profile::enabled() {
+        hwdb.extend(Hwdb::parse_str(profile.hwdb));
+    }
+    hwdb
+}
+
+/// 在内置数据和 hwdb 文件中查找
+pub(super) fn lookup(key: &str) -> Vec<(String, String)> {
+    DATABASE.lookup(key)
+}
+
+/// 在硬件数据库中查找设备，导出匹配记录的属性
+///
+/// 参数给出查找键时直接使用；否则使用 MODALIAS。USB 设备本身没有 MODALIAS，
+/// 改用 usb:vXXXXpYYYY，并合并当前已有接口的 modalias，使按接口类匹配的记录也能生效。
+pub struct HwdbBuiltin;
+
+impl Builtin for HwdbBuiltin {
+    fn name(&self) -> &'static str {
+        "hwdb"
+    }
+
+    fn run(
+        &self,
+        device: &UEventDevice,
+        args: &[&str],
+        _options: &BuiltinOptions,
+    ) -> io::Result<Vec<(String, String)>> {
+        if !args.is_empty() {
+            return Ok(DATABASE.lookup(&args.join(" ")));
+        }
+
+        let keys = lookup_keys(device);
+        if keys.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no modalias for {:?}", device.devpath()),
+            ));
+        }
+
+        let mut properties: Vec<(String, String)> = Vec::new();
+        for key in keys {
+            for (name, value) in DATABASE.lookup(&key) {
+                match properties.iter_mut().find(|(existing, _)| *existing == name) {
+                    Some(existing) => existing.1 = value,
+                    None => properties.push((name, value)),
+                }
+            }
+        }
+        Ok(properties)
+    }
+}
+
+fn lookup_keys(device: &UEventDevice) -> Vec<String> {
+    if let Some(modalias) = device.property("MODALIAS") {
+        return vec![modalias.to_string()];
+    }
+
+    let syspath = device.syspath();
+    let read = |dir: &Path, name: &str| {
+        fs::read_to_string(dir.join(name))
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+
+    let (Some(vendor), Some(product)) = (read(&syspath, "idVendor"), read(&syspath, "idProduct")) else {
+        return Vec::new();
+    };
+    let mut keys = vec![format!("usb:v{}p{}", vendor.to_uppercase(), product.to_uppercase())];
+
+    if let Ok(entries) = fs::read_dir(&syspath) {
+        let mut interfaces: Vec<String> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.join("bInterfaceNumber").exists())
+            .filter_map(|path| read(&path, "modalias"))
+            .collect();
+        interfaces.sort();
+        keys.extend(interfaces);
+    }
+
+    keys
+}
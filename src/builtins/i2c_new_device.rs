@@ -0,0 +1,95 @@
+// src/builtins/i2c_new_device.rs
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::*;
+
+use super::{Builtin, BuiltinOptions};
+use crate::device::UEventDevice;
+
+/// 内核 i2c_board_info.type 的长度（含结尾的 NUL）
+pub const I2C_NAME_SIZE: usize = 20;
+
+// 只支持 7 位地址；10 位地址在 new_device 中要带 0xa000 标志，目录名也不同
+const I2C_ADDR_MAX: u16 = 0x7f;
+
+/// 在 I2C 适配器上实例化设备，相当于 echo "bmp280 0x76" > /sys/bus/i2c/devices/i2c-1/new_device
+///
+/// 参数是芯片名和地址；同一地址上已经有设备时不再写入，重复的 add 事件不会报错。
+/// 由规则中的 I2C_NEW_DEVICE="bmp280 0x76" 在适配器出现时调用，也可以通过 IMPORT{builtin} 使用。
+pub struct I2cNewDevice;
+
+impl Builtin for I2cNewDevice {
+    fn name(&self) -> &'static str {
+        "i2c_new_device"
+    }
+
+    fn run(
+        &self,
+        device: &UEventDevice,
+        args: &[&str],
+        _options: &BuiltinOptions,
+    ) -> io::Result<Vec<(String, String)>> {
+        let (chip, addr) = parse_spec(&args.join(" ")).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let client = instantiate(&device.syspath(), &chip, addr)?;
+        Ok(vec![("I2C_CLIENT".to_string(), client)])
+    }
+}
+
+/// 解析 "芯片名 地址"，地址可以是 0x 开头的十六进制或十进制
+pub fn parse_spec(spec: &str) -> Result<(String, u16), String> {
+    let mut parts = spec.split_whitespace();
+    let (Some(chip), Some(addr), None) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!("expected '<chip> <address>', got '{}'", spec));
+    };
+    if chip.len() >= I2C_NAME_SIZE {
+        return Err(format!("chip name '{}' is longer than {} bytes", chip, I2C_NAME_SIZE - 1));
+    }
+
+    let parsed = match addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => addr.parse(),
+    };
+    match parsed {
+        Ok(addr) if addr > 0 && addr <= I2C_ADDR_MAX => Ok((chip.to_string(), addr)),
+        _ => Err(format!("invalid I2C address '{}'", addr)),
+    }
+}
+
+/// 在 adapter（适配器的 sysfs 目录，如 /sys/devices/.../i2c-1）上实例化设备，返回客户端名如 1-0076
+pub fn instantiate(adapter: &Path, chip: &str, addr: u16) -> io::Result<String> {
+    let bus = adapter
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("i2c-"))
+        .filter(|bus| bus.parse::<u32>().is_ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not an I2C adapter", adapter.display()),
+            )
+        })?;
+
+    // 内核以 总线号-四位十六进制地址 命名客户端目录
+    let client = format!("{}-{:04x}", bus, addr);
+    let client_dir = adapter.join(&client);
+    if client_dir.exists() {
+        match fs::read_to_string(client_dir.join("name")) {
+            Ok(existing) if existing.trim_end() != chip => warn!(
+                "I2C address 0x{:02x} on {} is already used by '{}', not adding '{}'",
+                addr,
+                adapter.display(),
+                existing.trim_end(),
+                chip
+            ),
+            _ => debug!("I2C device {} already exists", client),
+        }
+        return Ok(client);
+    }
+
+    fs::write(adapter.join("new_device"), format!("{} 0x{:02x}", chip, addr))?;
+    info!("Instantiated I2C device '{}' at 0x{:02x} on {}", chip, addr, adapter.display());
+    Ok(client)
+}
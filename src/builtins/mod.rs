@@ -0,0 +1,72 @@
+// src/builtins/mod.rs
+
+pub mod blkid;
+pub mod hwdb;
+pub mod i2c_new_device;
+pub mod security_token;
+pub mod serial_bridge;
+pub mod usb_id;
+
+use std::io;
+
+use log::*;
+
+use crate::device::UEventDevice;
+use crate::transliterate::Transliteration;
+pub use crate::transliterate::{encode_string, sanitize_id};
+
+/// 内置命令的设置，守护进程经 RuleSetOptions 传入
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuiltinOptions {
+    /// 厂商、型号、序列号等字符串用于 by-id 名字时不安全字符的处理方式
+    pub transliteration: Transliteration,
+}
+
+/// 内置命令，供 IMPORT{builtin}="name args" 调用，避免为每个事件启动外部程序
+pub trait Builtin: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// 返回需要写入设备的属性，不直接修改设备，便于调试时只打印结果
+    fn run(&self, device: &UEventDevice, args: &[&str], options: &BuiltinOptions) -> io::Result<Vec<(String, String)>>;
+}
+
+static BUILTINS: &[&dyn Builtin] = &[
+    &blkid::Blkid,
+    &hwdb::HwdbBuiltin,
+    &i2c_new_device::I2cNewDevice,
+    &security_token::SecurityToken,
+    &serial_bridge::SerialBridge,
+    &usb_id::UsbId,
+];
+
+pub fn find_builtin(name: &str) -> Option<&'static dyn Builtin> {
+    BUILTINS.iter().copied().find(|b| b.name() == name)
+}
+
+pub fn builtin_names() -> Vec<&'static str> {
+    BUILTINS.iter().map(|b| b.name()).collect()
+}
+
+/// 解析 "name arg1 arg2" 形式的命令，执行对应内置命令但不修改设备
+pub fn run_builtin(command: &str, device: &UEventDevice, options: &BuiltinOptions) -> io::Result<Vec<(String, String)>> {
+    let mut parts = command.split_whitespace();
+    let name = parts.next().unwrap_or("");
+    let args: Vec<&str> = parts.collect();
+
+    let builtin = find_builtin(name).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("unknown builtin '{}'", name))
+    })?;
+
+    debug!("Running builtin '{}' with args {:?}", name, args);
+    builtin.run(device, &args, options)
+}
+
+/// 执行内置命令并把结果导入设备属性
+pub fn import_builtin(command: &str, device: &mut UEventDevice, options: &BuiltinOptions) -> io::Result<usize> {
+    let properties = run_builtin(command, device, options)?;
+    for (key, value) in &properties {
+        device.set_property(key, value);
+    }
+    info!("Imported {} properties from builtin '{}'", properties.len(), command);
+    Ok(properties.len())
+}
@@ -0,0 +1,163 @@
+// src/builtins/security_token.rs
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{Builtin, BuiltinOptions};
+use crate::device::UEventDevice;
+
+// FIDO Alliance 的 HID usage page，U2F/FIDO2 令牌在报告描述符中声明
+const FIDO_USAGE_PAGE: u32 = 0xF1D0;
+// USB 接口类：智能卡读卡器（CCID）
+const CCID_INTERFACE_CLASS: &str = "0b";
+
+/// 识别 FIDO2/U2F 令牌（hidraw）和 CCID 智能卡读卡器（usb），
+/// 导出 ID_SECURITY_TOKEN=1 以及 ID_FIDO_TOKEN=1 或 ID_SMARTCARD_READER=1；其它设备不导出属性
+pub struct SecurityToken;
+
+impl Builtin for SecurityToken {
+    fn name(&self) -> &'static str {
+        "security_token"
+    }
+
+    fn run(
+        &self,
+        device: &UEventDevice,
+        _args: &[&str],
+        _options: &BuiltinOptions,
+    ) -> io::Result<Vec<(String, String)>> {
+        let syspath = device.syspath();
+        let kind = match device.subsystem() {
+            "hidraw" => is_fido_device(&syspath).then_some("ID_FIDO_TOKEN"),
+            "usb" => is_smartcard_reader(&syspath).then_some("ID_SMARTCARD_READER"),
+            _ => None,
+        };
+
+        Ok(match kind {
+            Some(kind) => vec![
+                ("ID_SECURITY_TOKEN".to_string(), "1".to_string()),
+                (kind.to_string(), "1".to_string()),
+            ],
+            None => Vec::new(),
+        })
+    }
+}
+
+/// 可选启用的内置规则：安全令牌的设备节点属于 group，组内用户可以读写；
+/// group 不是合法的组名或 GID 时返回 None
+pub fn security_token_rules(group: &str) -> Option<String> {
+    valid_group_name(group).then(|| {
+        format!(
+            "SUBSYSTEM==\"hidraw\", IMPORT{{builtin}}=\"security_token\"\n\
+             SUBSYSTEM==\"usb\", IMPORT{{builtin}}=\"security_token\"\n\
+             SUBSYSTEM==\"hidraw\", ENV{{ID_SECURITY_TOKEN}}==\"1\", GROUP=\"{group}\", MODE=\"0660\"\n\
+             SUBSYSTEM==\"usb\", ENV{{ID_SECURITY_TOKEN}}==\"1\", GROUP=\"{group}\", MODE=\"0660\"\n"
+        )
+    })
+}
+
+/// 组名按 useradd 的规则检查：字母或下划线开头，其后是字母、数字、'_' 或 '-'，可以以 '$' 结尾，
+/// 最长 32 个字符；也可以直接给出数字 GID。其它字符会破坏规则文本
+pub fn valid_group_name(name: &str) -> bool {
+    if !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) {
+        return true;
+    }
+    let body = name.strip_suffix('$').unwrap_or(name);
+    let mut bytes = body.bytes();
+    name.len() <= 32
+        && bytes.next().is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+// hidraw 设备的 device 链接指向 HID 设备，其报告描述符中出现 FIDO usage page 即为令牌；
+// 读不到报告描述符的设备不是令牌
+fn is_fido_device(syspath: &Path) -> bool {
+    fs::read(syspath.join("device/report_descriptor"))
+        .is_ok_and(|descriptor| usage_pages(&descriptor).contains(&FIDO_USAGE_PAGE))
+}
+
+// usb_device 看其下任一接口，usb_interface 看自身
+fn is_smartcard_reader(syspath: &Path) -> bool {
+    let is_ccid = |dir: &Path| {
+        fs::read_to_string(dir.join("bInterfaceClass"))
+            .is_ok_and(|class| class.trim() == CCID_INTERFACE_CLASS)
+    };
+    if is_ccid(syspath) {
+        return true;
+    }
+
+    fs::read_dir(syspath)
+        .map(|entries| entries.flatten().any(|entry| is_ccid(&entry.path())))
+        .unwrap_or(false)
+}
+
+/// 报告描述符中所有 Usage Page 全局项的值
+fn usage_pages(descriptor: &[u8]) -> Vec<u32> {
+    const LONG_ITEM: u8 = 0xFE;
+    // 类型为 global（1）、tag 为 Usage Page（0）的短项前缀，低两位是数据长度
+    const USAGE_PAGE_PREFIX: u8 = 0x04;
+
+    let mut pages = Vec::new();
+    let mut i = 0;
+    while i < descriptor.len() {
+        let prefix = descriptor[i];
+        if prefix == LONG_ITEM {
+            // 长项：前缀、数据长度、tag，之后是数据
+            let size = descriptor.get(i + 1).copied().unwrap_or(0) as usize;
+            i += 3 + size;
+            continue;
+        }
+
+        let size = match prefix & 0x03 {
+            3 => 4,
+            n => n as usize,
+        };
+        let Some(data) = descriptor.get(i + 1..i + 1 + size) else {
+            break;
+        };
+        if prefix & 0xFC == USAGE_PAGE_PREFIX {
+            let value = data
+                .iter()
+                .rev()
+                .fold(0u32, |value, &byte| (value << 8) | byte as u32);
+            pages.push(value);
+        }
+        i += 1 + size;
+    }
+
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_names_are_validated_before_they_reach_the_rules() {
+        assert!(valid_group_name("plugdev"));
+        assert!(valid_group_name("_fido-users"));
+        assert!(valid_group_name("1000"));
+        assert!(!valid_group_name(""));
+        assert!(!valid_group_name("1users"));
+        assert!(!valid_group_name("wheel\", RUN+=\"/bin/sh"));
+        assert!(!valid_group_name(&"a".repeat(33)));
+
+        assert!(security_token_rules("wheel\"").is_none());
+        let rules = security_token_rules("plugdev").unwrap();
+        assert!(rules.contains("GROUP=\"plugdev\""));
+    }
+
+    #[test]
+    fn hidraw_without_report_descriptor_is_not_a_token() {
+        let dir = std::env::temp_dir().join(format!("rust_udev-hidraw-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(!is_fido_device(&dir));
+
+        // Usage Page (0xF1D0)
+        std::fs::create_dir_all(dir.join("device")).unwrap();
+        std::fs::write(dir.join("device/report_descriptor"), [0x06, 0xD0, 0xF1, 0x09, 0x01]).unwrap();
+        assert!(is_fido_device(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
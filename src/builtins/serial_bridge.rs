@@ -0,0 +1,49 @@
+// src/builtins/serial_bridge.rs
+
+use std::io;
+
+use super::hwdb;
+use super::usb_id::{find_usb_device, read_attr};
+use super::{sanitize_id, Builtin, BuiltinOptions};
+use crate::device::UEventDevice;
+
+/// 识别 hwdb 中标记了 ID_SERIAL_BRIDGE=1 的 USB 转串口芯片（CH340、FTDI、CP210x 等），
+/// 导出 hwdb 中的属性以及稳定的 ID_SERIAL_STABLE：有序列号时用序列号，
+/// 否则（如多数 CH340）用 USB 端口路径，同一个口插同一块板子名字不变
+pub struct SerialBridge;
+
+impl Builtin for SerialBridge {
+    fn name(&self) -> &'static str {
+        "serial_bridge"
+    }
+
+    fn run(
+        &self,
+        device: &UEventDevice,
+        _args: &[&str],
+        options: &BuiltinOptions,
+    ) -> io::Result<Vec<(String, String)>> {
+        let Some((usb_dir, _)) = find_usb_device(device) else {
+            return Ok(Vec::new());
+        };
+
+        let (Some(vendor), Some(product)) = (read_attr(&usb_dir, "idVendor"), read_attr(&usb_dir, "idProduct")) else {
+            return Ok(Vec::new());
+        };
+        let key = format!("usb:v{}p{}", vendor.to_uppercase(), product.to_uppercase());
+        let mut props = hwdb::lookup(&key);
+        if !props.iter().any(|(name, value)| name == "ID_SERIAL_BRIDGE" && value == "1") {
+            return Ok(Vec::new());
+        }
+
+        let stable = match read_attr(&usb_dir, "serial") {
+            Some(serial) => sanitize_id(&serial, options.transliteration),
+            None => {
+                let port = usb_dir.file_name().unwrap_or_default().to_string_lossy();
+                format!("port-{}", sanitize_id(port.as_bytes(), options.transliteration))
+            }
+        };
+        props.push(("ID_SERIAL_STABLE".to_string(), stable));
+        Ok(props)
+    }
+}
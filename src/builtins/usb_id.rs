@@ -0,0 +1,121 @@
+// src/builtins/usb_id.rs
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{encode_string, sanitize_id, Builtin, BuiltinOptions};
+use crate::device::UEventDevice;
+
+/// 沿 sysfs 向上查找 usb_device，导出 ID_VENDOR、ID_MODEL、ID_SERIAL 等属性
+///
+/// 厂商、型号和序列号按 transliterate 的设置转换，默认与 systemd-udevd 的 usb_id 逐字节相同；
+/// ID_VENDOR_ENC、ID_MODEL_ENC 是转义后的原值
+pub struct UsbId;
+
+// 与 udev 相同：ID_VENDOR、ID_MODEL 只取属性的前 63 个字节
+const MAX_NAME_LEN: usize = 63;
+
+pub(super) fn read_attr(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// 字符串描述符不一定是 UTF-8，按字节读取，只去掉末尾的换行
+fn read_raw_attr(dir: &Path, name: &str) -> Option<Vec<u8>> {
+    let mut value = fs::read(dir.join(name)).ok()?;
+    if value.last() == Some(&b'\n') {
+        value.pop();
+    }
+    (!value.is_empty()).then_some(value)
+}
+
+/// 返回 (usb_device 目录, 离设备最近的 usb_interface 目录)，设备本身也算在内
+pub(super) fn find_usb_device(device: &UEventDevice) -> Option<(PathBuf, Option<PathBuf>)> {
+    let self_or_parent = |devtype: &str| {
+        if device.subsystem() == "usb" && device.devtype() == Some(devtype) {
+            Some(device.syspath())
+        } else {
+            device.parent_with_subsystem_devtype("usb", Some(devtype)).map(|parent| parent.syspath())
+        }
+    };
+
+    let usb_dir = self_or_parent("usb_device")?;
+    // 接口是 usb_device 的子设备；设备本身是 usb_device 时没有接口
+    let interface = self_or_parent("usb_interface").filter(|interface| interface.starts_with(&usb_dir));
+    Some((usb_dir, interface))
+}
+
+impl Builtin for UsbId {
+    fn name(&self) -> &'static str {
+        "usb_id"
+    }
+
+    fn run(
+        &self,
+        device: &UEventDevice,
+        _args: &[&str],
+        options: &BuiltinOptions,
+    ) -> io::Result<Vec<(String, String)>> {
+        let (usb_dir, interface_dir) = find_usb_device(device).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no usb_device parent for {:?}", device.syspath()),
+            )
+        })?;
+
+        let vendor_id = read_attr(&usb_dir, "idVendor").unwrap_or_default();
+        let model_id = read_attr(&usb_dir, "idProduct").unwrap_or_default();
+
+        let vendor_raw = read_raw_attr(&usb_dir, "manufacturer").unwrap_or_else(|| vendor_id.clone().into_bytes());
+        let model_raw = read_raw_attr(&usb_dir, "product").unwrap_or_else(|| model_id.clone().into_bytes());
+        let vendor = sanitize_id(&vendor_raw[..vendor_raw.len().min(MAX_NAME_LEN)], options.transliteration);
+        let model = sanitize_id(&model_raw[..model_raw.len().min(MAX_NAME_LEN)], options.transliteration);
+        // 含控制字符、非 ASCII 或 ',' 的序列号视为无效，与 udev 相同
+        let serial = read_raw_attr(&usb_dir, "serial")
+            .filter(|serial| serial.iter().all(|&b| (0x20..=0x7f).contains(&b) && b != b','))
+            .map(|serial| sanitize_id(&serial, options.transliteration))
+            .filter(|serial| !serial.is_empty());
+
+        let mut props = vec![
+            ("ID_BUS".to_string(), "usb".to_string()),
+            ("ID_VENDOR".to_string(), vendor.clone()),
+            ("ID_VENDOR_ENC".to_string(), encode_string(&vendor_raw)),
+            ("ID_VENDOR_ID".to_string(), vendor_id),
+            ("ID_MODEL".to_string(), model.clone()),
+            ("ID_MODEL_ENC".to_string(), encode_string(&model_raw)),
+            ("ID_MODEL_ID".to_string(), model_id),
+        ];
+
+        if let Some(revision) = read_attr(&usb_dir, "bcdDevice") {
+            props.push(("ID_REVISION".to_string(), revision));
+        }
+
+        let id_serial = match &serial {
+            Some(serial) => {
+                props.push(("ID_SERIAL_SHORT".to_string(), serial.clone()));
+                format!("{}_{}_{}", vendor, model, serial)
+            }
+            None => format!("{}_{}", vendor, model),
+        };
+        props.push(("ID_SERIAL".to_string(), id_serial));
+
+        if let Some(interface_dir) = interface_dir {
+            if let Some(num) = read_attr(&interface_dir, "bInterfaceNumber") {
+                props.push(("ID_USB_INTERFACE_NUM".to_string(), num));
+            }
+            if let Ok(driver) = fs::read_link(interface_dir.join("driver")) {
+                if let Some(name) = driver.file_name() {
+                    props.push((
+                        "ID_USB_DRIVER".to_string(),
+                        name.to_string_lossy().into_owned(),
+                    ));
+                }
+            }
+        }
+
+        Ok(props)
+    }
+}
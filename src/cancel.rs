@@ -0,0 +1,92 @@
+// src/cancel.rs
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::clock::{system_clock, Clock};
+
+/// 阻塞在系统调用（poll、accept 等）中的循环每隔这么久检查一次是否已取消
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 线程之间共享的停止信号：cancel 之后，检查它的循环在当前一轮结束后退出
+///
+/// 克隆得到的令牌与原令牌共享同一个状态。
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    cancelled: Mutex<bool>,
+    cond: Condvar,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::with_clock(system_clock())
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// wait_timeout 按给定的时钟计算超时，测试中可以用虚拟时钟
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                cancelled: Mutex::new(false),
+                cond: Condvar::new(),
+                clock,
+            }),
+        }
+    }
+
+    /// 请求停止并唤醒所有在 wait_timeout 中等待的线程；重复调用无副作用
+    pub fn cancel(&self) {
+        *self.inner.cancelled.lock().unwrap() = true;
+        self.inner.cond.notify_all();
+    }
+
+    /// 返回的守卫在 drop 时取消令牌，函数无论从哪里返回都会停止它启动的线程
+    pub fn drop_guard(&self) -> DropGuard {
+        DropGuard { token: self.clone() }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.inner.cancelled.lock().unwrap()
+    }
+
+    /// 代替 thread::sleep：最多等待 timeout，期间被取消时立即返回；返回是否已取消
+    ///
+    /// 每次最多睡 CHECK_INTERVAL 后重新读时钟，虚拟时钟被推进时也能及时返回
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let clock = &self.inner.clock;
+        let deadline = clock.now() + timeout;
+        let mut cancelled = self.inner.cancelled.lock().unwrap();
+        while !*cancelled {
+            let now = clock.now();
+            if now >= deadline {
+                break;
+            }
+            let wait = (deadline - now).min(CHECK_INTERVAL);
+            cancelled = self.inner.cond.wait_timeout(cancelled, wait).unwrap().0;
+        }
+        *cancelled
+    }
+}
+
+/// CancellationToken::drop_guard 返回的守卫
+#[derive(Debug)]
+pub struct DropGuard {
+    token: CancellationToken,
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
@@ -0,0 +1,131 @@
+// src/clock.rs
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// 时间来源；需要计时的模块都通过它取时间，测试中可以换成手动推进的时钟
+pub trait Clock: Debug + Send + Sync {
+    /// 单调时间，用于超时和时间窗口
+    fn now(&self) -> Instant;
+    /// 墙上时间，用于事件时间戳
+    fn wall(&self) -> SystemTime;
+    /// CLOCK_MONOTONIC 的微秒数，用于事件到达时间和数据库 I: 行
+    fn monotonic_usec(&self) -> u64;
+}
+
+/// 读取系统时间的时钟
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic_usec(&self) -> u64 {
+        monotonic_usec()
+    }
+}
+
+/// 默认时钟
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// 只在调用 advance 时前进的虚拟时钟
+#[derive(Debug)]
+pub struct ManualClock {
+    base: Instant,
+    wall_base: SystemTime,
+    usec_base: u64,
+    elapsed: Mutex<Duration>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    /// 指定墙上时间的起点
+    pub fn starting_at(wall: SystemTime) -> Self {
+        Self {
+            base: Instant::now(),
+            wall_base: wall,
+            usec_base: monotonic_usec(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.base + *self.elapsed.lock().unwrap()
+    }
+
+    fn wall(&self) -> SystemTime {
+        self.wall_base + *self.elapsed.lock().unwrap()
+    }
+
+    fn monotonic_usec(&self) -> u64 {
+        self.usec_base + self.elapsed.lock().unwrap().as_micros() as u64
+    }
+}
+
+/// CLOCK_MONOTONIC 的微秒数，与 udev 数据库 I: 行使用的时钟相同，重启守护进程后仍可比较
+pub fn monotonic_usec() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000
+}
+
+/// 事件到达的时间：墙上时间用于显示和历史记录，CLOCK_MONOTONIC 微秒数用于延迟和时间窗口
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiveTime {
+    pub realtime: SystemTime,
+    pub monotonic_usec: u64,
+}
+
+impl ReceiveTime {
+    /// 此刻，两个时间都取自给定的时钟
+    pub fn now(clock: &dyn Clock) -> Self {
+        Self {
+            realtime: clock.wall(),
+            monotonic_usec: clock.monotonic_usec(),
+        }
+    }
+
+    /// 内核用 SO_TIMESTAMPNS 记录的接收时间（CLOCK_REALTIME），在读出消息时立即调用；
+    /// 单调时间取读出时的 CLOCK_MONOTONIC，不从墙上时间回推，墙上时间跳变不会影响延迟的计算
+    pub fn from_kernel(realtime: SystemTime, clock: &dyn Clock) -> Self {
+        Self {
+            realtime,
+            monotonic_usec: clock.monotonic_usec(),
+        }
+    }
+
+    /// 到达至今经过的时间
+    pub fn elapsed(&self, clock: &dyn Clock) -> Duration {
+        Duration::from_micros(clock.monotonic_usec().saturating_sub(self.monotonic_usec))
+    }
+
+    /// 换算成同一时钟上的 Instant，供以 Instant 计时的队列使用
+    pub fn instant(&self, clock: &dyn Clock) -> Instant {
+        let now = clock.now();
+        now.checked_sub(self.elapsed(clock)).unwrap_or(now)
+    }
+}
@@ -0,0 +1,282 @@
+// src/config.rs
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::LevelFilter;
+
+use crate::actions::{ResolveNames, DEV_ROOT};
+use crate::filter::DEFAULT_IGNORED_INTERFACES;
+use crate::logging::{parse_level, LogTarget};
+use crate::rules::parser::{default_rules_dirs, parse_delay, parse_size, DEFAULT_MAX_RULES_FILE_SIZE};
+use crate::symlink_db::{parse_collision_policies, CollisionPolicy};
+use crate::transliterate::Transliteration;
+
+/// 守护进程配置文件，命令行 --config 可以指定另一个
+pub const CONFIG_PATH: &str = "/etc/udev/udev.conf";
+
+/// 与 udev 相同的默认事件时限
+pub const DEFAULT_EVENT_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// udev.conf 中的设置，没有出现的项取默认值；命令行参数在此基础上覆盖
+///
+/// 每行一项 key=value，# 开头的行是注释；同一项出现多次时以最后一次为准，rule= 则逐条累加。
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// udev_log=：没有设置 RUST_LOG 时的日志级别，如 err、info、debug 或 syslog 的数字级别
+    pub log_level: Option<LevelFilter>,
+    /// log_target=：日志后端
+    pub log_target: LogTarget,
+    /// children_max=：同时处理事件的线程数，默认与 CPU 数相同
+    pub children_max: Option<usize>,
+    /// exec_delay=：每个 RUN 命令启动前的等待时间，用于调试
+    pub exec_delay: Duration,
+    /// event_timeout=：PROGRAM、IMPORT{program} 和 RUN 命令运行超过这个时间被杀死
+    pub event_timeout: Duration,
+    /// resolve_names=：OWNER/GROUP 名字的解析时机
+    pub resolve_names: ResolveNames,
+    /// dev_root=：创建设备节点和符号链接的目录
+    pub dev_root: PathBuf,
+    /// rules_dirs=：空白分隔的规则目录，按优先级从低到高
+    pub rules_dirs: Vec<PathBuf>,
+    /// max_rules_file_size=：超过这个大小的规则文件被跳过，如 512K、8M
+    pub max_rules_file_size: u64,
+    /// trace_rules=：记录每个事件的规则匹配过程
+    pub trace_rules: bool,
+    /// id_transliteration=：厂商、型号等字符串用于 by-id 名字时不安全字符的处理方式
+    pub transliteration: Transliteration,
+    /// resync_on_gap=：发现丢失的内核事件时重新同步相关子系统的设备
+    pub resync_on_gap: bool,
+    /// broadcast_events=：把处理完的事件广播到 udev 多播组
+    pub broadcast_events: bool,
+    /// ignore_interfaces=：空白分隔的 glob，名字匹配的网络接口不处理；值为空时处理所有接口
+    pub ignore_interfaces: Vec<String>,
+    /// symlink_collision=：按目录的符号链接冲突策略
+    pub symlink_policies: Vec<(PathBuf, CollisionPolicy)>,
+    /// rule=：单行规则，可以出现多次，值原样保留
+    pub rules: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            log_level: None,
+            log_target: LogTarget::default(),
+            children_max: None,
+            exec_delay: Duration::ZERO,
+            event_timeout: DEFAULT_EVENT_TIMEOUT,
+            resolve_names: ResolveNames::default(),
+            dev_root: PathBuf::from(DEV_ROOT),
+            rules_dirs: default_rules_dirs(),
+            max_rules_file_size: DEFAULT_MAX_RULES_FILE_SIZE,
+            trace_rules: false,
+            transliteration: Transliteration::default(),
+            resync_on_gap: false,
+            broadcast_events: false,
+            ignore_interfaces: DEFAULT_IGNORED_INTERFACES.iter().map(|pattern| pattern.to_string()).collect(),
+            symlink_policies: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// 读取配置文件，文件不存在时为默认配置；第二个返回值是被忽略的无效项
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<(Self, Vec<String>)> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok((Self::default(), Vec::new())),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 解析配置文件的内容；无法识别的取值保留默认值并记入第二个返回值，未知的项直接跳过
+    pub fn parse(content: &str) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut problems = Vec::new();
+
+        for (key, raw) in entries(content) {
+            if key == "rule" {
+                config.rules.push(raw.to_string());
+                continue;
+            }
+            let value = raw.trim_matches('"');
+            let valid = match key {
+                "udev_log" => parse_level(value).map(|level| config.log_level = Some(level)).is_some(),
+                "log_target" => LogTarget::parse(value).map(|target| config.log_target = target).is_some(),
+                "children_max" => value
+                    .parse()
+                    .ok()
+                    .filter(|&n: &usize| n > 0)
+                    .map(|n| config.children_max = Some(n))
+                    .is_some(),
+                "exec_delay" => parse_delay(value).map(|delay| config.exec_delay = delay).is_some(),
+                "event_timeout" => parse_delay(value)
+                    .filter(|timeout| !timeout.is_zero())
+                    .map(|timeout| config.event_timeout = timeout)
+                    .is_some(),
+                "resolve_names" => ResolveNames::parse(value).map(|mode| config.resolve_names = mode).is_some(),
+                "dev_root" => {
+                    let valid = Path::new(value).is_absolute();
+                    if valid {
+                        config.dev_root = PathBuf::from(value);
+                    }
+                    valid
+                }
+                "rules_dirs" => {
+                    let dirs: Vec<PathBuf> = value.split_whitespace().map(PathBuf::from).collect();
+                    let valid = !dirs.is_empty();
+                    if valid {
+                        config.rules_dirs = dirs;
+                    }
+                    valid
+                }
+                "max_rules_file_size" => parse_size(value)
+                    .filter(|&size| size > 0)
+                    .map(|size| config.max_rules_file_size = size)
+                    .is_some(),
+                "trace_rules" => parse_bool(value).map(|enabled| config.trace_rules = enabled).is_some(),
+                "id_transliteration" => Transliteration::parse(value)
+                    .map(|mode| config.transliteration = mode)
+                    .is_some(),
+                "resync_on_gap" => parse_bool(value).map(|enabled| config.resync_on_gap = enabled).is_some(),
+                "broadcast_events" => parse_bool(value)
+                    .map(|enabled| config.broadcast_events = enabled)
+                    .is_some(),
+                "ignore_interfaces" => {
+                    config.ignore_interfaces = value.split_whitespace().map(String::from).collect();
+                    true
+                }
+                "symlink_collision" => match parse_collision_policies(value) {
+                    Ok(policies) => {
+                        config.symlink_policies = policies;
+                        true
+                    }
+                    Err(entry) => {
+                        problems.push(format!("invalid symlink_collision entry '{}'", entry));
+                        continue;
+                    }
+                },
+                _ => true,
+            };
+            if !valid {
+                problems.push(format!("invalid {} '{}'", key, value));
+            }
+        }
+
+        (config, problems)
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "yes" | "true" | "on" => Some(true),
+        "0" | "no" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+// 非注释行中的 (key, value)，两边的空白已去掉
+fn entries(content: &str) -> impl Iterator<Item = (&str, &str)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+/// 读取配置文件中 key=value 形式的一项，值两边的引号会被去掉，重复出现时最后一个生效；文件不存在时为 None
+pub fn config_value<P: AsRef<Path>>(path: P, key: &str) -> io::Result<Option<String>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let value = entries(&content)
+        .filter(|(name, _)| *name == key)
+        .last()
+        .map(|(_, value)| value.trim_matches('"').to_string());
+    Ok(value)
+}
+
+/// 读取配置文件中同一个键的全部取值，按出现顺序；值原样保留，不去掉引号。
+/// 用于可以重复出现、且值本身带引号的项，比如 rule=SUBSYSTEM=="tty", GROUP="dialout"
+pub fn config_values<P: AsRef<Path>>(path: P, key: &str) -> io::Result<Vec<String>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let values = entries(&content)
+        .filter(|(name, _)| *name == key)
+        .map(|(_, value)| value.to_string())
+        .collect();
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_keys_take_the_last_value() {
+        let content = r#"
+children_max=4
+rule=SUBSYSTEM=="tty", GROUP="dialout"
+children_max=8
+rule=KERNEL=="sda", OPTIONS+="nowatch"
+"#;
+        let (config, problems) = Config::parse(content);
+        assert!(problems.is_empty());
+        assert_eq!(config.children_max, Some(8));
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(config.dev_root, PathBuf::from("/dev"));
+    }
+
+    #[test]
+    fn quoted_values_comments_and_durations() {
+        let content = r#"
+# comment
+udev_log="debug"
+exec_delay=500ms
+event_timeout=2min
+dev_root=/run/testdev
+rules_dirs=/etc/a /etc/b
+max_rules_file_size=64K
+resolve_names=never
+id_transliteration=escape
+symlink_collision=disk/by-label:suffix, disk/by-id:keep-first
+"#;
+        let (config, problems) = Config::parse(content);
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(config.log_level, Some(LevelFilter::Debug));
+        assert_eq!(config.exec_delay, Duration::from_millis(500));
+        assert_eq!(config.event_timeout, Duration::from_secs(120));
+        assert_eq!(config.dev_root, PathBuf::from("/run/testdev"));
+        assert_eq!(config.rules_dirs, vec![PathBuf::from("/etc/a"), PathBuf::from("/etc/b")]);
+        assert_eq!(config.max_rules_file_size, 64 * 1024);
+        assert_eq!(config.resolve_names, ResolveNames::Never);
+        assert_eq!(config.transliteration, Transliteration::Escape);
+        assert_eq!(
+            config.symlink_policies,
+            vec![
+                (PathBuf::from("disk/by-label"), CollisionPolicy::Suffix),
+                (PathBuf::from("disk/by-id"), CollisionPolicy::KeepFirst),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_values_keep_defaults_and_are_reported() {
+        let (config, problems) = Config::parse("children_max=0\nevent_timeout=0\ndev_root=relative\nunknown_key=1\n");
+        assert_eq!(config.children_max, None);
+        assert_eq!(config.event_timeout, DEFAULT_EVENT_TIMEOUT);
+        assert_eq!(config.dev_root, PathBuf::from(DEV_ROOT));
+        assert_eq!(problems.len(), 3);
+    }
+}
@@ -0,0 +1,359 @@
+// src/control.rs
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::logging::{parse_level, parse_log_filter, LogDirective};
+
+/// 守护进程的控制套接字，udevadm control 通过它发送命令
+pub const CONTROL_PATH: &str = "/run/rust_udev/control";
+
+// 客户端发来下一条命令的时限，超过后断开连接
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// 同时保持的客户端连接数上限
+const MAX_CLIENTS: usize = 16;
+
+// 单条命令的长度上限
+const MAX_LINE: u64 = 4096;
+
+/// 控制命令，每行一条，比如 "log-level debug"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// 立即重新加载规则
+    Reload,
+    /// 修改守护进程的日志级别
+    SetLogLevel(LevelFilter),
+    /// 按模块修改日志级别，为空时清除之前的设置
+    SetLogFilter(Vec<LogDirective>),
+    /// 暂停处理事件，新事件继续排队
+    StopExecQueue,
+    /// 恢复处理排队的事件
+    StartExecQueue,
+    /// 只等守护进程回复，确认它在运行并处理完了之前的命令
+    Ping,
+}
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        // 过滤规则中逗号后面可以有空白，整行剩余部分都属于它
+        if let Some(spec) = line.trim().strip_prefix("log-filter") {
+            if spec.is_empty() || spec.starts_with(char::is_whitespace) {
+                return parse_log_filter(spec).map(ControlCommand::SetLogFilter);
+            }
+        }
+        let mut words = line.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("reload"), None) => ControlCommand::Reload,
+            (Some("log-level"), Some(level)) => {
+                ControlCommand::SetLogLevel(parse_level(level).ok_or_else(|| format!("invalid log level '{}'", level))?)
+            }
+            (Some("stop-exec-queue"), None) => ControlCommand::StopExecQueue,
+            (Some("start-exec-queue"), None) => ControlCommand::StartExecQueue,
+            (Some("ping"), None) => ControlCommand::Ping,
+            _ => return Err(format!("unknown command '{}'", line.trim())),
+        };
+        match words.next() {
+            Some(_) => Err(format!("unexpected arguments in '{}'", line.trim())),
+            None => Ok(command),
+        }
+    }
+
+    /// 发送给守护进程的一行，不含换行
+    pub fn to_line(&self) -> String {
+        match self {
+            ControlCommand::Reload => "reload".to_string(),
+            ControlCommand::SetLogLevel(level) => format!("log-level {}", level.as_str().to_ascii_lowercase()),
+            ControlCommand::SetLogFilter(directives) => {
+                let spec: Vec<String> = directives.iter().map(LogDirective::to_string).collect();
+                format!("log-filter {}", spec.join(",")).trim_end().to_string()
+            }
+            ControlCommand::StopExecQueue => "stop-exec-queue".to_string(),
+            ControlCommand::StartExecQueue => "start-exec-queue".to_string(),
+            ControlCommand::Ping => "ping".to_string(),
+        }
+    }
+}
+
+/// 控制套接字的监听端，由守护进程主循环 poll，drop 时删除套接字文件
+///
+/// 客户端连接是非阻塞的，主循环每次醒来最多执行一条命令，读写都不会等待客户端。
+pub struct ControlServer {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<Client>,
+    // 下一次从哪个客户端开始找完整的命令，轮流执行
+    next: usize,
+}
+
+// 一个已连接的客户端和它还没有执行的输入
+struct Client {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    // 在此之前要发来下一条完整的命令，否则断开
+    deadline: Instant,
+    closed: bool,
+}
+
+impl Client {
+    // 读出当前所有可读的数据，不等待
+    fn fill(&mut self) {
+        let mut chunk = [0u8; 512];
+        while !self.closed {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.closed = true,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Control connection failed: {}", e);
+                    self.closed = true;
+                }
+            }
+        }
+    }
+
+    // 缓冲区中的第一行，不含换行
+    fn take_line(&mut self) -> Option<String> {
+        let end = self.buffer.iter().position(|&b| b == b'\n')?;
+        let line: Vec<u8> = self.buffer.drain(..=end).collect();
+        Some(String::from_utf8_lossy(&line[..end]).into_owned())
+    }
+
+    fn has_line(&self) -> bool {
+        self.buffer.contains(&b'\n')
+    }
+
+    // 回复很短，套接字缓冲区放不下说明客户端不再读取，直接断开
+    fn reply(&mut self, reply: &str) {
+        if let Err(e) = self.stream.write_all(format!("{}\n", reply).as_bytes()) {
+            warn!("Control connection failed: {}", e);
+            self.closed = true;
+        }
+    }
+}
+
+impl ControlServer {
+    /// 在 path 上监听；上次运行留下的套接字文件会被替换，只有 root 可以连接
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+            clients: Vec::new(),
+            next: 0,
+        })
+    }
+
+    /// 需要 poll 的描述符：监听套接字和所有已连接的客户端
+    pub fn fds(&self) -> Vec<RawFd> {
+        std::iter::once(self.listener.as_raw_fd())
+            .chain(self.clients.iter().map(|client| client.stream.as_raw_fd()))
+            .collect()
+    }
+
+    /// 是否还有已经读到但没有执行的命令，主循环据此不等待直接再处理一次
+    pub fn pending(&self) -> bool {
+        self.clients.iter().any(Client::has_line)
+    }
+
+    /// 接受等待中的连接，读取客户端已经发来的数据，最多执行一条命令并回复 "ok" 或 "error: 原因"
+    pub fn handle<F: FnMut(ControlCommand) -> Result<(), String>>(&mut self, mut execute: F) {
+        self.accept();
+
+        let now = Instant::now();
+        for client in &mut self.clients {
+            client.fill();
+            if client.buffer.len() as u64 > MAX_LINE && !client.has_line() {
+                client.reply("error: command too long");
+                client.closed = true;
+            } else if !client.has_line() && now >= client.deadline {
+                debug!("Closing idle control connection");
+                client.closed = true;
+            }
+        }
+
+        let count = self.clients.len();
+        let ready = (0..count)
+            .map(|offset| (self.next + offset) % count.max(1))
+            .find(|&i| self.clients[i].has_line());
+        if let Some(i) = ready {
+            self.next = i + 1;
+            let client = &mut self.clients[i];
+            let line = client.take_line().unwrap_or_default();
+            if !line.trim().is_empty() {
+                let result = ControlCommand::parse(&line).and_then(|command| {
+                    debug!("Control command: {}", command.to_line());
+                    execute(command)
+                });
+                match result {
+                    Ok(()) => client.reply("ok"),
+                    Err(reason) => {
+                        warn!("Control command '{}' failed: {}", line.trim(), reason);
+                        client.reply(&format!("error: {}", reason));
+                    }
+                }
+            }
+            client.deadline = Instant::now() + CLIENT_TIMEOUT;
+        }
+
+        // 对端关闭之前发来的完整命令仍然执行
+        self.clients.retain(|client| !client.closed || client.has_line());
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("Failed to accept control connection: {}", e);
+                    return;
+                }
+            };
+            if self.clients.len() >= MAX_CLIENTS {
+                warn!("Too many control connections, refusing a new one");
+                continue;
+            }
+            if let Err(e) = stream.set_nonblocking(true) {
+                warn!("Control connection failed: {}", e);
+                continue;
+            }
+            self.clients.push(Client {
+                stream,
+                buffer: Vec::new(),
+                deadline: Instant::now() + CLIENT_TIMEOUT,
+                closed: false,
+            });
+        }
+    }
+}
+
+impl AsRawFd for ControlServer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// 依次发送命令，返回每条命令的结果；守护进程回复 error 时为 Err(原因)
+pub fn send_commands<P: AsRef<Path>>(
+    path: P,
+    commands: &[ControlCommand],
+    timeout: Duration,
+) -> io::Result<Vec<Result<(), String>>> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let mut results = Vec::with_capacity(commands.len());
+    // 一问一答，前一条命令执行完再发下一条，ping 才能确认之前的命令已经生效
+    for command in commands {
+        writeln!(writer, "{}", command.to_line())?;
+        let mut reply = String::new();
+        if reader.read_line(&mut reply)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "daemon closed the control connection"));
+        }
+        let reply = reply.trim();
+        results.push(match reply.strip_prefix("error: ") {
+            Some(reason) => Err(reason.to_string()),
+            None if reply == "ok" => Ok(()),
+            None => Err(format!("unexpected reply '{}'", reply)),
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn commands_round_trip_through_their_line() {
+        let commands = [
+            ControlCommand::Reload,
+            ControlCommand::SetLogLevel(LevelFilter::Debug),
+            ControlCommand::SetLogFilter(parse_log_filter("info,rules=trace").unwrap()),
+            ControlCommand::SetLogFilter(Vec::new()),
+            ControlCommand::StopExecQueue,
+            ControlCommand::StartExecQueue,
+            ControlCommand::Ping,
+        ];
+        for command in commands {
+            assert_eq!(ControlCommand::parse(&command.to_line()), Ok(command));
+        }
+    }
+
+    #[test]
+    fn malformed_commands_are_rejected() {
+        assert!(ControlCommand::parse("reload now").is_err());
+        assert!(ControlCommand::parse("log-level loud").is_err());
+        assert!(ControlCommand::parse("log-filterx").is_err());
+        assert!(ControlCommand::parse("frobnicate").is_err());
+        assert_eq!(
+            ControlCommand::parse("  log-filter  rules = debug, warn \n"),
+            Ok(ControlCommand::SetLogFilter(parse_log_filter("rules=debug,warn").unwrap()))
+        );
+    }
+
+    #[test]
+    fn server_executes_one_command_per_wakeup() {
+        let path = env::temp_dir().join(format!("rust-udev-control-test-{}", process::id()));
+        let mut server = ControlServer::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"ping\nreload\n").unwrap();
+
+        let mut executed = Vec::new();
+        server.handle(|command| {
+            executed.push(command);
+            Ok(())
+        });
+        assert_eq!(executed, [ControlCommand::Ping]);
+        assert!(server.pending());
+
+        server.handle(|command| {
+            executed.push(command);
+            Err("busy".to_string())
+        });
+        assert_eq!(executed, [ControlCommand::Ping, ControlCommand::Reload]);
+        assert!(!server.pending());
+
+        let mut replies = String::new();
+        client.set_read_timeout(Some(CLIENT_TIMEOUT)).unwrap();
+        BufReader::new(&client).take(15).read_to_string(&mut replies).unwrap();
+        assert_eq!(replies, "ok\nerror: busy\n");
+
+        // 不发命令的客户端不会让 handle 等待
+        let _idle = UnixStream::connect(&path).unwrap();
+        server.handle(|_| panic!("no command was sent"));
+    }
+}
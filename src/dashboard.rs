@@ -0,0 +1,123 @@
+// src/dashboard.rs
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{system_clock, Clock};
+use crate::device::UEventDevice;
+
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+const RECENT_EVENTS: usize = 10;
+
+/// udevadm monitor --subsystem-device-count 的实时统计面板
+#[derive(Debug)]
+pub struct Dashboard {
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    totals: BTreeMap<String, u64>,
+    window: VecDeque<(Instant, String)>,
+    recent: VecDeque<String>,
+}
+
+impl Default for Dashboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self::with_clock(system_clock())
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            started: clock.now(),
+            clock,
+            totals: BTreeMap::new(),
+            window: VecDeque::new(),
+            recent: VecDeque::with_capacity(RECENT_EVENTS),
+        }
+    }
+
+    pub fn record(&mut self, device: &UEventDevice) {
+        let subsystem = device.subsystem_label().to_string();
+        *self.totals.entry(subsystem.clone()).or_insert(0) += 1;
+        self.window.push_back((self.clock.now(), subsystem));
+
+        if self.recent.len() == RECENT_EVENTS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(format!(
+            "[{}] {} {} ({})",
+            device.seqnum(),
+            device.action_label(),
+            device.devpath().display(),
+            device.subsystem_label()
+        ));
+    }
+
+    fn expire(&mut self) {
+        let now = self.clock.now();
+        while let Some((at, _)) = self.window.front() {
+            if now.duration_since(*at) > RATE_WINDOW {
+                self.window.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// 渲染面板；device_counts 与 queue_depth 来自守护进程写出的统计文件
+    pub fn render(
+        &mut self,
+        device_counts: &BTreeMap<String, usize>,
+        queue_depth: Option<usize>,
+    ) -> String {
+        self.expire();
+        let uptime = self.clock.now().duration_since(self.started);
+
+        // 刚启动时窗口不足 10 秒，按实际时长计算速率
+        let window_secs = uptime.min(RATE_WINDOW).as_secs_f64().max(1.0);
+        let mut rates: BTreeMap<&str, usize> = BTreeMap::new();
+        for (_, subsystem) in &self.window {
+            *rates.entry(subsystem.as_str()).or_insert(0) += 1;
+        }
+
+        let subsystems: BTreeSet<&str> = device_counts
+            .keys()
+            .map(String::as_str)
+            .chain(self.totals.keys().map(String::as_str))
+            .collect();
+
+        let mut out = String::new();
+        let queue = queue_depth.map_or("-".to_string(), |d| d.to_string());
+        let _ = writeln!(out, "rust_udev monitor (Ctrl-C to quit)");
+        let _ = writeln!(
+            out,
+            "queue depth: {}    events seen: {}    uptime: {}s\n",
+            queue,
+            self.totals.values().sum::<u64>(),
+            uptime.as_secs()
+        );
+        let _ = writeln!(out, "{:<20} {:>8} {:>8} {:>8}", "SUBSYSTEM", "DEVICES", "EVENTS", "RATE/s");
+
+        for subsystem in subsystems {
+            let devices = device_counts
+                .get(subsystem)
+                .map_or("-".to_string(), |n| n.to_string());
+            let events = self.totals.get(subsystem).copied().unwrap_or(0);
+            let rate = rates.get(subsystem).copied().unwrap_or(0) as f64 / window_secs;
+            let _ = writeln!(out, "{:<20} {:>8} {:>8} {:>8.2}", subsystem, devices, events, rate);
+        }
+
+        let _ = writeln!(out, "\nrecent events:");
+        for line in self.recent.iter().rev() {
+            let _ = writeln!(out, "  {}", line);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,632 @@
+// src/db.rs
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use log::debug;
+
+use crate::device::{devlinks_property, DeviceAction, UEventDevice};
+use crate::lru::LruIndex;
+use crate::stats::CacheUsage;
+
+// 估算内存时每个条目、每个属性额外计入的字节数（容器节点、String 头部等）
+const ENTRY_OVERHEAD: usize = 128;
+const PROPERTY_OVERHEAD: usize = 64;
+
+/// 默认最多保留历史的设备数
+pub const DEFAULT_DB_CAPACITY: usize = 16384;
+
+/// ShardedDeviceDb 的分片数
+pub const DB_SHARDS: usize = 16;
+
+/// 每个设备保留的最近事件数
+pub const HISTORY_LEN: usize = 16;
+
+/// 每个设备一个历史文件，文件名为 devpath 中的 / 换成 !
+pub const HISTORY_DIR: &str = "/run/rust_udev/history";
+
+/// 每个设备一个记录文件，保存规则处理之后的属性、标签和符号链接，格式与 udev 的数据库相同；
+/// 文件名见 record_id
+pub const DATA_DIR: &str = "/run/udev/data";
+
+/// 每个设备一个赋值来源文件，文件名与历史文件相同
+pub const PROVENANCE_DIR: &str = "/run/rust_udev/provenance";
+
+/// 设备的一次事件
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub seqnum: u64,
+    pub action: DeviceAction,
+    /// 秒级 Unix 时间，同一设备的记录不会倒退
+    pub timestamp: u64,
+}
+
+/// 每个设备最近几次事件的历史，设备移除后仍然保留
+///
+/// 保留历史的设备数超过上限时淘汰最久没有收到事件的设备的历史。
+#[derive(Debug)]
+struct History {
+    entries: HashMap<PathBuf, VecDeque<HistoryEntry>>,
+    recency: LruIndex<PathBuf>,
+    capacity: usize,
+    evicted: u64,
+}
+
+impl History {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: LruIndex::new(),
+            capacity: capacity.max(1),
+            evicted: 0,
+        }
+    }
+
+    // move 事件把旧 devpath 的历史接到新 devpath 上；返回因超过上限被淘汰历史的设备
+    fn record(&mut self, device: &UEventDevice) -> Vec<PathBuf> {
+        let devpath = device.devpath().to_path_buf();
+        if *device.action() == DeviceAction::Move {
+            if let Some(old) = device.property_path("DEVPATH_OLD").map(Path::to_path_buf) {
+                if let Some(entries) = self.entries.remove(&old) {
+                    self.recency.remove(&old);
+                    self.entries.insert(devpath.clone(), entries);
+                }
+            }
+        }
+
+        let entries = self.entries.entry(devpath.clone()).or_default();
+        let last = entries.back().map_or(0, |entry| entry.timestamp);
+        entries.push_back(HistoryEntry {
+            seqnum: device.seqnum(),
+            action: device.action().clone(),
+            timestamp: device.timestamp().max(last),
+        });
+        if entries.len() > HISTORY_LEN {
+            entries.pop_front();
+        }
+
+        self.recency.touch(&devpath);
+        let mut evicted = Vec::new();
+        while self.entries.len() > self.capacity {
+            let Some(oldest) = self.recency.pop_oldest() else {
+                break;
+            };
+            debug!("Device history full, evicting {:?}", oldest);
+            self.entries.remove(&oldest);
+            self.evicted += 1;
+            evicted.push(oldest);
+        }
+        evicted
+    }
+
+    fn bytes(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|(devpath, entries)| {
+                ENTRY_OVERHEAD + devpath.as_os_str().len() + entries.len() * size_of::<HistoryEntry>()
+            })
+            .sum()
+    }
+}
+
+/// 守护进程当前已知的设备，以 devpath 为键保存最近一次事件的属性
+///
+/// Path 按路径分量排序，某个设备的所有子孙在 BTreeMap 中是连续的一段。
+/// 当前存在的设备从不淘汰：remove 的节点路径和孤儿子设备的清理都依赖这些条目，
+/// 设备数只取决于系统本身。另外为每个设备保留最近几次事件的历史，设备移除后历史仍然保留，
+/// 历史按设备数受上限约束，超过时淘汰最久没有收到事件的设备的历史。
+#[derive(Debug)]
+pub struct DeviceDb {
+    devices: BTreeMap<PathBuf, HashMap<String, String>>,
+    history: History,
+}
+
+impl Default for DeviceDb {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_DB_CAPACITY)
+    }
+}
+
+impl DeviceDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// capacity 为 0 时视为 1
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            devices: BTreeMap::new(),
+            history: History::with_capacity(capacity),
+        }
+    }
+
+    /// 根据事件更新：remove 删除条目，move 按 DEVPATH_OLD 改键，其它动作记录最新属性；
+    /// 返回历史被淘汰的设备，调用方据此删除保存在磁盘上的历史
+    pub fn update(&mut self, device: &UEventDevice) -> Vec<PathBuf> {
+        let evicted = self.history.record(device);
+        self.apply(device);
+        evicted
+    }
+
+    // 只更新设备条目，不记录历史
+    fn apply(&mut self, device: &UEventDevice) {
+        let devpath = device.devpath().to_path_buf();
+        match device.action() {
+            DeviceAction::Remove => {
+                self.devices.remove(&devpath);
+            }
+            DeviceAction::Move => {
+                if let Some(old) = device.property_path("DEVPATH_OLD") {
+                    self.devices.remove(old);
+                }
+                self.devices.insert(devpath, device.properties().clone());
+            }
+            _ => {
+                self.devices.insert(devpath, device.properties().clone());
+            }
+        }
+    }
+
+    // 设备改名到另一个分片时从旧分片删除
+    fn detach(&mut self, devpath: &Path) {
+        self.devices.remove(devpath);
+    }
+
+    /// 设备最近的事件，最早的在前
+    pub fn history(&self, devpath: &Path) -> Option<&VecDeque<HistoryEntry>> {
+        self.history.entries.get(devpath)
+    }
+
+    pub fn get(&self, devpath: &Path) -> Option<&HashMap<String, String>> {
+        self.devices.get(devpath)
+    }
+
+    /// 按 devpath 排序的所有设备及其属性
+    pub fn devices(&self) -> impl Iterator<Item = (&Path, &HashMap<String, String>)> {
+        self.devices.iter().map(|(devpath, properties)| (devpath.as_path(), properties))
+    }
+
+    pub fn contains(&self, devpath: &Path) -> bool {
+        self.devices.contains_key(devpath)
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.history.capacity
+    }
+
+    /// 当前设备数、历史上限、大致占用的内存和淘汰的历史数
+    pub fn usage(&self) -> CacheUsage {
+        CacheUsage {
+            name: "db".to_string(),
+            entries: self.devices.len(),
+            capacity: self.history.capacity,
+            bytes: self.device_bytes() + self.history.bytes(),
+            evicted: self.history.evicted,
+        }
+    }
+
+    fn device_bytes(&self) -> usize {
+        self.devices
+            .iter()
+            .map(|(devpath, properties)| {
+                ENTRY_OVERHEAD
+                    + devpath.as_os_str().len()
+                    + properties
+                        .iter()
+                        .map(|(key, value)| PROPERTY_OVERHEAD + key.len() + value.len())
+                        .sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// devpath 之下的所有已知子孙设备（不含自身），按路径顺序排列
+    pub fn devices_under(&self, devpath: &Path) -> Vec<&Path> {
+        self.devices
+            .range::<Path, _>((std::ops::Bound::Excluded(devpath), std::ops::Bound::Unbounded))
+            .map(|(path, _)| path.as_path())
+            .take_while(|path| path.starts_with(devpath))
+            .collect()
+    }
+
+    /// 父设备被移除时，为没有收到自己 remove 事件的子孙设备合成 remove 事件，最深的设备在前
+    pub fn orphan_removes(&self, devpath: &Path) -> Vec<UEventDevice> {
+        self.devices_under(devpath)
+            .into_iter()
+            .rev()
+            .filter_map(|child| {
+                let mut event = self.devices.get(child)?.clone();
+                event.insert("ACTION".to_string(), "remove".to_string());
+                UEventDevice::from_event(event)
+            })
+            .collect()
+    }
+}
+
+/// 按 devpath 的哈希分片的设备数据库，每个分片各有一把读写锁，
+/// 状态接口等其它线程的查询不会和主循环的更新争用同一把锁
+///
+/// 分片只保存设备条目；历史统一保存在一处，上限和淘汰顺序针对所有设备，与分片无关。
+/// 跨分片的查询（devices、devices_under 等）逐个分片加读锁，结果不是同一时刻的快照。
+/// 同一设备的事件由调用方保证顺序，守护进程只在主循环中更新。
+#[derive(Debug)]
+pub struct ShardedDeviceDb {
+    shards: Vec<RwLock<DeviceDb>>,
+    history: Mutex<History>,
+}
+
+impl Default for ShardedDeviceDb {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_DB_CAPACITY)
+    }
+}
+
+impl ShardedDeviceDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// capacity 为 0 时视为 1
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            shards: (0..DB_SHARDS).map(|_| RwLock::new(DeviceDb::with_capacity(0))).collect(),
+            history: Mutex::new(History::with_capacity(capacity)),
+        }
+    }
+
+    fn shard(&self, devpath: &Path) -> &RwLock<DeviceDb> {
+        let mut hasher = DefaultHasher::new();
+        devpath.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    /// 与 DeviceDb::update 相同；move 到另一个分片时先从旧分片删除再写入新分片，不同时持有两把锁
+    pub fn update(&self, device: &UEventDevice) -> Vec<PathBuf> {
+        let evicted = self.history.lock().unwrap().record(device);
+
+        let shard = self.shard(device.devpath());
+        if *device.action() == DeviceAction::Move {
+            if let Some(old) = device.property_path("DEVPATH_OLD") {
+                let old_shard = self.shard(old);
+                if !std::ptr::eq(old_shard, shard) {
+                    old_shard.write().unwrap().detach(old);
+                }
+            }
+        }
+        shard.write().unwrap().apply(device);
+        evicted
+    }
+
+    pub fn get(&self, devpath: &Path) -> Option<HashMap<String, String>> {
+        self.shard(devpath).read().unwrap().get(devpath).cloned()
+    }
+
+    /// 设备最近的事件，最早的在前
+    pub fn history(&self, devpath: &Path) -> Option<VecDeque<HistoryEntry>> {
+        self.history.lock().unwrap().entries.get(devpath).cloned()
+    }
+
+    pub fn contains(&self, devpath: &Path) -> bool {
+        self.shard(devpath).read().unwrap().contains(devpath)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.read().unwrap().is_empty())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.history.lock().unwrap().capacity
+    }
+
+    /// 按 devpath 排序的所有设备及其属性
+    pub fn devices(&self) -> Vec<(PathBuf, HashMap<String, String>)> {
+        let mut devices: Vec<_> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let db = shard.read().unwrap();
+                db.devices()
+                    .map(|(devpath, properties)| (devpath.to_path_buf(), properties.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        devices.sort_by(|a, b| a.0.cmp(&b.0));
+        devices
+    }
+
+    /// devpath 之下的所有已知子孙设备（不含自身），按路径顺序排列
+    pub fn devices_under(&self, devpath: &Path) -> Vec<PathBuf> {
+        let mut devices: Vec<PathBuf> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let db = shard.read().unwrap();
+                db.devices_under(devpath)
+                    .into_iter()
+                    .map(Path::to_path_buf)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        devices.sort();
+        devices
+    }
+
+    /// 与 DeviceDb::orphan_removes 相同，最深的设备在前
+    pub fn orphan_removes(&self, devpath: &Path) -> Vec<UEventDevice> {
+        self.devices_under(devpath)
+            .into_iter()
+            .rev()
+            .filter_map(|child| {
+                let mut event = self.get(&child)?;
+                event.insert("ACTION".to_string(), "remove".to_string());
+                UEventDevice::from_event(event)
+            })
+            .collect()
+    }
+
+    /// 各分片合计的设备数和内存，以及历史的上限和淘汰数
+    pub fn usage(&self) -> CacheUsage {
+        let history = self.history.lock().unwrap();
+        let device_bytes: usize = self.shards.iter().map(|shard| shard.read().unwrap().device_bytes()).sum();
+        CacheUsage {
+            name: "db".to_string(),
+            entries: self.len(),
+            capacity: history.capacity,
+            bytes: device_bytes + history.bytes(),
+            evicted: history.evicted,
+        }
+    }
+}
+
+/// 设备历史文件的路径
+pub fn history_file<P: AsRef<Path>>(dir: P, devpath: &Path) -> PathBuf {
+    let name = devpath.to_string_lossy().trim_start_matches('/').replace('/', "!");
+    dir.as_ref().join(name)
+}
+
+/// 写入一个设备的历史，每行为 seqnum、动作和时间戳，以制表符分隔
+pub fn save_history<P: AsRef<Path>>(
+    dir: P,
+    devpath: &Path,
+    entries: &VecDeque<HistoryEntry>,
+) -> io::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let content: String = entries
+        .iter()
+        .map(|entry| format!("{}\t{}\t{}\n", entry.seqnum, entry.action.as_str(), entry.timestamp))
+        .collect();
+
+    let path = history_file(dir, devpath);
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+pub fn load_history<P: AsRef<Path>>(dir: P, devpath: &Path) -> io::Result<Vec<HistoryEntry>> {
+    let content = fs::read_to_string(history_file(dir, devpath))?;
+    let mut entries = Vec::new();
+
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if let [seqnum, action, timestamp] = fields[..] {
+            if let (Ok(seqnum), Ok(action), Ok(timestamp)) =
+                (seqnum.parse(), action.parse(), timestamp.parse())
+            {
+                entries.push(HistoryEntry { seqnum, action, timestamp });
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// 数据库中一个设备的记录
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceRecord {
+    /// E: 行，规则处理后的属性，不含 TAGS
+    pub properties: HashMap<String, String>,
+    /// G: 行
+    pub tags: BTreeSet<String>,
+    /// S: 行，相对于设备根目录的符号链接
+    pub links: Vec<String>,
+    /// I: 行，设备第一次被处理时的 CLOCK_MONOTONIC 微秒数
+    pub initialized: Option<u64>,
+}
+
+impl DeviceRecord {
+    /// 记录中的 DEVPATH；udev 写出的记录只有规则添加的属性，可能没有
+    pub fn devpath(&self) -> Option<&Path> {
+        self.properties.get("DEVPATH").map(Path::new)
+    }
+
+    /// 合并为设备属性：标签写成 TAGS=:a:b:，符号链接写成设备根目录 dev_root 下的 DEVLINKS
+    pub fn into_properties(self, dev_root: &Path) -> HashMap<String, String> {
+        let mut properties = self.properties;
+        if !self.tags.is_empty() {
+            let tags: Vec<&str> = self.tags.iter().map(String::as_str).collect();
+            properties.insert("TAGS".into(), format!(":{}:", tags.join(":")));
+        }
+        if !self.links.is_empty() {
+            properties.insert("DEVLINKS".into(), devlinks_property(&self.links, dev_root));
+        }
+        properties
+    }
+}
+
+/// 设备在数据库中的文件名，与 udev 相同：有设备号时为 b8:0 或 c128:1，
+/// 网络接口为 n<ifindex>，其余为 +subsystem:sysname
+pub fn record_id(subsystem: &str, devpath: &Path, devnum: Option<(u32, u32)>, ifindex: Option<&str>) -> Option<String> {
+    if let Some((major, minor)) = devnum {
+        let kind = if subsystem == "block" { 'b' } else { 'c' };
+        return Some(format!("{}{}:{}", kind, major, minor));
+    }
+    if let Some(ifindex) = ifindex.filter(|_| subsystem == "net") {
+        return Some(format!("n{}", ifindex));
+    }
+    let sysname = devpath.file_name()?.to_string_lossy();
+    Some(format!("+{}:{}", subsystem, sysname))
+}
+
+/// 写入设备经过规则处理后的记录：I: 初始化时间、S: 符号链接、E: 属性（按键排序）、G: 标签。
+/// ACTION 和 SEQNUM 只属于这一次事件，不保存
+pub fn save_record<P: AsRef<Path>>(dir: P, device: &UEventDevice) -> io::Result<()> {
+    let dir = dir.as_ref();
+    let id = device.db_id().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("no database id for {:?}", device.devpath()))
+    })?;
+    fs::create_dir_all(dir)?;
+
+    let properties: BTreeMap<&String, &String> = device
+        .properties()
+        .iter()
+        .filter(|(key, _)| !matches!(key.as_str(), "ACTION" | "SEQNUM" | "TAGS" | "DEVLINKS"))
+        .collect();
+
+    let mut content = String::new();
+    if let Some(usec) = device.usec_initialized() {
+        content.push_str(&format!("I:{}\n", usec));
+    }
+    for link in device.devlinks() {
+        content.push_str(&format!("S:{}\n", link));
+    }
+    for (key, value) in properties {
+        content.push_str(&format!("E:{}={}\n", key, value));
+    }
+    for tag in device.tags() {
+        content.push_str(&format!("G:{}\n", tag));
+    }
+
+    // 文件名本身可能带有扩展名（如 +pci:0000:00:02.0），临时文件名直接追加
+    let path = dir.join(&id);
+    let tmp_path = dir.join(format!("{}.tmp", id));
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+pub fn remove_record<P: AsRef<Path>>(dir: P, id: &str) -> io::Result<()> {
+    match fs::remove_file(dir.as_ref().join(id)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// 不认识的行（udev 的 I:、L:、W: 等）被忽略
+fn parse_record(content: &str) -> DeviceRecord {
+    let mut record = DeviceRecord::default();
+    for line in content.lines() {
+        let Some((kind, value)) = line.split_once(':') else {
+            continue;
+        };
+        match kind {
+            "E" => {
+                if let Some((key, value)) = value.split_once('=') {
+                    record.properties.insert(key.to_string(), value.to_string());
+                }
+            }
+            "G" => {
+                record.tags.insert(value.to_string());
+            }
+            "S" => record.links.push(value.to_string()),
+            "I" => record.initialized = value.parse().ok(),
+            _ => {}
+        }
+    }
+    record
+}
+
+pub fn load_record<P: AsRef<Path>>(dir: P, id: &str) -> io::Result<DeviceRecord> {
+    Ok(parse_record(&fs::read_to_string(dir.as_ref().join(id))?))
+}
+
+/// 读取全部设备记录，以记录中的 DEVPATH 为键，没有 DEVPATH 的记录被跳过；目录不存在时为空
+pub fn load_records<P: AsRef<Path>>(dir: P) -> io::Result<BTreeMap<PathBuf, DeviceRecord>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut records = BTreeMap::new();
+    for entry in entries.filter_map(Result::ok) {
+        // 写了一半的临时文件
+        if entry.path().extension().is_some_and(|ext| ext == "tmp") {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let record = parse_record(&content);
+        if let Some(devpath) = record.devpath() {
+            records.insert(devpath.to_path_buf(), record);
+        }
+    }
+    Ok(records)
+}
+
+/// 一项生效的赋值（节点名、符号链接、OWNER/GROUP/MODE）及设置它的规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// NAME、SYMLINK、OWNER、GROUP 或 MODE
+    pub key: String,
+    /// 已完成变量替换的值
+    pub value: String,
+    /// 规则所在的 文件:行号
+    pub location: String,
+}
+
+/// 写入一个设备的赋值来源，每行为键、值和规则位置，以制表符分隔
+pub fn save_provenance<P: AsRef<Path>>(dir: P, devpath: &Path, entries: &[Provenance]) -> io::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let content: String = entries
+        .iter()
+        .map(|entry| format!("{}\t{}\t{}\n", entry.key, entry.value, entry.location))
+        .collect();
+
+    let path = history_file(dir, devpath);
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+pub fn load_provenance<P: AsRef<Path>>(dir: P, devpath: &Path) -> io::Result<Vec<Provenance>> {
+    let content = fs::read_to_string(history_file(dir, devpath))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            Some(Provenance {
+                key: fields.next()?.to_string(),
+                value: fields.next()?.to_string(),
+                location: fields.next()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// 设备改名或移除后删除 dir 中该 devpath 的文件（历史或赋值来源）
+pub fn remove_history<P: AsRef<Path>>(dir: P, devpath: &Path) -> io::Result<()> {
+    match fs::remove_file(history_file(dir, devpath)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,322 @@
+// src/deferred.rs
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::clock::{system_clock, Clock, ReceiveTime};
+use crate::device::{DeviceAction, UEventDevice};
+
+/// AT{} 允许的最长延迟，更长的在解析规则时报错
+pub const MAX_DEFERRED_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 每个设备同时等待的延迟动作上限，超出时丢弃新的
+pub const MAX_DEFERRED_PER_DEVICE: usize = 32;
+
+/// 规则用 AT{delay}= 安排的动作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeferredAction {
+    /// AT{30s}+="/usr/bin/led-off %k"：到期时在后台执行命令，与 RUN 一样记入 RUN 日志
+    Run(String),
+    /// AT{30s,event}="change"：到期时合成一个 change 事件，重新匹配规则
+    Event,
+}
+
+impl DeferredAction {
+    /// 按规则文件的写法给出键和值，如 ("AT{30s}", "/usr/bin/led-off %k")
+    pub fn assignment(&self, delay: Duration) -> (String, String) {
+        let delay = if delay.subsec_millis() == 0 {
+            format!("{}s", delay.as_secs())
+        } else {
+            format!("{}ms", delay.as_millis())
+        };
+        match self {
+            DeferredAction::Run(command) => (format!("AT{{{}}}", delay), command.clone()),
+            DeferredAction::Event => (format!("AT{{{},event}}", delay), "change".to_string()),
+        }
+    }
+}
+
+/// 到期的延迟动作
+#[derive(Debug)]
+pub enum Fired {
+    /// 已完成变量替换的命令及其所在规则，环境变量取自安排时的事件
+    Run {
+        command: String,
+        location: String,
+        env: HashMap<String, String>,
+        seqnum: u64,
+        devpath: PathBuf,
+        /// 安排它的事件到达的时间
+        received: ReceiveTime,
+    },
+    /// 要合成 change 事件重新处理的设备，事件由守护进程从 sysfs 重新读取
+    Event(PathBuf),
+}
+
+#[derive(Debug)]
+struct Timer {
+    devpath: PathBuf,
+    // 同一条规则对同一设备的同一动作只保留最近一次安排的
+    key: String,
+    fired: Fired,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // 按到期时间排列，序号区分同时到期的定时器
+    timers: BTreeMap<(Instant, u64), Timer>,
+    next_id: u64,
+    // 正在处理的合成事件 (devpath, SEQNUM)，它们不再安排新的合成事件；处理完时删除
+    replayed: HashSet<(PathBuf, u64)>,
+}
+
+/// 规则 AT{delay}= 安排的延迟动作，与设备的存在绑定
+///
+/// 同一条规则再次匹配同一设备时重新计时（比如设备最后一次 change 30 秒后关灯），
+/// 设备 remove 时取消它的全部定时器，到期时设备已经不在 sysfs 中的也不再执行。
+/// AT{...,event} 到期时守护进程合成新的 change 事件并用 replay 登记，处理它时不再安排合成事件，避免无限循环。
+#[derive(Debug)]
+pub struct DeferredScheduler {
+    inner: Mutex<Inner>,
+    clock: Arc<dyn Clock>,
+    sys_root: PathBuf,
+}
+
+impl Default for DeferredScheduler {
+    fn default() -> Self {
+        Self::with_clock(system_clock(), "/sys")
+    }
+}
+
+impl DeferredScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clock<P: AsRef<Path>>(clock: Arc<dyn Clock>, sys_root: P) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            clock,
+            sys_root: sys_root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// 安排 delay 之后执行 action；command 已完成变量替换，location 是规则的 文件:行号。
+    /// 超出每个设备的上限时返回 false
+    pub fn schedule(&self, device: &UEventDevice, delay: Duration, action: &DeferredAction, location: &str) -> bool {
+        let devpath = device.devpath().to_path_buf();
+        let (key, fired) = match action {
+            DeferredAction::Run(command) => (
+                format!("{} run {}", location, command),
+                Fired::Run {
+                    command: command.clone(),
+                    location: location.to_string(),
+                    env: device.properties().clone(),
+                    seqnum: device.seqnum(),
+                    devpath: devpath.clone(),
+                    received: device.received(),
+                },
+            ),
+            DeferredAction::Event => (format!("{} event", location), Fired::Event(devpath.clone())),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        if matches!(action, DeferredAction::Event) && inner.replayed.contains(&(devpath.clone(), device.seqnum())) {
+            debug!("{:?} is already a deferred re-evaluation, not scheduling another", devpath);
+            return false;
+        }
+        inner.timers.retain(|_, timer| timer.devpath != devpath || timer.key != key);
+        let count = inner.timers.values().filter(|timer| timer.devpath == devpath).count();
+        if count >= MAX_DEFERRED_PER_DEVICE {
+            warn!("{:?} already has {} deferred actions, ignoring AT from {}", devpath, count, location);
+            return false;
+        }
+
+        let due = self.clock.now() + delay.min(MAX_DEFERRED_DELAY);
+        let id = inner.next_id;
+        inner.next_id += 1;
+        debug!("Deferred action from {} for {:?} in {:?}", location, devpath, delay);
+        inner.timers.insert((due, id), Timer { devpath, key, fired });
+        true
+    }
+
+    /// 取出已经到期的动作，按到期顺序；设备已经不在的直接丢弃
+    pub fn due(&self) -> Vec<Fired> {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+
+        let mut fired = Vec::new();
+        while let Some((&(due, _), _)) = inner.timers.first_key_value() {
+            if due > now {
+                break;
+            }
+            let Some((_, timer)) = inner.timers.pop_first() else {
+                break;
+            };
+            if !self.present(&timer.devpath) {
+                debug!("Dropping deferred action for {:?}, device is gone", timer.devpath);
+                continue;
+            }
+            fired.push(timer.fired);
+        }
+        fired
+    }
+
+    /// 登记由 Fired::Event 合成的事件，处理它时不再安排新的合成事件
+    pub fn replay(&self, device: &UEventDevice) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.replayed.insert((device.devpath().to_path_buf(), device.seqnum()));
+    }
+
+    /// 事件处理完毕，不管规则是否再次匹配都清除它的登记
+    pub fn processed(&self, device: &UEventDevice) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.replayed.is_empty() {
+            inner.replayed.remove(&(device.devpath().to_path_buf(), device.seqnum()));
+        }
+    }
+
+    // 与事件处理的竞争：remove 之后才安排的定时器在这里过滤掉
+    fn present(&self, devpath: &Path) -> bool {
+        let relative = devpath.strip_prefix("/").unwrap_or(devpath);
+        self.sys_root.join(relative).join("uevent").exists()
+    }
+
+    /// 根据真实事件更新：remove 取消设备的全部定时器
+    pub fn update(&self, device: &UEventDevice) {
+        if *device.action() == DeviceAction::Remove {
+            self.cancel(device.devpath());
+        }
+    }
+
+    /// 取消设备的全部定时器，返回取消的个数
+    pub fn cancel(&self, devpath: &Path) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.timers.len();
+        inner.timers.retain(|_, timer| timer.devpath != devpath);
+        inner.replayed.retain(|(replayed, _)| replayed != devpath);
+        let cancelled = before - inner.timers.len();
+        if cancelled > 0 {
+            debug!("Cancelled {} deferred action(s) for {:?}", cancelled, devpath);
+        }
+        cancelled
+    }
+
+    /// 还没到期的动作数
+    pub fn pending(&self) -> usize {
+        self.inner.lock().unwrap().timers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    const DEVPATH: &str = "/devices/virtual/leds/led0";
+
+    struct Fixture {
+        clock: Arc<ManualClock>,
+        deferred: DeferredScheduler,
+        sys_root: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let sys_root = std::env::temp_dir().join(format!("rust_udev-deferred-{}-{}", name, std::process::id()));
+            let syspath = sys_root.join(DEVPATH.trim_start_matches('/'));
+            fs::create_dir_all(&syspath).unwrap();
+            fs::write(syspath.join("uevent"), "").unwrap();
+            let clock = Arc::new(ManualClock::new());
+            let deferred = DeferredScheduler::with_clock(clock.clone(), &sys_root);
+            Self {
+                clock,
+                deferred,
+                sys_root,
+            }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.sys_root);
+        }
+    }
+
+    fn device(action: &str, seqnum: u64) -> UEventDevice {
+        let properties = HashMap::from([
+            ("ACTION".to_string(), action.to_string()),
+            ("DEVPATH".to_string(), DEVPATH.to_string()),
+            ("SUBSYSTEM".to_string(), "leds".to_string()),
+            ("SEQNUM".to_string(), seqnum.to_string()),
+        ]);
+        UEventDevice::from_event(properties).unwrap()
+    }
+
+    fn run(command: &str) -> DeferredAction {
+        DeferredAction::Run(command.to_string())
+    }
+
+    #[test]
+    fn matching_again_restarts_the_timer() {
+        let fixture = Fixture::new("reschedule");
+        let delay = Duration::from_secs(30);
+        assert!(fixture.deferred.schedule(&device("add", 1), delay, &run("off"), "a.rules:1"));
+        fixture.clock.advance(Duration::from_secs(20));
+        assert!(fixture.deferred.schedule(&device("change", 2), delay, &run("off"), "a.rules:1"));
+        assert_eq!(fixture.deferred.pending(), 1);
+
+        fixture.clock.advance(Duration::from_secs(20));
+        assert!(fixture.deferred.due().is_empty());
+        fixture.clock.advance(Duration::from_secs(10));
+        match &fixture.deferred.due()[..] {
+            [Fired::Run { command, seqnum, .. }] => assert_eq!((command.as_str(), *seqnum), ("off", 2)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn remove_cancels_all_timers() {
+        let fixture = Fixture::new("cancel");
+        let delay = Duration::from_secs(5);
+        fixture.deferred.schedule(&device("add", 1), delay, &run("a"), "a.rules:1");
+        fixture.deferred.schedule(&device("add", 1), delay, &DeferredAction::Event, "a.rules:2");
+        fixture.deferred.update(&device("remove", 2));
+        assert_eq!(fixture.deferred.pending(), 0);
+        fixture.clock.advance(delay);
+        assert!(fixture.deferred.due().is_empty());
+    }
+
+    #[test]
+    fn per_device_cap_drops_new_timers() {
+        let fixture = Fixture::new("cap");
+        let add = device("add", 1);
+        for i in 0..MAX_DEFERRED_PER_DEVICE {
+            let location = format!("a.rules:{}", i);
+            assert!(fixture.deferred.schedule(&add, Duration::from_secs(1), &run("x"), &location));
+        }
+        assert!(!fixture.deferred.schedule(&add, Duration::from_secs(1), &run("x"), "b.rules:1"));
+        assert_eq!(fixture.deferred.pending(), MAX_DEFERRED_PER_DEVICE);
+    }
+
+    #[test]
+    fn replayed_events_do_not_reschedule_until_processed() {
+        let fixture = Fixture::new("replay");
+        let delay = Duration::from_secs(1);
+        assert!(fixture.deferred.schedule(&device("add", 1), delay, &DeferredAction::Event, "a.rules:1"));
+        fixture.clock.advance(delay);
+        assert!(matches!(&fixture.deferred.due()[..], [Fired::Event(devpath)] if devpath == Path::new(DEVPATH)));
+
+        let replayed = device("change", 1 << 63);
+        fixture.deferred.replay(&replayed);
+        assert!(!fixture.deferred.schedule(&replayed, delay, &DeferredAction::Event, "a.rules:1"));
+        fixture.deferred.processed(&replayed);
+        assert!(fixture.deferred.schedule(&replayed, delay, &DeferredAction::Event, "a.rules:1"));
+    }
+}
@@ -1,8 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::fmt;
 use std::str::FromStr;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::UNIX_EPOCH;
+
+use crate::actions::DEV_ROOT;
+use crate::clock::{Clock, ReceiveTime, SystemClock};
+use crate::db;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum DeviceAction {
@@ -34,10 +41,52 @@ impl FromStr for DeviceAction {
     }
 }
 
-#[derive(Debug)]
+impl DeviceAction {
+    /// 内核发出的所有动作名，Unknown 以外的每个变体一个
+    pub const NAMES: [&'static str; 8] = ["add", "remove", "change", "bind", "unbind", "move", "online", "offline"];
+
+    /// 小写的动作名，与 uevent 中的 ACTION 一致；缺少 ACTION 时为空字符串
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Add => "add",
+            Self::Remove => "remove",
+            Self::Change => "change",
+            Self::Bind => "bind",
+            Self::Unbind => "unbind",
+            Self::Move => "move",
+            Self::Online => "online",
+            Self::Offline => "offline",
+            Self::Unknown(s) => s,
+        }
+    }
+}
+
+/// 设备号属于字符设备还是块设备，两者的设备号空间相互独立
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevnumKind {
+    Char,
+    Block,
+}
+
+impl DevnumKind {
+    /// /sys/dev 下对应的目录名
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Char => "char",
+            Self::Block => "block",
+        }
+    }
+}
+
+/// 缺少 SUBSYSTEM 或 ACTION 的事件在统计和显示中使用的占位名
+pub const MISSING_FIELD: &str = "(none)";
+
+#[derive(Debug, Clone)]
 pub struct UEventDevice {
     action: DeviceAction,
     devpath: PathBuf,
+    // devpath 所在的 sysfs 根目录，通常是 /sys
+    sys_root: PathBuf,
     subsystem: String,
     devtype: Option<String>,
     kernel: Option<String>,
@@ -47,57 +96,197 @@ pub struct UEventDevice {
     devnum: Option<u64>,
 
     seqnum: u64,
-    timestamp: u64,
+    // 事件到达的时间，来自内核事件时是套接字收到它的时间
+    received: ReceiveTime,
 
     properties: HashMap<String, String>,
+    // 已经读取过的 sysfs 属性，去掉了结尾的空白
     sysattrs: HashMap<String, String>,
+    tags: BTreeSet<String>,
+    // 属于这个设备的符号链接，相对于 dev_root
+    devlinks: BTreeSet<String>,
+    // 设备节点和符号链接的根目录，DEVLINKS 属性中是它下面的完整路径
+    dev_root: PathBuf,
+    // 守护进程第一次处理完这个设备的时间（CLOCK_MONOTONIC 微秒），未处理过为 None
+    usec_initialized: Option<u64>,
+
+    // 最近一次 PROGRAM 的输出，用于 %c / $result 替换
+    program_result: Option<String>,
+    // 之前的规则用 NAME= 指定的名字，供 NAME== 匹配
+    name: Option<String>,
 }
 
 impl UEventDevice {
+    /// 只有 DEVPATH 是必需的；缺少 ACTION 或 SUBSYSTEM 时以空值占位，
+    /// 规则可以用 ACTION=="" 或 SUBSYSTEM=="" 匹配这类事件
     pub fn from_event(event: HashMap<String, String>) -> Option<Self> {
-        let action_str = event.get("ACTION")?;
-        let subsystem = event.get("SUBSYSTEM")?.clone();
+        Self::from_event_with_clock(event, &SystemClock)
+    }
+
+    /// 与 from_event 相同，时间戳取自给定的时钟
+    pub fn from_event_with_clock(event: HashMap<String, String>, clock: &dyn Clock) -> Option<Self> {
+        let action = event
+            .get("ACTION")
+            .map_or(DeviceAction::Unknown(String::new()), |s| {
+                DeviceAction::from_str(s).unwrap_or(DeviceAction::Unknown(s.clone()))
+            });
+        let subsystem = event.get("SUBSYSTEM").cloned().unwrap_or_default();
         
         let devpath = Path::new(event.get("DEVPATH")?).to_path_buf();
         
-        let major = event.get("MAJOR").and_then(|s| s.parse().ok());
-        let minor = event.get("MINOR").and_then(|s| s.parse().ok());
+        // 内核 uevent 不带 KERNEL，取 DEVPATH 的最后一段作为内核名
+        let kernel = event.get("KERNEL").cloned().or_else(|| {
+            devpath.file_name().map(|n| n.to_string_lossy().into_owned())
+        });
+
+        // 来自 udev 的事件以 ":tag1:tag2:" 的形式携带标签
+        let tags = event
+            .get("TAGS")
+            .map(|t| {
+                t.split(':')
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // DEVLINKS 是空格分隔的完整路径；不在默认根目录下的由 set_dev_root 换成相对名字
+        let devlinks = event
+            .get("DEVLINKS")
+            .map(|links| links.split_whitespace().map(|link| devlink_name(link, Path::new(DEV_ROOT))).collect())
+            .unwrap_or_default();
+
+        let major = event.get("MAJOR").and_then(|s| parse_u64(s)).and_then(|n| n.try_into().ok());
+        let minor = event.get("MINOR").and_then(|s| parse_u64(s)).and_then(|n| n.try_into().ok());
         
         Some(Self {
-            action: DeviceAction::from_str(action_str).ok()?,
+            action,
             devpath,
+            sys_root: PathBuf::from("/sys"),
             subsystem,
             devtype: event.get("DEVTYPE").cloned(),
             major,
             minor,
-            kernel: event.get("KERNEL").cloned(),
-            devnum: event.get("DEVNUM").and_then(|s| s.parse().ok()),
-            seqnum: event.get("SEQNUM").and_then(|s| s.parse().ok()).unwrap_or(0),
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .ok()?
-                .as_secs(),
+            kernel,
+            devnum: event.get("DEVNUM").and_then(|s| parse_u64(s)),
+            seqnum: event.get("SEQNUM").and_then(|s| parse_u64(s)).unwrap_or(0),
+            received: ReceiveTime::now(clock),
             properties: event.clone(),
             sysattrs: HashMap::new(),
+            tags,
+            devlinks,
+            dev_root: PathBuf::from(DEV_ROOT),
+            usec_initialized: None,
+            program_result: None,
+            name: None,
         })
     }
 
+    /// 从 sysfs 设备目录构造设备，路径可以经过 /sys/class 等处的符号链接；ACTION 为空
+    pub fn from_syspath<P: AsRef<Path>>(syspath: P) -> Option<Self> {
+        Self::from_syspath_in("/sys", syspath)
+    }
+
+    /// 按设备号查找设备，经由 /sys/dev/{char,block}/major:minor
+    pub fn from_devnum(kind: DevnumKind, major: u32, minor: u32) -> Option<Self> {
+        Self::from_devnum_in("/sys", kind, major, minor)
+    }
+
+    pub fn from_devnum_in<P: AsRef<Path>>(sys_root: P, kind: DevnumKind, major: u32, minor: u32) -> Option<Self> {
+        let sys_root = sys_root.as_ref();
+        let link = sys_root.join("dev").join(kind.as_str()).join(format!("{}:{}", major, minor));
+        Self::from_syspath_in(sys_root, link)
+    }
+
+    /// 按子系统和内核名查找设备，如 ("block", "sda")、("net", "eth0")、("usb", "1-1")，
+    /// 依次查找 /sys/class/<subsystem>/<sysname> 和 /sys/bus/<subsystem>/devices/<sysname>
+    pub fn from_subsystem_sysname(subsystem: &str, sysname: &str) -> Option<Self> {
+        Self::from_subsystem_sysname_in("/sys", subsystem, sysname)
+    }
+
+    pub fn from_subsystem_sysname_in<P: AsRef<Path>>(sys_root: P, subsystem: &str, sysname: &str) -> Option<Self> {
+        let sys_root = sys_root.as_ref();
+        // 名字中不能带路径分隔符，否则会走出子系统目录
+        if subsystem.contains('/') || sysname.contains('/') {
+            return None;
+        }
+        [
+            sys_root.join("class").join(subsystem).join(sysname),
+            sys_root.join("bus").join(subsystem).join("devices").join(sysname),
+        ]
+        .into_iter()
+        .find_map(|syspath| Self::from_syspath_in(sys_root, syspath))
+    }
+
+    /// 与 from_syspath 相同，但 sysfs 位于 sys_root；DEVPATH 相对于 sys_root
+    ///
+    /// 属性取自 uevent 文件，SUBSYSTEM 和 DRIVER 取自同名链接，uevent 中没有设备号时读取 dev 文件。
+    pub fn from_syspath_in<P: AsRef<Path>, Q: AsRef<Path>>(sys_root: P, syspath: Q) -> Option<Self> {
+        let sys_root = sys_root.as_ref().canonicalize().ok()?;
+        let syspath = syspath.as_ref().canonicalize().ok()?;
+        let devpath = Path::new("/").join(syspath.strip_prefix(&sys_root).ok()?);
+
+        let mut event: HashMap<String, String> = fs::read_to_string(syspath.join("uevent"))
+            .ok()?
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        event.insert("DEVPATH".into(), devpath.to_string_lossy().into_owned());
+        fill_from_sysfs(&syspath, &mut event);
+
+        let mut device = Self::from_event(event)?;
+        device.sys_root = sys_root;
+        Some(device)
+    }
+
     pub fn devnode(&self) -> Option<&str> {
-        self.properties.get("DEVNAME").map(|s| s.as_str())
+        self.property("DEVNAME")
     }
 
     pub fn driver(&self) -> Option<&str> {
-        self.properties.get("DRIVER").map(|s| s.as_str())
+        self.property("DRIVER")
+    }
+
+    /// 设备所在的 sysfs 挂载点，默认为 /sys
+    pub fn sys_root(&self) -> &Path {
+        &self.sys_root
     }
 
     pub fn syspath(&self) -> PathBuf {
-        Path::new("/sys").join(&self.devpath)
+        // DEVPATH 以 '/' 开头，直接 join 会丢掉 sysfs 根目录
+        self.sys_root.join(self.devpath.strip_prefix("/").unwrap_or(&self.devpath))
     }
 
     pub fn devpath(&self) -> &Path {
         &self.devpath
     }
 
+    /// 父设备：沿 devpath 向上第一个有 uevent 文件的目录，从设备所在的 sysfs 读取；
+    /// 中间的类目录（如 .../host0/scsi_host）不是设备，会被跳过
+    pub fn parent(&self) -> Option<Self> {
+        let devices = self.sys_root.join("devices");
+        self.syspath()
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&devices) && *dir != devices)
+            .filter(|dir| dir.join("uevent").exists())
+            .find_map(|dir| Self::from_syspath_in(&self.sys_root, dir))
+    }
+
+    /// 最近的子系统为 subsystem 的祖先设备；devtype 为 Some 时 DEVTYPE 也必须相同。
+    /// 比如块设备分区的磁盘是 ("block", Some("disk"))，USB 接口所属的设备是 ("usb", Some("usb_device"))
+    pub fn parent_with_subsystem_devtype(&self, subsystem: &str, devtype: Option<&str>) -> Option<Self> {
+        let mut parent = self.parent();
+        while let Some(device) = parent {
+            if device.subsystem == subsystem && devtype.is_none_or(|devtype| device.devtype() == Some(devtype)) {
+                return Some(device);
+            }
+            parent = device.parent();
+        }
+        None
+    }
+
     pub fn is_block_device(&self) -> bool {
         self.subsystem == "block"
     }
@@ -114,6 +303,31 @@ impl UEventDevice {
         &self.subsystem
     }
 
+    /// 子系统名，缺失时为 MISSING_FIELD
+    pub fn subsystem_label(&self) -> &str {
+        if self.subsystem.is_empty() {
+            MISSING_FIELD
+        } else {
+            &self.subsystem
+        }
+    }
+
+    /// 动作名，缺失时为 MISSING_FIELD
+    pub fn action_label(&self) -> &str {
+        match self.action.as_str() {
+            "" => MISSING_FIELD,
+            action => action,
+        }
+    }
+
+    /// 原始事件中缺少的关键字段
+    pub fn missing_fields(&self) -> Vec<&'static str> {
+        ["ACTION", "SUBSYSTEM"]
+            .into_iter()
+            .filter(|key| self.property(key).is_none_or(str::is_empty))
+            .collect()
+    }
+
     pub fn devtype(&self) -> Option<&str> {
         self.devtype.as_deref()
     }
@@ -138,17 +352,351 @@ impl UEventDevice {
         self.seqnum
     }
 
+    /// 到达时间的秒级 Unix 时间
     pub fn timestamp(&self) -> u64 {
-        self.timestamp
+        self.received
+            .realtime
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+
+    /// 事件到达的墙上时间和单调时间；不是从套接字收到的事件为构造它的时间
+    pub fn received(&self) -> ReceiveTime {
+        self.received
+    }
+
+    pub fn set_received(&mut self, received: ReceiveTime) {
+        self.received = received;
     }
 
     pub fn properties(&self) -> &HashMap<String, String> {
         &self.properties
     }
 
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(|s| s.as_str())
+    }
+
+    /// 按十进制解析属性值，忽略首尾空白
+    pub fn property_u64(&self, key: &str) -> Option<u64> {
+        self.property(key).and_then(parse_u64)
+    }
+
+    /// 解析布尔属性：1/yes/true/on 为真，0/no/false/off 为假，其它值返回 None
+    pub fn property_bool(&self, key: &str) -> Option<bool> {
+        self.property(key).and_then(parse_bool)
+    }
+
+    /// 属性值作为路径返回，空值返回 None
+    pub fn property_path(&self, key: &str) -> Option<&Path> {
+        self.property(key)
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Path::new)
+    }
+
+    pub fn set_property(&mut self, key: &str, value: &str) {
+        self.properties.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn tags(&self) -> &BTreeSet<String> {
+        &self.tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    pub fn add_tag(&mut self, tag: &str) {
+        self.tags.insert(tag.to_string());
+        self.sync_tags_property();
+    }
+
+    pub fn remove_tag(&mut self, tag: &str) {
+        self.tags.remove(tag);
+        self.sync_tags_property();
+    }
+
+    pub fn clear_tags(&mut self) {
+        self.tags.clear();
+        self.sync_tags_property();
+    }
+
+    // 保持 TAGS 属性与标签集合一致，RUN 的环境变量中才能看到
+    fn sync_tags_property(&mut self) {
+        if self.tags.is_empty() {
+            self.properties.remove("TAGS");
+        } else {
+            let joined = self.tags.iter().cloned().collect::<Vec<_>>().join(":");
+            self.properties.insert("TAGS".to_string(), format!(":{}:", joined));
+        }
+    }
+
+    /// 规则 SYMLINK 赋值并经符号链接数据库认可的链接，或从数据库读回的链接；
+    /// 名字相对于设备根目录，如 disk/by-id/...
+    pub fn devlinks(&self) -> &BTreeSet<String> {
+        &self.devlinks
+    }
+
+    pub fn dev_root(&self) -> &Path {
+        &self.dev_root
+    }
+
+    /// 设置设备节点和符号链接的根目录，默认为 DEV_ROOT；已有的链接按完整路径换算到新的根目录下
+    pub fn set_dev_root(&mut self, root: &Path) {
+        if self.dev_root == root {
+            return;
+        }
+        let old_root = std::mem::replace(&mut self.dev_root, root.to_path_buf());
+        self.devlinks = self
+            .devlinks
+            .iter()
+            .map(|link| devlink_name(&old_root.join(link).to_string_lossy(), root))
+            .collect();
+        self.sync_devlinks_property();
+    }
+
+    pub fn has_devlink(&self, link: &str) -> bool {
+        self.devlinks.contains(link)
+    }
+
+    pub fn add_devlink(&mut self, link: &str) {
+        self.devlinks.insert(link.to_string());
+        self.sync_devlinks_property();
+    }
+
+    pub fn remove_devlink(&mut self, link: &str) {
+        self.devlinks.remove(link);
+        self.sync_devlinks_property();
+    }
+
+    pub fn clear_devlinks(&mut self) {
+        self.devlinks.clear();
+        self.sync_devlinks_property();
+    }
+
+    // DEVLINKS 属性写成空格分隔的完整路径，与 udev 相同
+    fn sync_devlinks_property(&mut self) {
+        if self.devlinks.is_empty() {
+            self.properties.remove("DEVLINKS");
+        } else {
+            let joined = devlinks_property(&self.devlinks, &self.dev_root);
+            self.properties.insert("DEVLINKS".to_string(), joined);
+        }
+    }
+
+    /// 已经通过 sysattr 读取并缓存的属性
     pub fn sysattrs(&self) -> &HashMap<String, String> {
         &self.sysattrs
     }
+
+    /// sysfs 属性 /sys/<devpath>/<name>，第一次访问时读取并缓存去掉结尾空白的内容；
+    /// 读不到（不存在、只写、不是文本）时为 None，不缓存
+    pub fn sysattr(&mut self, name: &str) -> Option<&str> {
+        if !self.sysattrs.contains_key(name) {
+            let value = fs::read_to_string(self.syspath().join(name)).ok()?;
+            self.sysattrs.insert(name.to_string(), value.trim_end().to_string());
+        }
+        self.sysattrs.get(name).map(String::as_str)
+    }
+
+    /// 设备目录下可读的属性文件名，按名字排序；不含 uevent、子目录和链接
+    pub fn sysattr_names(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(self.syspath()) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name() != "uevent")
+            .filter(|entry| {
+                entry
+                    .metadata()
+                    .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o444 != 0)
+            })
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// 设备在数据库（db::DATA_DIR）中的文件名，如 b8:0、c128:1、n3、+pci:0000:00:02.0
+    pub fn db_id(&self) -> Option<String> {
+        db::record_id(
+            &self.subsystem,
+            &self.devpath,
+            self.major.zip(self.minor),
+            self.property("IFINDEX"),
+        )
+    }
+
+    /// 守护进程是否已经处理过这个设备（数据库中有它的记录），即规则是否已经生效
+    pub fn is_initialized(&self) -> bool {
+        self.usec_initialized.is_some()
+    }
+
+    /// 初始化时的 CLOCK_MONOTONIC 微秒数
+    pub fn usec_initialized(&self) -> Option<u64> {
+        self.usec_initialized
+    }
+
+    /// 按给定时钟计算距初始化过去的微秒数，未初始化时为 None
+    pub fn usec_since_initialized(&self, clock: &dyn Clock) -> Option<u64> {
+        self.usec_initialized.map(|usec| clock.monotonic_usec().saturating_sub(usec))
+    }
+
+    pub fn set_usec_initialized(&mut self, usec: Option<u64>) {
+        self.usec_initialized = usec;
+    }
+
+    /// 守护进程处理完事件后调用：沿用数据库记录中的初始化时间，没有记录时取事件到达的时间
+    pub fn mark_initialized(&mut self) {
+        if self.usec_initialized.is_some() {
+            return;
+        }
+        let previous = self
+            .db_id()
+            .and_then(|id| db::load_record(db::DATA_DIR, &id).ok())
+            .filter(|record| record.devpath().is_none_or(|devpath| devpath == self.devpath))
+            .and_then(|record| record.initialized);
+        self.usec_initialized = Some(previous.unwrap_or(self.received.monotonic_usec));
+    }
+
+    /// 合并守护进程为这个设备保存的记录：属性、标签、符号链接和初始化时间；
+    /// 没有记录或记录属于同一设备号上以前的另一个设备时返回 None
+    pub fn read_db(&mut self) -> Option<db::DeviceRecord> {
+        let record = db::load_record(db::DATA_DIR, &self.db_id()?).ok()?;
+        if record.devpath().is_some_and(|devpath| devpath != self.devpath) {
+            return None;
+        }
+        for (key, value) in record.clone().into_properties(&self.dev_root) {
+            if key == "TAGS" || key == "DEVLINKS" {
+                continue;
+            }
+            self.properties.insert(key, value);
+        }
+        for tag in &record.tags {
+            self.add_tag(tag);
+        }
+        for link in &record.links {
+            self.add_devlink(link);
+        }
+        // udev 写出的记录没有 I: 时，有记录本身就说明已经处理过
+        self.usec_initialized = record.initialized.or(Some(0));
+        Some(record)
+    }
+
+    /// 向 sysfs 中的 uevent 文件写入动作，请求内核为这个设备重新发出事件；
+    /// 给出 uuid 时内核在事件中附加 SYNTH_UUID=<uuid>，可以据此认出自己触发的事件
+    pub fn trigger(&self, action: &DeviceAction, uuid: Option<&str>) -> io::Result<()> {
+        self.trigger_in("/sys", action, uuid)
+    }
+
+    /// 与 trigger 相同，但 sysfs 位于 sys_root
+    pub fn trigger_in<P: AsRef<Path>>(&self, sys_root: P, action: &DeviceAction, uuid: Option<&str>) -> io::Result<()> {
+        if let DeviceAction::Unknown(name) = action {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot trigger unknown action {:?}", name),
+            ));
+        }
+        let request = match uuid {
+            Some(uuid) => format!("{} {}", action.as_str(), uuid),
+            None => action.as_str().to_string(),
+        };
+        let syspath = sys_root.as_ref().join(self.devpath.strip_prefix("/").unwrap_or(&self.devpath));
+        fs::write(syspath.join("uevent"), request)
+    }
+
+    /// 丢弃缓存的属性值，比如规则用 ATTR{name}= 写入之后
+    pub fn forget_sysattr(&mut self, name: &str) {
+        self.sysattrs.remove(name);
+    }
+
+    pub fn program_result(&self) -> Option<&str> {
+        self.program_result.as_deref()
+    }
+
+    /// 修改 ACTION，同时更新 ACTION 属性
+    pub fn set_action(&mut self, action: DeviceAction) {
+        self.properties.insert("ACTION".to_string(), action.as_str().to_string());
+        self.action = action;
+    }
+
+    /// 修改 SEQNUM，同时更新 SEQNUM 属性；用于守护进程合成的事件
+    pub fn set_seqnum(&mut self, seqnum: u64) {
+        self.properties.insert("SEQNUM".to_string(), seqnum.to_string());
+        self.seqnum = seqnum;
+    }
+
+    pub fn set_program_result(&mut self, result: Option<String>) {
+        self.program_result = result;
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn set_name(&mut self, name: Option<String>) {
+        self.name = name;
+    }
+}
+
+/// 生成一个随机 UUID，用作 trigger 的 SYNTH_UUID
+pub fn synth_uuid() -> io::Result<String> {
+    Ok(fs::read_to_string("/proc/sys/kernel/random/uuid")?.trim().to_string())
+}
+
+/// 补齐 properties 中缺少的 SUBSYSTEM、DRIVER（取自 sysfs 中的链接）和 MAJOR/MINOR（取自 dev 文件）
+pub(crate) fn fill_from_sysfs(syspath: &Path, properties: &mut HashMap<String, String>) {
+    let link_name = |link: &str| {
+        fs::read_link(syspath.join(link))
+            .ok()
+            .and_then(|target| target.file_name().map(|name| name.to_string_lossy().into_owned()))
+    };
+    for (key, link) in [("SUBSYSTEM", "subsystem"), ("DRIVER", "driver")] {
+        if !properties.contains_key(key) {
+            if let Some(name) = link_name(link) {
+                properties.insert(key.into(), name);
+            }
+        }
+    }
+
+    if !properties.contains_key("MAJOR") {
+        // dev 文件的内容是 "major:minor"
+        let dev = fs::read_to_string(syspath.join("dev")).unwrap_or_default();
+        if let Some((major, minor)) = dev.trim().split_once(':') {
+            properties.insert("MAJOR".into(), major.to_string());
+            properties.insert("MINOR".into(), minor.to_string());
+        }
+    }
+}
+
+/// DEVLINKS 中的完整路径去掉设备根目录 root 前缀，得到 SYMLINK 形式的链接名
+pub fn devlink_name(path: &str, root: &Path) -> String {
+    Path::new(path)
+        .strip_prefix(root)
+        .map_or_else(|_| path.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+/// 链接名组成 DEVLINKS 属性的值：设备根目录 root 下的完整路径，空格分隔
+pub fn devlinks_property<'a>(links: impl IntoIterator<Item = &'a String>, root: &Path) -> String {
+    links
+        .into_iter()
+        .map(|link| root.join(link).display().to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_u64(s: &str) -> Option<u64> {
+    s.trim().parse().ok()
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "1" | "y" | "yes" | "true" | "on" => Some(true),
+        "0" | "n" | "no" | "false" | "off" => Some(false),
+        _ => None,
+    }
 }
 
 impl fmt::Display for UEventDevice {
@@ -160,6 +708,16 @@ impl fmt::Display for UEventDevice {
         let devnum_str = self.devnum.map_or("null".to_string(), |n| n.to_string());
         let devnode_str = self.devnode().unwrap_or("null");
         let driver_str = self.driver().unwrap_or("null");
+        let tags_str = if self.tags.is_empty() {
+            "null".to_string()
+        } else {
+            self.tags.iter().cloned().collect::<Vec<_>>().join(", ")
+        };
+        let devlinks_str = if self.devlinks.is_empty() {
+            "null".to_string()
+        } else {
+            self.devlinks.iter().cloned().collect::<Vec<_>>().join(", ")
+        };
 
         let properties_str = if self.properties.is_empty() {
             "null".to_string()
@@ -194,12 +752,14 @@ impl fmt::Display for UEventDevice {
             \x20\x20devpath:    \"{}\",\n\
             \x20\x20devnode:    {},\n\
             \x20\x20driver:     {},\n\
+            \x20\x20tags:       {},\n\
+            \x20\x20devlinks:   {},\n\
             \x20\x20properties: {{\n{}\n\x20\x20}},\n\
             \x20\x20sysattrs:   {{\n{}\n\x20\x20}}\n}}",
             self.seqnum,
-            self.timestamp,
+            self.timestamp(),
             self.action,
-            self.subsystem,
+            self.subsystem_label(),
             devtype_str,
             kernel_str,
             major_str,
@@ -208,6 +768,8 @@ impl fmt::Display for UEventDevice {
             self.devpath.display(),
             devnode_str,
             driver_str,
+            tags_str,
+            devlinks_str,
             properties_str,
             sysattrs_str
         )
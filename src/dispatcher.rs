@@ -0,0 +1,434 @@
+// src/dispatcher.rs
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::clock::{system_clock, Clock};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// 子设备的事件等待父设备（或同一设备更早的事件）处理完成的最长时间，超时后照常处理
+pub const DEPENDENCY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 有事件在等待时，工作线程每次最多睡这么久再读时钟，这样虚拟时钟推进也能让等待超时
+const WAIT_SLICE: Duration = Duration::from_millis(50);
+
+/// 事件分发器：有上限的工作线程池，同一设备的事件按分发顺序逐个处理，不同设备的事件并行处理
+///
+/// 与 udev 相同，同一 devpath、祖先或子孙 devpath 上有更早分发的事件还在排队或处理时，
+/// 后来的事件要等待：同一设备的 add 和 remove 不会交错，分区总在磁盘之后处理。
+/// 等待超过 DEPENDENCY_TIMEOUT 的事件不再等待，挂住的父设备不会让子设备永远排队。
+/// 工作线程按需启动，最多 max_workers 个；shutdown 或 drop 时处理完已排队的事件并等待线程退出。
+pub struct EventDispatcher {
+    shared: Arc<Shared>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+    clock: Arc<dyn Clock>,
+    timeout: Duration,
+}
+
+struct Pending {
+    devpath: PathBuf,
+    // 开始处理后为 None
+    job: Option<Job>,
+    dispatched: Instant,
+    // 还在排队或处理、且与本事件相关的更早事件数
+    blockers: usize,
+    // 等待本事件的更早分发的相关事件
+    dependents: Vec<u64>,
+}
+
+struct State {
+    // 已分发但尚未处理完的事件，按分发序号
+    events: HashMap<u64, Pending>,
+    // 这些事件按 devpath 的索引；Path 按路径组件排序，一个设备的子孙紧跟在它后面
+    by_devpath: BTreeMap<PathBuf, Vec<u64>>,
+    // 可以处理的事件和仍在等待相关事件的事件，都按分发顺序
+    ready: BTreeSet<u64>,
+    blocked: BTreeSet<u64>,
+    next_id: u64,
+    workers: usize,
+    idle: usize,
+    max_workers: usize,
+    // udevadm control --stop-exec-queue：事件照常排队，但不开始处理
+    paused: bool,
+    shutdown: bool,
+}
+
+impl Default for EventDispatcher {
+    fn default() -> Self {
+        Self::new(default_workers())
+    }
+}
+
+/// 默认的工作线程数，与 CPU 数相同
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+impl State {
+    fn queued(&self) -> usize {
+        self.ready.len() + self.blocked.len()
+    }
+
+    // 加入一个事件：祖先、子孙和同一设备上还没处理完的事件都排在它前面
+    fn push(&mut self, devpath: PathBuf, job: Job, now: Instant) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut earlier: Vec<u64> = devpath
+            .ancestors()
+            .filter_map(|ancestor| self.by_devpath.get(ancestor))
+            .flatten()
+            .copied()
+            .collect();
+        earlier.extend(
+            self.by_devpath
+                .range::<Path, _>((std::ops::Bound::Excluded(devpath.as_path()), std::ops::Bound::Unbounded))
+                .take_while(|(other, _)| other.starts_with(&devpath))
+                .flat_map(|(_, ids)| ids.iter().copied()),
+        );
+        for other in &earlier {
+            if let Some(pending) = self.events.get_mut(other) {
+                pending.dependents.push(id);
+            }
+        }
+
+        if earlier.is_empty() {
+            self.ready.insert(id);
+        } else {
+            self.blocked.insert(id);
+        }
+        self.by_devpath.entry(devpath.clone()).or_default().push(id);
+        self.events.insert(
+            id,
+            Pending {
+                devpath,
+                job: Some(job),
+                dispatched: now,
+                blockers: earlier.len(),
+                dependents: Vec::new(),
+            },
+        );
+    }
+
+    // 最早分发的仍在等待的事件等到了什么时候
+    fn next_timeout(&self, timeout: Duration) -> Option<Instant> {
+        let oldest = self.blocked.first()?;
+        Some(self.events[oldest].dispatched + timeout)
+    }
+
+    // 等待超时的事件不再等，按分发顺序放行
+    fn release_timed_out(&mut self, now: Instant, timeout: Duration) {
+        while let Some(&id) = self.blocked.first() {
+            let pending = self.events.get_mut(&id).expect("blocked events are tracked");
+            if pending.dispatched + timeout > now {
+                break;
+            }
+            warn!(
+                "Timed out waiting for earlier events of {:?} or its parents, processing anyway",
+                pending.devpath
+            );
+            pending.blockers = 0;
+            self.blocked.remove(&id);
+            self.ready.insert(id);
+        }
+    }
+
+    // 取出第一个可以处理的事件
+    fn next_runnable(&mut self) -> Option<(u64, PathBuf, Job)> {
+        if self.paused && !self.shutdown {
+            return None;
+        }
+        let id = self.ready.pop_first()?;
+        let pending = self.events.get_mut(&id).expect("ready events are tracked");
+        let job = pending.job.take().expect("ready events have not started");
+        Some((id, pending.devpath.clone(), job))
+    }
+
+    // 事件处理完成，等待它的事件可能已经可以处理了
+    fn finish(&mut self, id: u64) {
+        let Some(pending) = self.events.remove(&id) else {
+            return;
+        };
+        if let Some(ids) = self.by_devpath.get_mut(&pending.devpath) {
+            ids.retain(|other| *other != id);
+            if ids.is_empty() {
+                self.by_devpath.remove(&pending.devpath);
+            }
+        }
+        for dependent in pending.dependents {
+            let Some(waiting) = self.events.get_mut(&dependent) else {
+                continue;
+            };
+            if waiting.blockers == 0 {
+                continue;
+            }
+            waiting.blockers -= 1;
+            if waiting.blockers == 0 && self.blocked.remove(&dependent) {
+                self.ready.insert(dependent);
+            }
+        }
+    }
+}
+
+impl EventDispatcher {
+    pub fn new(max_workers: usize) -> Self {
+        Self::with_clock(max_workers, system_clock(), DEPENDENCY_TIMEOUT)
+    }
+
+    /// 指定时钟和等待相关事件的超时，测试中可以用虚拟时钟
+    pub fn with_clock(max_workers: usize, clock: Arc<dyn Clock>, timeout: Duration) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    events: HashMap::new(),
+                    by_devpath: BTreeMap::new(),
+                    ready: BTreeSet::new(),
+                    blocked: BTreeSet::new(),
+                    next_id: 0,
+                    workers: 0,
+                    idle: 0,
+                    max_workers: max_workers.max(1),
+                    paused: false,
+                    shutdown: false,
+                }),
+                cond: Condvar::new(),
+                clock,
+                timeout,
+            }),
+            threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 修改工作线程上限；减少时多出的线程在处理完手头的事件后退出
+    pub fn set_max_workers(&self, max_workers: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.max_workers = max_workers.max(1);
+        self.shared.cond.notify_all();
+    }
+
+    pub fn max_workers(&self) -> usize {
+        self.shared.state.lock().unwrap().max_workers
+    }
+
+    /// 暂停或恢复处理事件；暂停时正在处理的事件会处理完，新事件只排队
+    pub fn set_paused(&self, paused: bool) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.paused = paused;
+        self.shared.cond.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.state.lock().unwrap().paused
+    }
+
+    /// 排队等待处理的事件数，不含正在处理的
+    pub fn queued(&self) -> usize {
+        self.shared.state.lock().unwrap().queued()
+    }
+
+    /// 把 devpath 的一个事件加入队列，轮到它时在工作线程中执行 job
+    pub fn dispatch<F: FnOnce() + Send + 'static>(&self, devpath: &Path, job: F) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.shutdown {
+            warn!("Dispatcher is shut down, dropping event for {:?}", devpath);
+            return;
+        }
+        state.push(devpath.to_path_buf(), Box::new(job), self.shared.clock.now());
+        if state.idle == 0 && state.workers < state.max_workers {
+            let shared = self.shared.clone();
+            match thread::Builder::new().name("udev-worker".into()).spawn(move || shared.work()) {
+                Ok(thread) => {
+                    state.workers += 1;
+                    let mut threads = self.threads.lock().unwrap();
+                    threads.retain(|thread| !thread.is_finished());
+                    threads.push(thread);
+                }
+                Err(e) => warn!("Failed to start event worker: {}", e),
+            }
+        }
+        self.shared.cond.notify_all();
+    }
+
+    /// 不再接受新事件，处理完已经排队的事件后等待所有工作线程退出；不能在工作线程中调用
+    pub fn shutdown(&self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.cond.notify_all();
+
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+        for thread in threads {
+            if thread.join().is_err() {
+                error!("Event worker panicked outside of an event");
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.workers > state.max_workers || (state.shutdown && state.events.is_empty()) {
+                state.workers -= 1;
+                return;
+            }
+            let now = self.clock.now();
+            state.release_timed_out(now, self.timeout);
+            let Some((id, devpath, job)) = state.next_runnable() else {
+                state.idle += 1;
+                state = match state.next_timeout(self.timeout) {
+                    Some(deadline) => {
+                        let wait = deadline.saturating_duration_since(now).clamp(Duration::from_millis(1), WAIT_SLICE);
+                        self.cond.wait_timeout(state, wait).unwrap().0
+                    }
+                    None => self.cond.wait(state).unwrap(),
+                };
+                state.idle -= 1;
+                continue;
+            };
+            drop(state);
+
+            // 单个事件的 panic 不能让这个设备的后续事件永远等下去
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                error!("Event worker panicked while processing {:?}", devpath);
+            }
+
+            state = self.state.lock().unwrap();
+            state.finish(id);
+            // 被这个事件阻塞的事件可能已经可以处理了
+            self.cond.notify_all();
+        }
+    }
+}
+
+impl Drop for EventDispatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crossbeam::channel::{unbounded, Receiver, Sender};
+
+    const DISK: &str = "/devices/pci0000:00/block/sda";
+    const PART: &str = "/devices/pci0000:00/block/sda/sda1";
+    const OTHER: &str = "/devices/pci0000:00/block/sdb";
+
+    // 事件开始时发出名字，等 gate 放行后才结束
+    fn gated(name: &'static str, started: &Sender<&'static str>) -> (impl FnOnce() + Send + 'static, Sender<()>) {
+        let (gate_tx, gate_rx): (Sender<()>, Receiver<()>) = unbounded();
+        let started = started.clone();
+        let job = move || {
+            started.send(name).unwrap();
+            let _ = gate_rx.recv();
+        };
+        (job, gate_tx)
+    }
+
+    fn next(started: &Receiver<&'static str>) -> Option<&'static str> {
+        started.recv_timeout(Duration::from_millis(500)).ok()
+    }
+
+    #[test]
+    fn children_wait_for_parent_and_unrelated_devices_run_in_parallel() {
+        let dispatcher = EventDispatcher::new(4);
+        let (started_tx, started) = unbounded();
+        let (disk, disk_gate) = gated("disk", &started_tx);
+        let (part, part_gate) = gated("part", &started_tx);
+        let (other, other_gate) = gated("other", &started_tx);
+
+        dispatcher.dispatch(Path::new(DISK), disk);
+        dispatcher.dispatch(Path::new(PART), part);
+        dispatcher.dispatch(Path::new(OTHER), other);
+
+        let mut first: Vec<_> = [next(&started), next(&started)].into_iter().flatten().collect();
+        first.sort();
+        assert_eq!(first, vec!["disk", "other"]);
+        assert_eq!(dispatcher.queued(), 1);
+
+        disk_gate.send(()).unwrap();
+        assert_eq!(next(&started), Some("part"));
+        part_gate.send(()).unwrap();
+        other_gate.send(()).unwrap();
+        dispatcher.shutdown();
+    }
+
+    #[test]
+    fn parent_waits_for_earlier_child_events() {
+        let dispatcher = EventDispatcher::new(4);
+        let (started_tx, started) = unbounded();
+        let (part, part_gate) = gated("part", &started_tx);
+        let (disk, disk_gate) = gated("disk", &started_tx);
+
+        dispatcher.dispatch(Path::new(PART), part);
+        dispatcher.dispatch(Path::new(DISK), disk);
+        assert_eq!(next(&started), Some("part"));
+        assert_eq!(next(&started), None);
+
+        part_gate.send(()).unwrap();
+        assert_eq!(next(&started), Some("disk"));
+        disk_gate.send(()).unwrap();
+    }
+
+    #[test]
+    fn paused_dispatcher_only_queues() {
+        let dispatcher = EventDispatcher::new(2);
+        dispatcher.set_paused(true);
+        let (started_tx, started) = unbounded();
+        let (disk, disk_gate) = gated("disk", &started_tx);
+        dispatcher.dispatch(Path::new(DISK), disk);
+        assert_eq!(next(&started), None);
+        assert_eq!(dispatcher.queued(), 1);
+
+        dispatcher.set_paused(false);
+        assert_eq!(next(&started), Some("disk"));
+        disk_gate.send(()).unwrap();
+    }
+
+    #[test]
+    fn hung_parent_times_out_on_virtual_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let dispatcher = EventDispatcher::with_clock(4, clock.clone(), DEPENDENCY_TIMEOUT);
+        let (started_tx, started) = unbounded();
+        let (disk, disk_gate) = gated("disk", &started_tx);
+        let (part, part_gate) = gated("part", &started_tx);
+
+        dispatcher.dispatch(Path::new(DISK), disk);
+        dispatcher.dispatch(Path::new(PART), part);
+        assert_eq!(next(&started), Some("disk"));
+        assert_eq!(next(&started), None);
+
+        clock.advance(DEPENDENCY_TIMEOUT);
+        assert_eq!(next(&started), Some("part"));
+        part_gate.send(()).unwrap();
+        disk_gate.send(()).unwrap();
+    }
+
+    #[test]
+    fn shutdown_drains_queue_and_joins_workers() {
+        let dispatcher = EventDispatcher::new(2);
+        let (done_tx, done) = unbounded();
+        for i in 0..20 {
+            let done_tx = done_tx.clone();
+            dispatcher.dispatch(Path::new(DISK), move || done_tx.send(i).unwrap());
+        }
+        dispatcher.shutdown();
+        assert_eq!(done.try_iter().collect::<Vec<_>>(), (0..20).collect::<Vec<_>>());
+        assert!(dispatcher.threads.lock().unwrap().is_empty());
+        assert_eq!(dispatcher.shared.state.lock().unwrap().workers, 0);
+    }
+}
@@ -0,0 +1,179 @@
+// src/filter.rs
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use log::*;
+
+use crate::device::{DeviceAction, UEventDevice};
+use crate::rules::glob::Glob;
+
+/// 默认不处理的网络接口：回环、容器的 veth 以及 docker/podman 创建的网桥。
+/// 容器频繁启停时这些接口的事件会占满事件队列，而规则很少需要处理它们
+pub const DEFAULT_IGNORED_INTERFACES: &[&str] = &[
+    "lo",
+    "veth*",
+    "docker*",
+    "br-[0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f]",
+    "podman*",
+    "cni-podman*",
+];
+
+/// 对来自其他命名空间/容器的设备事件的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamespacePolicy {
+    /// 不做过滤，正常处理所有事件
+    Process,
+    /// 仍然处理，但记录一条日志
+    Log,
+    /// 直接丢弃
+    Ignore,
+}
+
+#[derive(Debug, Clone)]
+pub struct NamespaceFilter {
+    pub policy: NamespacePolicy,
+    /// loop 设备的 backing file 位于这些目录下时视为容器设备
+    pub container_backing_prefixes: Vec<PathBuf>,
+    pub sysfs_root: PathBuf,
+    /// 名字匹配这些 glob 的网络接口的事件不处理，为空时处理所有接口
+    pub ignored_interfaces: Vec<Glob>,
+    // 因 ignored_interfaces 丢弃的事件数，克隆之间共享
+    ignored_interface_events: Arc<AtomicU64>,
+}
+
+impl Default for NamespaceFilter {
+    fn default() -> Self {
+        Self {
+            policy: NamespacePolicy::Ignore,
+            container_backing_prefixes: vec![
+                PathBuf::from("/var/lib/docker"),
+                PathBuf::from("/var/lib/containers"),
+                PathBuf::from("/var/lib/lxc"),
+                PathBuf::from("/var/lib/lxd"),
+            ],
+            sysfs_root: PathBuf::from("/sys"),
+            ignored_interfaces: DEFAULT_IGNORED_INTERFACES.iter().map(|pattern| Glob::new(pattern)).collect(),
+            ignored_interface_events: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl NamespaceFilter {
+    pub fn new(policy: NamespacePolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// 替换默认的忽略接口列表，空列表表示处理所有接口
+    pub fn with_ignored_interfaces<S: AsRef<str>>(mut self, patterns: &[S]) -> Self {
+        self.ignored_interfaces = patterns.iter().map(|pattern| Glob::new(pattern.as_ref())).collect();
+        self
+    }
+
+    /// 已经因 ignored_interfaces 丢弃的事件数
+    pub fn ignored_interface_events(&self) -> u64 {
+        self.ignored_interface_events.load(Ordering::Relaxed)
+    }
+
+    /// 设备是名字匹配 ignored_interfaces 的网络接口时返回接口名；remove 事件同样适用
+    pub fn ignored_interface<'a>(&self, device: &'a UEventDevice) -> Option<&'a str> {
+        if device.subsystem() != "net" {
+            return None;
+        }
+        let iface = device.property("INTERFACE").or(device.kernel())?;
+        self.ignored_interfaces.iter().any(|glob| glob.matches(iface)).then_some(iface)
+    }
+
+    /// 根据策略判断是否应丢弃该事件；忽略的接口不受策略影响，总是丢弃
+    pub fn should_ignore(&self, device: &UEventDevice) -> bool {
+        if let Some(iface) = self.ignored_interface(device) {
+            let count = self.ignored_interface_events.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!("Ignoring {} event for interface {}", device.action_label(), iface);
+            // 只在第 1、2、4、8……次时记一条，容器频繁启停时不刷屏
+            if count.is_power_of_two() {
+                info!("Ignored {} event(s) for loopback, veth and container bridge interfaces", count);
+            }
+            return true;
+        }
+
+        if self.policy == NamespacePolicy::Process {
+            return false;
+        }
+
+        let Some(reason) = self.foreign_reason(device) else {
+            return false;
+        };
+
+        match self.policy {
+            NamespacePolicy::Ignore => {
+                debug!("Ignoring event for {:?}: {}", device.devpath(), reason);
+                true
+            }
+            _ => {
+                info!("Event for {:?} looks foreign: {}", device.devpath(), reason);
+                false
+            }
+        }
+    }
+
+    /// 若设备看起来属于其他网络命名空间或容器，返回判断依据
+    pub fn foreign_reason(&self, device: &UEventDevice) -> Option<String> {
+        // remove 事件时 sysfs 条目已经不存在，无法判断
+        if *device.action() == DeviceAction::Remove {
+            return None;
+        }
+
+        match device.subsystem() {
+            "net" => self.check_net(device),
+            "block" => self.check_loop(device),
+            _ => None,
+        }
+    }
+
+    fn check_net(&self, device: &UEventDevice) -> Option<String> {
+        let iface = device.property("INTERFACE")?;
+        let ifindex = device.property_u64("IFINDEX")?;
+
+        let ifindex_path = self.sysfs_root.join("class/net").join(iface).join("ifindex");
+        match fs::read_to_string(&ifindex_path) {
+            Ok(local) if local.trim().parse::<u64>().ok() == Some(ifindex) => None,
+            Ok(local) => Some(format!(
+                "ifindex {} of {} belongs to another netns (local ifindex {})",
+                ifindex,
+                iface,
+                local.trim()
+            )),
+            Err(_) => Some(format!("interface {} is not visible in this netns", iface)),
+        }
+    }
+
+    fn check_loop(&self, device: &UEventDevice) -> Option<String> {
+        let kernel = device.kernel()?;
+        if !kernel.starts_with("loop") {
+            return None;
+        }
+
+        let backing_path = self
+            .sysfs_root
+            .join("block")
+            .join(kernel)
+            .join("loop/backing_file");
+        let backing = fs::read_to_string(backing_path).ok()?;
+        let backing = Path::new(backing.trim());
+
+        self.container_backing_prefixes
+            .iter()
+            .find(|prefix| backing.starts_with(prefix))
+            .map(|prefix| {
+                format!(
+                    "{} is backed by {:?} under container storage {:?}",
+                    kernel, backing, prefix
+                )
+            })
+    }
+}
@@ -0,0 +1,177 @@
+// src/http_status.rs
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+
+use crate::cancel::{CancellationToken, CHECK_INTERVAL};
+use crate::db::ShardedDeviceDb;
+use crate::rules::metrics::{self, RULE_METRICS_PATH, TOP_OFFENDERS};
+use crate::rules::ruleset::{RuleSet, SharedRules};
+use crate::stats::{load_counts, STATS_PATH};
+use crate::strict::json_escape;
+use crate::udevd::pending_events;
+
+/// 默认监听地址
+pub const DEFAULT_ADDR: &str = "127.0.0.1:9311";
+
+// 客户端迟迟不发请求时放弃连接，避免卡住唯一的服务线程
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 状态接口读取的数据，与守护进程共享
+#[derive(Debug, Clone)]
+pub struct StatusSources {
+    pub db: Arc<ShardedDeviceDb>,
+    pub rules: Arc<SharedRules>,
+}
+
+/// 在本机地址上提供只读的 HTTP 状态接口：/devices、/stats、/queue、/rules，均返回 JSON
+///
+/// 只接受回环地址；请求在一个后台线程中逐个处理，token 被取消后线程关闭监听并退出。
+pub fn serve(addr: SocketAddr, sources: StatusSources, token: CancellationToken) -> io::Result<()> {
+    if !addr.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("refusing to serve status on non-loopback address {}", addr),
+        ));
+    }
+
+    let listener = TcpListener::bind(addr)?;
+    // 非阻塞 accept，空闲时定期检查 token
+    listener.set_nonblocking(true)?;
+    info!("Serving HTTP status on http://{}", listener.local_addr()?);
+
+    thread::spawn(move || {
+        while !token.is_cancelled() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(false).and_then(|_| handle(stream, &sources)) {
+                        debug!("HTTP status request failed: {}", e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    token.wait_timeout(CHECK_INTERVAL);
+                }
+                Err(e) => warn!("Failed to accept HTTP status connection: {}", e),
+            }
+        }
+        debug!("HTTP status stopped");
+    });
+    Ok(())
+}
+
+fn handle(stream: TcpStream, sources: &StatusSources) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // 请求头不需要，读到空行为止
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or("");
+
+    let (status, body) = match (method, path) {
+        ("GET", "/devices") => ("200 OK", devices_json(&sources.db)),
+        ("GET", "/stats") => ("200 OK", stats_json()),
+        ("GET", "/queue") => ("200 OK", format!("{{\"pending\":{}}}", pending_events())),
+        ("GET", "/rules") => ("200 OK", rules_json(&sources.rules.load())),
+        ("GET", _) => ("404 Not Found", error_json("not found")),
+        _ => ("405 Method Not Allowed", error_json("only GET is supported")),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn error_json(message: &str) -> String {
+    format!("{{\"error\":\"{}\"}}", json_escape(message))
+}
+
+fn string_map_json<'a>(entries: impl Iterator<Item = (&'a String, &'a String)>) -> String {
+    let fields: Vec<String> = entries
+        .map(|(key, value)| format!("\"{}\":\"{}\"", json_escape(key), json_escape(value)))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn devices_json(db: &ShardedDeviceDb) -> String {
+    let devices: Vec<String> = db
+        .devices()
+        .iter()
+        .map(|(devpath, properties)| {
+            // 属性按名字排序，输出稳定
+            let sorted: BTreeMap<&String, &String> = properties.iter().collect();
+            format!(
+                "{{\"devpath\":\"{}\",\"properties\":{}}}",
+                json_escape(&devpath.to_string_lossy()),
+                string_map_json(sorted.into_iter())
+            )
+        })
+        .collect();
+    format!("[{}]", devices.join(","))
+}
+
+// 统计由守护进程写出，还没有收到事件时文件不存在，视为空
+fn stats_json() -> String {
+    let counts = load_counts(STATS_PATH).unwrap_or_default();
+    let subsystems: Vec<String> = counts
+        .iter()
+        .map(|(subsystem, count)| format!("\"{}\":{}", json_escape(subsystem), count))
+        .collect();
+    let slowest: Vec<String> = metrics::load(RULE_METRICS_PATH)
+        .unwrap_or_default()
+        .iter()
+        .take(TOP_OFFENDERS)
+        .map(|timing| {
+            format!(
+                "{{\"location\":\"{}\",\"kind\":\"{}\",\"count\":{},\"total_us\":{},\"max_us\":{}}}",
+                json_escape(&timing.location),
+                timing.kind,
+                timing.count,
+                timing.total.as_micros(),
+                timing.max.as_micros()
+            )
+        })
+        .collect();
+    format!(
+        "{{\"total\":{},\"subsystems\":{{{}}},\"slowest_rules\":[{}]}}",
+        counts.values().sum::<usize>(),
+        subsystems.join(","),
+        slowest.join(",")
+    )
+}
+
+fn rules_json(rules: &RuleSet) -> String {
+    let entries: Vec<String> = rules
+        .rules()
+        .iter()
+        .map(|rule| {
+            format!(
+                "{{\"location\":\"{}\",\"source\":{}}}",
+                json_escape(&rule.location()),
+                rule.source
+                    .as_deref()
+                    .map_or_else(|| "null".to_string(), |source| format!("\"{}\"", json_escape(source)))
+            )
+        })
+        .collect();
+    format!("{{\"count\":{},\"rules\":[{}]}}", rules.len(), entries.join(","))
+}
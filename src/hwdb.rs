@@ -0,0 +1,223 @@
+// src/hwdb.rs
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use crate::rules::glob::{literal_prefixes, Glob};
+
+/// hwdb 文件目录，按优先级从低到高排列，同名文件以后面的目录为准
+pub const HWDB_DIRS: [&str; 2] = ["/usr/lib/udev/hwdb.d", "/etc/udev/hwdb.d"];
+
+/// 硬件数据库：按 modalias 一类的键查找要附加到设备上的属性
+///
+/// 文件格式与 systemd hwdb 相同：每条记录是一行或多行不缩进的匹配模式，后面跟着以空格
+/// 开头的 KEY=value 行，记录之间用空行分隔。查找时合并所有匹配记录的属性，后出现的优先。
+/// 记录按匹配模式的固定前缀建立索引，查找时只检查前缀与键相符的记录。
+#[derive(Debug, Clone, Default)]
+pub struct Hwdb {
+    entries: Vec<HwdbEntry>,
+    // 模式的固定前缀 -> 记录下标
+    index: HashMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Clone)]
+struct HwdbEntry {
+    patterns: Vec<String>,
+    globs: Vec<Glob>,
+    properties: Vec<(String, String)>,
+}
+
+impl Hwdb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 解析一个 hwdb 文件的内容，格式有误的行打印日志后跳过
+    pub fn parse_str(content: &str) -> Self {
+        let mut hwdb = Self::new();
+        let mut patterns = Vec::new();
+        let mut properties = Vec::new();
+
+        for (index, line) in content.lines().enumerate() {
+            if line.starts_with('#') {
+                continue;
+            }
+            if line.trim().is_empty() {
+                hwdb.push(&mut patterns, &mut properties);
+                continue;
+            }
+
+            if line.starts_with(' ') {
+                match line.trim().split_once('=') {
+                    Some((key, value)) if !patterns.is_empty() => {
+                        properties.push((key.trim().to_string(), value.to_string()));
+                    }
+                    _ => warn!("hwdb line {}: ignoring property '{}'", index + 1, line.trim()),
+                }
+            } else {
+                // 属性之后出现新的匹配行，说明上一条记录已经结束
+                if !properties.is_empty() {
+                    hwdb.push(&mut patterns, &mut properties);
+                }
+                patterns.push(line.trim_end().to_string());
+            }
+        }
+        hwdb.push(&mut patterns, &mut properties);
+
+        hwdb
+    }
+
+    /// 按文件名顺序读取各目录中的 .hwdb 文件
+    pub fn load<P: AsRef<Path>>(dirs: &[P]) -> io::Result<Self> {
+        let mut files: BTreeMap<OsString, PathBuf> = BTreeMap::new();
+        for dir in dirs {
+            let entries = match dir.as_ref().read_dir() {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                if path.extension().is_some_and(|ext| ext == "hwdb") {
+                    files.insert(entry.file_name(), path);
+                }
+            }
+        }
+
+        let mut hwdb = Self::new();
+        for path in files.values() {
+            hwdb.extend(Self::parse_str(&fs::read_to_string(path)?));
+        }
+        Ok(hwdb)
+    }
+
+    /// 追加另一个数据库的记录，优先级高于已有记录
+    pub fn extend(&mut self, other: Hwdb) {
+        for entry in other.entries {
+            self.insert(entry);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 所有匹配记录的属性，同名属性以后出现的记录为准
+    pub fn lookup(&self, key: &str) -> Vec<(String, String)> {
+        let mut candidates: Vec<usize> = key
+            .char_indices()
+            .map(|(end, _)| &key[..end])
+            .chain([key])
+            .filter_map(|prefix| self.index.get(prefix))
+            .flatten()
+            .copied()
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut properties: Vec<(String, String)> = Vec::new();
+        for entry in candidates.into_iter().map(|i| &self.entries[i]) {
+            if !entry.globs.iter().any(|glob| glob.matches(key)) {
+                continue;
+            }
+            for (name, value) in &entry.properties {
+                match properties.iter_mut().find(|(existing, _)| existing == name) {
+                    Some(existing) => existing.1 = value.clone(),
+                    None => properties.push((name.clone(), value.clone())),
+                }
+            }
+        }
+        properties
+    }
+
+    fn push(&mut self, patterns: &mut Vec<String>, properties: &mut Vec<(String, String)>) {
+        if !patterns.is_empty() && !properties.is_empty() {
+            let patterns = std::mem::take(patterns);
+            self.insert(HwdbEntry {
+                globs: patterns.iter().map(|pattern| Glob::new(pattern)).collect(),
+                patterns,
+                properties: std::mem::take(properties),
+            });
+        }
+        patterns.clear();
+        properties.clear();
+    }
+
+    fn insert(&mut self, entry: HwdbEntry) {
+        let position = self.entries.len();
+        for pattern in &entry.patterns {
+            for prefix in literal_prefixes(pattern) {
+                let indices = self.index.entry(prefix.to_string()).or_default();
+                if indices.last() != Some(&position) {
+                    indices.push(position);
+                }
+            }
+        }
+        self.entries.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HWDB: &str = "\
+usb:v*p*d*dc*dsc*dp*icFFisc42ip01in*
+ ID_ANDROID=1
+
+usb:v18D1*
+ ID_ANDROID_VENDOR=Google
+
+usb:v18D1p4EE7*
+usb:v18D1p4EE8*
+ ID_ANDROID_VENDOR=Pixel
+";
+
+    #[test]
+    fn lookup_merges_matching_entries_in_order() {
+        let mut hwdb = Hwdb::parse_str(HWDB);
+        assert_eq!(hwdb.len(), 3);
+        assert_eq!(hwdb.lookup("usb:v18D1p4EE8"), vec![("ID_ANDROID_VENDOR".to_string(), "Pixel".to_string())]);
+        assert_eq!(hwdb.lookup("usb:v18D1p0001"), vec![("ID_ANDROID_VENDOR".to_string(), "Google".to_string())]);
+        assert_eq!(
+            hwdb.lookup("usb:v18D1p4EE7d0440dc00dsc00dp00icFFisc42ip01in01"),
+            vec![
+                ("ID_ANDROID".to_string(), "1".to_string()),
+                ("ID_ANDROID_VENDOR".to_string(), "Pixel".to_string())
+            ]
+        );
+        assert!(hwdb.lookup("pci:v00008086").is_empty());
+
+        // 后加入的记录优先
+        hwdb.extend(Hwdb::parse_str("usb:v18D1*\n ID_ANDROID_VENDOR=Alphabet\n"));
+        assert_eq!(hwdb.lookup("usb:v18D1p0001"), vec![("ID_ANDROID_VENDOR".to_string(), "Alphabet".to_string())]);
+    }
+
+    #[test]
+    fn patterns_without_literal_prefix_are_always_candidates() {
+        let hwdb = Hwdb::parse_str("*:v1234*\n ID_X=1\n\nusb:v12|pci:v12*\n ID_Y=1\n");
+        assert_eq!(hwdb.lookup("usb:v1234"), vec![("ID_X".to_string(), "1".to_string())]);
+        assert_eq!(hwdb.lookup("pci:v1299"), vec![("ID_Y".to_string(), "1".to_string())]);
+        assert_eq!(hwdb.lookup("usb:v12"), vec![("ID_Y".to_string(), "1".to_string())]);
+    }
+
+    #[cfg(feature = "android")]
+    #[test]
+    fn android_access_requires_an_adb_or_fastboot_interface() {
+        let hwdb = Hwdb::parse_str(crate::android::ANDROID.hwdb);
+        let vendor_only = hwdb.lookup("usb:v18D1p5000");
+        assert!(!vendor_only.iter().any(|(key, _)| key == "ID_ANDROID"));
+        let fastboot = hwdb.lookup("usb:v18D1p4EE0d0100dc00dsc00dp00icFFisc42ip03in00");
+        assert!(fastboot.contains(&("ID_ANDROID".to_string(), "1".to_string())));
+        assert!(fastboot.contains(&("ID_ANDROID_FASTBOOT".to_string(), "1".to_string())));
+    }
+}
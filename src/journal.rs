@@ -0,0 +1,158 @@
+// src/journal.rs
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// RUN 命令结果的日志文件
+pub const JOURNAL_PATH: &str = "/run/rust_udev/run.journal";
+
+/// 日志保留的最近记录数
+pub const JOURNAL_LEN: usize = 256;
+
+/// RUN 子进程的结束方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Exited(i32),
+    Signaled(i32),
+}
+
+impl RunStatus {
+    pub fn success(&self) -> bool {
+        *self == RunStatus::Exited(0)
+    }
+
+    // 日志文件中的写法：exit:N 或 signal:N
+    fn parse(value: &str) -> Option<Self> {
+        let (kind, code) = value.split_once(':')?;
+        let code = code.parse().ok()?;
+        match kind {
+            "exit" => Some(RunStatus::Exited(code)),
+            "signal" => Some(RunStatus::Signaled(code)),
+            _ => None,
+        }
+    }
+
+    fn as_field(&self) -> String {
+        match self {
+            RunStatus::Exited(code) => format!("exit:{}", code),
+            RunStatus::Signaled(signal) => format!("signal:{}", signal),
+        }
+    }
+}
+
+impl fmt::Display for RunStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunStatus::Exited(code) => write!(f, "exited with {}", code),
+            RunStatus::Signaled(signal) => write!(f, "killed by signal {}", signal),
+        }
+    }
+}
+
+/// 一条 RUN 命令的结果，按触发它的事件 seqnum 关联
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunRecord {
+    pub seqnum: u64,
+    pub devpath: PathBuf,
+    pub pid: u32,
+    pub status: RunStatus,
+    /// 子进程被回收时的秒级 Unix 时间
+    pub timestamp: u64,
+    /// 已完成变量替换的命令
+    pub command: String,
+}
+
+/// 最近结束的 RUN 命令，超过容量时丢弃最早的记录
+#[derive(Debug)]
+pub struct RunJournal {
+    records: Mutex<VecDeque<RunRecord>>,
+    capacity: usize,
+}
+
+impl Default for RunJournal {
+    fn default() -> Self {
+        Self::new(JOURNAL_LEN)
+    }
+}
+
+impl RunJournal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::new()),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, record: RunRecord) {
+        let mut records = self.records.lock().unwrap();
+        records.push_back(record);
+        while records.len() > self.capacity {
+            records.pop_front();
+        }
+    }
+
+    pub fn records(&self) -> Vec<RunRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// 以临时文件加重命名的方式写出，每行为 seqnum、devpath、pid、状态、时间戳和命令，以制表符分隔
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content: String = self
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|record| {
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\n",
+                    record.seqnum,
+                    record.devpath.display(),
+                    record.pid,
+                    record.status.as_field(),
+                    record.timestamp,
+                    // 命令中的换行和制表符会破坏行格式
+                    record.command.replace(['\t', '\n'], " "),
+                )
+            })
+            .collect();
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// 读取日志文件，格式不对的行被跳过
+pub fn load_journal<P: AsRef<Path>>(path: P) -> io::Result<Vec<RunRecord>> {
+    let content = fs::read_to_string(path)?;
+    let mut records = Vec::new();
+
+    for line in content.lines() {
+        let fields: Vec<&str> = line.splitn(6, '\t').collect();
+        if let [seqnum, devpath, pid, status, timestamp, command] = fields[..] {
+            if let (Ok(seqnum), Ok(pid), Some(status), Ok(timestamp)) =
+                (seqnum.parse(), pid.parse(), RunStatus::parse(status), timestamp.parse())
+            {
+                records.push(RunRecord {
+                    seqnum,
+                    devpath: PathBuf::from(devpath),
+                    pid,
+                    status,
+                    timestamp,
+                    command: command.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(records)
+}
@@ -0,0 +1,106 @@
+// src/kernel.rs
+
+use std::cmp::Ordering;
+use std::fs;
+use std::sync::LazyLock;
+
+use log::*;
+
+use crate::rules::tokenizer::Operator;
+
+const OSRELEASE: &str = "/proc/sys/kernel/osrelease";
+
+// 守护进程运行期间内核不会变化，启动后第一次用到时读取一次
+static RUNNING: LazyLock<Option<KernelVersion>> = LazyLock::new(|| {
+    let release = match fs::read_to_string(OSRELEASE) {
+        Ok(release) => release,
+        Err(e) => {
+            warn!("Failed to read kernel release from {}: {}", OSRELEASE, e);
+            return None;
+        }
+    };
+    let version = KernelVersion::parse(release.trim());
+    match &version {
+        Some(version) => debug!("Running kernel version {}", version),
+        None => warn!("Unrecognized kernel release '{}'", release.trim()),
+    }
+    version
+});
+
+/// 内核版本号开头的数字部分，如 "5.10.0-21-amd64" 取 5.10.0
+///
+/// 比较时缺少的部分按 0 处理，因此 5.10 与 5.10.0 相等。
+#[derive(Debug, Clone)]
+pub struct KernelVersion(Vec<u32>);
+
+impl KernelVersion {
+    /// 解析以数字开头、用 '.' 分隔的版本号，遇到其它字符停止
+    pub fn parse(release: &str) -> Option<Self> {
+        let end = release
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(release.len());
+        let parts = release[..end]
+            .split('.')
+            .take_while(|part| !part.is_empty())
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        if parts.is_empty() {
+            return None;
+        }
+        Some(Self(parts))
+    }
+
+    /// 当前运行的内核版本，无法读取时为 None
+    pub fn running() -> Option<&'static KernelVersion> {
+        RUNNING.as_ref()
+    }
+
+    /// 按 KERNELVER 的比较运算符检查 self <op> other
+    pub fn satisfies(&self, op: Operator, other: &KernelVersion) -> bool {
+        let ordering = self.cmp(other);
+        match op {
+            Operator::Match => ordering == Ordering::Equal,
+            Operator::Nomatch => ordering != Ordering::Equal,
+            Operator::Less => ordering == Ordering::Less,
+            Operator::LessEqual => ordering != Ordering::Greater,
+            Operator::Greater => ordering == Ordering::Greater,
+            Operator::GreaterEqual => ordering != Ordering::Less,
+            _ => false,
+        }
+    }
+}
+
+impl Ord for KernelVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        (0..len)
+            .map(|i| {
+                let a = self.0.get(i).copied().unwrap_or(0);
+                let b = other.0.get(i).copied().unwrap_or(0);
+                a.cmp(&b)
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for KernelVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for KernelVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for KernelVersion {}
+
+impl std::fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u32::to_string).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
@@ -2,7 +2,43 @@
 pub mod monitor;
 pub mod rules;
 pub mod libudev;
+pub mod logging;
 pub mod udevd;
 pub mod actions;
+#[cfg(feature = "android")]
+pub mod android;
+pub mod builtins;
+pub mod cancel;
+pub mod clock;
+pub mod config;
+pub mod control;
+pub mod dashboard;
+pub mod db;
+pub mod deferred;
+pub mod dispatcher;
 pub mod udevadm;
-pub mod device;
\ No newline at end of file
+pub mod device;
+pub mod filter;
+pub mod hwdb;
+#[cfg(feature = "http-status")]
+pub mod http_status;
+pub mod journal;
+pub mod kernel;
+pub mod lru;
+pub mod media;
+pub mod net;
+pub mod plan;
+pub mod prelude;
+#[cfg(feature = "printer")]
+pub mod printer;
+pub mod profile;
+pub mod reaper;
+pub mod reprobe;
+pub mod selinux;
+pub mod seqnum;
+pub mod stats;
+pub mod strict;
+pub mod symlink_db;
+pub mod transaction;
+pub mod transliterate;
+pub mod xattr;
\ No newline at end of file
@@ -1,85 +1,355 @@
 // src/libudev.rs
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     path::{Path, PathBuf},
     fs,
     os::unix::fs::{MetadataExt, FileTypeExt},
 };
 
+use log::*;
+
+use crate::actions::DEV_ROOT;
+use crate::db::{self, DeviceRecord};
+use crate::device::{fill_from_sysfs, DevnumKind, UEventDevice};
+use crate::rules::glob::Glob;
+
+/// 设备属性：sysfs 中的属性加上数据库中的记录；设备节点另外补充节点路径、权限和上层设备信息
 pub fn get_device_info(devpath: &str) -> Option<HashMap<String, String>> {
+    let mut device = resolve_device(devpath)?;
+    // 守护进程保存的规则处理结果：属性、标签和符号链接
+    device.read_db();
+    let mut info = device.properties().clone();
+
     let dev_path = Path::new(devpath);
-    
-    if !dev_path.exists() {
-        return None;
+    let Some(metadata) = fs::metadata(dev_path).ok().filter(|metadata| device_node_kind(metadata).is_some()) else {
+        return Some(info);
+    };
+    let sys_path = device.syspath();
+
+    info.insert("DEVNAME".into(), devpath.to_string());
+
+    if let Ok(devtype) = fs::read_to_string(sys_path.join("type")) {
+        info.insert("DEVTYPE".into(), devtype.trim().to_string());
     }
 
-    let mut info = HashMap::new();
+    if let Ok(driver) = fs::read_link(sys_path.join("device/driver")) {
+        if let Some(name) = driver.file_name() {
+            info.insert("DRIVER".into(), name.to_string_lossy().into_owned());
+        }
+    }
 
-    if let Ok(metadata) = fs::metadata(dev_path) {
-        if metadata.file_type().is_char_device() {
+    if let Ok(device_path) = fs::read_link(sys_path.join("device")) {
+        info.insert("PHYSDEVPATH".into(), device_path.to_string_lossy().into_owned());
+    }
 
-            let (major, minor) = (libc::major(metadata.rdev()), libc::minor(metadata.rdev()));
-            let sys_path = PathBuf::from(format!("/sys/dev/char/{}:{}", major, minor));
-            if !sys_path.exists() {
-                return None;
-            }
+    info.insert("DEVMODE".into(), format!("{:o}", metadata.mode() & 0o777));
+    Some(info)
+}
 
-            info.insert("DEVNAME".into(), devpath.to_string());
+/// 查找设备，spec 可以是设备节点（/dev/sda）、设备号（b8:0、c1:3）或 sysfs 路径；
+/// 设备节点和设备号都经由 /sys/dev 查找
+pub fn resolve_device(spec: &str) -> Option<UEventDevice> {
+    if let Some((kind, major, minor)) = parse_devnum(spec) {
+        return UEventDevice::from_devnum(kind, major, minor);
+    }
 
-            if let Ok(uevent) = fs::read_to_string(sys_path.join("uevent")) {
-                for line in uevent.lines() {
-                    if let Some((k, v)) = line.split_once('=') {
-                        if k != "DEVNAME" {
-                            info.insert(k.into(), v.into());
-                        }
-                    }
-                }
-            }
+    let metadata = fs::metadata(spec).ok()?;
+    match device_node_kind(&metadata) {
+        Some(kind) => {
+            let rdev = metadata.rdev();
+            UEventDevice::from_devnum(kind, libc::major(rdev), libc::minor(rdev))
+        }
+        None => UEventDevice::from_syspath(spec),
+    }
+}
 
-            if let Ok(subsystem) = fs::read_link(sys_path.join("subsystem")) {
-                if let Some(name) = subsystem.file_name() {
-                    info.insert("SUBSYSTEM".into(), name.to_string_lossy().into_owned());
-                }
-            }
+/// 把设备节点、设备号或 sysfs 路径解析为规范化的 sysfs 设备目录
+pub fn resolve_syspath(path: &str) -> Option<PathBuf> {
+    resolve_device(path).map(|device| device.syspath())
+}
 
-            if let Ok(devtype) = fs::read_to_string(sys_path.join("type")) {
-                info.insert("DEVTYPE".into(), devtype.trim().to_string());
-            }
+/// 解析 udevadm 风格的设备号，b 表示块设备、c 表示字符设备，如 b8:0
+pub fn parse_devnum(spec: &str) -> Option<(DevnumKind, u32, u32)> {
+    let kind = match spec.chars().next()? {
+        'b' => DevnumKind::Block,
+        'c' => DevnumKind::Char,
+        _ => return None,
+    };
+    let (major, minor) = spec[1..].split_once(':')?;
+    Some((kind, major.parse().ok()?, minor.parse().ok()?))
+}
 
-            if let Ok(driver) = fs::read_link(sys_path.join("device/driver")) {
-                if let Some(name) = driver.file_name() {
-                    info.insert("DRIVER".into(), name.to_string_lossy().into_owned());
-                }
-            }
+fn device_node_kind(metadata: &fs::Metadata) -> Option<DevnumKind> {
+    let file_type = metadata.file_type();
+    if file_type.is_char_device() {
+        Some(DevnumKind::Char)
+    } else if file_type.is_block_device() {
+        Some(DevnumKind::Block)
+    } else {
+        None
+    }
+}
 
-            if let Ok(device_path) = fs::read_link(sys_path.join("device")) {
-                info.insert("PHYSDEVPATH".into(), device_path.to_string_lossy().into_owned());
-            }
+/// syspath 之下的所有子孙设备（含 uevent 文件的目录），按路径排序，不跟随符号链接
+pub fn device_descendants(syspath: &Path) -> Vec<PathBuf> {
+    let mut devices = Vec::new();
+    collect_descendants(syspath, &mut devices);
+    devices.sort();
+    devices
+}
+
+fn collect_descendants(dir: &Path, devices: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        // subsystem、driver、device 等都是符号链接，跳过以免走出当前子树
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.join("uevent").exists() {
+            devices.push(path.clone());
+        }
+        // 类目录（如 tty/）本身不是设备，但下面可能有设备
+        collect_descendants(&path, devices);
+    }
+}
 
-            info.insert("DEVMODE".into(), format!("{:o}", metadata.mode() & 0o777));
-            return Some(info);
+/// 扫描 /sys/bus 和 /sys/class 枚举设备，相当于 libudev 的 udev_enumerate
+///
+/// 同类条件中 subsystem、sysname、属性只需满足其一，sysattr 和标签必须全部满足；
+/// 值都按 udev 的 glob 规则匹配。从 sysfs 读出的设备只有 uevent 中的属性，没有标签。
+#[derive(Debug, Clone)]
+pub struct Enumerator {
+    sys_root: PathBuf,
+    subsystems: Vec<Glob>,
+    sysnames: Vec<Glob>,
+    properties: Vec<(String, Glob)>,
+    sysattrs: Vec<(String, Glob)>,
+    tags: Vec<String>,
+    parent: Option<PathBuf>,
+    db_dir: Option<PathBuf>,
+    dev_root: PathBuf,
+    initialized_only: bool,
+}
+
+impl Default for Enumerator {
+    fn default() -> Self {
+        let mut enumerator = Self::with_sys_root("/sys");
+        enumerator.use_db(db::DATA_DIR);
+        enumerator
+    }
+}
+
+impl Enumerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从 sys_root 而不是 /sys 扫描，枚举出的 DEVPATH 相对于 sys_root
+    pub fn with_sys_root<P: AsRef<Path>>(sys_root: P) -> Self {
+        Self {
+            sys_root: sys_root.as_ref().to_path_buf(),
+            subsystems: Vec::new(),
+            sysnames: Vec::new(),
+            properties: Vec::new(),
+            sysattrs: Vec::new(),
+            tags: Vec::new(),
+            parent: None,
+            db_dir: None,
+            dev_root: PathBuf::from(DEV_ROOT),
+            initialized_only: false,
         }
     }
 
-    let uevent_path = dev_path.join("uevent");
-    if uevent_path.exists() {
-        if let Ok(content) = fs::read_to_string(uevent_path) {
-            for line in content.lines() {
-                if let Some((k, v)) = line.split_once('=') {
-                    info.insert(k.to_string(), v.to_string());
+    /// 优先用守护进程在 dir 中保存的设备记录构造设备，没有记录的设备才读取 sysfs。
+    /// 记录中有规则设置的属性和标签；new() 默认使用 db::DATA_DIR，with_sys_root 默认不使用
+    pub fn use_db<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.db_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// 记录中的符号链接所在的设备根目录，默认为 DEV_ROOT
+    pub fn use_dev_root<P: AsRef<Path>>(&mut self, root: P) -> &mut Self {
+        self.dev_root = root.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn match_subsystem(&mut self, subsystem: &str) -> &mut Self {
+        self.subsystems.push(Glob::new(subsystem));
+        self
+    }
+
+    pub fn match_sysname(&mut self, sysname: &str) -> &mut Self {
+        self.sysnames.push(Glob::new(sysname));
+        self
+    }
+
+    pub fn match_property(&mut self, key: &str, value: &str) -> &mut Self {
+        self.properties.push((key.to_string(), Glob::new(value)));
+        self
+    }
+
+    pub fn match_sysattr(&mut self, attr: &str, value: &str) -> &mut Self {
+        self.sysattrs.push((attr.to_string(), Glob::new(value)));
+        self
+    }
+
+    pub fn match_tag(&mut self, tag: &str) -> &mut Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    /// 只枚举已经被守护进程处理过（数据库中有记录）的设备
+    pub fn match_is_initialized(&mut self) -> &mut Self {
+        self.initialized_only = true;
+        self
+    }
+
+    /// 只枚举 parent（sysfs 设备目录）及其子孙
+    pub fn match_parent<P: AsRef<Path>>(&mut self, parent: P) -> &mut Self {
+        self.parent = Some(parent.as_ref().to_path_buf());
+        self
+    }
+
+    /// 满足全部条件的设备，按 DEVPATH 排序
+    pub fn scan_devices(&self) -> Vec<UEventDevice> {
+        let mut records = self.load_records();
+        let Ok(sys_root) = self.sys_root.canonicalize() else {
+            return Vec::new();
+        };
+
+        // 只有经过守护进程处理的设备才有标签和记录，这时不必遍历 sysfs
+        let db_only = !self.tags.is_empty() || self.initialized_only;
+        let syspaths = if !db_only || self.db_dir.is_none() {
+            self.scan_syspaths()
+        } else {
+            self.tagged_syspaths(&sys_root, &records)
+        };
+
+        syspaths
+            .into_iter()
+            .filter(|syspath| self.matches_sysname(syspath) && self.matches_sysattrs(syspath))
+            .filter_map(|syspath| {
+                let devpath = Path::new("/").join(syspath.strip_prefix(&sys_root).ok()?);
+                match records.remove(&devpath) {
+                    // 记录缺少的字段才读取 sysfs
+                    Some(record) => {
+                        let initialized = record.initialized.or(Some(0));
+                        let mut properties = record.into_properties(&self.dev_root);
+                        fill_from_sysfs(&syspath, &mut properties);
+                        let mut device = UEventDevice::from_event(properties)?;
+                        device.set_dev_root(&self.dev_root);
+                        device.set_usec_initialized(initialized);
+                        Some(device)
+                    }
+                    None => UEventDevice::from_syspath_in(&sys_root, &syspath),
                 }
-            }
+            })
+            .filter(|device| self.matches_subsystem(device) && self.matches_device(device))
+            .filter(|device| !self.initialized_only || device.is_initialized())
+            .collect()
+    }
+
+    fn load_records(&self) -> BTreeMap<PathBuf, DeviceRecord> {
+        let Some(dir) = &self.db_dir else {
+            return BTreeMap::new();
+        };
+        db::load_records(dir).unwrap_or_else(|e| {
+            warn!("Failed to read device records from {}: {}", dir.display(), e);
+            BTreeMap::new()
+        })
+    }
+
+    // 有记录、带有全部所需标签（没有标签条件时不限）且仍在 sysfs 中的设备
+    fn tagged_syspaths(
+        &self,
+        sys_root: &Path,
+        records: &BTreeMap<PathBuf, DeviceRecord>,
+    ) -> BTreeSet<PathBuf> {
+        let parent = self.parent.as_ref().and_then(|parent| parent.canonicalize().ok());
+        if self.parent.is_some() && parent.is_none() {
+            return BTreeSet::new();
         }
 
-        if let Ok(subsystem) = fs::read_link(dev_path.join("subsystem")) {
-            if let Some(name) = subsystem.file_name() {
-                info.insert("SUBSYSTEM".into(), name.to_string_lossy().into_owned());
+        records
+            .iter()
+            .filter(|(_, record)| self.tags.iter().all(|tag| record.tags.contains(tag)))
+            .map(|(devpath, _)| sys_root.join(devpath.strip_prefix("/").unwrap_or(devpath)))
+            .filter(|syspath| parent.as_ref().is_none_or(|parent| syspath.starts_with(parent)))
+            .filter(|syspath| syspath.join("uevent").exists())
+            .collect()
+    }
+
+    // bus/*/devices/* 和 class/*/* 都是指向设备目录的链接，同一设备可能出现多次
+    fn scan_syspaths(&self) -> BTreeSet<PathBuf> {
+        let parent = self.parent.as_ref().and_then(|parent| parent.canonicalize().ok());
+        if self.parent.is_some() && parent.is_none() {
+            return BTreeSet::new();
+        }
+
+        let mut syspaths = BTreeSet::new();
+        let mut collect = |dir: PathBuf| {
+            for entry in read_dir_paths(&dir) {
+                let Ok(syspath) = entry.canonicalize() else {
+                    continue;
+                };
+                let in_parent = parent.as_ref().is_none_or(|parent| syspath.starts_with(parent));
+                if in_parent && syspath.join("uevent").exists() {
+                    syspaths.insert(syspath);
+                }
             }
+        };
+
+        for subsystem in self.subsystem_dirs("bus") {
+            collect(subsystem.join("devices"));
         }
+        for subsystem in self.subsystem_dirs("class") {
+            collect(subsystem);
+        }
+        syspaths
+    }
 
-        return Some(info);
+    // kind 下名字满足 subsystem 条件的目录；最终仍按设备的 SUBSYSTEM 再检查一次
+    fn subsystem_dirs(&self, kind: &str) -> Vec<PathBuf> {
+        read_dir_paths(&self.sys_root.join(kind))
+            .into_iter()
+            .filter(|dir| {
+                let name = dir.file_name().unwrap_or_default().to_string_lossy();
+                self.subsystems.is_empty() || self.subsystems.iter().any(|glob| glob.matches(&name))
+            })
+            .collect()
+    }
+
+    fn matches_sysname(&self, syspath: &Path) -> bool {
+        let sysname = syspath.file_name().unwrap_or_default().to_string_lossy();
+        self.sysnames.is_empty() || self.sysnames.iter().any(|glob| glob.matches(&sysname))
+    }
+
+    fn matches_sysattrs(&self, syspath: &Path) -> bool {
+        self.sysattrs.iter().all(|(attr, glob)| {
+            fs::read_to_string(syspath.join(attr))
+                .is_ok_and(|value| glob.matches(value.trim_end_matches('\n')))
+        })
     }
 
-    None
+    fn matches_subsystem(&self, device: &UEventDevice) -> bool {
+        self.subsystems.is_empty() || self.subsystems.iter().any(|glob| glob.matches(device.subsystem()))
+    }
+
+    fn matches_device(&self, device: &UEventDevice) -> bool {
+        let property = |(key, glob): &(String, Glob)| device.property(key).is_some_and(|value| glob.matches(value));
+        (self.properties.is_empty() || self.properties.iter().any(property))
+            && self.tags.iter().all(|tag| device.has_tag(tag))
+    }
+}
+
+fn read_dir_paths(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries.filter_map(Result::ok).map(|entry| entry.path()).collect())
+        .unwrap_or_default()
 }
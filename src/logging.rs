@@ -0,0 +1,437 @@
+// src/logging.rs
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+pub use crate::config::{config_value, config_values, CONFIG_PATH};
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+const IDENTIFIER: &str = "rust_udev";
+
+/// 日志输出位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogTarget {
+    /// 前台运行时输出到 stderr，否则优先 journald，其次 syslog
+    #[default]
+    Auto,
+    Console,
+    Syslog,
+    Journal,
+}
+
+impl LogTarget {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Some(LogTarget::Auto),
+            "console" | "stderr" => Some(LogTarget::Console),
+            "syslog" => Some(LogTarget::Syslog),
+            "journal" | "journald" => Some(LogTarget::Journal),
+            _ => None,
+        }
+    }
+
+    /// 从配置文件读取 log_target=，文件不存在或没有该项时为 Auto
+    pub fn from_config<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match config_value(path, "log_target")? {
+            Some(value) => LogTarget::parse(&value).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid log_target '{}'", value),
+                )
+            }),
+            None => Ok(LogTarget::Auto),
+        }
+    }
+}
+
+// udevadm control --log-level 设置的级别，代替 RUST_LOG 或启动时的级别；0 表示没有设置
+static LEVEL_OVERRIDE: AtomicUsize = AtomicUsize::new(0);
+
+/// 运行中修改守护进程的日志级别，规则的 log_level 仍可为单个事件提高级别
+pub fn set_log_level(level: LevelFilter) {
+    LEVEL_OVERRIDE.store(level as usize + 1, Ordering::Relaxed);
+}
+
+fn level_override() -> Option<LevelFilter> {
+    let value = LEVEL_OVERRIDE.load(Ordering::Relaxed);
+    LevelFilter::iter().find(|level| *level as usize + 1 == value)
+}
+
+/// 一个模块的日志级别，比如 rules::matcher=debug；没有模块时作用于所有模块
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogDirective {
+    /// 完整的模块路径，如 rust_udev::rules::matcher，包含其子模块
+    pub module: Option<String>,
+    pub level: LevelFilter,
+}
+
+impl LogDirective {
+    fn matches(&self, target: &str) -> bool {
+        match &self.module {
+            Some(module) => target
+                .strip_prefix(module.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::")),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for LogDirective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = self.level.as_str().to_ascii_lowercase();
+        match &self.module {
+            Some(module) => write!(f, "{}={}", module, level),
+            None => write!(f, "{}", level),
+        }
+    }
+}
+
+/// 解析逗号分隔的 module=level 或单独的 level，模块可以省略开头的 rust_udev::
+pub fn parse_log_filter(spec: &str) -> Result<Vec<LogDirective>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (module, level) = match item.split_once('=') {
+                Some((module, level)) => (Some(module.trim()), level),
+                None => (None, item),
+            };
+            let level = parse_level(level).ok_or_else(|| format!("invalid log level in '{}'", item))?;
+            let module = match module {
+                Some("") => return Err(format!("missing module in '{}'", item)),
+                Some(module) if module == IDENTIFIER || module.starts_with("rust_udev::") => Some(module.to_string()),
+                Some(module) => Some(format!("{}::{}", IDENTIFIER, module)),
+                None => None,
+            };
+            Ok(LogDirective { module, level })
+        })
+        .collect()
+}
+
+// udevadm control --log-filter 设置的按模块级别，优先于 --log-level 和 RUST_LOG
+static LOG_FILTER: RwLock<Vec<LogDirective>> = RwLock::new(Vec::new());
+// LOG_FILTER 非空；没有设置过滤时每条日志不必去拿读锁
+static HAS_LOG_FILTER: AtomicBool = AtomicBool::new(false);
+
+/// 运行中替换按模块的日志级别；为空时恢复为只按全局级别过滤
+pub fn set_log_filter(directives: Vec<LogDirective>) {
+    let mut filter = LOG_FILTER.write().unwrap();
+    HAS_LOG_FILTER.store(!directives.is_empty(), Ordering::Relaxed);
+    *filter = directives;
+}
+
+fn filter_level(target: &str) -> Option<LevelFilter> {
+    if !HAS_LOG_FILTER.load(Ordering::Relaxed) {
+        return None;
+    }
+    most_specific(&LOG_FILTER.read().unwrap(), target)
+}
+
+// 最具体的匹配：模块路径最长的一项，后出现的同名项优先
+fn most_specific(directives: &[LogDirective], target: &str) -> Option<LevelFilter> {
+    directives
+        .iter()
+        .filter(|directive| directive.matches(target))
+        .max_by_key(|directive| directive.module.as_ref().map_or(0, String::len))
+        .map(|directive| directive.level)
+}
+
+thread_local! {
+    static EVENT_CONTEXT: RefCell<Option<(u64, PathBuf)>> = const { RefCell::new(None) };
+    // 规则用 OPTIONS+="log_level=..." 为当前事件提高的日志级别
+    static EVENT_LOG_LEVEL: Cell<Option<LevelFilter>> = const { Cell::new(None) };
+}
+
+/// 当前线程正在处理的事件，期间的日志会带上 seqnum 和 devpath；离开作用域时清除
+pub struct EventContext {
+    previous: Option<(u64, PathBuf)>,
+    previous_level: Option<LevelFilter>,
+}
+
+impl EventContext {
+    pub fn enter(seqnum: u64, devpath: &Path) -> Self {
+        let previous =
+            EVENT_CONTEXT.with(|context| context.replace(Some((seqnum, devpath.to_path_buf()))));
+        let previous_level = EVENT_LOG_LEVEL.with(|level| level.replace(None));
+        Self {
+            previous,
+            previous_level,
+        }
+    }
+}
+
+impl Drop for EventContext {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        EVENT_CONTEXT.with(|context| *context.borrow_mut() = previous);
+        EVENT_LOG_LEVEL.with(|level| level.set(self.previous_level));
+    }
+}
+
+fn current_event() -> Option<(u64, PathBuf)> {
+    EVENT_CONTEXT.with(|context| context.borrow().clone())
+}
+
+/// 在当前事件剩余的处理过程中至少输出到 level 级别的日志，不影响其它事件
+pub fn raise_event_log_level(level: LevelFilter) {
+    EVENT_LOG_LEVEL.with(|current| {
+        if current.get().is_none_or(|current| level > current) {
+            current.set(Some(level));
+        }
+    });
+}
+
+fn event_log_level() -> Option<LevelFilter> {
+    EVENT_LOG_LEVEL.with(Cell::get)
+}
+
+/// 解析日志级别：err/error、warning/warn、info、debug、trace，或 syslog 的数字级别 0-7
+pub fn parse_level(value: &str) -> Option<LevelFilter> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "emerg" | "alert" | "crit" | "err" | "error" | "0" | "1" | "2" | "3" => {
+            Some(LevelFilter::Error)
+        }
+        "warning" | "warn" | "notice" | "4" | "5" => Some(LevelFilter::Warn),
+        "info" | "6" => Some(LevelFilter::Info),
+        "debug" | "7" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// 初始化守护进程日志；级别过滤仍按 RUST_LOG，后端不可用时退回 stderr
+///
+/// 全局级别放开到 Trace，由 DaemonLogger 按 RUST_LOG 或当前事件提高后的级别过滤，
+/// 这样单条规则的 log_level 才能让被过滤掉的日志输出。
+pub fn init(target: LogTarget) -> Result<(), log::SetLoggerError> {
+    init_with_level(target, None)
+}
+
+/// 与 init 相同，但 level 给出时代替 RUST_LOG，比如命令行的 --log-level 或 udev.conf 的 udev_log=
+pub fn init_with_level(target: LogTarget, level: Option<LevelFilter>) -> Result<(), log::SetLoggerError> {
+    let filter = match level {
+        Some(level) => env_logger::Builder::new().filter_level(level).build(),
+        None => env_logger::Builder::from_default_env().build(),
+    };
+    let sink = connect(target).map(Mutex::new);
+
+    log::set_boxed_logger(Box::new(DaemonLogger { filter, sink }))?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
+
+// 选出实际使用的后端，None 表示使用 stderr
+fn connect(target: LogTarget) -> Option<Sink> {
+    match target {
+        LogTarget::Console => None,
+        LogTarget::Syslog => Sink::connect(SinkKind::Syslog),
+        LogTarget::Journal => Sink::connect(SinkKind::Journal),
+        LogTarget::Auto => {
+            if io::stderr().is_terminal() {
+                return None;
+            }
+            Sink::connect(SinkKind::Journal).or_else(|| Sink::connect(SinkKind::Syslog))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SinkKind {
+    Syslog,
+    Journal,
+}
+
+struct Sink {
+    kind: SinkKind,
+    socket: UnixDatagram,
+}
+
+impl Sink {
+    fn connect(kind: SinkKind) -> Option<Self> {
+        let path = match kind {
+            SinkKind::Syslog => SYSLOG_SOCKET,
+            SinkKind::Journal => JOURNAL_SOCKET,
+        };
+        let socket = UnixDatagram::unbound().ok()?;
+        socket.connect(path).ok()?;
+        Some(Self { kind, socket })
+    }
+
+    fn send(&self, record: &Record) -> io::Result<()> {
+        let message = record.args().to_string();
+        let event = current_event();
+
+        let datagram = match self.kind {
+            SinkKind::Syslog => syslog_datagram(record.level(), &message, event.as_ref()),
+            SinkKind::Journal => {
+                journal_datagram(record.level(), record.target(), &message, event.as_ref())
+            }
+        };
+        self.socket.send(&datagram).map(|_| ())
+    }
+}
+
+struct DaemonLogger {
+    filter: env_logger::Logger,
+    // None 表示输出到 stderr
+    sink: Option<Mutex<Sink>>,
+}
+
+impl DaemonLogger {
+    fn wanted(&self, metadata: &Metadata) -> bool {
+        if let Some(level) = filter_level(metadata.target()) {
+            return metadata.level() <= level;
+        }
+        match level_override() {
+            Some(level) => metadata.level() <= level,
+            None => self.filter.enabled(metadata),
+        }
+    }
+
+    fn raised(&self, level: Level) -> bool {
+        event_log_level().is_some_and(|raised| level <= raised)
+    }
+}
+
+impl Log for DaemonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.wanted(metadata) || self.raised(metadata.level())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.wanted(record.metadata()) && !self.raised(record.level()) {
+            return;
+        }
+
+        let Some(sink) = &self.sink else {
+            if self.filter.matches(record) {
+                self.filter.log(record);
+            } else {
+                // env_logger 会按自己的过滤条件丢掉这条日志，只能自己输出
+                eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+            }
+            return;
+        };
+
+        let sink = sink.lock().unwrap();
+        if sink.send(record).is_err() {
+            // 套接字暂时不可用时不能丢掉日志
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        self.filter.flush();
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// RFC 3164 格式，facility 为 daemon；syslog 没有结构化字段，事件信息放在消息前
+fn syslog_datagram(level: Level, message: &str, event: Option<&(u64, PathBuf)>) -> Vec<u8> {
+    const FACILITY_DAEMON: u8 = 3;
+    let priority = FACILITY_DAEMON * 8 + severity(level);
+
+    let mut line = format!("<{}>{}[{}]: ", priority, IDENTIFIER, std::process::id());
+    if let Some((seqnum, devpath)) = event {
+        line.push_str(&format!("seqnum={} devpath={}: ", seqnum, devpath.display()));
+    }
+    line.push_str(message);
+    line.into_bytes()
+}
+
+// journald 原生协议：每行一个 KEY=value，值含换行时改用长度前缀的二进制格式
+fn journal_datagram(
+    level: Level,
+    target: &str,
+    message: &str,
+    event: Option<&(u64, PathBuf)>,
+) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    let mut field = |key: &str, value: &str| {
+        datagram.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    };
+
+    field("MESSAGE", message);
+    field("PRIORITY", &severity(level).to_string());
+    field("SYSLOG_IDENTIFIER", IDENTIFIER);
+    field("RUST_UDEV_MODULE", target);
+    if let Some((seqnum, devpath)) = event {
+        field("UDEV_SEQNUM", &seqnum.to_string());
+        field("UDEV_DEVPATH", &devpath.to_string_lossy());
+    }
+
+    datagram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_filter_modules_get_the_crate_prefix() {
+        let directives = parse_log_filter("rules::matcher=debug, rust_udev::udevd=trace,warn").unwrap();
+        assert_eq!(
+            directives,
+            vec![
+                LogDirective {
+                    module: Some("rust_udev::rules::matcher".to_string()),
+                    level: LevelFilter::Debug,
+                },
+                LogDirective {
+                    module: Some("rust_udev::udevd".to_string()),
+                    level: LevelFilter::Trace,
+                },
+                LogDirective {
+                    module: None,
+                    level: LevelFilter::Warn,
+                },
+            ]
+        );
+        assert!(parse_log_filter("=debug").is_err());
+        assert!(parse_log_filter("rules=loud").is_err());
+    }
+
+    #[test]
+    fn longest_matching_module_wins() {
+        let directives = parse_log_filter("warn,rules=info,rules::matcher=trace").unwrap();
+        assert_eq!(most_specific(&directives, "rust_udev::rules::matcher"), Some(LevelFilter::Trace));
+        assert_eq!(most_specific(&directives, "rust_udev::rules::parser"), Some(LevelFilter::Info));
+        // 只按完整的路径段匹配
+        assert_eq!(most_specific(&directives, "rust_udev::rulesets"), Some(LevelFilter::Warn));
+        assert_eq!(most_specific(&directives, "other_crate"), Some(LevelFilter::Warn));
+    }
+
+    #[test]
+    fn empty_filter_clears_the_directives() {
+        assert_eq!(parse_log_filter(" , ").unwrap(), Vec::new());
+
+        set_log_filter(parse_log_filter("udevd=debug").unwrap());
+        assert_eq!(filter_level("rust_udev::udevd"), Some(LevelFilter::Debug));
+        set_log_filter(parse_log_filter("").unwrap());
+        assert_eq!(filter_level("rust_udev::udevd"), None);
+    }
+}
@@ -0,0 +1,58 @@
+// src/lru.rs
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// 记录键的最近使用顺序，各缓存用它配合自己的存储结构实现 LRU 淘汰
+#[derive(Debug)]
+pub struct LruIndex<K> {
+    order: BTreeMap<u64, K>,
+    stamps: HashMap<K, u64>,
+    next: u64,
+}
+
+impl<K> Default for LruIndex<K> {
+    fn default() -> Self {
+        Self {
+            order: BTreeMap::new(),
+            stamps: HashMap::new(),
+            next: 0,
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> LruIndex<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 把键标记为最近使用，不存在时加入
+    pub fn touch(&mut self, key: &K) {
+        if let Some(old) = self.stamps.insert(key.clone(), self.next) {
+            self.order.remove(&old);
+        }
+        self.order.insert(self.next, key.clone());
+        self.next += 1;
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(stamp) = self.stamps.remove(key) {
+            self.order.remove(&stamp);
+        }
+    }
+
+    /// 取出最久未使用的键
+    pub fn pop_oldest(&mut self) -> Option<K> {
+        let (_, key) = self.order.pop_first()?;
+        self.stamps.remove(&key);
+        Some(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stamps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stamps.is_empty()
+    }
+}
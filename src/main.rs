@@ -1,64 +1,719 @@
-///  src/actions.rs
+// src/main.rs
 
-mod monitor;
-use rust_udev::udevd::start_udevd;
-use rust_udev::udevadm::udevadm_cli;
-use clap::Command;
-use log::{info, error};
+use std::path::PathBuf;
+use std::time::Duration;
+use rust_udev::actions::ResolveNames;
+use rust_udev::builtins::security_token::valid_group_name;
+use rust_udev::config::{Config, CONFIG_PATH};
+use rust_udev::control::ControlCommand;
+use rust_udev::device::DeviceAction;
+use rust_udev::logging::{self, parse_level, parse_log_filter, LogDirective};
+use rust_udev::monitor::MonitorView;
+use rust_udev::rules::parser::{parse_delay, parse_size};
+use rust_udev::transliterate::Transliteration;
+use rust_udev::stats::{INCOMPLETE_PATH, STATS_PATH};
+use rust_udev::strict::StrictError;
+use rust_udev::udevd::{embedded_rules, start_udevd, DaemonOptions};
+use rust_udev::udevadm::{
+    udevadm_control, udevadm_debug_dump, udevadm_info, udevadm_info_attribute_walk, udevadm_info_export_db, udevadm_info_history,
+    udevadm_info_provenance, udevadm_info_recursive, udevadm_monitor, udevadm_run_failures, udevadm_settle, udevadm_stats,
+    udevadm_test, udevadm_test_builtin, udevadm_trigger, udevadm_verify,
+};
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use log::{info, error, warn};
 
-fn run_udevadm() {
-    // 处理 udevadm 子命令的逻辑
-    let matches = Command::new("rust_udev")
+fn build_cli() -> Command {
+    let command = Command::new("rust_udev")
         .version("1.0")
         .about("udev-like system in Rust")
+        .arg(
+            Arg::new("strict")
+                .help("Abort daemon startup on invalid rules, missing rules directory or unwritable dev root")
+                .long("strict")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("config")
+                .help("Read daemon settings from this file instead of /etc/udev/udev.conf")
+                .long("config")
+                .value_name("FILE")
+                .global(true)
+                .value_parser(clap::value_parser!(String)),
+        )
+        .arg(
+            Arg::new("dev-root")
+                .help("Directory for device nodes and symlinks; overrides dev_root in udev.conf")
+                .long("dev-root")
+                .value_name("DIR")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("rules-dir")
+                .help("Rules directory, lowest priority first; may be repeated, replaces rules_dirs in udev.conf")
+                .long("rules-dir")
+                .value_name("DIR")
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("log-level")
+                .help("Log level (err, warning, info, debug, trace); overrides RUST_LOG and udev_log in udev.conf")
+                .long("log-level")
+                .value_name("LEVEL")
+                .value_parser(|value: &str| parse_level(value).ok_or("expected err, warning, info, debug or trace")),
+        )
+        .arg(
+            Arg::new("children-max")
+                .help("Number of events processed at the same time; overrides children_max in udev.conf")
+                .long("children-max")
+                .value_name("N")
+                .value_parser(|value: &str| value.parse::<usize>().ok().filter(|&n| n > 0).ok_or("expected a positive number")),
+        )
+        .arg(
+            Arg::new("exec-delay")
+                .help("Wait this long before starting each RUN command, e.g. 2s; overrides exec_delay in udev.conf")
+                .long("exec-delay")
+                .value_name("DELAY")
+                .value_parser(|value: &str| parse_delay(value).ok_or("expected a duration such as 500ms, 2s or 1m")),
+        )
+        .arg(
+            Arg::new("event-timeout")
+                .help("Kill PROGRAM and RUN commands running longer than this; overrides event_timeout in udev.conf")
+                .long("event-timeout")
+                .value_name("TIMEOUT")
+                .value_parser(|value: &str| {
+                    parse_delay(value)
+                        .filter(|timeout| !timeout.is_zero())
+                        .ok_or("expected a non-zero duration such as 30s or 3m")
+                }),
+        )
+        .arg(
+            Arg::new("max-rules-file-size")
+                .help("Skip rule files larger than this, e.g. 512K or 8M; overrides max_rules_file_size in udev.conf")
+                .long("max-rules-file-size")
+                .value_name("SIZE")
+                .value_parser(|value: &str| parse_size(value).filter(|&size| size > 0).ok_or("expected a size such as 512K or 8M")),
+        )
+        .arg(
+            Arg::new("no-coldplug")
+                .help("Do not synthesize add events for devices that already exist at startup")
+                .long("no-coldplug")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-db-entries")
+                .help("Maximum number of devices whose event history is kept in memory; present devices are never evicted")
+                .long("max-db-entries")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("max-tracked-devices")
+                .help("Maximum number of devices tracked for subsystem statistics")
+                .long("max-tracked-devices")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("max-eval-cache-entries")
+                .help("Maximum number of devices whose rule match results are cached, 0 disables the cache")
+                .long("max-eval-cache-entries")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("rule")
+                .help("Add a rule without a rules file, e.g. --rule 'SUBSYSTEM==\"tty\", GROUP=\"dialout\"'; may be repeated, added after rule= lines in udev.conf")
+                .long("rule")
+                .value_name("RULE")
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(String)),
+        )
+        .arg(
+            Arg::new("security-token-group")
+                .help("Enable built-in rules giving GROUP access to FIDO tokens and smartcard readers")
+                .long("security-token-group")
+                .value_name("GROUP")
+                .value_parser(|value: &str| {
+                    valid_group_name(value).then(|| value.to_string()).ok_or("expected a group name or numeric GID")
+                }),
+        )
+        .arg(
+            Arg::new("resolve-names")
+                .help("When to resolve OWNER/GROUP names: at rule load (early), per event (late) or never; overrides resolve_names in udev.conf")
+                .long("resolve-names")
+                .value_parser(["early", "late", "never"]),
+        )
+        .arg(
+            Arg::new("id-transliteration")
+                .help("How unsafe characters in vendor, model and serial strings are written in by-id names: replaced with '_' like systemd-udevd, stripped, or escaped as \\xNN; overrides id_transliteration in udev.conf")
+                .long("id-transliteration")
+                .value_parser(["replace", "strip", "escape"]),
+        )
+        .arg(
+            Arg::new("resync-on-gap")
+                .help("When kernel events are lost (a gap in SEQNUM), re-trigger devices of the affected subsystems and synthesize removes for devices that disappeared; also enabled by resync_on_gap=yes in udev.conf")
+                .long("resync-on-gap")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("broadcast-events")
+                .help("Broadcast processed events to the udev netlink multicast group for libudev listeners; do not enable while systemd-udevd is running, its listeners would see every event twice; also enabled by broadcast_events=yes in udev.conf")
+                .long("broadcast-events")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore-interfaces")
+                .help("Space-separated globs of network interfaces whose events are counted but not processed (default: lo, veth*, docker and podman bridges); pass \"\" to process all interfaces; overrides ignore_interfaces in udev.conf")
+                .long("ignore-interfaces")
+                .value_name("GLOBS")
+                .value_parser(clap::value_parser!(String)),
+        )
+        .arg(
+            Arg::new("trace-rules")
+                .help("Log which rules each event was checked against, why they did not match and what matching rules assigned; also enabled by trace_rules=yes in udev.conf")
+                .long("trace-rules")
+                .action(ArgAction::SetTrue),
+        )
         .subcommand(
             Command::new("udevadm")
                 .about("udevadm utility for device management")
-                .arg(
-                    clap::Arg::new("path")
-                        .help("The device path to query")
-                        .required(true)
-                        .value_parser(clap::value_parser!(String))
-                        .long("path")
-                        .short('p'),
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("info")
+                        .about("Query device information")
+                        .arg(
+                            Arg::new("path")
+                                .help("The device to query: sysfs path, device node or device number such as b8:0")
+                                .required_unless_present_any([
+                                    "device", "devnum", "stats", "history", "failed-runs", "provenance", "export-db",
+                                ])
+                                .value_parser(clap::value_parser!(String))
+                                .long("path")
+                                .short('p'),
+                        )
+                        .arg(
+                            Arg::new("device")
+                                .help("Same as --path")
+                                .value_name("DEVICE")
+                                .value_parser(clap::value_parser!(String))
+                                .conflicts_with_all(["path", "stats", "history", "provenance", "export-db", "failed-runs"]),
+                        )
+                        .arg(
+                            Arg::new("devnum")
+                                .help("Query the device with this number, b for block and c for char devices, e.g. b8:0")
+                                .long("devnum")
+                                .short('d')
+                                .value_name("TYPE MAJOR:MINOR")
+                                .value_parser(clap::value_parser!(String))
+                                .conflicts_with_all(["path", "device", "stats", "history", "provenance", "export-db", "failed-runs"]),
+                        )
+                        .group(ArgGroup::new("target").args(["path", "device", "devnum"]))
+                        .arg(
+                            Arg::new("stats")
+                                .help("Show per-subsystem counts of devices tracked by the daemon")
+                                .long("stats")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("path"),
+                        )
+                        .arg(
+                            Arg::new("history")
+                                .help("Show the most recent events the daemon saw for a device, even one already removed")
+                                .long("history")
+                                .value_name("DEVICE")
+                                .value_parser(clap::value_parser!(String))
+                                .conflicts_with_all(["path", "stats"]),
+                        )
+                        .arg(
+                            Arg::new("provenance")
+                                .help("Show which rule file and line set the device's node name, each symlink, OWNER, GROUP and MODE")
+                                .long("provenance")
+                                .value_name("DEVICE")
+                                .value_parser(clap::value_parser!(String))
+                                .conflicts_with_all(["path", "stats", "history"]),
+                        )
+                        .arg(
+                            Arg::new("export-db")
+                                .help("Show every device, using the properties and tags the daemon recorded and sysfs for the rest")
+                                .long("export-db")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with_all(["path", "stats", "history", "provenance"]),
+                        )
+                        .arg(
+                            Arg::new("tag-match")
+                                .help("With --export-db, only show devices carrying this tag; may be repeated")
+                                .long("tag-match")
+                                .value_name("TAG")
+                                .action(ArgAction::Append)
+                                .value_parser(clap::value_parser!(String))
+                                .requires("export-db"),
+                        )
+                        .arg(
+                            Arg::new("failed-runs")
+                                .help("Show RUN commands that exited with an error, with the event that started them")
+                                .long("failed-runs")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with_all(["path", "stats", "history"]),
+                        )
+                        .arg(
+                            Arg::new("recursive")
+                                .help("Also show every descendant of the device")
+                                .long("recursive")
+                                .short('r')
+                                .action(ArgAction::SetTrue)
+                                .requires("target"),
+                        )
+                        .arg(
+                            Arg::new("attribute-walk")
+                                .help("Show the sysfs attributes of the device and of every parent, as rule match keys")
+                                .long("attribute-walk")
+                                .short('a')
+                                .action(ArgAction::SetTrue)
+                                .requires("target")
+                                .conflicts_with("recursive"),
+                        )
+                        .arg(
+                            Arg::new("verbose")
+                                .help("Annotate symlinks with their priority and owner")
+                                .long("verbose")
+                                .short('v')
+                                .action(ArgAction::SetTrue)
+                                .requires("target"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("monitor")
+                        .about("Listen to kernel uevents or to events processed by the daemon")
+                        .arg(
+                            Arg::new("subsystem-device-count")
+                                .help("Show a live per-subsystem dashboard instead of single events")
+                                .long("subsystem-device-count")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("udev")
+                                .help("Show events after rule processing instead of raw kernel uevents")
+                                .short('u')
+                                .long("udev")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("property")
+                                .help("Print event properties; with --udev, properties added or changed by rules are marked (udev)")
+                                .short('p')
+                                .long("property")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("control")
+                        .about("Send commands to the running daemon")
+                        .arg(
+                            Arg::new("log-level")
+                                .help("Change the daemon log level (err, warning, info, debug, trace)")
+                                .short('l')
+                                .long("log-level")
+                                .value_name("LEVEL")
+                                .value_parser(|value: &str| parse_level(value).ok_or("expected err, warning, info, debug or trace")),
+                        )
+                        .arg(
+                            Arg::new("log-filter")
+                                .help("Set per-module log levels, e.g. rules::matcher=debug,actions=info; empty clears them")
+                                .long("log-filter")
+                                .value_name("FILTER")
+                                .value_parser(|value: &str| parse_log_filter(value)),
+                        )
+                        .arg(
+                            Arg::new("stop-exec-queue")
+                                .help("Stop processing events; new events are queued")
+                                .short('s')
+                                .long("stop-exec-queue")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("start-exec-queue")
+                                .help("Resume processing queued events")
+                                .short('S')
+                                .long("start-exec-queue")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("reload")
+                                .help("Reload rules now")
+                                .short('R')
+                                .long("reload")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("ping")
+                                .help("Wait until the daemon has handled the preceding commands")
+                                .long("ping")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("timeout")
+                                .help("Give up waiting for the daemon after this long, e.g. 10s")
+                                .short('t')
+                                .long("timeout")
+                                .value_name("TIMEOUT")
+                                .default_value("60s")
+                                .value_parser(|value: &str| {
+                                    parse_delay(value)
+                                        .filter(|timeout| !timeout.is_zero())
+                                        .ok_or("expected a non-zero duration such as 5s")
+                                }),
+                        )
+                        .group(
+                            ArgGroup::new("commands")
+                                .args(["log-level", "log-filter", "stop-exec-queue", "start-exec-queue", "reload", "ping"])
+                                .multiple(true)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    Command::new("settle")
+                        .about("Wait until the daemon has processed all queued events")
+                        .arg(
+                            Arg::new("timeout")
+                                .help("Give up after this long, e.g. 30s; 0 only checks whether the queue is empty")
+                                .short('t')
+                                .long("timeout")
+                                .value_name("TIMEOUT")
+                                .default_value("120s")
+                                .value_parser(|value: &str| parse_delay(value).ok_or("expected a duration such as 30s or 2m")),
+                        ),
+                )
+                .subcommand(
+                    Command::new("debug-dump")
+                        .about("Show recent events that were missing SUBSYSTEM or ACTION"),
+                )
+                .subcommand(
+                    Command::new("test")
+                        .about("Simulate an event against the rules and print what would happen, without applying it")
+                        .arg(
+                            Arg::new("syspath")
+                                .help("sysfs path or device node of the device")
+                                .required(true)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            Arg::new("action")
+                                .help("ACTION of the simulated event")
+                                .long("action")
+                                .short('a')
+                                .default_value("add")
+                                .value_parser(DeviceAction::NAMES),
+                        ),
+                )
+                .subcommand(
+                    Command::new("test-builtin")
+                        .about("Run a single builtin against a device and print the properties it would set")
+                        .arg(
+                            Arg::new("command")
+                                .help("Builtin name, optionally followed by its arguments, e.g. \"usb_id\"")
+                                .required(true)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            Arg::new("syspath")
+                                .help("sysfs path or device node of the device")
+                                .required(true)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            Arg::new("action")
+                                .help("ACTION to present to the builtin")
+                                .long("action")
+                                .short('a')
+                                .default_value("add")
+                                .value_parser(DeviceAction::NAMES),
+                        ),
+                )
+                .subcommand(
+                    Command::new("trigger")
+                        .about("Ask the kernel to emit an event again for the given devices")
+                        .arg(
+                            Arg::new("devices")
+                                .help("sysfs path, device node or device number such as b8:0")
+                                .value_name("DEVICE")
+                                .required(true)
+                                .num_args(1..)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            Arg::new("action")
+                                .help("Action to request")
+                                .long("action")
+                                .short('c')
+                                .default_value("change")
+                                .value_parser(DeviceAction::NAMES),
+                        )
+                        .arg(
+                            Arg::new("uuid")
+                                .help("Tag each event with a random SYNTH_UUID and print it next to the device")
+                                .long("uuid")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("Check rules files for errors without running the daemon")
+                        .arg(
+                            Arg::new("path")
+                                .help("Rules file or directory, or - to read rules from stdin (defaults to the standard rules directories)")
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            Arg::new("security")
+                                .help("Also report rules that run programs, write sysfs or grant root-equivalent groups")
+                                .long("security")
+                                .action(ArgAction::SetTrue),
+                        ),
                 ),
-        )
-        .get_matches();
+        );
 
-    if let Some(("udevadm", sub_matches)) = matches.subcommand() {
-        if let Some(device_path) = sub_matches.get_one::<String>("path") {
-            // 执行 udevadm 子命令并处理结果
-            match udevadm_cli(device_path) {
-                Ok(_) => {
-                    info!("Successfully executed udevadm command for device {}", device_path);
-                }
-                Err(e) => {
-                    error!("Error while running udevadm command: {}", e);
+    #[cfg(feature = "http-status")]
+    let command = command.arg(
+        Arg::new("http-status")
+            .help("Serve read-only JSON status (/devices, /stats, /queue, /rules) on a loopback address")
+            .long("http-status")
+            .value_name("ADDR")
+            .num_args(0..=1)
+            .default_missing_value(rust_udev::http_status::DEFAULT_ADDR)
+            .value_parser(clap::value_parser!(std::net::SocketAddr)),
+    );
+
+    command
+}
+
+fn run_udevadm(sub_matches: &ArgMatches, config: &Config, options: &DaemonOptions) {
+    // 执行 udevadm 子命令并处理结果
+    let result = match sub_matches.subcommand() {
+        Some(("info", info_matches)) => {
+            if info_matches.get_flag("stats") {
+                udevadm_stats(STATS_PATH)
+            } else if info_matches.get_flag("failed-runs") {
+                udevadm_run_failures()
+            } else if let Some(device_path) = info_matches.get_one::<String>("history") {
+                udevadm_info_history(device_path)
+            } else if info_matches.get_flag("export-db") {
+                let tags: Vec<String> =
+                    info_matches.get_many::<String>("tag-match").into_iter().flatten().cloned().collect();
+                udevadm_info_export_db(&tags, &options.dev_root)
+            } else if let Some(device_path) = info_matches.get_one::<String>("provenance") {
+                udevadm_info_provenance(device_path)
+            } else if let Some(device_path) = ["path", "device", "devnum"]
+                .into_iter()
+                .find_map(|id| info_matches.get_one::<String>(id))
+            {
+                let verbose = info_matches.get_flag("verbose");
+                if info_matches.get_flag("attribute-walk") {
+                    udevadm_info_attribute_walk(device_path)
+                } else if info_matches.get_flag("recursive") {
+                    udevadm_info_recursive(device_path, &options.dev_root, verbose)
+                } else {
+                    udevadm_info(device_path, &options.dev_root, verbose)
                 }
+            } else {
+                return;
+            }
+        }
+        Some(("monitor", monitor_matches)) => {
+            let view = if monitor_matches.get_flag("udev") {
+                MonitorView::Udev
+            } else {
+                MonitorView::Kernel
+            };
+            udevadm_monitor(
+                monitor_matches.get_flag("subsystem-device-count"),
+                view,
+                monitor_matches.get_flag("property"),
+            )
+        }
+        Some(("control", control_matches)) => {
+            // 固定按这个顺序发送，ping 放在最后确认前面的命令都已执行
+            let mut commands = Vec::new();
+            if let Some(level) = control_matches.get_one::<log::LevelFilter>("log-level") {
+                commands.push(ControlCommand::SetLogLevel(*level));
+            }
+            if let Some(directives) = control_matches.get_one::<Vec<LogDirective>>("log-filter") {
+                commands.push(ControlCommand::SetLogFilter(directives.clone()));
+            }
+            if control_matches.get_flag("stop-exec-queue") {
+                commands.push(ControlCommand::StopExecQueue);
+            }
+            if control_matches.get_flag("start-exec-queue") {
+                commands.push(ControlCommand::StartExecQueue);
+            }
+            if control_matches.get_flag("reload") {
+                commands.push(ControlCommand::Reload);
+            }
+            if control_matches.get_flag("ping") {
+                commands.push(ControlCommand::Ping);
             }
+            let timeout = control_matches.get_one::<Duration>("timeout").copied().unwrap_or(Duration::from_secs(60));
+            udevadm_control(&commands, timeout)
+        }
+        Some(("settle", settle_matches)) => {
+            udevadm_settle(settle_matches.get_one::<Duration>("timeout").copied().unwrap_or(Duration::from_secs(120)))
+        }
+        Some(("debug-dump", _)) => udevadm_debug_dump(INCOMPLETE_PATH),
+        Some(("test", test_matches)) => {
+            let get = |id: &str| test_matches.get_one::<String>(id).map(String::as_str).unwrap_or_default();
+            udevadm_test(
+                get("syspath"),
+                get("action"),
+                &options.rules_dirs,
+                embedded_rules(options),
+                &options.rule_options(),
+            )
+        }
+        Some(("test-builtin", builtin_matches)) => {
+            // 三个参数都是必需的或有默认值
+            let get = |id: &str| builtin_matches.get_one::<String>(id).map(String::as_str).unwrap_or_default();
+            // 与守护进程使用同样的设置，生成的名字相同
+            udevadm_test_builtin(
+                get("command"),
+                get("syspath"),
+                get("action"),
+                &options.dev_root,
+                &options.rule_options().builtins,
+            )
+        }
+        Some(("trigger", trigger_matches)) => {
+            let devices: Vec<String> =
+                trigger_matches.get_many::<String>("devices").into_iter().flatten().cloned().collect();
+            let action = trigger_matches.get_one::<String>("action").map(String::as_str).unwrap_or("change");
+            udevadm_trigger(&devices, action, trigger_matches.get_flag("uuid"))
+        }
+        Some(("verify", verify_matches)) => {
+            udevadm_verify(
+                verify_matches.get_one::<String>("path").map(String::as_str),
+                &config.rules_dirs,
+                verify_matches.get_flag("security"),
+                options.max_rules_file_size,
+            )
+        }
+        _ => return,
+    };
+
+    match result {
+        Ok(_) => {
+            info!("Successfully executed udevadm command");
+        }
+        Err(e) => {
+            error!("Error while running udevadm command: {}", e);
+            std::process::exit(1);
         }
     }
 }
 
-fn start_udevd_daemon() {
+fn start_udevd_daemon(options: DaemonOptions) {
     // 启动守护进程
     info!("Starting udevd daemon...");
-    if let Err(e) = start_udevd() {
+    if let Err(e) = start_udevd(&options) {
+        // strict 模式下输出机器可读的摘要，便于镜像构建脚本检查
+        if let Some(strict) = e.downcast_ref::<StrictError>() {
+            println!("{}", strict.summary_json());
+        }
         error!("Failed to start udevd daemon: {}", e);
+        std::process::exit(1);
     } else {
-        info!("udevd daemon started successfully.");
+        info!("udevd daemon stopped.");
+    }
+}
+
+fn init_daemon_logging(config: &Config, matches: &ArgMatches) {
+    // 命令行 --log-level 优先，其次是 RUST_LOG，最后是配置文件的 udev_log=
+    let level = matches
+        .get_one::<log::LevelFilter>("log-level")
+        .copied()
+        .or_else(|| config.log_level.filter(|_| std::env::var_os("RUST_LOG").is_none()));
+    if let Err(e) = logging::init_with_level(config.log_target, level) {
+        eprintln!("Failed to initialize logging: {}", e);
+    }
+}
+
+// 读取失败时使用默认配置；无效项在日志初始化之后报告
+fn load_config(path: &str) -> (Config, Vec<String>) {
+    Config::load(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        (Config::default(), Vec::new())
+    })
+}
+
+// 命令行参数覆盖配置文件中的同名设置；--rule 加在 rule= 之后，--trace-rules 只能打开
+fn daemon_options(config: &Config, matches: &ArgMatches) -> DaemonOptions {
+    let mut options = DaemonOptions::from_config(config);
+    options.strict = matches.get_flag("strict");
+    options.coldplug = !matches.get_flag("no-coldplug");
+    if let Some(&capacity) = matches.get_one::<usize>("max-db-entries") {
+        options.db_capacity = capacity;
     }
+    if let Some(&capacity) = matches.get_one::<usize>("max-tracked-devices") {
+        options.stats_capacity = capacity;
+    }
+    if let Some(&capacity) = matches.get_one::<usize>("max-eval-cache-entries") {
+        options.eval_cache_capacity = capacity;
+    }
+    options.security_token_group = matches.get_one::<String>("security-token-group").cloned();
+    if let Some(value) = matches.get_one::<String>("resolve-names") {
+        options.resolve_names = ResolveNames::parse(value).unwrap_or_default();
+    }
+    if let Some(value) = matches.get_one::<String>("id-transliteration") {
+        options.transliteration = Transliteration::parse(value).unwrap_or_default();
+    }
+    options.resync_on_gap |= matches.get_flag("resync-on-gap");
+    options.broadcast_events |= matches.get_flag("broadcast-events");
+    if let Some(patterns) = matches.get_one::<String>("ignore-interfaces") {
+        options.ignore_interfaces = patterns.split_whitespace().map(String::from).collect();
+    }
+    options.trace_rules |= matches.get_flag("trace-rules");
+    options.inline_rules.extend(matches.get_many::<String>("rule").into_iter().flatten().cloned());
+    if let Some(dev_root) = matches.get_one::<PathBuf>("dev-root") {
+        options.dev_root = dev_root.clone();
+    }
+    if let Some(dirs) = matches.get_many::<PathBuf>("rules-dir") {
+        options.rules_dirs = dirs.cloned().collect();
+    }
+    if let Some(&children) = matches.get_one::<usize>("children-max") {
+        options.children_max = Some(children);
+    }
+    if let Some(&delay) = matches.get_one::<Duration>("exec-delay") {
+        options.exec_delay = delay;
+    }
+    if let Some(&timeout) = matches.get_one::<Duration>("event-timeout") {
+        options.event_timeout = timeout;
+    }
+    if let Some(&size) = matches.get_one::<u64>("max-rules-file-size") {
+        options.max_rules_file_size = size;
+    }
+    #[cfg(feature = "http-status")]
+    {
+        options.http_status = matches.get_one::<std::net::SocketAddr>("http-status").copied();
+    }
+    options
 }
 
 fn main() {
-    // 初始化日志记录
-    env_logger::init();
-    info!("🚀 Starting rust_udev system...");
+    let matches = build_cli().get_matches();
+    let config_path = matches.get_one::<String>("config").map_or(CONFIG_PATH, String::as_str);
+    let (config, problems) = load_config(config_path);
 
-    // 如果有命令行输入子命令，就执行 udevadm，否则启动守护进程
-    if std::env::args().any(|arg| arg == "udevadm") {
-        run_udevadm();
-    } else {
-        start_udevd_daemon();
+    // 如果有 udevadm 子命令就执行它，否则启动守护进程
+    match matches.subcommand() {
+        Some(("udevadm", sub_matches)) => {
+            env_logger::init();
+            for problem in &problems {
+                warn!("Ignoring {} in {}", problem, config_path);
+            }
+            // udevadm 与守护进程使用同一套选项，包括设备根目录
+            run_udevadm(sub_matches, &config, &daemon_options(&config, &matches))
+        }
+        _ => {
+            init_daemon_logging(&config, &matches);
+            for problem in &problems {
+                warn!("Ignoring {} in {}", problem, config_path);
+            }
+            info!("🚀 Starting rust_udev system...");
+            start_udevd_daemon(daemon_options(&config, &matches))
+        }
     }
 }
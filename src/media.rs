@@ -0,0 +1,152 @@
+// src/media.rs
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::*;
+
+use crate::cancel::CancellationToken;
+use crate::device::{DeviceAction, UEventDevice};
+use crate::plan::EnvPairs;
+
+pub const MEDIA_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub fn is_removable(syspath: &Path) -> bool {
+    fs::read_to_string(syspath.join("removable")).is_ok_and(|s| s.trim() == "1")
+}
+
+/// 以容量是否为 0 判断介质是否存在（光驱、读卡器在无介质时 size 为 0）
+pub fn media_present(syspath: &Path) -> bool {
+    fs::read_to_string(syspath.join("size"))
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .is_some_and(|size| size > 0)
+}
+
+/// 可移动介质设备需要设置的属性
+pub fn media_properties(device: &UEventDevice) -> EnvPairs {
+    let mut props = EnvPairs::new();
+    let syspath = device.syspath();
+    if !device.is_block_device() || !is_removable(&syspath) {
+        return props;
+    }
+
+    let present = media_present(&syspath);
+    let flag = if present { "1" } else { "0" };
+    props.push(("ID_MEDIA_PRESENT".to_string(), flag.to_string()));
+
+    if device.kernel().is_some_and(|k| k.starts_with("sr")) {
+        props.push(("ID_CDROM".to_string(), "1".to_string()));
+        if present {
+            props.push(("ID_CDROM_MEDIA".to_string(), "1".to_string()));
+        }
+    }
+
+    props
+}
+
+/// 轮询 USB 读卡器、光驱等可移动设备的介质状态，变化时向 sysfs 写入 change 让内核重新发出事件
+#[derive(Debug)]
+pub struct MediaWatcher {
+    tracked: Arc<Mutex<HashMap<PathBuf, bool>>>,
+    token: CancellationToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MediaWatcher {
+    /// 启动轮询线程，token 被取消后线程退出；drop 时取消 token 并等待线程退出
+    pub fn start(interval: Duration, token: CancellationToken) -> Self {
+        let tracked = Arc::new(Mutex::new(HashMap::new()));
+
+        let tracked_clone = tracked.clone();
+        let thread_token = token.clone();
+        let thread = thread::Builder::new()
+            .name("media".to_string())
+            .spawn(move || {
+                while !thread_token.wait_timeout(interval) {
+                    Self::poll_once(&tracked_clone);
+                }
+            })
+            .map_err(|e| error!("Failed to start the media poll thread: {}", e))
+            .ok();
+
+        Self { tracked, token, thread }
+    }
+
+    /// 根据事件更新跟踪列表：USB 设备上的可移动磁盘在 add/change 时加入，remove 时移除
+    pub fn update(&self, device: &UEventDevice) {
+        if !device.is_block_device() || device.devtype() != Some("disk") {
+            return;
+        }
+
+        let syspath = device.syspath();
+        let mut tracked = self.tracked.lock().unwrap();
+
+        match device.action() {
+            DeviceAction::Remove if tracked.remove(&syspath).is_some() => {
+                debug!("Stopped watching media on {:?}", syspath);
+            }
+            DeviceAction::Remove => {}
+            _ if is_removable(&syspath) && on_usb_device(device) => {
+                let present = media_present(&syspath);
+                if tracked.insert(syspath.clone(), present).is_none() {
+                    info!("Watching removable media on {:?}", syspath);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn poll_once(tracked: &Mutex<HashMap<PathBuf, bool>>) {
+        let mut tracked = tracked.lock().unwrap();
+
+        for (syspath, present) in tracked.iter_mut() {
+            let now = media_present(syspath);
+            if now == *present {
+                continue;
+            }
+
+            *present = now;
+            info!(
+                "Media {} on {:?}, triggering change",
+                if now { "inserted" } else { "removed" },
+                syspath
+            );
+
+            if let Err(e) = fs::write(syspath.join("uevent"), "change") {
+                warn!("Failed to trigger change on {:?}: {}", syspath, e);
+            }
+        }
+    }
+}
+
+impl Drop for MediaWatcher {
+    fn drop(&mut self) {
+        self.token.cancel();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// 磁盘是否挂在某个 USB 设备之下
+fn on_usb_device(device: &UEventDevice) -> bool {
+    std::iter::successors(device.parent(), UEventDevice::parent).any(|parent| parent.is_usb_device())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropping_the_watcher_stops_the_poll_thread() {
+        let token = CancellationToken::new();
+        let watcher = MediaWatcher::start(Duration::from_secs(3600), token.clone());
+        drop(watcher);
+        assert!(token.is_cancelled());
+    }
+}
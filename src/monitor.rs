@@ -1,21 +1,98 @@
 // src/monitor.rs
+use nix::cmsg_space;
 use nix::sys::socket::{
-    socket, bind, recv, AddressFamily, SockType, SockFlag,
+    socket, bind, recvmsg, sendto, setsockopt, sockopt, AddressFamily, ControlMessageOwned, SockType, SockFlag,
     NetlinkAddr, MsgFlags, SockProtocol
 };
+use nix::sys::time::TimeSpec;
 use nix::unistd::close;
-use std::io;
+use std::io::{self, IoSliceMut};
 use std::os::unix::io::{RawFd, AsRawFd};
-use std::collections::HashMap;
-use log::{info, warn, error};
+use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use log::{debug, info, warn, error};
+
+use crate::clock::{system_clock, Clock, ReceiveTime};
+use crate::device::UEventDevice;
+
+// 内核事件和 udev 处理后事件的多播组
+const KERNEL_GROUP: u32 = 1;
+const UDEV_GROUP: u32 = 2;
+
+// libudev 消息头：前缀 "libudev\0"，随后是网络字节序的魔数
+const UDEV_PREFIX: &[u8; 8] = b"libudev\0";
+const UDEV_MAGIC: u32 = 0xfeed_cafe;
+const UDEV_HEADER_SIZE: usize = 40;
+
+// 处理后视图中等待 udev 消息的内核事件数上限，超出时丢弃最早的
+const MAX_PENDING_KERNEL_EVENTS: usize = 256;
+
+/// 订阅时选择的视图
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MonitorView {
+    /// 只接收内核原始事件
+    #[default]
+    Kernel,
+    /// 接收守护进程处理后的事件，内核字段和规则添加的属性合并在一起，并标明来源
+    Udev,
+}
+
+/// 属性的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertySource {
+    /// 内核事件中原有的字段，值没有被规则修改
+    Kernel,
+    /// 守护进程添加或修改的属性
+    Udev,
+}
+
+/// 收到的事件：设备包含全部属性，sources 记录每个属性的来源
+#[derive(Debug)]
+pub struct MonitorEvent {
+    pub device: UEventDevice,
+    sources: HashMap<String, PropertySource>,
+}
+
+impl MonitorEvent {
+    pub fn source(&self, key: &str) -> Option<PropertySource> {
+        self.sources.get(key).copied()
+    }
+
+    /// 来自 source 的属性，按名字排序
+    pub fn properties_from(&self, source: PropertySource) -> BTreeMap<&str, &str> {
+        self.device
+            .properties()
+            .iter()
+            .filter(|(key, _)| self.source(key) == Some(source))
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+}
 
 pub struct UEventMonitor {
     fd: RawFd,
+    view: MonitorView,
+    // 处理后视图中先到达的内核事件，按 SEQNUM 等待对应的 udev 消息
+    kernel_events: Mutex<BTreeMap<u64, HashMap<String, String>>>,
+    // 事件到达时间取自这个时钟
+    clock: Arc<dyn Clock>,
 }
 
-#[allow(dead_code)]
 impl UEventMonitor {
+    /// 订阅内核事件
     pub fn new() -> io::Result<Self> {
+        Self::with_view(MonitorView::Kernel)
+    }
+
+    /// Udev 视图同时订阅两个多播组：内核事件只用于判断属性来源，不单独返回
+    pub fn with_view(view: MonitorView) -> io::Result<Self> {
+        Self::with_clock(view, system_clock())
+    }
+
+    /// 指定记录事件到达时间的时钟
+    pub fn with_clock(view: MonitorView, clock: Arc<dyn Clock>) -> io::Result<Self> {
         let protocol = SockProtocol::NetlinkKObjectUEvent;
 
         let fd = socket(
@@ -25,51 +102,287 @@ impl UEventMonitor {
             Some(protocol)
         ).map_err(|e| {
             error!("Socket creation failed: {}", e);
-            io::Error::new(io::ErrorKind::Other, format!("socket error: {e}"))
+            io::Error::other(format!("socket error: {e}"))
         })?;
 
-        let addr = NetlinkAddr::new(0, 1);
+        let groups = match view {
+            MonitorView::Kernel => KERNEL_GROUP,
+            MonitorView::Udev => KERNEL_GROUP | UDEV_GROUP,
+        };
+        let addr = NetlinkAddr::new(0, groups);
         bind(fd, &addr).map_err(|e| {
             error!("Socket binding failed: {}", e);
-            io::Error::new(io::ErrorKind::Other, format!("bind error: {e}"))
+            io::Error::other(format!("bind error: {e}"))
         })?;
 
+        // 由内核记录每条消息进入套接字的时间；不支持时退回到读出消息的时间
+        if let Err(e) = setsockopt(fd, sockopt::ReceiveTimestampns, &true) {
+            warn!("Failed to enable SO_TIMESTAMPNS, using read time for events: {}", e);
+        }
+
         info!("UEvent monitor initialized");
-        Ok(Self { fd })
+        Ok(Self {
+            fd,
+            view,
+            kernel_events: Mutex::new(BTreeMap::new()),
+            clock,
+        })
     }
 
-    pub fn receive_event(&self) -> io::Result<HashMap<String, String>> {
-        let mut buf = [0u8; 4096];
+    pub fn view(&self) -> MonitorView {
+        self.view
+    }
 
-        match recv(self.fd, &mut buf, MsgFlags::empty()) {
-            Ok(size) if size > 0 => {
-                let msg = String::from_utf8_lossy(&buf[..size]);
-                let mut event_map = HashMap::new();
+    pub fn receive_event(&self) -> io::Result<HashMap<String, String>> {
+        self.receive_timed_event().map(|(event, _)| event)
+    }
 
-                for field in msg.split('\0') {
-                    if let Some((k, v)) = field.split_once('=') {
-                        // println!("Key: {}, Value: {}", k, v);
-                        event_map.insert(k.to_string(), v.to_string());
-                    }
-                }
+    /// 与 receive_event 相同，同时返回内核收到这条消息的时间
+    pub fn receive_timed_event(&self) -> io::Result<(HashMap<String, String>, ReceiveTime)> {
+        let mut buf = [0u8; 4096];
 
-                Ok(event_map)
-            },
+        match self.recv_timed(&mut buf) {
+            Ok((size, _, received)) if size > 0 => Ok((parse_uevent(&buf[..size]), received)),
             Ok(_) => {
                 warn!("Empty packet received");
                 Err(io::ErrorKind::WouldBlock.into())
             },
-            Err(e) if e == nix::errno::Errno::EAGAIN => {
+            Err(nix::errno::Errno::EAGAIN) => {
                 Err(io::ErrorKind::WouldBlock.into())
             },
+            // 接收缓冲区溢出，保留错误码让调用方知道有消息丢失
+            Err(e @ nix::errno::Errno::ENOBUFS) => Err(e.into()),
+            Err(e) => {
+                error!("Receive error: {}", e);
+                Err(io::Error::other(format!("recv error: {e}")))
+            }
+        }
+    }
+
+    // 读出一条消息及其发送方和 SO_TIMESTAMPNS 时间戳，没有时间戳时取当前时间
+    fn recv_timed(&self, buf: &mut [u8]) -> nix::Result<(usize, Option<NetlinkAddr>, ReceiveTime)> {
+        let mut iov = [IoSliceMut::new(buf)];
+        let mut cmsg = cmsg_space!(TimeSpec);
+        let msg = recvmsg::<NetlinkAddr>(self.fd, &mut iov, Some(&mut cmsg), MsgFlags::empty())?;
+
+        let received = msg
+            .cmsgs()
+            .find_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmTimestampns(ts) => {
+                    Some(ReceiveTime::from_kernel(UNIX_EPOCH + Duration::from(ts), &*self.clock))
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| ReceiveTime::now(&*self.clock));
+        Ok((msg.bytes, msg.address, received))
+    }
+
+    /// 按订阅的视图接收一个设备。Kernel 视图返回内核事件，属性都来自内核；
+    /// Udev 视图返回处理后的事件，与同一 SEQNUM 的内核事件比较得出每个属性的来源，
+    /// 没有收到对应内核事件的（如合成的事件）全部算作 Udev。暂时没有可返回的事件时为 WouldBlock
+    pub fn receive_device(&self) -> io::Result<MonitorEvent> {
+        let mut buf = vec![0u8; 16384];
+        let (size, sender, received) = match self.recv_timed(&mut buf) {
+            Ok((size, sender, received)) if size > 0 => (size, sender, received),
+            Ok(_) | Err(nix::errno::Errno::EAGAIN) => return Err(io::ErrorKind::WouldBlock.into()),
             Err(e) => {
                 error!("Receive error: {}", e);
-                Err(io::Error::new(io::ErrorKind::Other, format!("recv error: {e}")))
+                return Err(io::Error::other(format!("recv error: {e}")));
+            }
+        };
+        let msg = &buf[..size];
+        let sender_pid = sender.map_or(0, |addr| addr.pid());
+
+        if msg.starts_with(UDEV_PREFIX) {
+            // 内核消息的发送方 pid 为 0，udev 消息来自用户空间进程
+            if self.view != MonitorView::Udev || sender_pid == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let properties = parse_udev_message(msg).ok_or_else(|| {
+                warn!("Dropping malformed udev message from pid {}", sender_pid);
+                io::Error::from(io::ErrorKind::WouldBlock)
+            })?;
+            let seqnum = properties.get("SEQNUM").and_then(|s| s.parse().ok()).unwrap_or(0);
+            let kernel = self.kernel_events.lock().unwrap().remove(&seqnum).unwrap_or_default();
+            let sources = properties
+                .iter()
+                .map(|(key, value)| {
+                    let source = if kernel.get(key) == Some(value) {
+                        PropertySource::Kernel
+                    } else {
+                        PropertySource::Udev
+                    };
+                    (key.clone(), source)
+                })
+                .collect();
+            return into_event(properties, sources, received);
+        }
+
+        if sender_pid != 0 {
+            debug!("Dropping kernel-format message from pid {}", sender_pid);
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let properties = parse_uevent(msg);
+        match self.view {
+            MonitorView::Kernel => {
+                let sources = properties.keys().map(|key| (key.clone(), PropertySource::Kernel)).collect();
+                into_event(properties, sources, received)
+            }
+            MonitorView::Udev => {
+                let seqnum = properties.get("SEQNUM").and_then(|s| s.parse().ok()).unwrap_or(0);
+                let mut pending = self.kernel_events.lock().unwrap();
+                pending.insert(seqnum, properties);
+                while pending.len() > MAX_PENDING_KERNEL_EVENTS {
+                    pending.pop_first();
+                }
+                Err(io::ErrorKind::WouldBlock.into())
             }
         }
     }
 }
 
+fn into_event(
+    properties: HashMap<String, String>,
+    sources: HashMap<String, PropertySource>,
+    received: ReceiveTime,
+) -> io::Result<MonitorEvent> {
+    let mut device = UEventDevice::from_event(properties)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "event without DEVPATH"))?;
+    device.set_received(received);
+    Ok(MonitorEvent { device, sources })
+}
+
+/// 把处理后的设备广播到 udev 多播组，供以 MonitorView::Udev 订阅的进程接收
+///
+/// 这是 systemd-udevd 使用的同一个多播组，两者同时运行时 libudev 的接收方会收到重复的事件
+pub struct UdevBroadcaster {
+    fd: RawFd,
+}
+
+impl UdevBroadcaster {
+    pub fn new() -> io::Result<Self> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            Some(SockProtocol::NetlinkKObjectUEvent),
+        )?;
+        Ok(Self { fd })
+    }
+
+    /// 相对的 DEVNAME 按 dev_root 补成绝对路径，与 libudev 的约定一致
+    pub fn broadcast(&self, device: &UEventDevice, dev_root: &Path) -> io::Result<()> {
+        let msg = encode_udev_message(device, dev_root);
+        match sendto(self.fd, &msg, &NetlinkAddr::new(0, UDEV_GROUP), MsgFlags::empty()) {
+            // 多播之外还会发给内核，内核不接收，说明的只是没有单播的接收方
+            Ok(_) | Err(nix::errno::Errno::ECONNREFUSED) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for UdevBroadcaster {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+/// 按 libudev 的格式编码：消息头之后是以 NUL 分隔的 KEY=value。
+/// 头中的子系统、设备类型散列和标签布隆过滤器供 libudev 的接收方在内核中过滤；相对的 DEVNAME 以 dev_root 为根
+pub fn encode_udev_message(device: &UEventDevice, dev_root: &Path) -> Vec<u8> {
+    let mut properties: BTreeMap<&str, String> =
+        device.properties().iter().map(|(key, value)| (key.as_str(), value.clone())).collect();
+    if let Some(devname) = device.devnode().filter(|name| !Path::new(name).is_absolute()) {
+        properties.insert("DEVNAME", dev_root.join(devname).display().to_string());
+    }
+    if let Some(usec) = device.usec_initialized() {
+        properties.insert("USEC_INITIALIZED", usec.to_string());
+    }
+    let mut body = Vec::new();
+    for (key, value) in &properties {
+        body.extend_from_slice(key.as_bytes());
+        body.push(b'=');
+        body.extend_from_slice(value.as_bytes());
+        body.push(0);
+    }
+
+    let mut bloom = 0u64;
+    for tag in device.tags() {
+        let hash = murmur_hash2(tag.as_bytes());
+        for shift in [0, 6, 12, 18] {
+            bloom |= 1 << ((hash >> shift) & 63);
+        }
+    }
+
+    let mut msg = Vec::with_capacity(UDEV_HEADER_SIZE + body.len());
+    msg.extend_from_slice(UDEV_PREFIX);
+    msg.extend_from_slice(&UDEV_MAGIC.to_be_bytes());
+    msg.extend_from_slice(&(UDEV_HEADER_SIZE as u32).to_ne_bytes());
+    msg.extend_from_slice(&(UDEV_HEADER_SIZE as u32).to_ne_bytes());
+    msg.extend_from_slice(&(body.len() as u32).to_ne_bytes());
+    msg.extend_from_slice(&murmur_hash2(device.subsystem().as_bytes()).to_be_bytes());
+    msg.extend_from_slice(&device.devtype().map_or(0, |devtype| murmur_hash2(devtype.as_bytes())).to_be_bytes());
+    msg.extend_from_slice(&((bloom >> 32) as u32).to_be_bytes());
+    msg.extend_from_slice(&(bloom as u32).to_be_bytes());
+    msg.extend_from_slice(&body);
+    msg
+}
+
+/// 解析 libudev 格式的消息，前缀、魔数或属性区域不对时为 None
+pub fn parse_udev_message(msg: &[u8]) -> Option<HashMap<String, String>> {
+    if msg.len() < UDEV_HEADER_SIZE || !msg.starts_with(UDEV_PREFIX) {
+        return None;
+    }
+    let word = |at: usize| -> [u8; 4] { msg[at..at + 4].try_into().unwrap() };
+    if u32::from_be_bytes(word(8)) != UDEV_MAGIC {
+        return None;
+    }
+    let offset = u32::from_ne_bytes(word(16)) as usize;
+    let len = u32::from_ne_bytes(word(20)) as usize;
+    let properties = msg.get(offset..offset.checked_add(len)?)?;
+    Some(parse_uevent(properties))
+}
+
+// libudev 用于子系统散列和标签过滤的 MurmurHash2，种子为 0
+fn murmur_hash2(data: &[u8]) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h = data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_ne_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &b) in tail.iter().enumerate() {
+            h ^= (b as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+// 内核事件通常有十几个字段，预留空间避免插入时多次扩容
+const TYPICAL_FIELDS: usize = 16;
+
+/// 解析一条内核 uevent 消息：以 NUL 分隔的 KEY=value，开头的 "add@/devices/..." 没有 '=' 被跳过；
+/// 按字段逐个解码，不为整条消息分配中间字符串
+pub fn parse_uevent(msg: &[u8]) -> HashMap<String, String> {
+    let mut event_map = HashMap::with_capacity(TYPICAL_FIELDS);
+    for field in msg.split(|&b| b == 0) {
+        let field = String::from_utf8_lossy(field);
+        if let Some((k, v)) = field.split_once('=') {
+            event_map.insert(k.to_string(), v.to_string());
+        }
+    }
+    event_map
+}
+
 impl Drop for UEventMonitor {
     fn drop(&mut self) {
         if let Err(e) = close(self.fd) {
@@ -82,4 +395,23 @@ impl AsRawFd for UEventMonitor {
     fn as_raw_fd(&self) -> RawFd {
         self.fd
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn udev_messages_carry_an_absolute_devname() {
+        let properties = HashMap::from([
+            ("ACTION".to_string(), "add".to_string()),
+            ("DEVPATH".to_string(), "/devices/virtual/block/loop0".to_string()),
+            ("SUBSYSTEM".to_string(), "block".to_string()),
+            ("DEVNAME".to_string(), "loop0".to_string()),
+        ]);
+        let device = UEventDevice::from_event(properties).unwrap();
+
+        let parsed = parse_udev_message(&encode_udev_message(&device, Path::new("/dev"))).unwrap();
+        assert_eq!(parsed["DEVNAME"], "/dev/loop0");
+        assert_eq!(parsed["SUBSYSTEM"], "block");
+    }
+}
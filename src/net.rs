@@ -0,0 +1,149 @@
+// src/net.rs
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use log::*;
+
+/// 网卡名的最大长度（含结尾的 NUL）
+pub const IFNAMSIZ: usize = 16;
+
+// include/uapi/linux/if_link.h
+const IFLA_IFNAME: u16 = 3;
+
+const NLMSG_HDRLEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const RTA_HDRLEN: usize = 4;
+
+/// 内核接受的网卡名：非空、不超过 15 字节、不是 . 或 ..，不含 '/'、':' 和空白
+pub fn valid_ifname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() < IFNAMSIZ
+        && name != "."
+        && name != ".."
+        && !name.chars().any(|c| c == '/' || c == ':' || c.is_whitespace())
+}
+
+/// 按名字查找网卡的 ifindex
+pub fn ifindex(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+/// 通过 rtnetlink（RTM_SETLINK + IFLA_IFNAME）重命名网卡
+///
+/// 网卡处于 up 状态时，不支持在线改名的内核返回 EBUSY。
+pub fn rename(ifindex: u32, new_name: &str) -> io::Result<()> {
+    if !valid_ifname(new_name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid interface name '{}'", new_name),
+        ));
+    }
+
+    let socket = route_socket()?;
+    let request = setlink_ifname(ifindex, new_name);
+    let sent = unsafe {
+        let mut addr: libc::sockaddr_nl = mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        libc::sendto(
+            socket.as_raw_fd(),
+            request.as_ptr() as *const libc::c_void,
+            request.len(),
+            0,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut reply = [0u8; 1024];
+    let received = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            reply.as_mut_ptr() as *mut libc::c_void,
+            reply.len(),
+            0,
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    check_ack(&reply[..received as usize])?;
+    debug!("Renamed interface {} to {}", ifindex, new_name);
+    Ok(())
+}
+
+fn route_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+// nlmsghdr + ifinfomsg + 一个 IFLA_IFNAME 属性，各部分按 4 字节对齐
+fn setlink_ifname(ifindex: u32, name: &str) -> Vec<u8> {
+    let align = |len: usize| (len + 3) & !3;
+    let rta_len = RTA_HDRLEN + name.len() + 1;
+    let total = NLMSG_HDRLEN + IFINFOMSG_LEN + align(rta_len);
+    let flags = (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16;
+
+    let mut msg = Vec::with_capacity(total);
+    // nlmsghdr：长度、类型、标志、序号、端口号
+    msg.extend_from_slice(&(total as u32).to_ne_bytes());
+    msg.extend_from_slice(&libc::RTM_SETLINK.to_ne_bytes());
+    msg.extend_from_slice(&flags.to_ne_bytes());
+    msg.extend_from_slice(&1u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    // ifinfomsg：family、填充、type、index、flags、change
+    msg.extend_from_slice(&[libc::AF_UNSPEC as u8, 0]);
+    msg.extend_from_slice(&0u16.to_ne_bytes());
+    msg.extend_from_slice(&(ifindex as i32).to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    // rtattr + 以 NUL 结尾的名字
+    msg.extend_from_slice(&(rta_len as u16).to_ne_bytes());
+    msg.extend_from_slice(&IFLA_IFNAME.to_ne_bytes());
+    msg.extend_from_slice(name.as_bytes());
+    msg.resize(total, 0);
+    msg
+}
+
+// 应答是 NLMSG_ERROR，其后的 error 字段为 0 表示成功，否则为负的 errno
+fn check_ack(reply: &[u8]) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed netlink reply");
+
+    let msg_type = reply
+        .get(4..6)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u16::from_ne_bytes)
+        .ok_or_else(invalid)?;
+    if msg_type as libc::c_int != libc::NLMSG_ERROR {
+        return Err(invalid());
+    }
+
+    let error = reply
+        .get(NLMSG_HDRLEN..NLMSG_HDRLEN + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(i32::from_ne_bytes)
+        .ok_or_else(invalid)?;
+    match error {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(-errno)),
+    }
+}
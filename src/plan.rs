@@ -0,0 +1,581 @@
+// src/plan.rs
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use log::*;
+
+use crate::actions::{
+    import_cmdline, import_file, import_program, substitute_vars, substitute_vars_escaped, write_sysattr, ResolveNames,
+};
+use crate::builtins::import_builtin;
+use crate::db::Provenance;
+use crate::deferred::DeferredAction;
+use crate::device::{DeviceAction, UEventDevice};
+use crate::logging::{raise_event_log_level, EventContext};
+use crate::media::media_properties;
+use crate::rules::matcher::{Rule, StringEscape};
+use crate::rules::metrics::{self, TimingKind};
+use crate::rules::ruleset::{RuleSet, RuleSetOptions};
+use crate::rules::trace::EventTrace;
+use crate::transliterate::replace_chars;
+
+/// 符号链接列表；启用 small-vec 特性时前 4 个存放在执行计划内部
+#[cfg(feature = "small-vec")]
+pub type Links = smallvec::SmallVec<[String; 4]>;
+#[cfg(not(feature = "small-vec"))]
+pub type Links = Vec<String>;
+
+/// 名字和值的列表（扩展属性、介质属性等）；启用 small-vec 特性时前 4 对不单独分配
+#[cfg(feature = "small-vec")]
+pub type EnvPairs = smallvec::SmallVec<[(String, String); 4]>;
+#[cfg(not(feature = "small-vec"))]
+pub type EnvPairs = Vec<(String, String)>;
+
+/// 一个事件所有匹配规则的赋值合并后的结果，规则遍历结束后统一执行
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionPlan {
+    /// 最终的设备节点名（相对于设备根目录），NAME= 可以替换 DEVNAME
+    pub name: Option<String>,
+    /// net 子系统设备的新网卡名，来自 NAME=
+    pub interface_name: Option<String>,
+    /// 已完成变量替换和字符转义的符号链接名（相对于设备根目录）
+    pub symlinks: Links,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub mode: Option<String>,
+    /// OWNER/GROUP 名字的解析时机，取自规则集合的选项
+    pub resolve_names: ResolveNames,
+    /// 设备节点的扩展属性，已完成变量替换，同名属性后写者生效
+    pub xattrs: EnvPairs,
+    /// SELinux 安全上下文，后写者生效
+    pub seclabel: Option<String>,
+    /// 符号链接优先级，默认为 0
+    pub link_priority: i32,
+    /// 是否在设备节点上监听写入后关闭
+    pub watch: bool,
+    /// 匹配规则的全部 RUN 命令，按规则顺序排列，并满足 RUN_AFTER 约束
+    pub run: Vec<String>,
+    /// 与 run 一一对应，每条命令所在规则的 文件:行号
+    pub run_locations: Vec<String>,
+    // 每条 RUN 命令的来源规则组及其 RUN_AFTER，用于重新排序
+    run_entries: Vec<RunEntry>,
+    pub ignore_device: bool,
+    pub matched_rules: usize,
+    /// 规则请求的重新探测延迟，后写者生效
+    pub reprobe: Option<Duration>,
+    /// 匹配规则的 AT{} 延迟动作及其所在规则的 文件:行号，命令尚未做变量替换
+    pub deferred: Vec<(Duration, DeferredAction, String)>,
+    /// 已完成变量替换的 I2C_NEW_DEVICE 值，适配器 add 时依次实例化
+    pub i2c_new_devices: Vec<String>,
+    /// 节点名、每个符号链接和 OWNER/GROUP/MODE 分别由哪条规则设置
+    pub provenance: Vec<Provenance>,
+}
+
+impl ExecutionPlan {
+    pub fn new(device: &UEventDevice) -> Self {
+        Self {
+            name: device.devnode().map(String::from),
+            ..Self::default()
+        }
+    }
+
+    /// 合并一条匹配的规则：符号链接累加去重，OWNER/GROUP/MODE 替换变量后后写者生效，RUN 依次追加
+    pub fn merge(&mut self, rule: &Rule, device: &UEventDevice) {
+        self.matched_rules += 1;
+
+        // 与 udev 一致，链接名在规则生效时替换；结果中的空白分隔多个链接
+        let escape = rule.string_escape.unwrap_or_default();
+        for link in &rule.symlink {
+            let value = substitute_escaped(link, device, escape, " ");
+
+            for link in value.split_whitespace() {
+                if !within_dev_root(link) {
+                    warn!("Ignoring SYMLINK '{}' outside the device root ({})", link, rule.location());
+                    continue;
+                }
+                if !self.symlinks.iter().any(|l| l == link) {
+                    self.symlinks.push(link.to_string());
+                    self.record_provenance("SYMLINK", link, rule);
+                }
+            }
+        }
+
+        if let Some(name) = &rule.name {
+            if device.subsystem() == "net" {
+                let value = substitute_vars(name, device);
+                self.record_provenance("NAME", &value, rule);
+                self.interface_name = Some(value);
+            } else {
+                // 节点名可以包含子目录，但只能来自规则中的字面部分
+                let value = substitute_escaped(name, device, escape, "");
+                if within_dev_root(&value) {
+                    self.record_provenance("NAME", &value, rule);
+                    self.name = Some(value);
+                } else {
+                    warn!("Ignoring NAME '{}' outside the device root ({})", value, rule.location());
+                }
+            }
+        }
+
+        // OWNER/GROUP/MODE 同样在规则生效时替换，如 GROUP="$env{SEAT_GROUP}"
+        if let Some(owner) = &rule.owner {
+            let value = substitute_vars(owner, device);
+            self.record_provenance("OWNER", &value, rule);
+            self.owner = Some(value);
+        }
+        if let Some(group) = &rule.group {
+            let value = substitute_vars(group, device);
+            self.record_provenance("GROUP", &value, rule);
+            self.group = Some(value);
+        }
+        if let Some(mode) = &rule.mode {
+            let value = substitute_vars(mode, device);
+            self.record_provenance("MODE", &value, rule);
+            self.mode = Some(value);
+        }
+        for (name, value) in &rule.xattr {
+            let value = substitute_vars(value, device);
+            match self.xattrs.iter_mut().find(|(existing, _)| existing == name) {
+                Some(existing) => existing.1 = value,
+                None => self.xattrs.push((name.clone(), value)),
+            }
+        }
+        if rule.seclabel.is_some() {
+            self.seclabel = rule.seclabel.clone();
+        }
+        if let Some(priority) = rule.link_priority {
+            self.link_priority = priority;
+        }
+        if let Some(watch) = rule.watch {
+            self.watch = watch;
+        }
+
+        if !rule.run.is_empty() {
+            self.run_entries.extend(rule.run.iter().map(|command| RunEntry {
+                command: command.clone(),
+                source: rule.source.clone(),
+                after: rule.run_after.clone(),
+                location: rule.location(),
+            }));
+            let order = order_run_entries(&self.run_entries);
+            self.run = order.iter().map(|&i| self.run_entries[i].command.clone()).collect();
+            self.run_locations = order.iter().map(|&i| self.run_entries[i].location.clone()).collect();
+        }
+
+        for spec in &rule.i2c_new_device {
+            let spec = substitute_vars(spec, device);
+            if !self.i2c_new_devices.contains(&spec) {
+                self.i2c_new_devices.push(spec);
+            }
+        }
+
+        if rule.reprobe.is_some() {
+            self.reprobe = rule.reprobe;
+        }
+        self.deferred
+            .extend(rule.at.iter().map(|(delay, action)| (*delay, action.clone(), rule.location())));
+
+        if rule.ignore_device {
+            self.ignore_device = true;
+        }
+    }
+
+    // NAME/OWNER/GROUP/MODE 后写者生效，只保留最后一条；每个符号链接各记一条
+    fn record_provenance(&mut self, key: &str, value: &str, rule: &Rule) {
+        if key != "SYMLINK" {
+            self.provenance.retain(|entry| entry.key != key);
+        }
+        self.provenance.push(Provenance {
+            key: key.to_string(),
+            value: value.to_string(),
+            location: rule.location(),
+        });
+    }
+}
+
+// string_escape=replace 时只转义替换出的值：不安全字符（包括 '/'）换成 '_'，keep 中的字符保留
+fn substitute_escaped(input: &str, device: &UEventDevice, escape: StringEscape, keep: &str) -> String {
+    match escape {
+        StringEscape::Replace => substitute_vars_escaped(input, device, |value| replace_chars(&value, keep)),
+        StringEscape::None => substitute_vars(input, device),
+    }
+}
+
+// 节点名和链接名是相对于设备根目录的路径，不能是绝对路径，也不能含 ".." 跳出设备根目录
+fn within_dev_root(name: &str) -> bool {
+    Path::new(name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// 对设备依次匹配规则：匹配规则的即时赋值作用到 device 上，其余赋值合并进返回的执行计划，
+/// 计划中的符号链接同时加入 device 的链接。no_act 时不写 ATTR{}，也不读写匹配缓存
+pub fn evaluate_rules(
+    device: &mut UEventDevice,
+    rules: &RuleSet,
+    no_act: bool,
+    mut trace: Option<&mut EventTrace>,
+) -> ExecutionPlan {
+    // 规则添加的链接和 DEVLINKS 属性都相对于这个规则集合的设备根目录
+    device.set_dev_root(&rules.options().dev_root);
+    for (key, value) in media_properties(device) {
+        device.set_property(&key, &value);
+    }
+
+    // 遍历所有规则，匹配规则的赋值累积到执行计划中，最后统一执行
+    // SUBSYSTEM 不同或 DEVPATH 前缀不可能匹配的规则直接跳过
+    let mut plan = ExecutionPlan::new(device);
+    plan.resolve_names = rules.options().resolve_names;
+    let devpath = device.devpath().to_path_buf();
+    let candidates = rules.candidate_indices(&devpath, device.subsystem());
+    let cache = rules.eval_cache();
+    let cache_key = cache.key(device).filter(|_| !no_act);
+
+    // 属性没有变化的 change 事件直接重放上次匹配到的规则
+    if let Some(matched) = cache_key.as_ref().and_then(|key| cache.lookup(key)) {
+        debug!("Reusing {} cached rule match(es) for {:?}", matched.len(), devpath);
+        if let Some(trace) = trace.as_mut() {
+            trace.cached = true;
+        }
+        for index in matched {
+            let rule = &rules.rules()[index];
+            if let Some(trace) = trace.as_mut() {
+                trace.record_match(rule);
+            }
+            apply_rule_effects(rule, device, no_act, rules.options());
+            plan.merge(rule, device);
+        }
+    } else {
+        let mut matched = Vec::new();
+        // 有规则的结果取决于 sysfs、文件或外部程序时不能缓存
+        let mut cacheable = true;
+        let mut symbols = rules.prepare(device);
+        for index in candidates {
+            let rule = &rules.rules()[index];
+            debug!("Checking rule: {:?}", rule);
+            let result = rules.evaluate(index, &symbols, device);
+            // 事件条件满足后才会读取外部状态
+            if rule.reads_external_state() && result.err().is_none_or(|mismatch| !mismatch.before_external()) {
+                cacheable = false;
+            }
+            if let Err(mismatch) = result {
+                if let Some(trace) = trace.as_mut() {
+                    trace.record_mismatch(rule, rules.describe_mismatch(index, mismatch));
+                }
+                continue;
+            }
+
+            if let Some(trace) = trace.as_mut() {
+                trace.record_match(rule);
+            }
+            matched.push(index);
+            apply_rule_effects(rule, device, no_act, rules.options());
+            plan.merge(rule, device);
+            // 标签和导入的属性可能已改变
+            symbols = rules.prepare(device);
+
+            if rule.ignore_device {
+                break;
+            }
+            if rule.last_rule {
+                debug!("Rule requested last_rule, stop evaluating further rules");
+                break;
+            }
+        }
+
+        if let Some(key) = cache_key.filter(|_| cacheable) {
+            cache.store(key, matched);
+        }
+    }
+    if *device.action() == DeviceAction::Remove && !no_act {
+        cache.forget(&devpath);
+    }
+
+    // 规则声明的链接，创建时可能因冲突策略被改名或拒绝
+    for link in &plan.symlinks {
+        device.add_devlink(link);
+    }
+    plan
+}
+
+/// 立即生效的规则赋值：标签、sysfs 属性写入和属性导入，后续规则的匹配可以看到它们
+pub fn apply_rule(rule: &Rule, device: &mut UEventDevice) {
+    apply_rule_effects(rule, device, false, &RuleSetOptions::default());
+}
+
+// no_act 时只记录要写的 sysfs 属性；导入照常进行，后续规则的匹配依赖它们。
+// options 给出 IMPORT{program} 计时的时钟和内置命令的设置
+fn apply_rule_effects(rule: &Rule, device: &mut UEventDevice, no_act: bool, options: &RuleSetOptions) {
+    if let Some(level) = rule.log_level {
+        raise_event_log_level(level);
+        debug!("Rule requested log_level={}, raising log level for this event", level);
+    }
+
+    if let Some(name) = &rule.name {
+        let name = substitute_vars(name, device);
+        device.set_name(Some(name));
+    }
+
+    if rule.tag_reset {
+        device.clear_tags();
+    }
+    for tag in &rule.tag_add {
+        device.add_tag(tag);
+    }
+    for tag in &rule.tag_remove {
+        device.remove_tag(tag);
+    }
+
+    for (attr, value) in &rule.attr_assign {
+        if no_act {
+            info!("Not setting ATTR{{{}}}='{}' in no-act mode", attr, value);
+            continue;
+        }
+        if let Err(e) = write_sysattr(attr, value, device) {
+            warn!("Failed to set ATTR{{{}}}='{}': {}", attr, value, e);
+        }
+        device.forget_sysattr(attr);
+    }
+
+    for (kind, value) in &rule.import {
+        match kind.as_str() {
+            "program" => {
+                let clock = &options.clock;
+                let started = clock.now();
+                let result = import_program(value, device, options.event_timeout);
+                metrics::record(&rule.location(), TimingKind::Import, clock.now().saturating_duration_since(started));
+                if let Err(e) = result {
+                    warn!("Failed to execute IMPORT{{program}} '{}': {}", value, e);
+                }
+            }
+            "file" => {
+                if let Err(e) = import_file(value, device) {
+                    warn!("Failed to execute IMPORT{{file}} '{}': {}", value, e);
+                }
+            }
+            "cmdline" => {
+                if let Err(e) = import_cmdline(value, device) {
+                    warn!("Failed to execute IMPORT{{cmdline}} '{}': {}", value, e);
+                }
+            }
+            "builtin" => {
+                if let Err(e) = import_builtin(value, device, &options.builtins) {
+                    warn!("Failed to execute IMPORT{{builtin}} '{}': {}", value, e);
+                }
+            }
+            other => warn!("Unsupported IMPORT type '{}'", other),
+        }
+    }
+}
+
+/// 规则对一个事件的最终效果，只计算不执行，见 plan_actions
+#[derive(Debug, Clone)]
+pub struct ActionPlan {
+    /// 设备节点的完整路径，没有 DEVNAME 且规则没有 NAME= 时为 None
+    pub node: Option<PathBuf>,
+    /// net 子系统设备的新网卡名
+    pub interface_name: Option<String>,
+    /// 规则声明的符号链接的完整路径；实际创建时可能因冲突策略被改名或拒绝
+    pub symlinks: Vec<PathBuf>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub mode: Option<String>,
+    /// 已完成变量替换的 RUN 命令及其所在规则的 文件:行号，按执行顺序
+    pub run: Vec<(String, String)>,
+    /// 规则处理后的设备属性，包括导入的属性
+    pub properties: BTreeMap<String, String>,
+    pub tags: Vec<String>,
+    pub ignore_device: bool,
+    /// 检查过哪些规则、匹配了哪些、不匹配的原因
+    pub trace: EventTrace,
+}
+
+/// 计算规则会对设备做什么而不实际去做：不创建节点和链接、不修改权限、不写 ATTR{}、不执行 RUN，
+/// 也不影响守护进程的匹配缓存。PROGRAM 和 IMPORT 照常执行，规则的匹配依赖它们的结果
+pub fn plan_actions(device: &UEventDevice, rules: &RuleSet) -> ActionPlan {
+    let mut device = device.clone();
+    let mut trace = EventTrace::new(&device);
+    // log_level= 只提高这次计算的日志级别，离开时恢复调用者的设置
+    let _context = EventContext::enter(device.seqnum(), device.devpath());
+    let plan = evaluate_rules(&mut device, rules, true, Some(&mut trace));
+
+    let root = &rules.options().dev_root;
+    let run = plan
+        .run
+        .iter()
+        .zip(&plan.run_locations)
+        .map(|(command, location)| (substitute_vars(command, &device), location.clone()))
+        .collect();
+    ActionPlan {
+        node: plan.name.as_deref().map(|name| root.join(name)),
+        interface_name: plan.interface_name.clone(),
+        symlinks: plan.symlinks.iter().map(|link| root.join(link)).collect(),
+        owner: plan.owner.clone(),
+        group: plan.group.clone(),
+        mode: plan.mode.clone(),
+        run,
+        properties: device.properties().iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        tags: device.tags().iter().cloned().collect(),
+        ignore_device: plan.ignore_device,
+        trace,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RunEntry {
+    command: String,
+    source: Option<String>,
+    after: Vec<String>,
+    location: String,
+}
+
+/// 按 RUN_AFTER 对命令做稳定的拓扑排序：没有约束的命令保持规则顺序；
+/// 出现循环依赖时剩余命令按原顺序追加；返回 entries 的下标
+fn order_run_entries(entries: &[RunEntry]) -> Vec<usize> {
+    // deps[i]：命令 i 必须等待的命令下标
+    let deps: Vec<Vec<usize>> = entries
+        .iter()
+        .map(|entry| {
+            entries
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| {
+                    other
+                        .source
+                        .as_ref()
+                        .is_some_and(|source| entry.after.contains(source))
+                })
+                .map(|(j, _)| j)
+                .collect()
+        })
+        .collect();
+
+    let mut done = vec![false; entries.len()];
+    let mut order = Vec::with_capacity(entries.len());
+
+    while order.len() < entries.len() {
+        let ready = (0..entries.len())
+            .find(|&i| !done[i] && deps[i].iter().all(|&j| done[j] || j == i));
+
+        match ready {
+            Some(i) => {
+                done[i] = true;
+                order.push(i);
+            }
+            None => {
+                warn!("Cyclic RUN_AFTER dependencies, running remaining commands in rule order");
+                order.extend((0..entries.len()).filter(|&i| !done[i]));
+                break;
+            }
+        }
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::parser::parse_rules_str;
+    use std::collections::HashMap;
+
+    fn device(props: &[(&str, &str)]) -> UEventDevice {
+        let mut event: HashMap<String, String> = [
+            ("ACTION", "add"),
+            ("DEVPATH", "/devices/virtual/block/loop0"),
+            ("SUBSYSTEM", "block"),
+            ("DEVNAME", "loop0"),
+            ("SEQNUM", "1"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        event.extend(props.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        UEventDevice::from_event(event).unwrap()
+    }
+
+    fn merged(rule: &str, device: &UEventDevice) -> ExecutionPlan {
+        let rules = parse_rules_str(rule);
+        let mut plan = ExecutionPlan::new(device);
+        plan.merge(&rules[0], device);
+        plan
+    }
+
+    #[test]
+    fn substituted_slashes_cannot_escape_dev_root() {
+        let device = device(&[("ID_LABEL", "../../etc/x")]);
+        let plan = merged(r#"SYMLINK+="disk/by-label/$env{ID_LABEL}", NAME="disk/$env{ID_LABEL}""#, &device);
+        assert_eq!(plan.symlinks.to_vec(), vec!["disk/by-label/.._.._etc_x".to_string()]);
+        assert_eq!(plan.name.as_deref(), Some("disk/.._.._etc_x"));
+    }
+
+    #[test]
+    fn parent_components_are_rejected() {
+        let device = device(&[("ID_LABEL", "../../etc/x")]);
+        let plan = merged(
+            r#"SYMLINK+="../outside $env{ID_LABEL}", NAME="$env{ID_LABEL}", OPTIONS+="string_escape=none""#,
+            &device,
+        );
+        assert!(plan.symlinks.is_empty());
+        assert_eq!(plan.name.as_deref(), Some("loop0"));
+    }
+
+    #[test]
+    fn missing_env_expands_to_nothing() {
+        let device = device(&[]);
+        let plan = merged(r#"SYMLINK+="disk/by-id/x$env{ID_SERIAL}""#, &device);
+        assert_eq!(plan.symlinks.to_vec(), vec!["disk/by-id/x".to_string()]);
+    }
+
+    #[test]
+    fn plan_actions_computes_effects_without_writing_attributes() {
+        // DEVPATH 经 /sys/.. 指向临时目录，ATTR{} 真的写入时能看到
+        let dir = std::env::temp_dir().join(format!("rust_udev_plan_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("state"), "old").unwrap();
+        let devpath = format!("/..{}", dir.display());
+        let kernel = dir.file_name().unwrap().to_string_lossy().into_owned();
+        let device = device(&[("DEVPATH", &devpath), ("ID_SERIAL", "abc")]);
+        let rules = RuleSet::new(parse_rules_str(concat!(
+            r#"SUBSYSTEM=="block", NAME="disk/%k", SYMLINK+="disk/by-id/$env{ID_SERIAL}", "#,
+            r#"ATTR{state}="new", RUN+="/bin/echo %k $env{ID_SERIAL}""#,
+        )));
+
+        let plan = plan_actions(&device, &rules);
+        let root = &rules.options().dev_root;
+        assert_eq!(plan.node, Some(root.join("disk").join(&kernel)));
+        assert_eq!(plan.symlinks, vec![root.join("disk/by-id/abc")]);
+        assert_eq!(plan.run.len(), 1);
+        assert_eq!(plan.run[0].0, format!("/bin/echo {} abc", kernel));
+        assert_eq!(std::fs::read_to_string(dir.join("state")).unwrap(), "old");
+
+        // 对照：实际执行时同一条规则会写入属性
+        evaluate_rules(&mut device.clone(), &rules, false, None);
+        assert_eq!(std::fs::read_to_string(dir.join("state")).unwrap(), "new");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn each_rule_set_plans_under_its_own_dev_root() {
+        let device = device(&[]);
+        let rules = |root: &str| {
+            let options = RuleSetOptions { dev_root: PathBuf::from(root), ..RuleSetOptions::default() };
+            RuleSet::with_options(parse_rules_str(r#"KERNEL=="loop0", SYMLINK+="disk/by-id/x""#), &options)
+        };
+
+        for root in ["/tmp/root-a", "/tmp/root-b"] {
+            let plan = plan_actions(&device, &rules(root));
+            assert_eq!(plan.node, Some(Path::new(root).join("loop0")));
+            assert_eq!(plan.symlinks, vec![Path::new(root).join("disk/by-id/x")]);
+        }
+    }
+
+    #[test]
+    fn encoded_labels_keep_their_escapes() {
+        let device = device(&[("ID_FS_LABEL_ENC", "my\\x20disk")]);
+        let plan = merged(r#"SYMLINK+="disk/by-label/$env{ID_FS_LABEL_ENC}""#, &device);
+        assert_eq!(plan.symlinks.to_vec(), vec!["disk/by-label/my\\x20disk".to_string()]);
+    }
+}
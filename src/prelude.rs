@@ -0,0 +1,16 @@
+// src/prelude.rs
+//
+// 常用类型的统一导出：use rust_udev::prelude::*;
+
+pub use crate::cancel::CancellationToken;
+pub use crate::device::{DeviceAction, DevnumKind, UEventDevice};
+pub use crate::libudev::Enumerator;
+pub use crate::monitor::{MonitorEvent, MonitorView, PropertySource, UEventMonitor};
+pub use crate::plan::{apply_rule, plan_actions, ActionPlan, ExecutionPlan};
+pub use crate::rules::matcher::Rule;
+pub use crate::rules::parser::{
+    parse_rules_dir, parse_rules_file, parse_rules_str, ParseErrorKind, ParseReport, RuleManager,
+    RuleManagerError,
+};
+pub use crate::rules::ruleset::{RuleSet, SharedRules};
+pub use crate::udevd::{execute_plan, process_event, DaemonState, EventHandle, EventOutcome, Udevd};
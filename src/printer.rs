@@ -0,0 +1,10 @@
+// src/printer.rs
+
+use crate::profile::Profile;
+
+/// 3D 打印机和 CNC 控制板常用的 USB 转串口芯片表，以及为这些串口设置 dialout 组并创建稳定名称的规则
+pub const PRINTER: Profile = Profile {
+    name: "<60-3dprinter.rules>",
+    rules: include_str!("../rules/60-3dprinter.rules"),
+    hwdb: include_str!("../hwdb.d/20-usb-serial-bridge.hwdb"),
+};
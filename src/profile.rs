@@ -0,0 +1,21 @@
+// src/profile.rs
+
+/// 可选特性内置的规则包：一份规则文本及其使用的 hwdb 数据表
+#[derive(Debug, Clone, Copy)]
+pub struct Profile {
+    /// 规则在日志中显示的文件名
+    pub name: &'static str,
+    pub rules: &'static str,
+    /// hwdb 格式的数据表，与 hwdb 文件一起查找
+    pub hwdb: &'static str,
+}
+
+/// 编译时启用的规则包
+pub fn enabled() -> &'static [&'static Profile] {
+    &[
+        #[cfg(feature = "android")]
+        &crate::android::ANDROID,
+        #[cfg(feature = "printer")]
+        &crate::printer::PRINTER,
+    ]
+}
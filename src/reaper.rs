@@ -0,0 +1,414 @@
+// src/reaper.rs
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use log::*;
+
+use crate::actions::spawn_command;
+use crate::cancel::{CancellationToken, CHECK_INTERVAL};
+use crate::clock::{system_clock, Clock, ReceiveTime};
+use crate::config::DEFAULT_EVENT_TIMEOUT;
+use crate::journal::{RunJournal, RunRecord, RunStatus};
+use crate::rules::metrics::{self, TimingKind};
+
+// SIGCHLD 处理函数写入的管道，-1 表示回收线程还没有启动
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_sigchld(_signal: libc::c_int) {
+    wake();
+}
+
+// 只调用 write，可以在信号处理函数中使用；管道写满时丢弃，回收线程总会醒来
+fn wake() {
+    let fd = WAKE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = 1u8;
+        unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+// 回收线程退出时调用：先让信号处理函数不再写管道，再关闭两端
+fn stop_wake_pipe(read_fd: libc::c_int, write_fd: libc::c_int) {
+    unsafe { libc::signal(libc::SIGCHLD, libc::SIG_DFL) };
+    let _ = WAKE_FD.compare_exchange(write_fd, -1, Ordering::Relaxed, Ordering::Relaxed);
+    unsafe {
+        libc::close(write_fd);
+        libc::close(read_fd);
+    }
+}
+
+/// 回收脱离事件处理的 RUN 子进程，把退出状态按事件 seqnum 记入日志
+///
+/// 一个事件的 RUN 命令按顺序执行：前一个子进程被回收后才启动下一个，事件线程不等待它们。
+/// 只等待自己启动的子进程，PROGRAM 和 IMPORT{program} 的子进程仍由调用方等待。
+/// 运行超过 event_timeout 的命令被杀死；exec_delay 不为零时每个命令推迟这么久才启动。
+/// 设备的 remove 事件先用 wait_device 等待之前事件的命令结束。
+#[derive(Debug)]
+pub struct Reaper {
+    children: Mutex<Vec<RunChain>>,
+    delayed: Mutex<Vec<DelayedRun>>,
+    // 每次回收之后通知 wait_device
+    reaped: Condvar,
+    journal: RunJournal,
+    journal_path: PathBuf,
+    clock: Arc<dyn Clock>,
+    exec_delay: Duration,
+    event_timeout: Duration,
+}
+
+// 一个事件正在执行的 RUN 命令及其后还没启动的命令
+#[derive(Debug)]
+struct RunChain {
+    child: Child,
+    command: String,
+    // 命令所在规则的 文件:行号，用于统计耗时
+    location: String,
+    started: Instant,
+    remaining: VecDeque<(String, String)>,
+    envs: HashMap<String, String>,
+    origin: Origin,
+}
+
+// 命令所属的事件，记入日志
+#[derive(Debug, Clone)]
+struct Origin {
+    seqnum: u64,
+    devpath: PathBuf,
+    received: ReceiveTime,
+}
+
+// 等待 exec_delay 结束的命令
+#[derive(Debug)]
+struct DelayedRun {
+    start_at: Instant,
+    remaining: VecDeque<(String, String)>,
+    envs: HashMap<String, String>,
+    origin: Origin,
+}
+
+impl Reaper {
+    pub fn new<P: AsRef<Path>>(journal_path: P) -> Self {
+        Self::with_clock(system_clock(), journal_path)
+    }
+
+    /// 指定计算超时和 exec_delay 的时钟
+    pub fn with_clock<P: AsRef<Path>>(clock: Arc<dyn Clock>, journal_path: P) -> Self {
+        Self {
+            children: Mutex::new(Vec::new()),
+            delayed: Mutex::new(Vec::new()),
+            reaped: Condvar::new(),
+            journal: RunJournal::default(),
+            journal_path: journal_path.as_ref().to_path_buf(),
+            clock,
+            exec_delay: Duration::ZERO,
+            event_timeout: DEFAULT_EVENT_TIMEOUT,
+        }
+    }
+
+    /// 每个命令启动前的等待时间，默认不等待
+    pub fn with_exec_delay(mut self, delay: Duration) -> Self {
+        self.exec_delay = delay;
+        self
+    }
+
+    /// 命令最长的运行时间，默认为 DEFAULT_EVENT_TIMEOUT
+    pub fn with_event_timeout(mut self, timeout: Duration) -> Self {
+        self.event_timeout = timeout;
+        self
+    }
+
+    /// 安装 SIGCHLD 处理函数并启动回收线程；同一时间进程中只应有一个回收线程。
+    /// token 被取消后线程恢复默认的 SIGCHLD 处理、关闭管道并退出
+    pub fn start(self: &Arc<Self>, token: CancellationToken) -> io::Result<()> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let [read_fd, write_fd] = fds;
+        // 写端不能阻塞信号处理函数
+        unsafe { libc::fcntl(write_fd, libc::F_SETFL, libc::O_NONBLOCK) };
+        WAKE_FD.store(write_fd, Ordering::Relaxed);
+
+        let ret = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sigchld as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGCHLD, &action, std::ptr::null_mut())
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let reaper = self.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let mut pollfd = libc::pollfd {
+                fd: read_fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            while !token.is_cancelled() {
+                let ready = unsafe { libc::poll(&mut pollfd, 1, CHECK_INTERVAL.as_millis() as libc::c_int) };
+                if ready < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                    error!("SIGCHLD pipe failed: {}", io::Error::last_os_error());
+                    break;
+                }
+                if ready == 0 {
+                    // 没有子进程退出，仍要检查超时的命令和到时间的延迟命令
+                    if reaper.running() > 0 || !reaper.delayed.lock().unwrap().is_empty() {
+                        reaper.reap();
+                    }
+                    continue;
+                }
+                if ready < 0 {
+                    continue;
+                }
+                let n = unsafe { libc::read(read_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                if n < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                    error!("SIGCHLD pipe failed: {}", io::Error::last_os_error());
+                    break;
+                }
+                reaper.reap();
+            }
+            stop_wake_pipe(read_fd, write_fd);
+        });
+        Ok(())
+    }
+
+    /// 启动一个事件的 RUN 命令（已完成变量替换）及其所在规则的位置，立即返回；
+    /// received 是事件到达的时间，日志中的时间戳取自它
+    pub fn run(
+        &self,
+        commands: Vec<(String, String)>,
+        envs: HashMap<String, String>,
+        seqnum: u64,
+        devpath: &Path,
+        received: ReceiveTime,
+    ) {
+        let mut remaining: VecDeque<(String, String)> = commands.into();
+        let origin = Origin {
+            seqnum,
+            devpath: devpath.to_path_buf(),
+            received,
+        };
+        let delay = self.exec_delay;
+        if !delay.is_zero() {
+            self.delayed.lock().unwrap().push(DelayedRun {
+                start_at: self.clock.now() + delay,
+                remaining,
+                envs,
+                origin,
+            });
+            return;
+        }
+        if let Some(chain) = self.spawn_next(&mut remaining, envs, origin) {
+            self.children.lock().unwrap().push(chain);
+            // 子进程可能在登记之前就已经退出
+            wake();
+        }
+    }
+
+    /// 回收已经结束的子进程，杀死超时的子进程，并启动同一事件的下一个命令；返回回收的数量
+    pub fn reap(&self) -> usize {
+        let mut reaped = 0;
+        let mut children = self.children.lock().unwrap();
+        let mut running = Vec::with_capacity(children.len());
+        let timeout = self.event_timeout;
+        let delay = self.exec_delay;
+        let mut delayed = self.delayed.lock().unwrap();
+
+        for mut chain in children.drain(..) {
+            let status = match chain.child.try_wait() {
+                Ok(Some(status)) => status,
+                Ok(None) if self.clock.now().saturating_duration_since(chain.started) < timeout => {
+                    running.push(chain);
+                    continue;
+                }
+                Ok(None) => {
+                    warn!("RUN '{}' for seq {} killed after {:?}", chain.command, chain.origin.seqnum, timeout);
+                    let _ = chain.child.kill();
+                    match chain.child.wait() {
+                        Ok(status) => status,
+                        Err(e) => {
+                            warn!("Failed to wait for RUN '{}': {}", chain.command, e);
+                            continue;
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to wait for RUN '{}': {}", chain.command, e);
+                    continue;
+                }
+            };
+
+            reaped += 1;
+            self.record(&chain, status);
+            if !delay.is_zero() && !chain.remaining.is_empty() {
+                delayed.push(DelayedRun {
+                    start_at: self.clock.now() + delay,
+                    remaining: chain.remaining,
+                    envs: chain.envs,
+                    origin: chain.origin,
+                });
+            } else if let Some(next) = self.spawn_next(&mut chain.remaining, chain.envs, chain.origin) {
+                running.push(next);
+            }
+        }
+
+        let now = self.clock.now();
+        let (due, waiting): (Vec<_>, Vec<_>) = delayed.drain(..).partition(|run| run.start_at <= now);
+        *delayed = waiting;
+        drop(delayed);
+        for mut run in due {
+            if let Some(chain) = self.spawn_next(&mut run.remaining, run.envs, run.origin) {
+                running.push(chain);
+            }
+        }
+        *children = running;
+        drop(children);
+        self.reaped.notify_all();
+
+        if reaped > 0 {
+            if let Err(e) = self.journal.save(&self.journal_path) {
+                warn!("Failed to save RUN journal to {:?}: {}", self.journal_path, e);
+            }
+        }
+        reaped
+    }
+
+    /// 等待 devpath 之前的事件启动或推迟的 RUN 命令全部结束，最多等待 timeout；返回是否都已结束
+    pub fn wait_device(&self, devpath: &Path, timeout: Duration) -> bool {
+        let deadline = self.clock.now() + timeout;
+        let mut children = self.children.lock().unwrap();
+        loop {
+            let busy = children.iter().any(|chain| chain.origin.devpath == devpath)
+                || self.delayed.lock().unwrap().iter().any(|run| run.origin.devpath == devpath);
+            if !busy {
+                return true;
+            }
+            let now = self.clock.now();
+            if now >= deadline {
+                warn!("RUN commands for {:?} still running after {:?}", devpath, timeout);
+                return false;
+            }
+            // 虚拟时钟被推进时也要及时发现超时
+            let wait = (deadline - now).min(CHECK_INTERVAL);
+            children = self.reaped.wait_timeout(children, wait).unwrap().0;
+        }
+    }
+
+    /// 还在运行的 RUN 子进程数
+    pub fn running(&self) -> usize {
+        self.children.lock().unwrap().len()
+    }
+
+    pub fn journal(&self) -> &RunJournal {
+        &self.journal
+    }
+
+    // 启动 remaining 中第一个能启动的命令；启动失败的命令记录后跳过
+    fn spawn_next(
+        &self,
+        remaining: &mut VecDeque<(String, String)>,
+        envs: HashMap<String, String>,
+        origin: Origin,
+    ) -> Option<RunChain> {
+        while let Some((command, location)) = remaining.pop_front() {
+            match spawn_command(&command, &envs) {
+                Ok(child) => {
+                    debug!("Started RUN '{}' (pid {}) for seq {}", command, child.id(), origin.seqnum);
+                    return Some(RunChain {
+                        child,
+                        command,
+                        location,
+                        started: self.clock.now(),
+                        remaining: std::mem::take(remaining),
+                        envs,
+                        origin,
+                    });
+                }
+                Err(e) => warn!("Failed to start RUN '{}' for seq {}: {}", command, origin.seqnum, e),
+            }
+        }
+        None
+    }
+
+    fn record(&self, chain: &RunChain, status: ExitStatus) {
+        let status = match (status.code(), status.signal()) {
+            (Some(code), _) => RunStatus::Exited(code),
+            (None, Some(signal)) => RunStatus::Signaled(signal),
+            (None, None) => RunStatus::Exited(-1),
+        };
+        let elapsed = self.clock.now().saturating_duration_since(chain.started);
+        metrics::record(&chain.location, TimingKind::Run, elapsed);
+        if status.success() {
+            debug!("RUN '{}' for seq {} finished", chain.command, chain.origin.seqnum);
+        } else {
+            warn!("RUN '{}' for seq {} {}", chain.command, chain.origin.seqnum, status);
+        }
+
+        // 与设备历史一致，记录事件到达的时间
+        let timestamp = chain
+            .origin
+            .received
+            .realtime
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.journal.record(RunRecord {
+            seqnum: chain.origin.seqnum,
+            devpath: chain.origin.devpath.clone(),
+            pid: chain.child.id(),
+            status,
+            timestamp,
+            command: chain.command.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin() -> ReceiveTime {
+        ReceiveTime::now(&crate::clock::SystemClock)
+    }
+
+    #[test]
+    fn wait_device_returns_after_the_device_commands_are_reaped() {
+        let dir = std::env::temp_dir().join(format!("rust_udev-reaper-{}", std::process::id()));
+        let reaper = Arc::new(Reaper::new(dir.join("journal")));
+        let devpath = Path::new("/devices/virtual/test/reaper0");
+        let commands = vec![("sleep 0.2".to_string(), "test:1".to_string())];
+        reaper.run(commands, HashMap::new(), 1, devpath, origin());
+
+        // 其它设备不需要等待
+        assert!(reaper.wait_device(Path::new("/devices/virtual/test/other"), Duration::ZERO));
+        assert!(!reaper.wait_device(devpath, Duration::ZERO));
+
+        let token = CancellationToken::new();
+        let _stop = token.drop_guard();
+        let reaping = {
+            let reaper = reaper.clone();
+            let token = token.clone();
+            thread::spawn(move || {
+                while !token.wait_timeout(Duration::from_millis(10)) {
+                    reaper.reap();
+                }
+            })
+        };
+        assert!(reaper.wait_device(devpath, Duration::from_secs(10)));
+        assert_eq!(reaper.running(), 0);
+        token.cancel();
+        reaping.join().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
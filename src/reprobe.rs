@@ -0,0 +1,159 @@
+// src/reprobe.rs
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::clock::{system_clock, Clock};
+use crate::device::{DeviceAction, UEventDevice};
+
+/// 每个设备自 add 以来最多重新探测的次数
+pub const MAX_REPROBES: u32 = 3;
+
+/// 规则请求的最长延迟，更长的值被截断
+pub const MAX_REPROBE_DELAY: Duration = Duration::from_secs(60);
+
+/// 为 add 时属性还没准备好的设备（网卡的 MAC、GPU 的序列号等）延迟合成 change 事件
+///
+/// 规则用 OPTIONS+="reprobe=2s" 请求；同一设备同时只有一个待处理的探测，
+/// 超过 MAX_REPROBES 次后不再安排，设备移除或重新 add 时清零。
+/// 这里只记录到期的 devpath，事件由守护进程到期时从 sysfs 重新读取，规则看到的是最新的属性。
+#[derive(Debug)]
+pub struct ReprobeScheduler {
+    inner: Mutex<Inner>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // devpath -> 到期时间
+    pending: HashMap<PathBuf, Instant>,
+    attempts: HashMap<PathBuf, u32>,
+}
+
+impl Default for ReprobeScheduler {
+    fn default() -> Self {
+        Self::with_clock(system_clock())
+    }
+}
+
+impl ReprobeScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            clock,
+        }
+    }
+
+    /// 安排 delay 之后重新探测；已有待处理的探测或次数用完时返回 false
+    pub fn schedule(&self, device: &UEventDevice, delay: Duration) -> bool {
+        let devpath = device.devpath().to_path_buf();
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.pending.contains_key(&devpath) {
+            return false;
+        }
+        let attempts = inner.attempts.get(&devpath).copied().unwrap_or(0);
+        if attempts >= MAX_REPROBES {
+            warn!("Giving up re-probing {:?} after {} attempts", devpath, attempts);
+            return false;
+        }
+
+        let due = self.clock.now() + delay.min(MAX_REPROBE_DELAY);
+
+        debug!("Re-probing {:?} in {:?} (attempt {})", devpath, delay, attempts + 1);
+        inner.attempts.insert(devpath.clone(), attempts + 1);
+        inner.pending.insert(devpath, due);
+        true
+    }
+
+    /// 取出已经到期的探测，返回要合成 change 事件的 devpath
+    pub fn due(&self) -> Vec<PathBuf> {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+
+        let mut ready: Vec<PathBuf> = inner
+            .pending
+            .iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(devpath, _)| devpath.clone())
+            .collect();
+        for devpath in &ready {
+            inner.pending.remove(devpath);
+        }
+        ready.sort();
+        ready
+    }
+
+    /// 根据真实事件更新：add 重新计数，remove 取消待处理的探测
+    pub fn update(&self, device: &UEventDevice) {
+        match device.action() {
+            DeviceAction::Add | DeviceAction::Remove => self.forget(device.devpath()),
+            _ => {}
+        }
+    }
+
+    pub fn forget(&self, devpath: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.pending.remove(devpath);
+        inner.attempts.remove(devpath);
+    }
+
+    /// 已安排但还没到期的探测数
+    pub fn pending(&self) -> usize {
+        self.inner.lock().unwrap().pending.len()
+    }
+
+    /// 设备已经用掉的探测次数
+    pub fn attempts(&self, devpath: &Path) -> u32 {
+        self.inner.lock().unwrap().attempts.get(devpath).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn device(action: &str) -> UEventDevice {
+        let properties = HashMap::from([
+            ("ACTION".to_string(), action.to_string()),
+            ("DEVPATH".to_string(), "/devices/virtual/net/eth9".to_string()),
+            ("SUBSYSTEM".to_string(), "net".to_string()),
+        ]);
+        UEventDevice::from_event(properties).unwrap()
+    }
+
+    #[test]
+    fn due_returns_devpaths_once_and_caps_attempts() {
+        let clock = Arc::new(ManualClock::new());
+        let reprobes = ReprobeScheduler::with_clock(clock.clone());
+        let add = device("add");
+
+        assert!(reprobes.schedule(&add, Duration::from_secs(2)));
+        assert!(!reprobes.schedule(&add, Duration::from_secs(2)));
+        assert!(reprobes.due().is_empty());
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(reprobes.due(), vec![PathBuf::from("/devices/virtual/net/eth9")]);
+        assert!(reprobes.due().is_empty());
+
+        for _ in 1..MAX_REPROBES {
+            assert!(reprobes.schedule(&add, Duration::ZERO));
+            assert_eq!(reprobes.due().len(), 1);
+        }
+        assert!(!reprobes.schedule(&add, Duration::ZERO));
+
+        // 重新 add 后重新计数，remove 取消待处理的探测
+        reprobes.update(&add);
+        assert!(reprobes.schedule(&add, Duration::from_secs(1)));
+        reprobes.update(&device("remove"));
+        assert_eq!(reprobes.pending(), 0);
+    }
+}
@@ -0,0 +1,245 @@
+// src/rules/cache.rs
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::debug;
+
+use crate::device::{DeviceAction, UEventDevice};
+use crate::lru::LruIndex;
+use crate::rules::matcher::Rule;
+use crate::stats::CacheUsage;
+
+/// 默认最多缓存匹配结果的设备数
+pub const EVAL_CACHE_CAPACITY: usize = 1024;
+
+// 估算内存时每个条目额外计入的字节数
+const ENTRY_OVERHEAD: usize = 128;
+
+// 匹配时总会读取的设备字段，ENV{} 引用的属性另外加入
+const FIXED_FIELDS: [&str; 7] = ["ACTION", "SUBSYSTEM", "DEVTYPE", "KERNEL", "DEVPATH", "DRIVER", "TAGS"];
+
+/// 规则匹配结果缓存，用于传感器一类频繁发出属性不变的 change 事件的设备
+///
+/// 以 devpath 为键保存上次匹配到的规则下标和当时规则读取到的属性。调用方只在结果
+/// 完全取决于事件属性时（见 Rule::reads_external_state）保存；命中前逐项比较属性，
+/// 任何一项不同都会重新匹配。缓存属于 RuleSet，规则重新加载后自然失效。
+/// 设备数超过上限时淘汰最久没有命中的设备。
+#[derive(Debug)]
+pub struct EvalCache {
+    env_keys: Vec<String>,
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<PathBuf, CachedEval>,
+    recency: LruIndex<PathBuf>,
+    evicted: u64,
+}
+
+#[derive(Debug)]
+struct CachedEval {
+    hash: u64,
+    snapshot: Vec<Option<String>>,
+    matched: Vec<usize>,
+}
+
+/// 某个事件的缓存键
+#[derive(Debug, Clone)]
+pub struct EvalKey {
+    devpath: PathBuf,
+    hash: u64,
+    snapshot: Vec<Option<String>>,
+}
+
+impl Default for EvalCache {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+impl EvalCache {
+    pub fn new(rules: &[Rule]) -> Self {
+        Self::with_capacity(rules, EVAL_CACHE_CAPACITY)
+    }
+
+    /// capacity 为 0 时不缓存
+    pub fn with_capacity(rules: &[Rule], capacity: usize) -> Self {
+        let env_keys: BTreeSet<&String> = rules
+            .iter()
+            .flat_map(|rule| rule.env_vars.iter().map(|(key, _)| key))
+            .collect();
+
+        Self {
+            env_keys: env_keys.into_iter().cloned().collect(),
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// 只对 change 事件返回键
+    pub fn key(&self, device: &UEventDevice) -> Option<EvalKey> {
+        if *device.action() != DeviceAction::Change {
+            return None;
+        }
+
+        let snapshot = self.snapshot(device);
+        let mut hasher = DefaultHasher::new();
+        snapshot.hash(&mut hasher);
+
+        Some(EvalKey {
+            devpath: device.devpath().to_path_buf(),
+            hash: hasher.finish(),
+            snapshot,
+        })
+    }
+
+    /// 相关属性与上次完全相同时返回上次匹配到的规则下标
+    pub fn lookup(&self, key: &EvalKey) -> Option<Vec<usize>> {
+        let mut inner = self.inner.lock().unwrap();
+        let cached = inner.entries.get(&key.devpath)?;
+
+        if cached.hash != key.hash || cached.snapshot != key.snapshot {
+            if let Some(field) = self.first_difference(&cached.snapshot, &key.snapshot) {
+                debug!("{} of {:?} changed, re-evaluating rules", field, key.devpath);
+            }
+            return None;
+        }
+
+        let matched = cached.matched.clone();
+        inner.recency.touch(&key.devpath);
+        Some(matched)
+    }
+
+    pub fn store(&self, key: EvalKey, matched: Vec<usize>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.recency.touch(&key.devpath);
+        inner.entries.insert(
+            key.devpath,
+            CachedEval {
+                hash: key.hash,
+                snapshot: key.snapshot,
+                matched,
+            },
+        );
+
+        while inner.entries.len() > self.capacity {
+            let Some(oldest) = inner.recency.pop_oldest() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+            inner.evicted += 1;
+        }
+    }
+
+    /// 设备移除后丢弃它的缓存
+    pub fn forget(&self, devpath: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.remove(devpath).is_some() {
+            inner.recency.remove(&devpath.to_path_buf());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let inner = self.inner.lock().unwrap();
+        let bytes = inner
+            .entries
+            .iter()
+            .map(|(devpath, cached)| {
+                ENTRY_OVERHEAD
+                    + devpath.as_os_str().len()
+                    + cached.snapshot.iter().flatten().map(String::len).sum::<usize>()
+                    + cached.matched.len() * size_of::<usize>()
+            })
+            .sum();
+
+        CacheUsage {
+            name: "eval".to_string(),
+            entries: inner.entries.len(),
+            capacity: self.capacity,
+            bytes,
+            evicted: inner.evicted,
+        }
+    }
+
+    // 顺序与 FIXED_FIELDS 一致：固定字段在前，ENV 键在后
+    fn snapshot(&self, device: &UEventDevice) -> Vec<Option<String>> {
+        let tags: Vec<&str> = device.tags().iter().map(String::as_str).collect();
+        let mut snapshot = vec![
+            Some(device.action().as_str().to_string()),
+            Some(device.subsystem().to_string()),
+            device.devtype().map(String::from),
+            device.kernel().map(String::from),
+            Some(device.devpath().to_string_lossy().into_owned()),
+            device.driver().map(String::from),
+            Some(tags.join(":")),
+        ];
+        snapshot.extend(self.env_keys.iter().map(|key| device.property(key).map(String::from)));
+        snapshot
+    }
+
+    fn first_difference(&self, old: &[Option<String>], new: &[Option<String>]) -> Option<String> {
+        let index = old.iter().zip(new).position(|(a, b)| a != b)?;
+        Some(match FIXED_FIELDS.get(index) {
+            Some(field) => field.to_string(),
+            None => format!("ENV{{{}}}", self.env_keys[index - FIXED_FIELDS.len()]),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn change(devpath: &str) -> UEventDevice {
+        let properties = HashMap::from([
+            ("ACTION".to_string(), "change".to_string()),
+            ("DEVPATH".to_string(), devpath.to_string()),
+            ("SUBSYSTEM".to_string(), "iio".to_string()),
+        ]);
+        UEventDevice::from_event(properties).unwrap()
+    }
+
+    #[test]
+    fn least_recently_hit_device_is_evicted() {
+        let cache = EvalCache::with_capacity(&[], 2);
+        let devices: Vec<_> = (0..3).map(|i| change(&format!("/devices/virtual/iio/dev{}", i))).collect();
+
+        cache.store(cache.key(&devices[0]).unwrap(), vec![1]);
+        cache.store(cache.key(&devices[1]).unwrap(), vec![2]);
+        assert_eq!(cache.lookup(&cache.key(&devices[0]).unwrap()), Some(vec![1]));
+        cache.store(cache.key(&devices[2]).unwrap(), vec![3]);
+
+        assert!(cache.lookup(&cache.key(&devices[1]).unwrap()).is_none());
+        assert_eq!(cache.lookup(&cache.key(&devices[0]).unwrap()), Some(vec![1]));
+        let usage = cache.usage();
+        assert_eq!((usage.entries, usage.capacity, usage.evicted), (2, 2, 1));
+    }
+
+    #[test]
+    fn zero_capacity_disables_the_cache() {
+        let cache = EvalCache::with_capacity(&[], 0);
+        let device = change("/devices/virtual/iio/dev0");
+        cache.store(cache.key(&device).unwrap(), vec![1]);
+        assert!(cache.is_empty());
+    }
+}
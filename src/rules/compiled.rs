@@ -0,0 +1,339 @@
+// src/rules/compiled.rs
+
+use std::collections::HashMap;
+
+use crate::device::UEventDevice;
+use crate::rules::glob::Glob;
+use crate::rules::matcher::Rule;
+use crate::rules::trace::Mismatch;
+use crate::stats::CacheUsage;
+
+/// 驻留字符串的编号，相同的字符串编号相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// 字符串驻留表：规则中重复出现的 SUBSYSTEM、KERNEL 等值只保存一份，比较时只比较编号
+#[derive(Debug, Default)]
+pub struct Interner {
+    ids: HashMap<Box<str>, Symbol>,
+    strings: Vec<Box<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, value: &str) -> Symbol {
+        if let Some(&symbol) = self.ids.get(value) {
+            return symbol;
+        }
+        let symbol = Symbol(self.strings.len() as u32);
+        self.strings.push(value.into());
+        self.ids.insert(value.into(), symbol);
+        symbol
+    }
+
+    /// 不在表中的字符串返回 None，说明没有规则会与它比较相等
+    pub fn get(&self, value: &str) -> Option<Symbol> {
+        self.ids.get(value).copied()
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.strings[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// 只在编译规则时加入字符串，匹配事件时不会增长，大小由规则文本决定；
+    /// 上限就是当前条目数，规则重新加载时随旧的 RuleSet 一起释放
+    pub fn usage(&self) -> CacheUsage {
+        let bytes = self
+            .strings
+            .iter()
+            .map(|value| 2 * value.len() + size_of::<Box<str>>() * 2 + size_of::<Symbol>())
+            .sum();
+
+        CacheUsage {
+            name: "interner".to_string(),
+            entries: self.strings.len(),
+            capacity: self.strings.len(),
+            bytes,
+            evicted: 0,
+        }
+    }
+}
+
+// 单个匹配条件；ACTION、SUBSYSTEM 等不区分大小写，编译时已转成小写
+#[derive(Debug, Clone)]
+enum Token {
+    Subsystem(Pattern),
+    Action(Pattern),
+    Kernel(Pattern),
+    Devtype(Pattern),
+    Driver(Pattern),
+    Tag(Symbol),
+    Name(Pattern),
+    Env(Symbol, Pattern),
+    Devpath(Glob),
+}
+
+// 不含通配符和 | 的值只比较驻留编号，其余在编译时转成 glob
+#[derive(Debug, Clone)]
+enum Pattern {
+    Literal(Symbol),
+    Glob(Glob),
+}
+
+impl Pattern {
+    fn new(value: &str, interner: &mut Interner) -> Self {
+        if value.contains(['*', '?', '[', '|']) {
+            Pattern::Glob(Glob::new(value))
+        } else {
+            Pattern::Literal(interner.intern(value))
+        }
+    }
+
+    fn matches(&self, field: &Field) -> bool {
+        match self {
+            Pattern::Literal(symbol) => field.symbol == Some(*symbol),
+            Pattern::Glob(glob) => glob.matches(&field.text),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    // 没有任何条件的规则，或 KERNELVER 与运行中的内核不符的规则，永远不匹配
+    conditional: bool,
+    tokens: Vec<Token>,
+}
+
+/// 规则的事件条件编译成的 token 序列，与规则下标一一对应
+///
+/// 每个事件先用 prepare 把设备字段换成驻留编号，之后逐条执行 token 时字面值只需比较编号；
+/// 含通配符的值和 DEVPATH 模式在编译时拆好备选项。ATTR、TEST、PROGRAM 仍由 Rule 自己检查。
+#[derive(Debug, Default)]
+pub struct CompiledRules {
+    interner: Interner,
+    rules: Vec<CompiledRule>,
+}
+
+/// 某个事件的字段及其在驻留表中的编号；规则修改了设备（标签、导入的属性）后需要重新准备
+#[derive(Debug, Clone)]
+pub struct EventSymbols {
+    action: Field,
+    subsystem: Field,
+    devtype: Field,
+    kernel: Field,
+    driver: Field,
+    name: Field,
+    devpath: String,
+    tags: Vec<Symbol>,
+}
+
+// 字段文本供 glob 匹配，编号供字面值比较
+#[derive(Debug, Clone)]
+struct Field {
+    symbol: Option<Symbol>,
+    text: String,
+}
+
+impl CompiledRules {
+    pub fn compile(rules: &[Rule]) -> Self {
+        let mut interner = Interner::default();
+        let rules = rules
+            .iter()
+            .map(|rule| compile_rule(rule, &mut interner))
+            .collect();
+        Self { interner, rules }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    pub fn prepare(&self, device: &UEventDevice) -> EventSymbols {
+        let field = |text: String| Field {
+            symbol: self.interner.get(&text),
+            text,
+        };
+        let lookup = |value: &str| field(value.to_lowercase());
+        EventSymbols {
+            action: lookup(device.action().as_str()),
+            subsystem: lookup(device.subsystem()),
+            // 设备缺少的字段按空字符串比较，规则可以用 =="" 匹配缺失的字段
+            devtype: lookup(device.devtype().unwrap_or("")),
+            kernel: lookup(device.kernel().unwrap_or("")),
+            driver: lookup(device.driver().unwrap_or("")),
+            // 网卡名区分大小写
+            name: field(device.name().unwrap_or("").to_string()),
+            devpath: device.devpath().to_string_lossy().to_lowercase(),
+            tags: device
+                .tags()
+                .iter()
+                .filter_map(|tag| self.interner.get(tag))
+                .collect(),
+        }
+    }
+
+    /// 执行第 index 条规则的 token，只检查取决于事件本身的条件
+    pub fn matches_event(&self, index: usize, symbols: &EventSymbols, device: &UEventDevice) -> bool {
+        self.mismatch(index, symbols, device).is_none()
+    }
+
+    /// 同 matches_event，返回第一个不满足的 token；没有条件或被 KERNELVER 排除的规则为 NoConditions
+    pub fn mismatch(&self, index: usize, symbols: &EventSymbols, device: &UEventDevice) -> Option<Mismatch> {
+        let rule = &self.rules[index];
+        if !rule.conditional {
+            return Some(Mismatch::NoConditions);
+        }
+        rule.tokens
+            .iter()
+            .position(|token| !self.token_matches(token, symbols, device))
+            .map(Mismatch::Event)
+    }
+
+    /// 第 index 条规则的第 token 个条件，按规则文件的写法显示（值已转成小写）
+    pub fn describe(&self, index: usize, token: usize) -> String {
+        let resolve = |symbol: &Symbol| self.interner.resolve(*symbol);
+        let pattern = |pattern: &Pattern| match pattern {
+            Pattern::Literal(symbol) => resolve(symbol).to_string(),
+            Pattern::Glob(glob) => glob.to_string(),
+        };
+        match &self.rules[index].tokens[token] {
+            Token::Subsystem(p) => format!("SUBSYSTEM==\"{}\"", pattern(p)),
+            Token::Action(p) => format!("ACTION==\"{}\"", pattern(p)),
+            Token::Kernel(p) => format!("KERNEL==\"{}\"", pattern(p)),
+            Token::Devtype(p) => format!("DEVTYPE==\"{}\"", pattern(p)),
+            Token::Driver(p) => format!("DRIVER==\"{}\"", pattern(p)),
+            Token::Tag(s) => format!("TAG==\"{}\"", resolve(s)),
+            Token::Name(p) => format!("NAME==\"{}\"", pattern(p)),
+            Token::Env(key, value) => format!("ENV{{{}}}==\"{}\"", resolve(key), pattern(value)),
+            Token::Devpath(glob) => format!("DEVPATH==\"{}\"", glob),
+        }
+    }
+
+    fn token_matches(&self, token: &Token, symbols: &EventSymbols, device: &UEventDevice) -> bool {
+        match token {
+            Token::Subsystem(p) => p.matches(&symbols.subsystem),
+            Token::Action(p) => p.matches(&symbols.action),
+            Token::Kernel(p) => p.matches(&symbols.kernel),
+            Token::Devtype(p) => p.matches(&symbols.devtype),
+            Token::Driver(p) => p.matches(&symbols.driver),
+            Token::Tag(s) => symbols.tags.contains(s),
+            Token::Name(p) => p.matches(&symbols.name),
+            // 缺少的属性按空字符串匹配，ENV{X}=="?*" 要求属性非空
+            Token::Env(key, value) => {
+                let property = device.property(self.interner.resolve(*key)).unwrap_or("");
+                match value {
+                    Pattern::Literal(symbol) => property == self.interner.resolve(*symbol),
+                    Pattern::Glob(glob) => glob.matches(property),
+                }
+            }
+            Token::Devpath(glob) => glob.matches(&symbols.devpath),
+        }
+    }
+}
+
+// 先放比较编号的 token，DEVPATH 的 glob 最慢放在最后
+fn compile_rule(rule: &Rule, interner: &mut Interner) -> CompiledRule {
+    let mut tokens = Vec::new();
+    let mut lower = |value: &str| Pattern::new(&value.to_lowercase(), interner);
+
+    if let Some(subsystem) = &rule.subsystem {
+        tokens.push(Token::Subsystem(lower(subsystem)));
+    }
+    if let Some(action) = &rule.action {
+        tokens.push(Token::Action(lower(action)));
+    }
+    if let Some(kernel) = &rule.kernel {
+        tokens.push(Token::Kernel(lower(kernel)));
+    }
+    if let Some(devtype) = &rule.devtype {
+        tokens.push(Token::Devtype(lower(devtype)));
+    }
+    if let Some(driver) = &rule.driver {
+        tokens.push(Token::Driver(lower(driver)));
+    }
+
+    // TAGS 在 udev 中还会向上查找父设备，目前只检查设备自身的标签
+    for tag in rule.tag.iter().chain(&rule.tags) {
+        tokens.push(Token::Tag(interner.intern(tag)));
+    }
+    if let Some(name) = &rule.name_match {
+        tokens.push(Token::Name(Pattern::new(name, interner)));
+    }
+    for (key, value) in &rule.env_vars {
+        tokens.push(Token::Env(interner.intern(key), Pattern::new(value, interner)));
+    }
+
+    if let Some(devpath) = &rule.devpath {
+        tokens.push(Token::Devpath(Glob::new(&devpath.to_lowercase())));
+    }
+
+    CompiledRule {
+        conditional: rule.has_conditions() && rule.matches_kernel_version(),
+        tokens,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::rules::parser::parse_rules_str;
+
+    fn device(kernel: &str, properties: &[(&str, &str)]) -> UEventDevice {
+        let mut event = HashMap::from([
+            ("ACTION".to_string(), "add".to_string()),
+            ("DEVPATH".to_string(), format!("/devices/pci0000:00/usb1/1-1/1-1:1.0/tty/{}", kernel)),
+            ("SUBSYSTEM".to_string(), "tty".to_string()),
+            ("SEQNUM".to_string(), "1".to_string()),
+        ]);
+        event.extend(properties.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+        UEventDevice::from_event(event).unwrap()
+    }
+
+    fn matching(rules: &str, device: &UEventDevice) -> Vec<usize> {
+        let compiled = CompiledRules::compile(&parse_rules_str(rules));
+        let symbols = compiled.prepare(device);
+        (0..compiled.len())
+            .filter(|&index| compiled.matches_event(index, &symbols, device))
+            .collect()
+    }
+
+    #[test]
+    fn kernel_globs_match() {
+        let rules = r#"
+KERNEL=="ttyUSB*", NAME="a"
+KERNEL=="ttyACM[0-9]|ttyUSB?", NAME="b"
+KERNEL=="ttyusb0", NAME="c"
+KERNEL=="ttyS*", NAME="d"
+"#;
+        assert_eq!(matching(rules, &device("ttyUSB0", &[])), vec![0, 1, 2]);
+        assert_eq!(matching(rules, &device("ttyACM3", &[])), vec![1]);
+    }
+
+    #[test]
+    fn env_globs_require_a_non_empty_value() {
+        let rules = r#"
+ENV{K}=="?*", NAME="a"
+ENV{K}=="", NAME="b"
+ENV{K}=="ABC123", NAME="c"
+"#;
+        assert_eq!(matching(rules, &device("ttyUSB0", &[("K", "ABC123")])), vec![0, 2]);
+        assert_eq!(matching(rules, &device("ttyUSB0", &[])), vec![1]);
+    }
+}
@@ -0,0 +1,166 @@
+// src/rules/glob.rs
+
+/// udev 风格的 glob 匹配：支持 *、?、[abc]、[a-z]、[!a] 以及用 | 分隔的多个备选模式
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    Glob::new(pattern).matches(text)
+}
+
+/// 预先拆分好备选项的 glob 模式，规则加载时编译一次，匹配时不再解析模式
+///
+/// 不含通配符的备选项直接比较字符串，只以一个 * 结尾的按前缀比较，其余逐字符匹配。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    alternatives: Vec<Alternative>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Alternative {
+    Literal(String),
+    Prefix(String),
+    Pattern(Vec<char>),
+}
+
+impl Alternative {
+    fn new(pattern: &str) -> Self {
+        let wildcard = |s: &str| s.contains(['*', '?', '[']);
+        match pattern.strip_suffix('*') {
+            _ if !wildcard(pattern) => Alternative::Literal(pattern.to_string()),
+            Some(prefix) if !wildcard(prefix) => Alternative::Prefix(prefix.to_string()),
+            _ => Alternative::Pattern(pattern.chars().collect()),
+        }
+    }
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            alternatives: pattern.split('|').map(Alternative::new).collect(),
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        // 只有需要逐字符匹配时才拆分文本
+        let mut chars: Option<Vec<char>> = None;
+        self.alternatives.iter().any(|alt| match alt {
+            Alternative::Literal(literal) => text == literal,
+            Alternative::Prefix(prefix) => text.starts_with(prefix.as_str()),
+            Alternative::Pattern(pattern) => {
+                match_chars(pattern, chars.get_or_insert_with(|| text.chars().collect()))
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Glob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let alternatives: Vec<String> = self
+            .alternatives
+            .iter()
+            .map(|alt| match alt {
+                Alternative::Literal(literal) => literal.clone(),
+                Alternative::Prefix(prefix) => format!("{}*", prefix),
+                Alternative::Pattern(pattern) => pattern.iter().collect(),
+            })
+            .collect();
+        write!(f, "{}", alternatives.join("|"))
+    }
+}
+
+/// 模式中第一个通配符之前的固定前缀；含 | 时返回每个备选的前缀
+pub fn literal_prefixes(pattern: &str) -> Vec<&str> {
+    pattern
+        .split('|')
+        .map(|alt| {
+            let end = alt.find(['*', '?', '[']).unwrap_or(alt.len());
+            &alt[..end]
+        })
+        .collect()
+}
+
+fn match_chars(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // 最近一个 * 的位置以及它当时对应的文本位置，用于回溯
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    star = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next)) = match_class(&pattern[p..], text[t]) {
+                        if matched {
+                            p += next;
+                            t += 1;
+                            continue;
+                        }
+                    } else if text[t] == '[' {
+                        // 没有闭合的 [ 按普通字符处理
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+                c if c == text[t] => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// 匹配 [...] 字符类，返回是否匹配以及字符类占用的模式长度；没有闭合时返回 None
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &ch = pattern.get(i)?;
+        if ch == ']' && !first {
+            break;
+        }
+        first = false;
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&end| end != ']') {
+            let end = pattern[i + 2];
+            if ch <= c && c <= end {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if ch == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+
+    Some((matched != negate, i + 1))
+}
@@ -1,33 +1,95 @@
 // src/rules/matcher.rs
 
-use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use log::*;
+
+use crate::actions::{run_program, substitute_vars};
+use crate::deferred::DeferredAction;
 use crate::device::UEventDevice;
+use crate::kernel::KernelVersion;
+use crate::rules::metrics::{self, TimingKind, SLOW_ATTR_THRESHOLD};
+use crate::rules::ruleset::RuleSetOptions;
+use crate::rules::tokenizer::Operator;
+use crate::rules::trace::Mismatch;
+
+/// OPTIONS+="string_escape=..."：NAME/SYMLINK 中替换进来的字符串如何处理不安全字符
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StringEscape {
+    /// 不安全字符替换为 '_'（默认）
+    #[default]
+    Replace,
+    /// 原样使用
+    None,
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rule {
     // 基本字段匹配
     pub action: Option<String>,
     pub kernel: Option<String>,
     pub subsystem: Option<String>,
+    pub devtype: Option<String>,
     pub driver: Option<String>,
     pub devpath: Option<String>,
     pub tag: Option<String>,
+    pub tags: Option<String>,
+
+    // KERNELVER>="5.10"：与运行中的内核版本比较，(运算符, 版本)
+    pub kernel_version: Vec<(Operator, KernelVersion)>,
 
     // 属性和环境变量匹配
     pub attr: Vec<(String, String)>,
     pub env_vars: Vec<(String, String)>,
 
-    // 文件创建控制
+    // 父设备匹配：KERNELS、SUBSYSTEMS、DRIVERS 和 ATTRS{} 必须由设备本身或同一个祖先设备同时满足
+    pub kernels: Option<String>,
+    pub subsystems: Option<String>,
+    pub drivers: Option<String>,
+    pub attrs: Vec<(String, String)>,
+
+    // 文件存在性检查，(== 或 !=, 可选的八进制权限掩码, 路径)
+    pub test: Vec<(Operator, Option<u32>, String)>,
+
+    // 标签赋值：TAG+= 添加，TAG-= 删除，TAG= 先清空再添加
+    pub tag_add: Vec<String>,
+    pub tag_remove: Vec<String>,
+    pub tag_reset: bool,
+
+    // sysfs 属性赋值，ATTR{key}="value"
+    pub attr_assign: Vec<(String, String)>,
+
+    // I2C_NEW_DEVICE="bmp280 0x76"：适配器出现时在其上实例化的设备
+    pub i2c_new_device: Vec<String>,
+
+    // 设备节点扩展属性，XATTR{trusted.name}="value"，只允许 trusted. 命名空间
+    pub xattr: Vec<(String, String)>,
+
+    // SECLABEL{selinux}="context"：设备节点和符号链接的 SELinux 安全上下文
+    pub seclabel: Option<String>,
+
+    // NAME=="..."：与之前的规则用 NAME= 指定的名字比较
+    pub name_match: Option<String>,
+
+    // 文件创建控制；NAME= 对 net 子系统是新的网卡名，对其它设备是替代 DEVNAME 的节点名
     pub name: Option<String>,
     pub symlink: Vec<String>,
     pub owner: Option<String>,
     pub group: Option<String>,
     pub mode: Option<String>,
 
-    // 运行操作
-    pub run: HashMap<String, Vec<String>>,
+    // 运行操作，按出现顺序排列；是否执行取决于规则是否匹配当前事件
+    pub run: Vec<String>,
+    // 本规则的 RUN 命令需在这些规则组的命令之后执行
+    pub run_after: Vec<String>,
     pub program: Option<String>,
+    // AT{30s}+="命令" 或 AT{30s,event}="change"：规则匹配后延迟执行的动作
+    pub at: Vec<(Duration, DeferredAction)>,
+
+    // 属性导入，(类型, 值)，如 ("program", "/bin/foo")
+    pub import: Vec<(String, String)>,
 
     // 内部跳转控制
     pub label: Option<String>,
@@ -36,79 +98,255 @@ pub struct Rule {
     // 其他标志
     pub ignore_device: bool,
     pub last_rule: bool,
+    // 多个设备声明同一符号链接时，优先级最高的设备获得该链接
+    pub link_priority: Option<i32>,
+    // watch / nowatch：是否监听设备节点的写入关闭
+    pub watch: Option<bool>,
+    // static_node=name：启动时按本规则的 OWNER/GROUP/MODE 创建的静态节点
+    pub static_node: Vec<String>,
+    pub string_escape: Option<StringEscape>,
+    // log_level=debug：规则匹配后，本事件剩余处理过程的日志至少输出到该级别
+    pub log_level: Option<LevelFilter>,
+    // reprobe=2s：设备属性可能还没准备好，延迟后合成 change 事件重新匹配
+    pub reprobe: Option<Duration>,
+
+    // 规则所属的组名，取自规则文件名去掉数字前缀和扩展名，如 60-persistent-storage.rules -> persistent-storage
+    pub source: Option<String>,
+    // 规则所在的文件和起始行号（从 1 开始）
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
 }
 
 impl Rule {
-    pub fn matches(&self, device: &UEventDevice) -> bool {
-        let has_conditions = self.action.is_some()
+    /// 规则所在的 文件:行号，用于日志
+    pub fn location(&self) -> String {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => format!("{}:{}", file.display(), line),
+            (Some(file), None) => file.display().to_string(),
+            _ => "<unknown>".to_string(),
+        }
+    }
+
+    /// 规则的赋值，按规则文件的写法列出，值未做变量替换
+    pub fn assignments(&self) -> Vec<String> {
+        let mut assignments = Vec::new();
+        let mut push = |key: &str, op: &str, value: &str| {
+            assignments.push(format!("{}{}\"{}\"", key, op, value));
+        };
+
+        for tag in &self.tag_add {
+            push("TAG", if self.tag_reset { "=" } else { "+=" }, tag);
+        }
+        for tag in &self.tag_remove {
+            push("TAG", "-=", tag);
+        }
+        for (attr, value) in &self.attr_assign {
+            push(&format!("ATTR{{{}}}", attr), "=", value);
+        }
+        for (name, value) in &self.xattr {
+            push(&format!("XATTR{{{}}}", name), "=", value);
+        }
+        if let Some(label) = &self.seclabel {
+            push("SECLABEL{selinux}", "=", label);
+        }
+        for (kind, value) in &self.import {
+            push(&format!("IMPORT{{{}}}", kind), "=", value);
+        }
+        if let Some(name) = &self.name {
+            push("NAME", "=", name);
+        }
+        for link in &self.symlink {
+            push("SYMLINK", "+=", link);
+        }
+        if let Some(owner) = &self.owner {
+            push("OWNER", "=", owner);
+        }
+        if let Some(group) = &self.group {
+            push("GROUP", "=", group);
+        }
+        if let Some(mode) = &self.mode {
+            push("MODE", "=", mode);
+        }
+        for command in &self.run {
+            push("RUN", "+=", command);
+        }
+        for spec in &self.i2c_new_device {
+            push("I2C_NEW_DEVICE", "+=", spec);
+        }
+        for (delay, action) in &self.at {
+            let (key, value) = action.assignment(*delay);
+            push(&key, "+=", &value);
+        }
+        assignments
+    }
+
+    /// 规则是否读取事件之外的状态（ATTR、父设备、TEST、PROGRAM）或会导入外部属性
+    pub fn reads_external_state(&self) -> bool {
+        !self.attr.is_empty()
+            || self.has_parent_conditions()
+            || !self.test.is_empty()
+            || self.program.is_some()
+            || !self.import.is_empty()
+    }
+
+    fn has_parent_conditions(&self) -> bool {
+        self.kernels.is_some() || self.subsystems.is_some() || self.drivers.is_some() || !self.attrs.is_empty()
+    }
+
+    pub(crate) fn has_conditions(&self) -> bool {
+        self.action.is_some()
             || self.subsystem.is_some()
+            || self.devtype.is_some()
             || self.kernel.is_some()
             || self.devpath.is_some()
             || self.driver.is_some()
             || self.tag.is_some()
+            || self.tags.is_some()
+            || self.name_match.is_some()
+            || !self.kernel_version.is_empty()
             || !self.env_vars.is_empty()
-            || !self.attr.is_empty();
+            || !self.attr.is_empty()
+            || self.has_parent_conditions()
+            || !self.test.is_empty()
+            || self.program.is_some()
+    }
 
-        if !has_conditions {
-            return false;
-        }
+    /// KERNELVER 条件是否都满足；读不到内核版本时这类规则一律不匹配
+    pub fn matches_kernel_version(&self) -> bool {
+        self.kernel_version.iter().all(|(op, version)| {
+            KernelVersion::running().is_some_and(|running| running.satisfies(*op, version))
+        })
+    }
 
-        if let Some(action) = &self.action {
-            let dev_action = format!("{:?}", device.action()).to_lowercase();
-            if dev_action != action.to_lowercase() {
-                return false;
+    // 检查读取 sysfs、文件系统或运行外部程序的条件，返回第一个不满足的条件；
+    // 事件本身的条件由 RuleSet 中编译好的 token 检查。options 给出 ATTR 和 PROGRAM 计时的时钟和 PROGRAM 的超时
+    pub(crate) fn external_mismatch(&self, device: &mut UEventDevice, options: &RuleSetOptions) -> Option<Mismatch> {
+        let clock = &*options.clock;
+        for (index, (key, value)) in self.attr.iter().enumerate() {
+            let started = clock.now();
+            let matched = device.sysattr(key).is_some_and(|content| content.trim_start() == value);
+            // 有的驱动读属性时要访问硬件，可能阻塞很久；缓存命中时不会超过阈值
+            let elapsed = clock.now().saturating_duration_since(started);
+            if elapsed >= SLOW_ATTR_THRESHOLD {
+                metrics::record(&self.location(), TimingKind::Attr, elapsed);
             }
-        }
-
-        if let Some(subsystem) = &self.subsystem {
-            if device.subsystem().to_lowercase() != subsystem.to_lowercase() {
-                return false;
+            if !matched {
+                return Some(Mismatch::Attr(index));
             }
         }
 
-        if let Some(kernel) = &self.kernel {
-            if device.kernel().map_or(true, |k| k.to_lowercase() != kernel.to_lowercase()) {
-                return false;
-            }
+        if self.has_parent_conditions() && !self.matches_parents(device) {
+            return Some(Mismatch::Parents);
         }
 
-        if let Some(devpath) = &self.devpath {
-            if device.devpath().to_string_lossy().to_lowercase() != devpath.to_lowercase() {
-                return false;
+        for (index, (op, mode, path)) in self.test.iter().enumerate() {
+            if test_file(path, *mode, device) == (*op == Operator::Nomatch) {
+                return Some(Mismatch::Test(index));
             }
         }
 
-        if let Some(driver) = &self.driver {
-            if device.driver().map_or(true, |d| d.to_lowercase() != driver.to_lowercase()) {
-                return false;
+        // PROGRAM 放在最后执行，避免为不匹配的规则启动外部进程
+        if let Some(program) = &self.program {
+            let started = clock.now();
+            let result = run_program(program, device, options.event_timeout);
+            metrics::record(&self.location(), TimingKind::Program, clock.now().saturating_duration_since(started));
+            match result {
+                Ok(Some(output)) => device.set_program_result(Some(output)),
+                Ok(None) => return Some(Mismatch::Program),
+                Err(e) => {
+                    warn!("Failed to execute PROGRAM '{}': {}", program, e);
+                    return Some(Mismatch::Program);
+                }
             }
         }
 
-        if let Some(tag) = &self.tag {
-            if device.properties().get("TAG").map_or(true, |t| t.to_lowercase() != tag.to_lowercase()) {
-                return false;
-            }
-        }
+        None
+    }
 
-        for (key, value) in &self.env_vars {
-            if device.properties().get(key).map_or(true, |v| v != value) {
-                return false;
+    // 设备本身算作第一级，父设备逐级从 sysfs 读取，直到某一级满足全部父设备条件
+    fn matches_parents(&self, device: &mut UEventDevice) -> bool {
+        if self.matches_parent(device) {
+            return true;
+        }
+        let mut parent = device.parent();
+        while let Some(mut candidate) = parent {
+            if self.matches_parent(&mut candidate) {
+                return true;
             }
+            parent = candidate.parent();
         }
+        false
+    }
+
+    // 与 KERNEL、SUBSYSTEM、DRIVER 一样不区分大小写，ATTRS{} 与 ATTR{} 一样忽略开头的空白
+    fn matches_parent(&self, device: &mut UEventDevice) -> bool {
+        let same = |expected: &Option<String>, actual: Option<&str>| {
+            expected.as_ref().is_none_or(|expected| expected.eq_ignore_ascii_case(actual.unwrap_or("")))
+        };
+        same(&self.kernels, device.kernel())
+            && same(&self.subsystems, Some(device.subsystem()))
+            && same(&self.drivers, device.driver())
+            && self
+                .attrs
+                .iter()
+                .all(|(key, value)| device.sysattr(key).is_some_and(|content| content.trim_start() == value))
+    }
 
-        let sys_path = device.syspath();
-        for (key, value) in &self.attr {
-            let attr_path = sys_path.join(key);
-            match std::fs::read_to_string(&attr_path) {
-                Ok(content) => {
-                    if content.trim() != value {
-                        return false;
+    /// 不匹配原因的可读描述，如 ATTR{size}=="0"
+    pub fn describe_mismatch(&self, mismatch: Mismatch) -> String {
+        match mismatch {
+            Mismatch::NoConditions => "rule has no conditions".to_string(),
+            Mismatch::KernelVersion(index) => {
+                let (op, version) = &self.kernel_version[index];
+                let running = KernelVersion::running()
+                    .map_or_else(|| "unknown".to_string(), |running| running.to_string());
+                format!("KERNELVER{}\"{}\" (running {})", op, version, running)
+            }
+            Mismatch::Attr(index) => {
+                let (key, value) = &self.attr[index];
+                format!("ATTR{{{}}}==\"{}\"", key, value)
+            }
+            Mismatch::Parents => {
+                let mut conditions = Vec::new();
+                let keys = [("KERNELS", &self.kernels), ("SUBSYSTEMS", &self.subsystems), ("DRIVERS", &self.drivers)];
+                for (key, value) in keys {
+                    if let Some(value) = value {
+                        conditions.push(format!("{}==\"{}\"", key, value));
                     }
                 }
-                Err(_) => return false,
+                conditions.extend(self.attrs.iter().map(|(key, value)| format!("ATTRS{{{}}}==\"{}\"", key, value)));
+                format!("no device in the parent chain matches {}", conditions.join(", "))
             }
+            Mismatch::Test(index) => match &self.test[index] {
+                (op, Some(mode), path) => format!("TEST{{{:o}}}{}\"{}\"", mode, op, path),
+                (op, None, path) => format!("TEST{}\"{}\"", op, path),
+            },
+            Mismatch::Program => format!("PROGRAM==\"{}\"", self.program.as_deref().unwrap_or("")),
+            // 事件条件由编译后的 token 描述
+            Mismatch::Event(index) => format!("event condition #{}", index + 1),
         }
+    }
+}
+
+/// TEST 键：替换变量后检查文件是否存在，相对路径以设备的 sysfs 目录为基准；
+/// 给定权限掩码时还要求文件权限与掩码有交集
+fn test_file(path: &str, mode: Option<u32>, device: &UEventDevice) -> bool {
+    let substituted = substitute_vars(path, device);
+    let path = Path::new(&substituted);
+    let full_path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        device.syspath().join(path)
+    };
 
-        true
+    match std::fs::metadata(&full_path) {
+        Ok(metadata) => match mode {
+            Some(mask) => metadata.permissions().mode() & mask != 0,
+            None => true,
+        },
+        Err(_) => {
+            debug!("TEST: {:?} does not exist", full_path);
+            false
+        }
     }
 }
@@ -0,0 +1,176 @@
+// src/rules/metrics.rs
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// 守护进程写出按规则汇总的耗时，udevadm info --stats 显示最慢的几条
+pub const RULE_METRICS_PATH: &str = "/run/rust_udev/rule_metrics";
+
+/// 读取时间达到这个值的 ATTR 条件才被记录
+pub const SLOW_ATTR_THRESHOLD: Duration = Duration::from_millis(10);
+
+/// udevadm info --stats 和状态接口显示的条目数
+pub const TOP_OFFENDERS: usize = 10;
+
+static TIMINGS: LazyLock<Mutex<HashMap<(String, TimingKind), RuleTiming>>> = LazyLock::new(Default::default);
+
+// 每次记录加一，守护进程据此判断是否需要重新写出文件
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// 计时的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimingKind {
+    /// PROGRAM 条件执行的程序
+    Program,
+    /// IMPORT{program} 执行的程序
+    Import,
+    /// RUN 命令，从启动到被回收
+    Run,
+    /// 超过 SLOW_ATTR_THRESHOLD 的 ATTR 条件读取
+    Attr,
+}
+
+impl TimingKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimingKind::Program => "PROGRAM",
+            TimingKind::Import => "IMPORT",
+            TimingKind::Run => "RUN",
+            TimingKind::Attr => "ATTR",
+        }
+    }
+}
+
+impl FromStr for TimingKind {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PROGRAM" => Ok(TimingKind::Program),
+            "IMPORT" => Ok(TimingKind::Import),
+            "RUN" => Ok(TimingKind::Run),
+            "ATTR" => Ok(TimingKind::Attr),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for TimingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 一条规则（文件:行号）中某类操作的累计耗时
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleTiming {
+    pub location: String,
+    pub kind: TimingKind,
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for RuleTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<7} {}: {} times, total {:.3}s, max {:.3}s",
+            self.kind,
+            self.location,
+            self.count,
+            self.total.as_secs_f64(),
+            self.max.as_secs_f64()
+        )
+    }
+}
+
+/// 记录 location 处的规则中一次操作的耗时
+pub fn record(location: &str, kind: TimingKind, elapsed: Duration) {
+    let mut timings = TIMINGS.lock().unwrap();
+    let timing = timings
+        .entry((location.to_string(), kind))
+        .or_insert_with(|| RuleTiming {
+            location: location.to_string(),
+            kind,
+            count: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        });
+    timing.count += 1;
+    timing.total += elapsed;
+    timing.max = timing.max.max(elapsed);
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// 到目前为止的全部记录，按总耗时从高到低排序
+pub fn snapshot() -> Vec<RuleTiming> {
+    let mut timings: Vec<RuleTiming> = TIMINGS.lock().unwrap().values().cloned().collect();
+    sort_by_total(&mut timings);
+    timings
+}
+
+fn sort_by_total(timings: &mut [RuleTiming]) {
+    timings.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.location.cmp(&b.location)));
+}
+
+/// 每行 "kind\tcount\ttotal_us\tmax_us\tlocation"；location 放在最后，可以含有制表符以外的任何字符
+pub fn save<P: AsRef<Path>>(path: P, timings: &[RuleTiming]) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let content: String = timings
+        .iter()
+        .map(|timing| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                timing.kind,
+                timing.count,
+                timing.total.as_micros(),
+                timing.max.as_micros(),
+                timing.location
+            )
+        })
+        .collect();
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// 读取 save 写出的文件，按总耗时从高到低排序；格式不对的行被忽略
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<RuleTiming>> {
+    let content = fs::read_to_string(path)?;
+    let mut timings: Vec<RuleTiming> = content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            let kind = fields.next()?.parse().ok()?;
+            let count = fields.next()?.parse().ok()?;
+            let total = Duration::from_micros(fields.next()?.parse().ok()?);
+            let max = Duration::from_micros(fields.next()?.parse().ok()?);
+            let location = fields.next()?.to_string();
+            Some(RuleTiming {
+                location,
+                kind,
+                count,
+                total,
+                max,
+            })
+        })
+        .collect();
+    sort_by_total(&mut timings);
+    Ok(timings)
+}
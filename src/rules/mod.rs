@@ -1,2 +1,10 @@
+pub mod cache;
+pub mod compiled;
+pub mod glob;
 pub mod matcher;
-pub mod parser;
\ No newline at end of file
+pub mod metrics;
+pub mod parser;
+pub mod ruleset;
+pub mod security;
+pub mod tokenizer;
+pub mod trace;
@@ -1,44 +1,130 @@
-use crate::rules::matcher::Rule;
+use crate::actions::unknown_substitutions;
+use crate::builtins::i2c_new_device::parse_spec as parse_i2c_spec;
+use crate::cancel::CancellationToken;
+use crate::clock::{system_clock, Clock};
+use crate::deferred::{DeferredAction, MAX_DEFERRED_DELAY};
+use crate::kernel::KernelVersion;
+use crate::rules::matcher::{Rule, StringEscape};
+use crate::rules::ruleset::{RuleSet, RuleSetOptions, SharedRules};
+use crate::rules::tokenizer::{tokenize, Operator};
+use crate::logging::parse_level;
+use crate::xattr::TRUSTED_NAMESPACE;
 use log::*;
-use regex::Regex;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use notify::event::ModifyKind;
 use notify::{Watcher, RecommendedWatcher, RecursiveMode, EventKind};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
-use crossbeam::channel::{unbounded, Receiver};
+use std::time::{Duration, Instant, SystemTime};
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError};
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct RuleManager {
-    rules: Arc<Mutex<Vec<Rule>>>,
-    watcher: RecommendedWatcher,
+    rules: Arc<SharedRules>,
+    // 无法监视文件时为 None，改为定期重新扫描
+    watcher: Option<RecommendedWatcher>,
     paths: Vec<PathBuf>,
+    embedded: Vec<Rule>,
+    options: RuleSetOptions,
+    // drop 时取消，让重新加载线程退出
+    token: CancellationToken,
+}
+
+/// 无法监视规则目录时重新扫描规则文件的间隔
+pub const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// RuleManager 无法建立对规则目录的监视
+#[derive(Debug)]
+pub enum RuleManagerError {
+    /// 创建 inotify 实例失败，例如达到了 fs.inotify.max_user_instances
+    Watcher(notify::Error),
+}
+
+impl fmt::Display for RuleManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleManagerError::Watcher(e) => write!(f, "cannot watch rules directories: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RuleManagerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RuleManagerError::Watcher(e) => Some(e),
+        }
+    }
+}
+
+/// 标准规则目录，按优先级从低到高排列
+pub const RULES_DIRS: [&str; 3] = ["/usr/lib/udev/rules.d", "/run/udev/rules.d", "/etc/udev/rules.d"];
+
+/// 标准规则目录组成的分层布局
+pub fn default_rules_dirs() -> Vec<PathBuf> {
+    RULES_DIRS.iter().map(PathBuf::from).collect()
 }
 
 impl RuleManager {
+    /// rule_paths 按优先级从低到高排列，同名文件以后面的目录为准
+    ///
+    /// 无法监视规则目录时不会失败，改为每隔 RESCAN_INTERVAL 重新扫描。
     pub fn new(rule_paths: Vec<PathBuf>) -> Self {
-        // 初始加载规则
-        let rules = match load_all_rules(&rule_paths) {
-            Ok(r) => Arc::new(Mutex::new(r)),
+        Self::with_embedded(rule_paths, Vec::new())
+    }
+
+    /// 与 new 相同，但无法监视规则目录时返回错误
+    pub fn try_new(rule_paths: Vec<PathBuf>) -> Result<Self, RuleManagerError> {
+        Self::try_with_embedded(rule_paths, Vec::new())
+    }
+
+    /// 在规则文件之前加入内置规则，重新加载时保留；规则文件中的赋值可以覆盖它们
+    pub fn with_embedded(rule_paths: Vec<PathBuf>, embedded: Vec<Rule>) -> Self {
+        Self::with_options(rule_paths, embedded, RuleSetOptions::default())
+    }
+
+    /// 与 with_embedded 相同，每次加载都按 options 编译规则
+    pub fn with_options(rule_paths: Vec<PathBuf>, embedded: Vec<Rule>, options: RuleSetOptions) -> Self {
+        match Self::try_with_options(rule_paths.clone(), embedded.clone(), options.clone()) {
+            Ok(manager) => manager,
             Err(e) => {
-                warn!("Failed to load initial rules: {}", e);
-                Arc::new(Mutex::new(Vec::new()))
+                warn!("{}, rescanning rules every {:?} instead", e, RESCAN_INTERVAL);
+                Self::rescanning(rule_paths, embedded, options)
             }
-        };
+        }
+    }
 
+    /// 与 with_embedded 相同，但无法监视规则目录时返回错误
+    pub fn try_with_embedded(
+        rule_paths: Vec<PathBuf>,
+        embedded: Vec<Rule>,
+    ) -> Result<Self, RuleManagerError> {
+        Self::try_with_options(rule_paths, embedded, RuleSetOptions::default())
+    }
+
+    /// 与 with_options 相同，但无法监视规则目录时返回错误
+    pub fn try_with_options(
+        rule_paths: Vec<PathBuf>,
+        embedded: Vec<Rule>,
+        options: RuleSetOptions,
+    ) -> Result<Self, RuleManagerError> {
         let (tx, rx) = unbounded();
 
         let mut watcher = notify::recommended_watcher(move |res| {
             if let Ok(event) = res {
-                tx.send(event).unwrap();
+                // 重新加载线程已经退出时丢弃事件
+                let _ = tx.send(event);
             }
         })
-        .unwrap();
+        .map_err(RuleManagerError::Watcher)?;
 
-        for path in &rule_paths {
+        for path in rule_paths.iter().filter(|path| path.is_dir()) {
             watcher
                 .watch(path, RecursiveMode::NonRecursive)
                 .unwrap_or_else(|e| {
@@ -46,165 +132,1142 @@ impl RuleManager {
                 });
         }
 
+        let rules = load_initial_rules(&rule_paths, &embedded, &options);
+        let token = CancellationToken::new();
+        let rules_clone = rules.clone();
+        let paths_clone = rule_paths.clone();
+        let embedded_clone = embedded.clone();
+        let options_clone = options.clone();
+        let token_clone = token.clone();
+        thread::spawn(move || {
+            Self::reload_loop(rx, rules_clone, paths_clone, embedded_clone, options_clone, token_clone);
+        });
+
+        Ok(Self {
+            rules,
+            watcher: Some(watcher),
+            paths: rule_paths,
+            embedded,
+            options,
+            token,
+        })
+    }
+
+    // 没有文件监视时的退路：定期比较规则文件列表和修改时间
+    fn rescanning(rule_paths: Vec<PathBuf>, embedded: Vec<Rule>, options: RuleSetOptions) -> Self {
+        let rules = load_initial_rules(&rule_paths, &embedded, &options);
+        let token = CancellationToken::new();
         let rules_clone = rules.clone();
         let paths_clone = rule_paths.clone();
+        let embedded_clone = embedded.clone();
+        let options_clone = options.clone();
+        let token_clone = token.clone();
         thread::spawn(move || {
-            Self::reload_loop(rx, rules_clone, paths_clone);
+            Self::rescan_loop(rules_clone, paths_clone, embedded_clone, options_clone, token_clone);
         });
 
         Self {
             rules,
-            watcher,
+            watcher: None,
             paths: rule_paths,
+            embedded,
+            options,
+            token,
         }
     }
 
-    pub fn get_rules(&self) -> Arc<Mutex<Vec<Rule>>> {
+    /// 是否通过文件监视发现规则变化；否则靠定期重新扫描
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    /// 当前规则的快照，重新加载不影响已经取得的快照
+    pub fn get_rules(&self) -> Arc<RuleSet> {
+        self.rules.load()
+    }
+
+    /// 不等文件变化，立即重新读取规则文件，比如 udevadm control --reload
+    pub fn reload(&self) {
+        reload_rules(&self.rules, &self.paths, &self.embedded, &self.options);
+    }
+
+    /// 始终指向最新规则的句柄
+    pub fn shared(&self) -> Arc<SharedRules> {
         self.rules.clone()
     }
 
-    fn reload_loop(rx: Receiver<notify::Event>, rules: Arc<Mutex<Vec<Rule>>>, paths: Vec<PathBuf>) {
-        for event in rx {
-            if matches!(event.kind, EventKind::Modify(_)) {
-                info!("Rules directory modified, triggering reload...");
-                match load_all_rules(&paths) {
-                    Ok(new_rules) => {
-                        *rules.lock().unwrap() = new_rules;
-                        info!(
-                            "Successfully reloaded {} rules",
-                            rules.lock().unwrap().len()
-                        );
-                    }
-                    Err(e) => warn!("Rule reload failed: {}", e),
-                }
+    // 规则文件的新建、删除、改名和修改都触发重新加载；一批连续的变化只加载一次。
+    // RuleManager 被 drop 时监视器随之关闭，通道断开，线程退出
+    fn reload_loop(
+        rx: Receiver<notify::Event>,
+        rules: Arc<SharedRules>,
+        paths: Vec<PathBuf>,
+        embedded: Vec<Rule>,
+        options: RuleSetOptions,
+        token: CancellationToken,
+    ) {
+        let mut debouncer = ReloadDebouncer::new(system_clock());
+        while !token.is_cancelled() {
+            let event = match debouncer.remaining() {
+                Some(timeout) => match rx.recv_timeout(timeout) {
+                    Ok(event) => Some(event),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                },
+                None => match rx.recv() {
+                    Ok(event) => Some(event),
+                    Err(_) => return,
+                },
+            };
+            if let Some(event) = event.filter(affects_rules) {
+                debug!("Rules changed: {:?} {:?}", event.kind, event.paths);
+                debouncer.notice();
+            }
+
+            if !debouncer.take_due() {
+                continue;
+            }
+            info!("Rules directory changed, triggering reload...");
+            reload_rules(&rules, &paths, &embedded, &options);
+        }
+    }
+
+    fn rescan_loop(
+        rules: Arc<SharedRules>,
+        paths: Vec<PathBuf>,
+        embedded: Vec<Rule>,
+        options: RuleSetOptions,
+        token: CancellationToken,
+    ) {
+        let mut last = rules_fingerprint(&paths);
+        while !token.wait_timeout(RESCAN_INTERVAL) {
+            let current = rules_fingerprint(&paths);
+            if current != last {
+                info!("Rules changed on rescan, triggering reload...");
+                reload_rules(&rules, &paths, &embedded, &options);
+                last = current;
             }
         }
     }
 }
 
-fn load_all_rules<P: AsRef<Path>>(paths: &[P]) -> io::Result<Vec<Rule>> {
-    let mut all_rules = Vec::new();
-    for path in paths {
-        let path = path.as_ref();
-        if let Ok(rules) = parse_rules_file(path) {
-            all_rules.extend(rules);
+impl Drop for RuleManager {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+fn load_initial_rules(paths: &[PathBuf], embedded: &[Rule], options: &RuleSetOptions) -> Arc<SharedRules> {
+    match load_all_rules(paths, embedded, options.max_file_size) {
+        Ok(r) => Arc::new(SharedRules::new(RuleSet::with_options(r, options))),
+        Err(e) => {
+            warn!("Failed to load initial rules: {}", e);
+            Arc::new(SharedRules::default())
         }
     }
-    Ok(all_rules)
 }
 
+fn reload_rules(rules: &SharedRules, paths: &[PathBuf], embedded: &[Rule], options: &RuleSetOptions) {
+    match load_all_rules(paths, embedded, options.max_file_size) {
+        Ok(new_rules) => {
+            // 先在锁外编译好新规则，替换时只短暂持有写锁
+            let new_rules = RuleSet::with_options(new_rules, options);
+            let after = new_rules.len();
+            let before = rules.store(new_rules).len();
+            info!(
+                "Successfully reloaded {} rules ({:+})",
+                after,
+                after as i64 - before as i64
+            );
+        }
+        Err(e) => warn!("Rule reload failed: {}", e),
+    }
+}
+
+// 生效的规则文件及其修改时间和大小，任何一项变化都需要重新加载
+fn rules_fingerprint(paths: &[PathBuf]) -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+    rule_files(paths)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|path| {
+            let metadata = fs::metadata(&path).ok();
+            let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+            let len = metadata.map_or(0, |m| m.len());
+            (path, modified, len)
+        })
+        .collect()
+}
+
+/// 同一批变化中最后一次变化之后等待的安静时间
+pub const RELOAD_QUIET: Duration = Duration::from_millis(200);
+
+/// 变化持续不断时，自第一次变化起最多推迟这么久
+pub const RELOAD_MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// 合并规则目录的连续变化：安装软件包或编辑器保存时往往在很短时间内产生多个事件
+#[derive(Debug)]
+pub struct ReloadDebouncer {
+    clock: Arc<dyn Clock>,
+    // 本批第一次和最近一次变化的时间
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl ReloadDebouncer {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            first: None,
+            last: None,
+        }
+    }
+
+    /// 记录一次变化
+    pub fn notice(&mut self) {
+        let now = self.clock.now();
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        Some((self.last? + RELOAD_QUIET).min(self.first? + RELOAD_MAX_DELAY))
+    }
+
+    /// 距离应当重新加载还有多久；没有待处理的变化时为 None
+    pub fn remaining(&self) -> Option<Duration> {
+        Some(self.deadline()?.saturating_duration_since(self.clock.now()))
+    }
+
+    /// 已经到了重新加载的时间时返回 true 并开始新的一批
+    pub fn take_due(&mut self) -> bool {
+        match self.deadline() {
+            Some(deadline) if deadline <= self.clock.now() => {
+                self.first = None;
+                self.last = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// 只关心 .rules 文件；改名事件中旧名字或新名字是 .rules 都算
+fn affects_rules(event: &notify::Event) -> bool {
+    let kind_matches = match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(kind) => !matches!(kind, ModifyKind::Metadata(_)),
+        _ => false,
+    };
+    kind_matches
+        && event
+            .paths
+            .iter()
+            .any(|path| path.extension().is_some_and(|ext| ext == "rules"))
+}
+
+/// 与 RuleManager 加载的规则相同：内置规则在前，然后是各目录的规则文件，语法问题只打印日志
+pub fn load_all_rules<P: AsRef<Path>>(paths: &[P], embedded: &[Rule], max_file_size: u64) -> io::Result<Vec<Rule>> {
+    let report = parse_rules_with_errors(paths, max_file_size)?;
+    log_parse_errors(&report.diagnostics);
+    let mut rules = embedded.to_vec();
+    rules.extend(report.rules);
+    Ok(rules)
+}
+
+/// 语法问题的类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParseErrorKind {
+    /// 不认识的键
+    UnknownKey,
+    /// 键合法但不支持该操作符
+    InvalidOperator,
+    /// 引号没有闭合
+    UnbalancedQuote,
+    /// 键和操作符合法但取值无效
+    InvalidValue,
+    /// GOTO 找不到对应的 LABEL
+    MissingLabel,
+    /// RUN、OWNER、GROUP、MODE 中有不认识的 %x / $name
+    InvalidSubstitution,
+    /// 其它词法错误
+    Syntax,
+    /// 不是普通文件或超过大小上限，整个文件被跳过
+    SkippedFile,
+    /// 匹配条件有问题，整条规则没有加载
+    SkippedRule,
+}
+
+impl ParseErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseErrorKind::UnknownKey => "unknown-key",
+            ParseErrorKind::InvalidOperator => "invalid-operator",
+            ParseErrorKind::UnbalancedQuote => "unbalanced-quote",
+            ParseErrorKind::InvalidValue => "invalid-value",
+            ParseErrorKind::MissingLabel => "missing-label",
+            ParseErrorKind::InvalidSubstitution => "invalid-substitution",
+            ParseErrorKind::Syntax => "syntax",
+            ParseErrorKind::SkippedFile => "skipped-file",
+            ParseErrorKind::SkippedRule => "skipped-rule",
+        }
+    }
+}
+
+impl std::fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 规则文件中的语法问题
+#[derive(Debug, Clone)]
+pub struct RuleParseError {
+    pub file: PathBuf,
+    /// 行号，从 1 开始；针对整个文件的问题为 0
+    pub line: usize,
+    /// 出错位置的列号，从 1 开始；针对整行的问题为 None
+    pub column: Option<usize>,
+    pub kind: ParseErrorKind,
+    pub message: String,
+}
+
+/// 一次解析的结果：成功解析的规则以及全部诊断信息
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    pub rules: Vec<Rule>,
+    pub diagnostics: Vec<RuleParseError>,
+}
+
+impl ParseReport {
+    pub fn is_clean(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn diagnostics_of(&self, kind: ParseErrorKind) -> impl Iterator<Item = &RuleParseError> {
+        self.diagnostics.iter().filter(move |e| e.kind == kind)
+    }
+
+    fn extend(&mut self, other: ParseReport) {
+        self.rules.extend(other.rules);
+        self.diagnostics.extend(other.diagnostics);
+    }
+}
+
+impl std::fmt::Display for RuleParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.column {
+            Some(column) => write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file.display(),
+                self.line,
+                column,
+                self.message
+            ),
+            None if self.line == 0 => write!(f, "{}: {}", self.file.display(), self.message),
+            None => write!(f, "{}:{}: {}", self.file.display(), self.line, self.message),
+        }
+    }
+}
+
+/// 解析单个规则文件，语法问题只打印日志
 pub fn parse_rules_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<Rule>> {
+    let report = parse_rules_file_with_errors(path, DEFAULT_MAX_RULES_FILE_SIZE)?;
+    log_parse_errors(&report.diagnostics);
+    Ok(report.rules)
+}
 
-    let mut rules = Vec::new();
+/// 按文件名排序解析目录中的所有 .rules 文件，语法问题只打印日志
+pub fn parse_rules_dir<P: AsRef<Path>>(path: P) -> io::Result<Vec<Rule>> {
+    let report = parse_rules_with_errors(&[path], DEFAULT_MAX_RULES_FILE_SIZE)?;
+    log_parse_errors(&report.diagnostics);
+    Ok(report.rules)
+}
 
-    let kv_re = Regex::new(
-        r#"(?P<key>[A-Z_]+|ENV\{.*?\}|ATTR\{.*?\}|OPTIONS)(?P<op>==|\+=|\=)(?P<val>".*?")"#,
-    )
-    .unwrap();
+/// 解析内存中的规则文本，语法问题只打印日志
+pub fn parse_rules_str(content: &str) -> Vec<Rule> {
+    let report = parse_rules_str_with_errors(content, Path::new(INLINE_RULES));
+    log_parse_errors(&report.diagnostics);
+    report.rules
+}
 
-    let path = path.as_ref();
+/// 配置文件 rule= 和命令行 --rule 给出的规则在日志和错误信息中显示的文件名，行号为第几条规则
+pub const INLINE_RULES_NAME: &str = "<inline>";
+
+/// 解析配置文件和命令行中的单行规则，每个字符串是一条规则
+pub fn parse_inline_rules(rules: &[String]) -> ParseReport {
+    parse_rules_str_with_errors(&rules.join("\n"), Path::new(INLINE_RULES_NAME))
+}
+
+// 解析器认识的不带 {attr} 的键，用于区分未知键和不支持的操作符
+const KNOWN_KEYS: &[&str] = &[
+    "ACTION", "DEVPATH", "DEVTYPE", "DRIVER", "DRIVERS", "GOTO", "GROUP", "I2C_NEW_DEVICE", "KERNEL", "KERNELS",
+    "KERNELVER", "LABEL", "MODE", "NAME", "OPTIONS", "OWNER", "PROGRAM", "RUN", "RUN_AFTER", "SUBSYSTEM", "SUBSYSTEMS",
+    "SYMLINK", "TAG", "TAGS", "TEST",
+];
 
-    let mut entries: Vec<_> = path.read_dir()?.filter_map(Result::ok).collect();
+// IMPORT{} 支持的类型，与 plan.rs 中 apply_rule_effects 处理的一致
+const IMPORT_KINDS: &[&str] = &["program", "builtin", "file", "cmdline"];
 
-    entries.sort_by_key(|entry| {
-        entry
-            .file_name()
+// parse_rules_str 的规则在错误信息中显示的文件名
+const INLINE_RULES: &str = "<string>";
+
+fn log_parse_errors(errors: &[RuleParseError]) {
+    for e in errors {
+        warn!("[{}] {}", e.kind, e);
+    }
+}
+
+/// 把以 \ 结尾的行与下一行拼接，返回逻辑行及其起始行的下标（从 0 开始）
+fn join_continued_lines(lines: impl Iterator<Item = String>) -> Vec<(usize, String)> {
+    let mut joined = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (index, line) in lines.enumerate() {
+        let (start, mut text) = pending.take().unwrap_or((index, String::new()));
+        match line.strip_suffix('\\') {
+            Some(head) => {
+                text.push_str(head);
+                pending = Some((start, text));
+            }
+            None => {
+                text.push_str(&line);
+                joined.push((start, text));
+            }
+        }
+    }
+
+    // 文件最后一行以 \ 结尾时按普通行处理
+    joined.extend(pending);
+    joined
+}
+
+/// 按 udev 约定收集各目录中生效的 .rules 文件，dirs 按优先级从低到高排列
+///
+/// 高优先级目录中的同名文件覆盖低优先级目录中的文件；内容为空或指向 /dev/null
+/// 的文件会屏蔽该名字。不存在的目录直接跳过。
+pub fn rule_files<P: AsRef<Path>>(dirs: &[P]) -> io::Result<Vec<PathBuf>> {
+    let mut files: BTreeMap<OsString, Option<PathBuf>> = BTreeMap::new();
+
+    for dir in dirs {
+        let entries = match dir.as_ref().read_dir() {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            // 编辑器的交换文件、备份文件（.swp、~、.bak 等）扩展名都不是 .rules；
+            // 隐藏文件与 udev 一样跳过，比如某些编辑器保存时的 .foo.rules
+            if path.extension().is_none_or(|ext| ext != "rules")
+                || entry.file_name().as_encoded_bytes().starts_with(b".")
+            {
+                debug!("Ignoring {} in rules directory", path.display());
+                continue;
+            }
+            let masked = is_masked(&path);
+            files.insert(entry.file_name(), (!masked).then_some(path));
+        }
+    }
+
+    let mut files: Vec<PathBuf> = files.into_values().flatten().collect();
+    // 按文件名中的第一个数字排序，数字相同时按文件名
+    files.sort_by_key(|path| {
+        path.file_name()
+            .unwrap_or_default()
             .to_string_lossy()
-            .split(|c: char| !c.is_digit(10))
+            .split(|c: char| !c.is_ascii_digit())
             .filter_map(|s| s.parse::<u32>().ok())
             .next()
             .unwrap_or(0)
     });
+    Ok(files)
+}
 
-    // println!("entries: {:?}", entries);
+// AT{30s} 或 AT{30s,event} 中的延迟，以及是否合成事件
+fn parse_at(spec: &str) -> Result<(Duration, bool), String> {
+    let (delay, event) = match spec.split_once(',') {
+        Some((delay, "event")) => (delay, true),
+        Some((_, kind)) => return Err(format!("unknown AT type '{}', expected event", kind)),
+        None => (spec, false),
+    };
+    let delay = parse_delay(delay).ok_or_else(|| format!("invalid AT delay '{}'", delay))?;
+    if delay > MAX_DEFERRED_DELAY {
+        return Err(format!("AT delay '{}' is longer than {:?}", spec, MAX_DEFERRED_DELAY));
+    }
+    Ok((delay, event))
+}
 
-    for entry in entries {
-        // println!("sub_path: {:?}", entry.path());
+/// max_rules_file_size= 等处的大小：字节数，或带 K、M、G 后缀（1024 进制）
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let shift = match unit {
+        "" => 0,
+        "K" | "k" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => return None,
+    };
+    number.checked_mul(1 << shift)
+}
 
-        let file = File::open(entry.path())?;
-        let reader = io::BufReader::new(file);
+/// reprobe= 等处的时长：500ms、2s、1m，不带单位时按秒
+pub fn parse_delay(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "m" | "min" => Some(Duration::from_secs(number * 60)),
+        _ => None,
+    }
+}
 
-        for line in reader.lines().flatten() {
-            let line = line.trim();
-            if line.starts_with('#') || line.is_empty() {
+// 空文件或指向 /dev/null 的链接用于屏蔽低优先级目录中的同名文件
+fn is_masked(path: &Path) -> bool {
+    if fs::canonicalize(path).is_ok_and(|target| target == Path::new("/dev/null")) {
+        return true;
+    }
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() == 0)
+}
+
+/// 与 parse_rules_dir 相同，但读取按优先级叠加的多个目录，并把语法问题返回给调用方而不是只打印日志
+pub fn parse_rules_with_errors<P: AsRef<Path>>(dirs: &[P], max_file_size: u64) -> io::Result<ParseReport> {
+    let mut report = ParseReport::default();
+    for file_path in rule_files(dirs)? {
+        report.extend(parse_rules_file_with_errors(&file_path, max_file_size)?);
+    }
+    Ok(report)
+}
+
+/// 规则文件默认的大小上限
+pub const DEFAULT_MAX_RULES_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// 解析单个规则文件，语法问题返回给调用方
+///
+/// 不是普通文件（FIFO、设备节点、目录等）或超过 max_file_size 的文件不读取，
+/// 只返回一条 SkippedFile 诊断：FIFO 会让加载卡住，打开设备节点可能有副作用，超大的文件会耗尽内存。
+pub fn parse_rules_file_with_errors<P: AsRef<Path>>(path: P, max_file_size: u64) -> io::Result<ParseReport> {
+    let path = path.as_ref();
+    let content = match read_rules_file(path, max_file_size)? {
+        Ok(content) => content,
+        Err(message) => {
+            return Ok(ParseReport {
+                rules: Vec::new(),
+                diagnostics: vec![RuleParseError {
+                    file: path.to_path_buf(),
+                    line: 0,
+                    column: None,
+                    kind: ParseErrorKind::SkippedFile,
+                    message,
+                }],
+            })
+        }
+    };
+    // 非 UTF-8 内容按替换字符处理，不让整个文件失败
+    Ok(parse_rules_str_with_errors(&String::from_utf8_lossy(&content), path))
+}
+
+// 不是普通文件的原因
+fn file_kind(file_type: fs::FileType) -> &'static str {
+    if file_type.is_fifo() {
+        "a FIFO"
+    } else if file_type.is_char_device() || file_type.is_block_device() {
+        "a device node"
+    } else if file_type.is_socket() {
+        "a socket"
+    } else if file_type.is_dir() {
+        "a directory"
+    } else {
+        "not a regular file"
+    }
+}
+
+// 先按路径检查类型，不是普通文件就不打开：打开 /dev/watchdog 一类的字符设备本身就有副作用。
+// 检查之后文件可能被替换，所以以非阻塞方式打开并对打开的文件再检查一次。
+// 内层的 Err 是跳过这个文件的原因
+fn read_rules_file(path: &Path, limit: u64) -> io::Result<Result<Vec<u8>, String>> {
+    let file_type = fs::metadata(path)?.file_type();
+    if !file_type.is_file() {
+        return Ok(Err(format!("skipped, {}", file_kind(file_type))));
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+        .open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.file_type().is_file() {
+        return Ok(Err(format!("skipped, {}", file_kind(metadata.file_type()))));
+    }
+
+    if metadata.len() > limit {
+        return Ok(Err(format!("skipped, {} bytes exceeds the {} byte limit", metadata.len(), limit)));
+    }
+    // 文件在检查之后还可能继续变大
+    let mut content = Vec::with_capacity(metadata.len() as usize);
+    file.by_ref().take(limit + 1).read_to_end(&mut content)?;
+    if content.len() as u64 > limit {
+        return Ok(Err(format!("skipped, grew beyond the {} byte limit while reading", limit)));
+    }
+    Ok(Ok(content))
+}
+
+/// 解析内存中的规则文本；file 只用于错误信息和规则来源
+pub fn parse_rules_str_with_errors(content: &str, file: &Path) -> ParseReport {
+    let source = rule_source(file);
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, line) in join_continued_lines(content.lines().map(String::from)) {
+        let trimmed = line.trim();
+        if trimmed.starts_with('#') || trimmed.is_empty() {
+            continue;
+        }
+
+        let mut report_at = |column: Option<usize>, kind: ParseErrorKind, message: String| {
+            errors.push(RuleParseError {
+                file: file.to_path_buf(),
+                line: index + 1,
+                column,
+                kind,
+                message,
+            });
+        };
+
+        // 有词法错误的行整行跳过，与 udev 一致
+        let tokens = match tokenize(&line) {
+            Ok(tokens) if tokens.is_empty() => {
+                report_at(
+                    None,
+                    ParseErrorKind::Syntax,
+                    format!("no valid KEY<op>\"value\" pairs in '{}'", trimmed),
+                );
                 continue;
             }
+            Ok(tokens) => tokens,
+            Err(e) => {
+                let kind = if e.unterminated_quote {
+                    ParseErrorKind::UnbalancedQuote
+                } else {
+                    ParseErrorKind::Syntax
+                };
+                report_at(Some(e.column), kind, e.message);
+                continue;
+            }
+        };
 
-            let mut rule = Rule {
-                action: None,
-                kernel: None,
-                subsystem: None,
-                driver: None,
-                devpath: None,
-                tag: None,
-                attr: Vec::new(),
-                env_vars: Vec::new(),
-                name: None,
-                symlink: Vec::new(),
-                owner: None,
-                group: None,
-                mode: None,
-                run: HashMap::new(),
-                program: None,
-                label: None,
-                goto: None,
-                ignore_device: false,
-                last_rule: false,
-            };
+        let mut rule = Rule {
+            source: Some(source.clone()),
+            file: Some(file.to_path_buf()),
+            line: Some(index + 1),
+            ..Rule::default()
+        };
 
-            for cap in kv_re.captures_iter(line) {
-                let raw_key = &cap["key"];
-                let op = &cap["op"];
-                let val = cap["val"].trim_matches('"').to_string();
-
-                if raw_key.starts_with("ENV{") {
-                    let key = raw_key.trim_start_matches("ENV{").trim_end_matches('}');
-                    rule.env_vars.push((key.to_string(), val));
-                } else if raw_key.starts_with("ATTR{") {
-                    let key = raw_key.trim_start_matches("ATTR{").trim_end_matches('}');
-                    rule.attr.push((key.to_string(), val));
-                } else {
-                    match (raw_key, op) {
-                        ("ACTION", "==") => rule.action = Some(val),
-                        ("KERNEL", "==") => rule.kernel = Some(val),
-                        ("SUBSYSTEM", "==") => rule.subsystem = Some(val),
-                        ("DRIVER", "==") => rule.driver = Some(val),
-                        ("DEVPATH", "==") => rule.devpath = Some(val),
-                        ("TAG", "==") => rule.tag = Some(val),
-                        ("NAME", "==") => rule.name = Some(val),
-                        ("SYMLINK", "+=") => rule.symlink.push(val),
-                        ("OWNER", "=") => rule.owner = Some(val),
-                        ("GROUP", "=") => rule.group = Some(val),
-                        ("MODE", "=") => rule.mode = Some(val),
-                        ("RUN", "+=") => {
-                            if let Some(action) = &rule.action {
-                                rule.run.entry(action.clone()).or_default().push(val);
-                            } else {
-                                warn!(
-                                    "RUN+=... found without ACTION==..., ignoring command: {}",
-                                    val
-                                );
-                            }
+        // 匹配条件有问题时整条规则不加载：去掉这个条件的规则会匹配比预期多得多的设备
+        let mut broken_match = false;
+        for token in tokens {
+            let mut problems = Vec::new();
+            let mut report = |kind: ParseErrorKind, message: String| problems.push((kind, message));
+            let op = token.op.as_str();
+            let val = token.value;
+
+            match (token.key.as_str(), token.attr) {
+                ("ENV", Some(key)) => match token.op {
+                    Operator::Match => rule.env_vars.push((key, val)),
+                    _ => report(
+                        ParseErrorKind::InvalidOperator,
+                        format!("unsupported operator 'ENV{{{}}}{}', only == is supported", key, op),
+                    ),
+                },
+                ("ATTR", Some(key)) => match token.op {
+                    Operator::Match => rule.attr.push((key, val)),
+                    Operator::Assign => rule.attr_assign.push((key, val)),
+                    _ => report(
+                            ParseErrorKind::InvalidOperator,
+                            format!("unsupported operator 'ATTR{{{}}}{}'", key, op),
+                        ),
+                },
+                ("ATTRS", Some(key)) => match token.op {
+                    Operator::Match => rule.attrs.push((key, val)),
+                    _ => report(
+                        ParseErrorKind::InvalidOperator,
+                        format!("unsupported operator 'ATTRS{{{}}}{}', only == is supported", key, op),
+                    ),
+                },
+                ("XATTR", Some(name)) => match token.op {
+                    Operator::Assign if !name.starts_with(TRUSTED_NAMESPACE) => report(
+                        ParseErrorKind::InvalidValue,
+                        format!("XATTR{{{}}}: only {}* attributes can be set", name, TRUSTED_NAMESPACE),
+                    ),
+                    Operator::Assign => {
+                        for unknown in unknown_substitutions(&val) {
+                            report(
+                                ParseErrorKind::InvalidSubstitution,
+                                format!("unknown substitution '{}' in XATTR value", unknown),
+                            );
                         }
+                        rule.xattr.push((name, val));
+                    }
+                    _ => report(
+                        ParseErrorKind::InvalidOperator,
+                        format!("unsupported operator 'XATTR{{{}}}{}'", name, op),
+                    ),
+                },
+                ("SECLABEL", Some(module)) => match (module.as_str(), token.op) {
+                    ("selinux", Operator::Assign) => rule.seclabel = Some(val),
+                    ("selinux", _) => report(
+                        ParseErrorKind::InvalidOperator,
+                        format!("unsupported operator 'SECLABEL{{selinux}}{}'", op),
+                    ),
+                    _ => report(
+                        ParseErrorKind::InvalidValue,
+                        format!("unsupported security module '{}' in SECLABEL", module),
+                    ),
+                },
+                ("TEST", Some(mode)) => match (u32::from_str_radix(&mode, 8), token.op) {
+                    (Err(_), _) => report(ParseErrorKind::InvalidValue, format!("invalid TEST mode '{}'", mode)),
+                    (Ok(mask), Operator::Match | Operator::Nomatch) => rule.test.push((token.op, Some(mask), val)),
+                    _ => report(
+                        ParseErrorKind::InvalidOperator,
+                        format!("unsupported operator 'TEST{{{}}}{}'", mode, op),
+                    ),
+                },
+                ("IMPORT", Some(kind)) => match (IMPORT_KINDS.contains(&kind.as_str()), token.op) {
+                    (false, _) => report(
+                        ParseErrorKind::InvalidValue,
+                        format!("unsupported IMPORT type '{}'", kind),
+                    ),
+                    (true, Operator::Assign | Operator::Add) => rule.import.push((kind, val)),
+                    _ => report(
+                        ParseErrorKind::InvalidOperator,
+                        format!("unsupported operator 'IMPORT{{{}}}{}'", kind, op),
+                    ),
+                },
+                ("AT", Some(spec)) => match (parse_at(&spec), token.op) {
+                    (Err(message), _) => report(ParseErrorKind::InvalidValue, message),
+                    (Ok(_), Operator::Assign | Operator::Add) if val.is_empty() => report(
+                        ParseErrorKind::InvalidValue,
+                        format!("AT{{{}}} needs a command or \"change\"", spec),
+                    ),
+                    (Ok((delay, true)), Operator::Assign | Operator::Add) => match val.as_str() {
+                        "change" => rule.at.push((delay, DeferredAction::Event)),
+                        _ => report(
+                            ParseErrorKind::InvalidValue,
+                            format!("AT{{{}}} can only synthesize \"change\", not '{}'", spec, val),
+                        ),
+                    },
+                    (Ok((delay, false)), Operator::Assign | Operator::Add) => {
+                        for unknown in unknown_substitutions(&val) {
+                            report(
+                                ParseErrorKind::InvalidSubstitution,
+                                format!("unknown substitution '{}' in AT value", unknown),
+                            );
+                        }
+                        rule.at.push((delay, DeferredAction::Run(val)));
+                    }
+                    _ => report(
+                        ParseErrorKind::InvalidOperator,
+                        format!("unsupported operator 'AT{{{}}}{}'", spec, op),
+                    ),
+                },
+                (key, Some(attr)) => {
+                    report(ParseErrorKind::UnknownKey, format!("unsupported key '{}{{{}}}'", key, attr));
+                }
+                (key, None) => match (key, op) {
+                    ("ACTION", "==") => rule.action = Some(val),
+                    ("KERNEL", "==") => rule.kernel = Some(val),
+                    ("KERNELVER", "==" | "!=" | "<" | "<=" | ">" | ">=") => match KernelVersion::parse(&val) {
+                        Some(version) => rule.kernel_version.push((token.op, version)),
+                        None => report(
+                            ParseErrorKind::InvalidValue,
+                            format!("invalid kernel version '{}' in KERNELVER", val),
+                        ),
+                    },
+                    ("SUBSYSTEM", "==") => rule.subsystem = Some(val),
+                    ("DEVTYPE", "==") => rule.devtype = Some(val),
+                    ("DRIVER", "==") => rule.driver = Some(val),
+                    ("KERNELS", "==") => rule.kernels = Some(val),
+                    ("SUBSYSTEMS", "==") => rule.subsystems = Some(val),
+                    ("DRIVERS", "==") => rule.drivers = Some(val),
+                    ("DEVPATH", "==") => rule.devpath = Some(val),
+                    ("TAG", "==") => rule.tag = Some(val),
+                    ("TAGS", "==") => rule.tags = Some(val),
+                    ("TAG", "+=") => rule.tag_add.push(val),
+                    ("TAG", "-=") => rule.tag_remove.push(val),
+                    ("TAG", "=") => {
+                        rule.tag_reset = true;
+                        rule.tag_add.push(val);
+                    }
+                    ("TEST", "==" | "!=") => rule.test.push((token.op, None, val)),
+                    ("NAME", "==") => rule.name_match = Some(val),
+                    ("SYMLINK", "+=") => rule.symlink.push(val),
+                    ("NAME", "=") => {
+                        for unknown in unknown_substitutions(&val) {
+                            report(
+                                ParseErrorKind::InvalidSubstitution,
+                                format!("unknown substitution '{}' in NAME value", unknown),
+                            );
+                        }
+                        rule.name = Some(val);
+                    }
+                    ("OWNER", "=") | ("GROUP", "=") | ("MODE", "=") | ("RUN", "+=") => {
+                        for unknown in unknown_substitutions(&val) {
+                            report(
+                                ParseErrorKind::InvalidSubstitution,
+                                format!("unknown substitution '{}' in {} value", unknown, key),
+                            );
+                        }
+                        match key {
+                            "OWNER" => rule.owner = Some(val),
+                            "GROUP" => rule.group = Some(val),
+                            "MODE" => rule.mode = Some(val),
+                            _ => rule.run.push(val),
+                        }
+                    }
 
-                        ("PROGRAM", "==") => rule.program = Some(val),
-                        ("LABEL", "=") => rule.label = Some(val),
-                        ("GOTO", "=") => rule.goto = Some(val),
-                        ("OPTIONS", "+=") => {
-                            if val == "ignore_device" {
-                                rule.ignore_device = true;
-                            } else if val == "last_rule" {
-                                rule.last_rule = true;
+                    ("I2C_NEW_DEVICE", "=") | ("I2C_NEW_DEVICE", "+=") => {
+                        let unknown = unknown_substitutions(&val);
+                        for unknown in &unknown {
+                            report(
+                                ParseErrorKind::InvalidSubstitution,
+                                format!("unknown substitution '{}' in I2C_NEW_DEVICE value", unknown),
+                            );
+                        }
+                        // 含变量替换的值只能在事件处理时检查
+                        let literal = !val.contains(['%', '$']);
+                        match parse_i2c_spec(&val) {
+                            Err(e) if literal && unknown.is_empty() => {
+                                report(ParseErrorKind::InvalidValue, format!("{} in I2C_NEW_DEVICE", e))
                             }
+                            _ => rule.i2c_new_device.push(val),
                         }
-                        _ => {}
                     }
-                }
+                    ("RUN_AFTER", "=") | ("RUN_AFTER", "+=") => rule.run_after.extend(
+                        val.split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|s| !s.is_empty())
+                            .map(String::from),
+                    ),
+                    ("PROGRAM", "==") | ("PROGRAM", "=") => rule.program = Some(val),
+                    ("LABEL", "=") => rule.label = Some(val),
+                    ("GOTO", "=") => rule.goto = Some(val),
+                    ("OPTIONS", "+=") => {
+                        for option in val.split(',').map(str::trim) {
+                            match option {
+                                "ignore_device" => rule.ignore_device = true,
+                                "last_rule" => rule.last_rule = true,
+                                "string_escape=none" => rule.string_escape = Some(StringEscape::None),
+                                "string_escape=replace" => {
+                                    rule.string_escape = Some(StringEscape::Replace)
+                                }
+                                "watch" => rule.watch = Some(true),
+                                "nowatch" => rule.watch = Some(false),
+                                _ if option.starts_with("static_node=") => {
+                                    let name = &option["static_node=".len()..];
+                                    if name.is_empty() {
+                                        report(
+                                            ParseErrorKind::InvalidValue,
+                                            "static_node requires a node name".to_string(),
+                                        );
+                                    } else {
+                                        rule.static_node.push(name.to_string());
+                                    }
+                                }
+                                _ if option.starts_with("log_level=") => {
+                                    let value = &option["log_level=".len()..];
+                                    match parse_level(value) {
+                                        Some(level) => rule.log_level = Some(level),
+                                        None => report(
+                                            ParseErrorKind::InvalidValue,
+                                            format!("invalid log_level '{}'", value),
+                                        ),
+                                    }
+                                }
+                                _ if option.starts_with("reprobe=") => {
+                                    let value = &option["reprobe=".len()..];
+                                    match parse_delay(value) {
+                                        Some(delay) => rule.reprobe = Some(delay),
+                                        None => report(
+                                            ParseErrorKind::InvalidValue,
+                                            format!("invalid reprobe delay '{}'", value),
+                                        ),
+                                    }
+                                }
+                                _ if option.starts_with("link_priority=") => {
+                                    let value = &option["link_priority=".len()..];
+                                    match value.parse::<i32>() {
+                                        Ok(priority) => rule.link_priority = Some(priority),
+                                        Err(_) => report(
+                                            ParseErrorKind::InvalidValue,
+                                            format!("invalid link_priority '{}'", value),
+                                        ),
+                                    }
+                                }
+                                _ => report(
+                                    ParseErrorKind::InvalidValue,
+                                    format!("unsupported OPTIONS value '{}'", option),
+                                ),
+                            }
+                        }
+                    }
+                    _ if KNOWN_KEYS.contains(&key) => report(
+                        ParseErrorKind::InvalidOperator,
+                        format!("unsupported operator '{}{}'", key, op),
+                    ),
+                    _ => report(ParseErrorKind::UnknownKey, format!("unknown key '{}'", key)),
+                },
             }
 
-            rules.push(rule);
+            if !problems.is_empty() && is_match_operator(token.op) {
+                broken_match = true;
+            }
+            for (kind, message) in problems {
+                report_at(Some(token.column), kind, message);
+            }
+        }
+
+        if broken_match {
+            report_at(
+                None,
+                ParseErrorKind::SkippedRule,
+                "rule not loaded, one of its match keys is invalid".to_string(),
+            );
+            continue;
         }
+        rules.push(rule);
     }
 
-    Ok(rules)
+    // GOTO 只能跳到同一文件中后面的 LABEL
+    for (i, rule) in rules.iter().enumerate() {
+        let Some(target) = &rule.goto else {
+            continue;
+        };
+        if !rules[i + 1..].iter().any(|later| later.label.as_ref() == Some(target)) {
+            errors.push(RuleParseError {
+                file: file.to_path_buf(),
+                line: rule.line.unwrap_or(0),
+                column: None,
+                kind: ParseErrorKind::MissingLabel,
+                message: format!("GOTO target '{}' has no matching LABEL later in the file", target),
+            });
+        }
+    }
+
+    ParseReport {
+        rules,
+        diagnostics: errors,
+    }
+}
+
+// 比较类的操作符，其余是赋值
+fn is_match_operator(op: Operator) -> bool {
+    matches!(
+        op,
+        Operator::Match
+            | Operator::Nomatch
+            | Operator::Less
+            | Operator::LessEqual
+            | Operator::Greater
+            | Operator::GreaterEqual
+    )
+}
+
+/// 规则组名：文件名去掉扩展名和开头的数字序号
+fn rule_source(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    stem.trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches('-')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> ParseReport {
+        parse_rules_str_with_errors(content, Path::new("test.rules"))
+    }
+
+    #[test]
+    fn rules_with_invalid_match_keys_are_dropped() {
+        let report = parse(concat!(
+            "KERNEL!=\"sda\", SYMLINK+=\"a\"\n",
+            "FOO==\"bar\", SYMLINK+=\"b\"\n",
+            "ENV{ID_BUS}!=\"usb\", SYMLINK+=\"c\"\n",
+            "KERNELVER>=\"not-a-version\", SYMLINK+=\"d\"\n",
+            "KERNEL==\"sdb\", SYMLINK+=\"e\"\n",
+        ));
+        assert_eq!(report.rules.len(), 1);
+        assert_eq!(report.rules[0].symlink, vec!["e".to_string()]);
+        assert_eq!(report.rules[0].line, Some(5));
+        let skipped: Vec<usize> = report.diagnostics_of(ParseErrorKind::SkippedRule).map(|e| e.line).collect();
+        assert_eq!(skipped, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn loaded_rules_exclude_skipped_ones() {
+        let dir = std::env::temp_dir().join(format!("rust_udev-parser-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("50-test.rules"), "KERNEL!=\"sda\", RUN+=\"x\"\nKERNEL==\"sdb\", RUN+=\"y\"\n").unwrap();
+        let rules = load_all_rules(&[&dir], &[], DEFAULT_MAX_RULES_FILE_SIZE).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].run, vec!["y".to_string()]);
+    }
+
+    #[test]
+    fn sizes_accept_binary_suffixes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size(" 512K "), Some(512 * 1024));
+        assert_eq!(parse_size("8k"), Some(8 * 1024));
+        assert_eq!(parse_size("8M"), Some(8 << 20));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("8MB"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size(&format!("{}G", u64::MAX)), None);
+    }
+
+    #[test]
+    fn hidden_and_backup_files_are_not_rules() {
+        let dir = std::env::temp_dir().join(format!("rust_udev-parser-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["10-a.rules", ".20-hidden.rules", "30-b.rules~", "40-c.rules.bak", "50-d.rules.swp", "60-e.rules"] {
+            fs::write(dir.join(name), "KERNEL==\"x\", RUN+=\"y\"\n").unwrap();
+        }
+        let files: Vec<_> = rule_files(&[&dir])
+            .unwrap()
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, ["10-a.rules", "60-e.rules"]);
+    }
+
+    #[test]
+    fn fifos_and_oversized_files_are_skipped_without_reading() {
+        let dir = std::env::temp_dir().join(format!("rust_udev-parser-fifo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let fifo = dir.join("50-fifo.rules");
+        let c_path = std::ffi::CString::new(fifo.as_os_str().as_encoded_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        fs::write(dir.join("60-big.rules"), "KERNEL==\"sda\", RUN+=\"x\"\n".repeat(4)).unwrap();
+        fs::write(dir.join("70-ok.rules"), "KERNEL==\"sdb\", RUN+=\"y\"\n").unwrap();
+
+        // 没有写端的 FIFO 若被打开读取会一直阻塞
+        let report = parse_rules_with_errors(&[&dir], 64).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.rules.len(), 1);
+        assert_eq!(report.rules[0].run, vec!["y".to_string()]);
+        let skipped: Vec<String> = report
+            .diagnostics_of(ParseErrorKind::SkippedFile)
+            .map(|e| e.message.clone())
+            .collect();
+        assert_eq!(skipped.len(), 2);
+        assert!(skipped[0].contains("FIFO"), "{}", skipped[0]);
+        assert!(skipped[1].contains("64 byte limit"), "{}", skipped[1]);
+    }
+
+    #[test]
+    fn env_assignment_is_not_a_match() {
+        let report = parse("KERNEL==\"sda\", ENV{ID_X}=\"1\"");
+        assert_eq!(report.rules.len(), 1);
+        assert!(report.rules[0].env_vars.is_empty());
+        assert_eq!(report.diagnostics_of(ParseErrorKind::InvalidOperator).count(), 1);
+    }
+
+    #[test]
+    fn invalid_assignments_keep_the_rule() {
+        let report = parse("KERNEL==\"sda\", OPTIONS+=\"bogus\", MODE=\"0660\"");
+        assert_eq!(report.rules.len(), 1);
+        assert_eq!(report.rules[0].mode.as_deref(), Some("0660"));
+        assert_eq!(report.diagnostics_of(ParseErrorKind::InvalidValue).count(), 1);
+    }
+
+    #[test]
+    fn xattr_only_accepts_trusted_namespace() {
+        let report = parse("KERNEL==\"sda\", XATTR{user.a}=\"1\", XATTR{trusted.b}=\"2\"");
+        assert_eq!(report.rules.len(), 1);
+        assert_eq!(report.rules[0].xattr, vec![("trusted.b".to_string(), "2".to_string())]);
+        assert_eq!(report.diagnostics_of(ParseErrorKind::InvalidValue).count(), 1);
+    }
+
+    #[test]
+    fn at_delays_and_event_kind() {
+        assert_eq!(parse_at("30s"), Ok((Duration::from_secs(30), false)));
+        assert_eq!(parse_at("500ms,event"), Ok((Duration::from_millis(500), true)));
+        assert_eq!(parse_at("2min"), Ok((Duration::from_secs(120), false)));
+        assert!(parse_at("30s,run").is_err());
+        assert!(parse_at("soon").is_err());
+        assert!(parse_at("2000min").is_err());
+    }
+
+    #[test]
+    fn at_assignments_become_deferred_actions() {
+        let report = parse(concat!(
+            "KERNEL==\"sda\", AT{30s}+=\"/usr/bin/led-off %k\", AT{1m,event}=\"change\"\n",
+            "KERNEL==\"sdb\", AT{5s,event}=\"add\", AT{5s}=\"\"\n",
+            "KERNEL==\"sdc\", AT{5s}==\"x\"\n",
+        ));
+        assert_eq!(report.rules.len(), 2);
+        assert_eq!(
+            report.rules[0].at,
+            vec![
+                (Duration::from_secs(30), DeferredAction::Run("/usr/bin/led-off %k".to_string())),
+                (Duration::from_secs(60), DeferredAction::Event),
+            ]
+        );
+        assert!(report.rules[1].at.is_empty());
+        assert_eq!(report.diagnostics_of(ParseErrorKind::InvalidValue).count(), 2);
+        // AT{} 比较没有意义，和其它错误的匹配项一样整条规则不加载
+        let skipped: Vec<usize> = report.diagnostics_of(ParseErrorKind::SkippedRule).map(|e| e.line).collect();
+        assert_eq!(skipped, vec![3]);
+    }
+
+    #[test]
+    fn test_keys_support_negation() {
+        let report = parse(concat!(
+            "KERNEL==\"sda\", TEST{0100}!=\"/x\", TEST==\"/y\"\n",
+            "KERNEL==\"sdb\", TEST!=\"/z\"\n",
+            "KERNEL==\"sdc\", TEST{0100}=\"/x\"\n",
+        ));
+        assert_eq!(report.rules.len(), 3);
+        assert_eq!(
+            report.rules[0].test,
+            vec![
+                (Operator::Nomatch, Some(0o100), "/x".to_string()),
+                (Operator::Match, None, "/y".to_string()),
+            ]
+        );
+        assert_eq!(report.rules[1].test, vec![(Operator::Nomatch, None, "/z".to_string())]);
+        assert!(report.rules[2].test.is_empty());
+        assert_eq!(report.diagnostics_of(ParseErrorKind::InvalidOperator).count(), 1);
+    }
+
+    #[test]
+    fn import_accepts_only_assignments_of_known_kinds() {
+        let report = parse(concat!(
+            "KERNEL==\"sda\", IMPORT{program}=\"/bin/id %k\", IMPORT{builtin}+=\"usb_id\"\n",
+            "KERNEL==\"sdb\", IMPORT{program}==\"/bin/id\", IMPORT{progam}=\"/bin/id\"\n",
+        ));
+        assert_eq!(
+            report.rules[0].import,
+            vec![
+                ("program".to_string(), "/bin/id %k".to_string()),
+                ("builtin".to_string(), "usb_id".to_string()),
+            ]
+        );
+        // IMPORT{program}== 是匹配项，整条规则不加载
+        assert_eq!(report.rules.len(), 1);
+        assert_eq!(report.diagnostics_of(ParseErrorKind::InvalidOperator).count(), 1);
+        assert_eq!(report.diagnostics_of(ParseErrorKind::InvalidValue).count(), 1);
+    }
 }
@@ -0,0 +1,405 @@
+// src/rules/ruleset.rs
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use log::*;
+
+use crate::actions::{resolve_gid, resolve_uid, ResolveNames, DEV_ROOT};
+use crate::builtins::BuiltinOptions;
+use crate::clock::{system_clock, Clock};
+use crate::config::DEFAULT_EVENT_TIMEOUT;
+use crate::device::UEventDevice;
+use crate::rules::cache::{EvalCache, EVAL_CACHE_CAPACITY};
+use crate::rules::compiled::{CompiledRules, EventSymbols};
+use crate::rules::glob::literal_prefixes;
+use crate::kernel::KernelVersion;
+use crate::rules::matcher::Rule;
+use crate::rules::parser::DEFAULT_MAX_RULES_FILE_SIZE;
+use crate::rules::trace::Mismatch;
+use crate::stats::CacheUsage;
+
+/// 加载和编译规则集合时的选项，规则重新加载时沿用
+#[derive(Debug, Clone)]
+pub struct RuleSetOptions {
+    /// 匹配结果缓存最多保存的设备数
+    pub eval_cache_capacity: usize,
+    /// 超过这个大小的规则文件被跳过
+    pub max_file_size: u64,
+    /// ATTR、PROGRAM 和 IMPORT{program} 计时使用的时钟
+    pub clock: Arc<dyn Clock>,
+    /// OWNER/GROUP 名字的解析时机；early 在构建 RuleSet 时解析
+    pub resolve_names: ResolveNames,
+    /// IMPORT{builtin} 执行内置命令时的设置
+    pub builtins: BuiltinOptions,
+    /// 设备节点和符号链接的根目录，SYMLINK 和 NAME 相对于它
+    pub dev_root: PathBuf,
+    /// PROGRAM 和 IMPORT{program} 最长的运行时间
+    pub event_timeout: Duration,
+}
+
+impl Default for RuleSetOptions {
+    fn default() -> Self {
+        Self {
+            eval_cache_capacity: EVAL_CACHE_CAPACITY,
+            max_file_size: DEFAULT_MAX_RULES_FILE_SIZE,
+            clock: system_clock(),
+            resolve_names: ResolveNames::default(),
+            builtins: BuiltinOptions::default(),
+            dev_root: PathBuf::from(DEV_ROOT),
+            event_timeout: DEFAULT_EVENT_TIMEOUT,
+        }
+    }
+}
+
+/// 加载完成的规则集合，附带编译好的匹配 token、按 SUBSYSTEM 和 DEVPATH 模式前缀建立的索引
+/// 以及匹配结果缓存
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    compiled: CompiledRules,
+    subsystem_index: SubsystemIndex,
+    devpath_index: DevpathIndex,
+    eval_cache: EvalCache,
+    options: RuleSetOptions,
+}
+
+impl RuleSet {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self::with_options(rules, &RuleSetOptions::default())
+    }
+
+    pub fn with_options(mut rules: Vec<Rule>, options: &RuleSetOptions) -> Self {
+        if options.resolve_names == ResolveNames::Early {
+            resolve_names_early(&mut rules);
+        }
+        for rule in rules.iter().filter(|rule| !rule.matches_kernel_version()) {
+            debug!(
+                "Rule at {} is disabled by KERNELVER on this kernel",
+                rule.location()
+            );
+        }
+        let subsystem_index = SubsystemIndex::build(&rules);
+        let devpath_index = DevpathIndex::build(&rules);
+        let eval_cache = EvalCache::with_capacity(&rules, options.eval_cache_capacity);
+        let compiled = CompiledRules::compile(&rules);
+        Self {
+            rules,
+            compiled,
+            subsystem_index,
+            devpath_index,
+            eval_cache,
+            options: options.clone(),
+        }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// 按原有顺序返回可能匹配该设备的规则，SUBSYSTEM 不同或 DEVPATH 前缀不符的规则被跳过
+    pub fn candidates<'a>(
+        &'a self,
+        devpath: &Path,
+        subsystem: &str,
+    ) -> impl Iterator<Item = &'a Rule> + 'a {
+        self.candidate_indices(devpath, subsystem)
+            .into_iter()
+            .map(|i| &self.rules[i])
+    }
+
+    /// 与 candidates 相同，但返回规则下标；开销与 SUBSYSTEM 可能匹配的规则数成正比，与规则总数无关
+    pub fn candidate_indices(&self, devpath: &Path, subsystem: &str) -> Vec<usize> {
+        let by_devpath = self.devpath_index.lookup(devpath);
+        self.subsystem_index
+            .lookup(subsystem)
+            .iter()
+            .copied()
+            .filter(|&i| self.rules[i].devpath.is_none() || by_devpath.binary_search(&i).is_ok())
+            .collect()
+    }
+
+    /// 为事件准备匹配用的驻留编号；匹配到的规则修改设备后要重新调用
+    pub fn prepare(&self, device: &UEventDevice) -> EventSymbols {
+        self.compiled.prepare(device)
+    }
+
+    /// 第 index 条规则中取决于事件本身的条件是否满足
+    pub fn matches_event(&self, index: usize, symbols: &EventSymbols, device: &UEventDevice) -> bool {
+        self.compiled.matches_event(index, symbols, device)
+    }
+
+    /// 第 index 条规则是否匹配；事件条件满足后才检查 ATTR、TEST 和 PROGRAM
+    pub fn matches(&self, index: usize, symbols: &EventSymbols, device: &mut UEventDevice) -> bool {
+        self.evaluate(index, symbols, device).is_ok()
+    }
+
+    /// 同 matches，不匹配时给出第一个不满足的条件，用于规则跟踪
+    pub fn evaluate(&self, index: usize, symbols: &EventSymbols, device: &mut UEventDevice) -> Result<(), Mismatch> {
+        let rule = &self.rules[index];
+        match self.compiled.mismatch(index, symbols, device) {
+            // 有条件却不可能匹配，说明是 KERNELVER 排除的
+            Some(Mismatch::NoConditions) if rule.has_conditions() => {
+                let failed = rule
+                    .kernel_version
+                    .iter()
+                    .position(|(op, version)| {
+                        !KernelVersion::running().is_some_and(|running| running.satisfies(*op, version))
+                    })
+                    .unwrap_or(0);
+                Err(Mismatch::KernelVersion(failed))
+            }
+            Some(mismatch) => Err(mismatch),
+            None => rule.external_mismatch(device, &self.options).map_or(Ok(()), Err),
+        }
+    }
+
+    /// 第 index 条规则不匹配原因的可读描述
+    pub fn describe_mismatch(&self, index: usize, mismatch: Mismatch) -> String {
+        match mismatch {
+            Mismatch::Event(token) => self.compiled.describe(index, token),
+            other => self.rules[index].describe_mismatch(other),
+        }
+    }
+
+    pub fn compiled(&self) -> &CompiledRules {
+        &self.compiled
+    }
+
+    pub fn eval_cache(&self) -> &EvalCache {
+        &self.eval_cache
+    }
+
+    /// 编译这个规则集合时使用的选项
+    pub fn options(&self) -> &RuleSetOptions {
+        &self.options
+    }
+
+    /// 匹配结果缓存和驻留表的占用
+    pub fn usage(&self) -> [CacheUsage; 2] {
+        [self.eval_cache.usage(), self.compiled.interner().usage()]
+    }
+}
+
+/// 可以整体替换的规则集合
+///
+/// 读者取得当前 RuleSet 的快照后立即释放锁，重新加载只替换指针，不等待正在使用旧规则的事件。
+#[derive(Debug, Default)]
+pub struct SharedRules {
+    current: RwLock<Arc<RuleSet>>,
+}
+
+impl SharedRules {
+    pub fn new(rules: RuleSet) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(rules)),
+        }
+    }
+
+    /// 当前规则的快照
+    pub fn load(&self) -> Arc<RuleSet> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// 换上新的规则，返回被替换的规则
+    pub fn store(&self, rules: RuleSet) -> Arc<RuleSet> {
+        std::mem::replace(&mut *self.current.write().unwrap(), Arc::new(rules))
+    }
+}
+
+impl From<Vec<Rule>> for RuleSet {
+    fn from(rules: Vec<Rule>) -> Self {
+        Self::new(rules)
+    }
+}
+
+// resolve_names=early：把不含变量替换的 OWNER/GROUP 名字换成数字 ID，事件处理时不再查询 NSS
+fn resolve_names_early(rules: &mut [Rule]) {
+    let literal = |value: &str| !value.contains(['%', '$']);
+    for rule in rules {
+        let location = rule.location();
+        if let Some(owner) = rule.owner.as_mut().filter(|owner| literal(owner)) {
+            match resolve_uid(owner, ResolveNames::Early) {
+                Some(uid) => *owner = uid.to_string(),
+                None => warn!("Unknown user '{}' in rule at {}", owner, location),
+            }
+        }
+        if let Some(group) = rule.group.as_mut().filter(|group| literal(group)) {
+            match resolve_gid(group, ResolveNames::Early) {
+                Some(gid) => *group = gid.to_string(),
+                None => warn!("Unknown group '{}' in rule at {}", group, location),
+            }
+        }
+    }
+}
+
+/// 按 SUBSYSTEM 分桶的规则下标，没有 SUBSYSTEM 条件或 SUBSYSTEM 含通配符的规则放在通配桶中
+///
+/// a|b 形式的规则放进每个备选的桶。构建时把通配桶合并进每个桶，查找直接返回按规则顺序排列的下标
+#[derive(Debug, Default)]
+struct SubsystemIndex {
+    buckets: HashMap<String, Vec<usize>>,
+    wildcard: Vec<usize>,
+}
+
+impl SubsystemIndex {
+    fn build(rules: &[Rule]) -> Self {
+        let mut index = Self::default();
+        for (i, rule) in rules.iter().enumerate() {
+            match &rule.subsystem {
+                // SUBSYSTEM 比较不区分大小写
+                Some(subsystem) if !subsystem.contains(['*', '?', '[']) => {
+                    for alternative in subsystem.to_lowercase().split('|') {
+                        index.buckets.entry(alternative.to_string()).or_default().push(i);
+                    }
+                }
+                _ => index.wildcard.push(i),
+            }
+        }
+        for bucket in index.buckets.values_mut() {
+            bucket.extend_from_slice(&index.wildcard);
+            bucket.sort_unstable();
+            // a|a 这样重复的备选会把规则放进同一个桶两次
+            bucket.dedup();
+        }
+        index
+    }
+
+    fn lookup(&self, subsystem: &str) -> &[usize] {
+        self.buckets.get(lowercase(subsystem).as_ref()).unwrap_or(&self.wildcard)
+    }
+}
+
+/// DEVPATH 模式固定前缀组成的前缀树，节点上记录以该前缀结尾的规则下标；
+/// 没有 DEVPATH 条件的规则总是候选，不放进树中
+#[derive(Debug, Default)]
+struct DevpathIndex {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: BTreeMap<u8, usize>,
+    rules: Vec<usize>,
+}
+
+impl DevpathIndex {
+    fn build(rules: &[Rule]) -> Self {
+        let mut index = Self {
+            nodes: vec![TrieNode::default()],
+        };
+
+        for (i, rule) in rules.iter().enumerate() {
+            // DEVPATH 匹配不区分大小写，前缀统一转成小写
+            if let Some(pattern) = &rule.devpath {
+                for prefix in literal_prefixes(&pattern.to_lowercase()) {
+                    index.insert(prefix.as_bytes(), i);
+                }
+            }
+        }
+
+        index
+    }
+
+    fn insert(&mut self, prefix: &[u8], rule: usize) {
+        let mut node = 0;
+        for &b in prefix {
+            node = match self.nodes[node].children.get(&b) {
+                Some(&next) => next,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let next = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(b, next);
+                    next
+                }
+            };
+        }
+        self.nodes[node].rules.push(rule);
+    }
+
+    // 沿 devpath 走前缀树，途经节点上的规则都是候选；返回排好序的规则下标
+    fn lookup(&self, devpath: &Path) -> Vec<usize> {
+        let devpath = devpath.to_string_lossy();
+        let devpath = lowercase(&devpath);
+        let mut node = 0;
+        let mut bytes = devpath.bytes();
+        let mut rules = Vec::new();
+        loop {
+            rules.extend_from_slice(&self.nodes[node].rules);
+            match bytes.next().and_then(|b| self.nodes[node].children.get(&b)) {
+                Some(&next) => node = next,
+                None => break,
+            }
+        }
+        rules.sort_unstable();
+        rules.dedup();
+        rules
+    }
+}
+
+// SUBSYSTEM 和 DEVPATH 几乎总是不含大写字母的 ASCII，这时不必分配
+fn lowercase(value: &str) -> Cow<'_, str> {
+    if value.bytes().all(|b| b.is_ascii() && !b.is_ascii_uppercase()) {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(value.to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::parser::parse_rules_str;
+
+    #[test]
+    fn candidates_combine_subsystem_and_devpath_indexes() {
+        let rules = parse_rules_str(
+            r#"
+SUBSYSTEM=="block", DEVPATH=="/devices/pci*", NAME="a"
+SUBSYSTEM=="net", NAME="b"
+DEVPATH=="/devices/virtual/*", NAME="c"
+NAME="d"
+SUBSYSTEM=="Block", DEVPATH=="/devices/virtual/*", NAME="e"
+"#,
+        );
+        let rules = RuleSet::new(rules);
+
+        let pci = Path::new("/devices/pci0000:00/block/sda");
+        let virt = Path::new("/devices/virtual/block/loop0");
+        assert_eq!(rules.candidate_indices(pci, "block"), vec![0, 3]);
+        assert_eq!(rules.candidate_indices(virt, "BLOCK"), vec![2, 3, 4]);
+        assert_eq!(rules.candidate_indices(virt, "net"), vec![1, 2, 3]);
+        // 没有规则针对的子系统只剩通配的规则
+        assert_eq!(rules.candidate_indices(virt, "usb"), vec![2, 3]);
+    }
+
+    #[test]
+    fn subsystem_alternatives_and_globs_are_indexed() {
+        let rules = parse_rules_str(
+            r#"
+SUBSYSTEM=="tty|usb", NAME="a"
+SUBSYSTEM=="usb*", NAME="b"
+SUBSYSTEM=="net", NAME="c"
+SUBSYSTEM=="usb|usb", NAME="d"
+"#,
+        );
+        let rules = RuleSet::new(rules);
+
+        let path = Path::new("/devices/pci0000:00/usb1");
+        assert_eq!(rules.candidate_indices(path, "usb"), vec![0, 1, 3]);
+        assert_eq!(rules.candidate_indices(path, "tty"), vec![0, 1]);
+        assert_eq!(rules.candidate_indices(path, "usbmisc"), vec![1]);
+        assert_eq!(rules.candidate_indices(path, "net"), vec![1, 2]);
+    }
+}
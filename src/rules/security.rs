@@ -0,0 +1,132 @@
+// src/rules/security.rs
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::deferred::DeferredAction;
+use crate::rules::matcher::Rule;
+
+/// 拥有这些组等同于拿到 root：能读写裸磁盘、内核内存、密码文件，或能提权
+pub const PRIVILEGED_GROUPS: [&str; 8] =
+    ["root", "wheel", "sudo", "admin", "adm", "disk", "kmem", "shadow"];
+
+/// 安全检查关注的规则行为
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SecurityIssueKind {
+    /// RUN、PROGRAM 或 IMPORT{program} 以 root 身份执行外部程序
+    ExecutesProgram,
+    /// ATTR{key}= 或 I2C_NEW_DEVICE= 写入 sysfs
+    WritesSysfs,
+    /// GROUP= 把设备节点交给等同于 root 的组
+    PrivilegedGroup,
+}
+
+impl SecurityIssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecurityIssueKind::ExecutesProgram => "executes-program",
+            SecurityIssueKind::WritesSysfs => "writes-sysfs",
+            SecurityIssueKind::PrivilegedGroup => "privileged-group",
+        }
+    }
+}
+
+impl fmt::Display for SecurityIssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 规则中的一处需要人工审查的赋值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityFinding {
+    pub kind: SecurityIssueKind,
+    pub line: Option<usize>,
+    /// 原样的赋值，如 RUN+="/usr/bin/foo"
+    pub detail: String,
+}
+
+impl fmt::Display for SecurityFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: [{}] {}", line, self.kind, self.detail),
+            None => write!(f, "[{}] {}", self.kind, self.detail),
+        }
+    }
+}
+
+/// 按规则文件汇总的安全检查结果；没有问题的文件对应空列表
+#[derive(Debug, Clone, Default)]
+pub struct SecurityReport {
+    pub files: BTreeMap<PathBuf, Vec<SecurityFinding>>,
+}
+
+impl SecurityReport {
+    /// 检查 files 中的规则；rules 中不属于这些文件的规则被忽略
+    pub fn new<P: AsRef<Path>>(files: &[P], rules: &[Rule]) -> Self {
+        let mut report: BTreeMap<PathBuf, Vec<SecurityFinding>> = files
+            .iter()
+            .map(|file| (file.as_ref().to_path_buf(), Vec::new()))
+            .collect();
+        for rule in rules {
+            if let Some(findings) = rule.file.as_ref().and_then(|file| report.get_mut(file)) {
+                findings.extend(rule_findings(rule));
+            }
+        }
+        Self { files: report }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.findings() == 0
+    }
+
+    pub fn findings(&self) -> usize {
+        self.files.values().map(Vec::len).sum()
+    }
+}
+
+/// 一条规则中需要审查的赋值，按 执行程序、写 sysfs、特权组 的顺序
+pub fn rule_findings(rule: &Rule) -> Vec<SecurityFinding> {
+    let finding = |kind, detail: String| SecurityFinding {
+        kind,
+        line: rule.line,
+        detail,
+    };
+    let mut findings = Vec::new();
+
+    if let Some(program) = &rule.program {
+        findings.push(finding(SecurityIssueKind::ExecutesProgram, format!("PROGRAM==\"{}\"", program)));
+    }
+    for (kind, value) in rule.import.iter().filter(|(kind, _)| kind == "program") {
+        findings.push(finding(
+            SecurityIssueKind::ExecutesProgram,
+            format!("IMPORT{{{}}}=\"{}\"", kind, value),
+        ));
+    }
+    for command in &rule.run {
+        findings.push(finding(SecurityIssueKind::ExecutesProgram, format!("RUN+=\"{}\"", command)));
+    }
+    for (delay, action) in &rule.at {
+        if let DeferredAction::Run(_) = action {
+            let (key, value) = action.assignment(*delay);
+            findings.push(finding(SecurityIssueKind::ExecutesProgram, format!("{}+=\"{}\"", key, value)));
+        }
+    }
+    for (key, value) in &rule.attr_assign {
+        findings.push(finding(SecurityIssueKind::WritesSysfs, format!("ATTR{{{}}}=\"{}\"", key, value)));
+    }
+    for spec in &rule.i2c_new_device {
+        findings.push(finding(SecurityIssueKind::WritesSysfs, format!("I2C_NEW_DEVICE+=\"{}\"", spec)));
+    }
+    if let Some(group) = rule.group.as_deref().filter(|group| privileged_group(group)) {
+        findings.push(finding(SecurityIssueKind::PrivilegedGroup, format!("GROUP=\"{}\"", group)));
+    }
+
+    findings
+}
+
+// 组名或数字 gid 0
+fn privileged_group(group: &str) -> bool {
+    group == "0" || PRIVILEGED_GROUPS.contains(&group)
+}
@@ -0,0 +1,306 @@
+// src/rules/tokenizer.rs
+
+use std::fmt;
+
+/// 规则行中的一项 KEY{attr}<op>"value"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub key: String,
+    pub attr: Option<String>,
+    pub op: Operator,
+    pub value: String,
+    /// key 所在的列号，从 1 开始
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// ==
+    Match,
+    /// !=
+    Nomatch,
+    /// +=
+    Add,
+    /// -=
+    Remove,
+    /// =
+    Assign,
+    /// :=
+    AssignFinal,
+    /// <
+    Less,
+    /// <=
+    LessEqual,
+    /// >
+    Greater,
+    /// >=
+    GreaterEqual,
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Match => "==",
+            Operator::Nomatch => "!=",
+            Operator::Add => "+=",
+            Operator::Remove => "-=",
+            Operator::Assign => "=",
+            Operator::AssignFinal => ":=",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 词法错误，column 从 1 开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenError {
+    pub column: usize,
+    pub message: String,
+    /// 引号没有闭合
+    pub unterminated_quote: bool,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+/// 把一行规则拆成 token，项之间用逗号和/或空白分隔
+///
+/// 普通字符串中只有 \" 会被转义，其余反斜杠原样保留；
+/// e"..." 字符串支持 C 风格转义（\n、\t、\\、\xHH 等），\xHH 按 UTF-8 字节解码。
+pub fn tokenize(line: &str) -> Result<Vec<Token>, TokenError> {
+    let mut lexer = Lexer {
+        chars: line.chars().collect(),
+        pos: 0,
+    };
+    let mut tokens = Vec::new();
+
+    loop {
+        lexer.skip_while(|c| c == ',' || c.is_whitespace());
+        if lexer.peek().is_none() {
+            break;
+        }
+        tokens.push(lexer.token()?);
+    }
+
+    Ok(tokens)
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn skip_while(&mut self, pred: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&pred) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, pos: usize, message: impl Into<String>) -> TokenError {
+        TokenError {
+            column: pos + 1,
+            message: message.into(),
+            unterminated_quote: false,
+        }
+    }
+
+    fn token(&mut self) -> Result<Token, TokenError> {
+        let start = self.pos;
+
+        self.skip_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if self.pos == start {
+            let found = self.peek().unwrap_or(' ');
+            return Err(self.error(start, format!("expected key, found '{}'", found)));
+        }
+        let key: String = self.chars[start..self.pos].iter().collect();
+
+        let attr = if self.peek() == Some('{') {
+            Some(self.attr()?)
+        } else {
+            None
+        };
+
+        self.skip_while(char::is_whitespace);
+        let op = self.operator()?;
+        self.skip_while(char::is_whitespace);
+        let value = self.value()?;
+
+        Ok(Token {
+            key,
+            attr,
+            op,
+            value,
+            column: start + 1,
+        })
+    }
+
+    fn attr(&mut self) -> Result<String, TokenError> {
+        let open = self.pos;
+        self.pos += 1;
+
+        let start = self.pos;
+        self.skip_while(|c| c != '}');
+        if self.peek().is_none() {
+            return Err(self.error(open, "unterminated '{'"));
+        }
+
+        let attr: String = self.chars[start..self.pos].iter().collect();
+        self.pos += 1;
+
+        if attr.is_empty() {
+            return Err(self.error(open, "empty attribute name in '{}'"));
+        }
+        Ok(attr)
+    }
+
+    fn operator(&mut self) -> Result<Operator, TokenError> {
+        let op = match (self.peek(), self.peek_at(1)) {
+            (Some('='), Some('=')) => Operator::Match,
+            (Some('!'), Some('=')) => Operator::Nomatch,
+            (Some('+'), Some('=')) => Operator::Add,
+            (Some('-'), Some('=')) => Operator::Remove,
+            (Some(':'), Some('=')) => Operator::AssignFinal,
+            (Some('<'), Some('=')) => Operator::LessEqual,
+            (Some('>'), Some('=')) => Operator::GreaterEqual,
+            (Some(c @ ('=' | '<' | '>')), _) => {
+                self.pos += 1;
+                return Ok(match c {
+                    '<' => Operator::Less,
+                    '>' => Operator::Greater,
+                    _ => Operator::Assign,
+                });
+            }
+            _ => return Err(self.error(self.pos, "expected operator")),
+        };
+        self.pos += 2;
+        Ok(op)
+    }
+
+    fn value(&mut self) -> Result<String, TokenError> {
+        let escaped = self.peek() == Some('e') && self.peek_at(1) == Some('"');
+        if escaped {
+            self.pos += 1;
+        }
+
+        let open = self.pos;
+        if self.peek() != Some('"') {
+            return Err(self.error(open, "expected quoted value"));
+        }
+        self.pos += 1;
+
+        // \xHH 给出的是字节，多个连起来才是一个 UTF-8 字符，最后统一解码
+        let mut value = Vec::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(TokenError {
+                    unterminated_quote: true,
+                    ..self.error(open, "unterminated quoted value")
+                });
+            };
+            self.pos += 1;
+
+            match c {
+                '"' => {
+                    return String::from_utf8(value)
+                        .map_err(|_| self.error(open, "\\x escapes do not form valid UTF-8"));
+                }
+                '\\' if escaped => match self.escape()? {
+                    Escape::Char(c) => value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    Escape::Byte(b) => value.push(b),
+                },
+                '\\' if self.peek() == Some('"') => {
+                    self.pos += 1;
+                    value.push(b'"');
+                }
+                _ => value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+    }
+
+    // e"..." 中反斜杠之后的转义序列
+    fn escape(&mut self) -> Result<Escape, TokenError> {
+        let backslash = self.pos - 1;
+        let Some(c) = self.peek() else {
+            return Err(self.error(backslash, "unterminated escape sequence"));
+        };
+        self.pos += 1;
+
+        let unescaped = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'a' => '\x07',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '0' => '\0',
+            '\\' | '"' | '\'' => c,
+            'x' => {
+                let hex: String = self.chars[self.pos..].iter().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| self.error(backslash, "invalid \\x escape, expected two hex digits"))?;
+                self.pos += 2;
+                return Ok(Escape::Byte(byte));
+            }
+            other => {
+                return Err(self.error(backslash, format!("unknown escape sequence '\\{}'", other)));
+            }
+        };
+        Ok(Escape::Char(unescaped))
+    }
+}
+
+// 转义序列的结果：\xHH 是一个原始字节，其余是一个字符
+enum Escape {
+    Char(char),
+    Byte(u8),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(line: &str) -> Result<String, TokenError> {
+        tokenize(line).map(|tokens| tokens[0].value.clone())
+    }
+
+    #[test]
+    fn hex_escapes_decode_as_utf8() {
+        assert_eq!(value(r#"SYMLINK+=e"caf\xc3\xa9""#).unwrap(), "café");
+        assert_eq!(value(r#"SYMLINK+=e"a\tb\\c""#).unwrap(), "a\tb\\c");
+    }
+
+    #[test]
+    fn invalid_utf8_from_hex_escapes_is_an_error() {
+        let error = value(r#"SYMLINK+=e"\xe9""#).unwrap_err();
+        assert!(error.message.contains("UTF-8"));
+    }
+
+    #[test]
+    fn plain_strings_only_unescape_quotes() {
+        assert_eq!(value(r#"RUN+="echo \"x\" \xc3""#).unwrap(), r#"echo "x" \xc3"#);
+    }
+}
@@ -0,0 +1,137 @@
+// src/rules/trace.rs
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::device::UEventDevice;
+use crate::rules::matcher::Rule;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 打开或关闭守护进程的规则匹配跟踪（udev.conf 中的 trace_rules=，或 --trace-rules）
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 规则不匹配的原因：第一个不满足的条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// 规则只有赋值，没有任何条件
+    NoConditions,
+    /// 第 n 个 KERNELVER 条件与运行中的内核不符
+    KernelVersion(usize),
+    /// 第 n 个编译后的事件条件（SUBSYSTEM、ACTION、ENV 等）
+    Event(usize),
+    /// 第 n 个 ATTR 条件
+    Attr(usize),
+    /// 设备本身和所有父设备都不能同时满足 KERNELS、SUBSYSTEMS、DRIVERS 和 ATTRS{}
+    Parents,
+    /// 第 n 个 TEST 条件
+    Test(usize),
+    /// PROGRAM 执行失败或返回非零
+    Program,
+}
+
+impl Mismatch {
+    /// 在检查 ATTR、TEST、PROGRAM 等外部条件之前就已经不匹配
+    pub fn before_external(&self) -> bool {
+        matches!(self, Mismatch::NoConditions | Mismatch::KernelVersion(_) | Mismatch::Event(_))
+    }
+}
+
+/// 一条规则对当前事件的结果
+#[derive(Debug, Clone)]
+pub enum RuleOutcome {
+    /// 规则匹配，附带它贡献的赋值
+    Matched(Vec<String>),
+    /// 规则不匹配，附带不满足的条件
+    NoMatch(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleTrace {
+    /// 规则所在的 文件:行号
+    pub location: String,
+    pub outcome: RuleOutcome,
+}
+
+/// 一个事件的规则匹配过程：检查过哪些规则、为什么不匹配、匹配的规则做了哪些赋值
+///
+/// SUBSYSTEM 或 DEVPATH 索引直接排除的规则没有被检查，不出现在跟踪中。
+#[derive(Debug, Clone)]
+pub struct EventTrace {
+    pub seqnum: u64,
+    pub action: String,
+    pub devpath: PathBuf,
+    /// 是否重放了缓存的匹配结果，此时只有匹配的规则
+    pub cached: bool,
+    pub rules: Vec<RuleTrace>,
+}
+
+impl EventTrace {
+    pub fn new(device: &UEventDevice) -> Self {
+        Self {
+            seqnum: device.seqnum(),
+            action: device.action().as_str().to_string(),
+            devpath: device.devpath().to_path_buf(),
+            cached: false,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn record_match(&mut self, rule: &Rule) {
+        self.rules.push(RuleTrace {
+            location: rule.location(),
+            outcome: RuleOutcome::Matched(rule.assignments()),
+        });
+    }
+
+    pub fn record_mismatch(&mut self, rule: &Rule, condition: String) {
+        self.rules.push(RuleTrace {
+            location: rule.location(),
+            outcome: RuleOutcome::NoMatch(condition),
+        });
+    }
+
+    /// 匹配的规则数
+    pub fn matched(&self) -> usize {
+        self.rules
+            .iter()
+            .filter(|rule| matches!(rule.outcome, RuleOutcome::Matched(_)))
+            .count()
+    }
+}
+
+impl fmt::Display for EventTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Rule trace for seq {} {} {}{}: {} rule(s) checked, {} matched",
+            self.seqnum,
+            self.action,
+            self.devpath.display(),
+            if self.cached { " (cached)" } else { "" },
+            self.rules.len(),
+            self.matched(),
+        )?;
+        for rule in &self.rules {
+            match &rule.outcome {
+                RuleOutcome::Matched(assignments) => {
+                    writeln!(f, "  {}: matched", rule.location)?;
+                    for assignment in assignments {
+                        writeln!(f, "      {}", assignment)?;
+                    }
+                }
+                RuleOutcome::NoMatch(condition) => {
+                    writeln!(f, "  {}: no match, {}", rule.location, condition)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+// src/selinux.rs
+
+use std::io;
+use std::path::Path;
+
+use log::*;
+
+/// 保存 SELinux 安全上下文的扩展属性
+pub const SELINUX_XATTR: &str = "security.selinux";
+
+/// 内核是否启用了 SELinux（selinuxfs 已挂载）
+pub fn enabled() -> bool {
+    Path::new("/sys/fs/selinux/enforce").exists()
+}
+
+/// 把文件（符号链接本身，而不是其目标）标记为给定的安全上下文；
+/// SELinux 未启用或编译时没有 selinux 功能时什么都不做
+#[cfg(feature = "selinux")]
+pub fn set_label(path: &Path, label: &str) -> io::Result<()> {
+    if !enabled() {
+        debug!("SELinux is not enabled, not labeling {:?}", path);
+        return Ok(());
+    }
+    info!("Labeling {:?} as {}", path, label);
+    crate::xattr::set(path, SELINUX_XATTR, label)
+}
+
+#[cfg(not(feature = "selinux"))]
+pub fn set_label(path: &Path, label: &str) -> io::Result<()> {
+    debug!("Built without SELinux support, ignoring label {} for {:?}", label, path);
+    Ok(())
+}
@@ -0,0 +1,153 @@
+// src/seqnum.rs
+
+use std::collections::BTreeSet;
+
+use log::*;
+
+use crate::device::UEventDevice;
+
+/// 一段缺失的 SEQNUM，以及缺号前后两个事件的子系统，丢失的事件很可能属于它们
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqnumGap {
+    pub first: u64,
+    pub last: u64,
+    pub subsystems: BTreeSet<String>,
+}
+
+impl SeqnumGap {
+    /// 缺失的事件数
+    pub fn count(&self) -> u64 {
+        self.last - self.first + 1
+    }
+}
+
+/// 跟踪内核事件的 SEQNUM，发现 netlink 缓冲区溢出丢失的事件
+///
+/// 其它网络命名空间中的事件也会占用 SEQNUM，缺号本身很常见，事件照常立即处理；
+/// 只有收到 ENOBUFS 之后的第一段缺号才记为丢失。netlink 按顺序投递，不需要等待缺号补上。
+/// SEQNUM 为 0 的合成事件和比期望小的迟到事件不影响跟踪。
+#[derive(Debug, Default)]
+pub struct SeqnumTracker {
+    next: Option<u64>,
+    // 最近一个事件的子系统，记录缺号时使用
+    last_subsystem: Option<String>,
+    // 收到 ENOBUFS 之后、发现缺号之前为 true
+    overflowed: bool,
+    gaps: Vec<SeqnumGap>,
+}
+
+impl SeqnumTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 接收时遇到 ENOBUFS：内核丢弃了消息，下一段缺号就是丢失的事件
+    pub fn overflowed(&mut self) {
+        self.overflowed = true;
+    }
+
+    /// 记录一个收到的事件
+    pub fn observe(&mut self, device: &UEventDevice) {
+        let seqnum = device.seqnum();
+        let next = *self.next.get_or_insert(seqnum);
+        if seqnum == 0 || seqnum < next {
+            if seqnum != 0 {
+                debug!("Event seqnum {} arrived after seqnum {}", seqnum, next - 1);
+            }
+            return;
+        }
+
+        if seqnum != next {
+            if self.overflowed {
+                let mut subsystems: BTreeSet<String> = self.last_subsystem.iter().cloned().collect();
+                subsystems.insert(device.subsystem().to_string());
+                subsystems.remove("");
+                let gap = SeqnumGap {
+                    first: next,
+                    last: seqnum - 1,
+                    subsystems,
+                };
+                warn!(
+                    "Missing {} event(s), seqnum {}..={} (around subsystems {:?})",
+                    gap.count(),
+                    gap.first,
+                    gap.last,
+                    gap.subsystems
+                );
+                self.gaps.push(gap);
+                self.overflowed = false;
+            } else {
+                trace!("Seqnum {}..={} used by other network namespaces", next, seqnum - 1);
+            }
+        }
+        self.next = Some(seqnum + 1);
+        self.last_subsystem = Some(device.subsystem().to_string());
+    }
+
+    /// 取出上次调用以来发现的缺号
+    pub fn take_gaps(&mut self) -> Vec<SeqnumGap> {
+        std::mem::take(&mut self.gaps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn event(seqnum: u64, subsystem: &str) -> UEventDevice {
+        let properties = HashMap::from([
+            ("ACTION".to_string(), "add".to_string()),
+            ("DEVPATH".to_string(), format!("/devices/virtual/test/dev{}", seqnum)),
+            ("SUBSYSTEM".to_string(), subsystem.to_string()),
+            ("SEQNUM".to_string(), seqnum.to_string()),
+        ]);
+        UEventDevice::from_event(properties).unwrap()
+    }
+
+    #[test]
+    fn gaps_without_overflow_are_not_losses() {
+        let mut tracker = SeqnumTracker::new();
+        for seqnum in [10, 11, 15, 16, 30] {
+            tracker.observe(&event(seqnum, "net"));
+        }
+        assert!(tracker.take_gaps().is_empty());
+    }
+
+    #[test]
+    fn first_gap_after_overflow_is_reported_once() {
+        let mut tracker = SeqnumTracker::new();
+        tracker.observe(&event(10, "block"));
+        tracker.overflowed();
+        // 溢出前已经在缓冲区中的事件仍然连续
+        tracker.observe(&event(11, "block"));
+        tracker.observe(&event(15, "usb"));
+        tracker.observe(&event(20, "usb"));
+
+        let gaps = tracker.take_gaps();
+        assert_eq!(
+            gaps,
+            vec![SeqnumGap {
+                first: 12,
+                last: 14,
+                subsystems: BTreeSet::from(["block".to_string(), "usb".to_string()]),
+            }]
+        );
+        assert_eq!(gaps[0].count(), 3);
+        assert!(tracker.take_gaps().is_empty());
+    }
+
+    #[test]
+    fn synthetic_and_late_events_do_not_move_the_expected_seqnum() {
+        let mut tracker = SeqnumTracker::new();
+        tracker.observe(&event(10, "block"));
+        tracker.overflowed();
+        tracker.observe(&event(0, "block"));
+        tracker.observe(&event(5, "block"));
+        tracker.observe(&event(11, "block"));
+        assert!(tracker.take_gaps().is_empty());
+        tracker.observe(&event(13, "block"));
+        assert_eq!(tracker.take_gaps()[0].first, 12);
+    }
+}
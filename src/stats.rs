@@ -0,0 +1,351 @@
+// src/stats.rs
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::device::{DeviceAction, UEventDevice};
+use crate::lru::LruIndex;
+
+/// 守护进程写出统计信息的位置，udevadm info --stats 从这里读取
+pub const STATS_PATH: &str = "/run/rust_udev/stats";
+
+/// 守护进程写出当前未处理完的事件数量和最近收到的内核事件 SEQNUM，udevadm settle 据此等待
+pub const QUEUE_PATH: &str = "/run/rust_udev/queue";
+
+/// 守护进程写出最近收到的不完整事件，udevadm debug-dump 从这里读取
+pub const INCOMPLETE_PATH: &str = "/run/rust_udev/incomplete";
+
+/// 守护进程写出各内存缓存的占用情况，udevadm info --stats 一并显示
+pub const CACHES_PATH: &str = "/run/rust_udev/caches";
+
+/// 默认最多跟踪的设备数
+pub const DEFAULT_STATS_CAPACITY: usize = 16384;
+
+// 最多保留的不完整事件数
+const INCOMPLETE_KEEP: usize = 32;
+
+// 估算内存时每个设备额外计入的字节数
+const DEVICE_OVERHEAD: usize = 96;
+
+/// 某个内存缓存的条目数、上限、估算字节数和累计淘汰次数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheUsage {
+    pub name: String,
+    pub entries: usize,
+    pub capacity: usize,
+    pub bytes: usize,
+    pub evicted: u64,
+}
+
+/// 按子系统统计守护进程认为当前存在的设备
+///
+/// 跟踪的设备数超过上限时淘汰最久没有收到事件的设备。
+#[derive(Debug)]
+pub struct DeviceStats {
+    present: HashMap<String, HashSet<PathBuf>>,
+    recency: LruIndex<PathBuf>,
+    capacity: usize,
+    evicted: u64,
+}
+
+impl Default for DeviceStats {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_STATS_CAPACITY)
+    }
+}
+
+impl DeviceStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// capacity 为 0 时视为 1
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            present: HashMap::new(),
+            recency: LruIndex::new(),
+            capacity: capacity.max(1),
+            evicted: 0,
+        }
+    }
+
+    /// 根据事件更新设备的存在状态，重复的 add 不会重复计数
+    pub fn record(&mut self, device: &UEventDevice) {
+        let subsystem = device.subsystem_label().to_string();
+        let devpath = device.devpath().to_path_buf();
+
+        match device.action() {
+            DeviceAction::Remove => {
+                if let Some(devices) = self.present.get_mut(&subsystem) {
+                    devices.remove(&devpath);
+                    if devices.is_empty() {
+                        self.present.remove(&subsystem);
+                    }
+                }
+                self.recency.remove(&devpath);
+            }
+            DeviceAction::Move => {
+                if let Some(old) = device.property_path("DEVPATH_OLD") {
+                    if let Some(devices) = self.present.get_mut(&subsystem) {
+                        devices.remove(old);
+                    }
+                    self.recency.remove(&old.to_path_buf());
+                }
+                self.insert(subsystem, devpath);
+            }
+            _ => {
+                self.insert(subsystem, devpath);
+            }
+        }
+    }
+
+    fn insert(&mut self, subsystem: String, devpath: PathBuf) {
+        self.recency.touch(&devpath);
+        self.present.entry(subsystem).or_default().insert(devpath);
+
+        while self.recency.len() > self.capacity {
+            let Some(oldest) = self.recency.pop_oldest() else {
+                break;
+            };
+            debug!("Device stats full, evicting {:?}", oldest);
+            self.present.retain(|_, devices| {
+                devices.remove(&oldest);
+                !devices.is_empty()
+            });
+            self.evicted += 1;
+        }
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let bytes = self
+            .present
+            .iter()
+            .map(|(subsystem, devices)| {
+                subsystem.len()
+                    + devices
+                        .iter()
+                        .map(|devpath| DEVICE_OVERHEAD + devpath.as_os_str().len())
+                        .sum::<usize>()
+            })
+            .sum();
+
+        CacheUsage {
+            name: "stats".to_string(),
+            entries: self.recency.len(),
+            capacity: self.capacity,
+            bytes,
+            evicted: self.evicted,
+        }
+    }
+
+    pub fn counts(&self) -> BTreeMap<String, usize> {
+        self.present
+            .iter()
+            .map(|(subsystem, devices)| (subsystem.clone(), devices.len()))
+            .collect()
+    }
+
+    /// 以 "subsystem=count" 每行一条的格式写出统计
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let content: String = self
+            .counts()
+            .iter()
+            .map(|(subsystem, count)| format!("{}={}\n", subsystem, count))
+            .collect();
+
+        // 先写临时文件再 rename，避免 udevadm 读到写了一半的内容
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// 最近收到的缺少 SUBSYSTEM 或 ACTION 的事件，保留原始属性便于排查驱动问题
+#[derive(Debug, Default)]
+pub struct IncompleteEvents {
+    events: VecDeque<String>,
+}
+
+impl IncompleteEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, device: &UEventDevice) {
+        if self.events.len() == INCOMPLETE_KEEP {
+            self.events.pop_front();
+        }
+
+        let mut properties: Vec<_> = device.properties().iter().collect();
+        properties.sort();
+
+        let mut entry = format!(
+            "# seqnum {} missing {}\n",
+            device.seqnum(),
+            device.missing_fields().join(", ")
+        );
+        for (key, value) in properties {
+            entry.push_str(&format!("{}={}\n", key, value));
+        }
+        self.events.push_back(entry);
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        CacheUsage {
+            name: "incomplete".to_string(),
+            entries: self.events.len(),
+            capacity: INCOMPLETE_KEEP,
+            bytes: self.events.iter().map(String::len).sum(),
+            evicted: 0,
+        }
+    }
+
+    /// 每个事件一段，段之间以空行分隔
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let content = self.events.iter().cloned().collect::<Vec<_>>().join("\n");
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// 守护进程的事件队列状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueState {
+    /// 已经收到但还没有处理完的事件数，包括等待缺号的内核事件
+    pub depth: usize,
+    /// 最近收到的内核事件的 SEQNUM，还没有收到过时为 0
+    pub last_seqnum: u64,
+}
+
+/// 每项一行：第一行是事件数，第二行是 SEQNUM
+pub fn save_queue_state<P: AsRef<Path>>(path: P, state: QueueState) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, format!("{}\n{}\n", state.depth, state.last_seqnum))?;
+    fs::rename(&tmp_path, path)
+}
+
+/// 旧版本守护进程只写出事件数，此时 last_seqnum 为 0
+pub fn load_queue_state<P: AsRef<Path>>(path: P) -> io::Result<QueueState> {
+    let content = fs::read_to_string(path)?;
+    let invalid = |e: std::num::ParseIntError| io::Error::new(io::ErrorKind::InvalidData, format!("invalid queue state: {e}"));
+    let mut lines = content.lines().map(str::trim);
+    let depth = lines.next().unwrap_or_default().parse().map_err(invalid)?;
+    let last_seqnum = match lines.next() {
+        Some(seqnum) => seqnum.parse().map_err(invalid)?,
+        None => 0,
+    };
+    Ok(QueueState { depth, last_seqnum })
+}
+
+pub fn load_queue_depth<P: AsRef<Path>>(path: P) -> io::Result<usize> {
+    load_queue_state(path).map(|state| state.depth)
+}
+
+/// 以 "name=entries,capacity,bytes,evicted" 每行一条的格式写出缓存占用
+pub fn save_cache_usage<P: AsRef<Path>>(path: P, usage: &[CacheUsage]) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let content: String = usage
+        .iter()
+        .map(|cache| {
+            format!(
+                "{}={},{},{},{}\n",
+                cache.name, cache.entries, cache.capacity, cache.bytes, cache.evicted
+            )
+        })
+        .collect();
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+pub fn load_cache_usage<P: AsRef<Path>>(path: P) -> io::Result<Vec<CacheUsage>> {
+    let content = fs::read_to_string(path)?;
+    let mut usage = Vec::new();
+
+    for line in content.lines() {
+        let Some((name, fields)) = line.split_once('=') else {
+            continue;
+        };
+        let fields: Vec<&str> = fields.split(',').map(str::trim).collect();
+        if let [entries, capacity, bytes, evicted] = fields[..] {
+            if let (Ok(entries), Ok(capacity), Ok(bytes), Ok(evicted)) =
+                (entries.parse(), capacity.parse(), bytes.parse(), evicted.parse())
+            {
+                usage.push(CacheUsage {
+                    name: name.trim().to_string(),
+                    entries,
+                    capacity,
+                    bytes,
+                    evicted,
+                });
+            }
+        }
+    }
+
+    Ok(usage)
+}
+
+pub fn load_counts<P: AsRef<Path>>(path: P) -> io::Result<BTreeMap<String, usize>> {
+    let content = fs::read_to_string(path)?;
+    let mut counts = BTreeMap::new();
+
+    for line in content.lines() {
+        if let Some((subsystem, count)) = line.split_once('=') {
+            if let Ok(count) = count.trim().parse() {
+                counts.insert(subsystem.trim().to_string(), count);
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+/// 格式化为 "block: 14, tty: 6, input: 23" 形式的摘要
+pub fn format_summary(counts: &BTreeMap<String, usize>) -> String {
+    if counts.is_empty() {
+        return "no devices tracked".to_string();
+    }
+
+    counts
+        .iter()
+        .map(|(subsystem, count)| format!("{}: {}", subsystem, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// 格式化为 "db: 120/16384 entries, ~45 KiB, 0 evicted" 形式的一行
+pub fn format_cache_usage(cache: &CacheUsage) -> String {
+    format!(
+        "{}: {}/{} entries, ~{} KiB, {} evicted",
+        cache.name,
+        cache.entries,
+        cache.capacity,
+        cache.bytes.div_ceil(1024),
+        cache.evicted
+    )
+}
@@ -0,0 +1,135 @@
+// src/strict.rs
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{access, AccessFlags};
+
+use crate::rules::parser::{parse_inline_rules, parse_rules_with_errors};
+
+/// --strict 模式下发现的单个问题
+#[derive(Debug, Clone)]
+pub struct StartupProblem {
+    pub kind: &'static str,
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// --strict 启动检查失败，包含全部问题
+#[derive(Debug)]
+pub struct StrictError {
+    pub problems: Vec<StartupProblem>,
+}
+
+impl fmt::Display for StrictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "strict startup checks failed with {} problem(s)", self.problems.len())?;
+        for p in &self.problems {
+            match p.line {
+                Some(line) => write!(f, "\n  [{}] {}:{}: {}", p.kind, p.path.display(), line, p.message)?,
+                None => write!(f, "\n  [{}] {}: {}", p.kind, p.path.display(), p.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for StrictError {}
+
+impl StrictError {
+    /// 供镜像构建脚本解析的 JSON 摘要
+    pub fn summary_json(&self) -> String {
+        let problems: Vec<String> = self
+            .problems
+            .iter()
+            .map(|p| {
+                let line = p.line.map_or("null".to_string(), |l| l.to_string());
+                format!(
+                    "{{\"kind\":\"{}\",\"path\":\"{}\",\"line\":{},\"message\":\"{}\"}}",
+                    p.kind,
+                    json_escape(&p.path.to_string_lossy()),
+                    line,
+                    json_escape(&p.message)
+                )
+            })
+            .collect();
+
+        format!("{{\"ok\":false,\"problems\":[{}]}}", problems.join(","))
+    }
+}
+
+/// 转义 JSON 字符串中的引号、反斜杠和控制字符
+pub(crate) fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// 检查至少有一个规则目录存在且没有语法问题、设备根目录可写
+pub fn check_startup(
+    rule_paths: &[PathBuf],
+    inline_rules: &[String],
+    dev_root: &Path,
+    max_rules_file_size: u64,
+) -> Result<(), StrictError> {
+    let mut problems = Vec::new();
+
+    // 分层布局中部分目录不存在是正常的，但至少要有一个；只用内联规则的容器可以没有规则目录
+    if inline_rules.is_empty() && !rule_paths.iter().any(|dir| dir.is_dir()) {
+        problems.extend(rule_paths.iter().map(|dir| StartupProblem {
+            kind: "missing_rules_dir",
+            path: dir.clone(),
+            line: None,
+            message: "rules directory does not exist".to_string(),
+        }));
+    }
+
+    let mut diagnostics = parse_inline_rules(inline_rules).diagnostics;
+    match parse_rules_with_errors(rule_paths, max_rules_file_size) {
+        Ok(report) => diagnostics.extend(report.diagnostics),
+        Err(e) => problems.push(StartupProblem {
+            kind: "rules_io",
+            path: rule_paths.first().cloned().unwrap_or_default(),
+            line: None,
+            message: e.to_string(),
+        }),
+    }
+    problems.extend(diagnostics.into_iter().map(|e| StartupProblem {
+        kind: "rules_parse",
+        path: e.file,
+        line: Some(e.line),
+        message: match e.column {
+            Some(column) => format!("column {}: {}", column, e.message),
+            None => e.message,
+        },
+    }));
+
+    let writable = fs::create_dir_all(dev_root)
+        .map_err(|e| e.to_string())
+        .and_then(|_| access(dev_root, AccessFlags::W_OK).map_err(|e| e.to_string()));
+    if let Err(e) = writable {
+        problems.push(StartupProblem {
+            kind: "dev_root_unwritable",
+            path: dev_root.to_path_buf(),
+            line: None,
+            message: e,
+        });
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(StrictError { problems })
+    }
+}
@@ -0,0 +1,331 @@
+// src/symlink_db.rs
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::*;
+
+/// 守护进程写出的设备节点和符号链接记录，udevadm info 从这里读取 N: 和 S: 行
+pub const LINKS_PATH: &str = "/run/rust_udev/links";
+
+/// 某个设备创建的节点以及它声明的符号链接
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceLinks {
+    pub node: Option<PathBuf>,
+    pub links: Vec<LinkRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkRecord {
+    pub link: PathBuf,
+    pub priority: i32,
+    /// 链接当前是否指向该设备
+    pub active: bool,
+}
+
+/// 多个设备声明同一个符号链接时的处理方式，按链接所在目录配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// 优先级高的设备获得链接，优先级相同时后声明的设备胜出（默认）
+    #[default]
+    Overwrite,
+    /// 优先级相同时先声明的设备保留链接
+    KeepFirst,
+    /// 后来的设备改用 name_1、name_2 等带序号的链接
+    Suffix,
+    /// 拒绝后来的设备的声明并记录错误
+    Error,
+}
+
+impl CollisionPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "overwrite" => Some(CollisionPolicy::Overwrite),
+            "keep-first" => Some(CollisionPolicy::KeepFirst),
+            "suffix" | "suffix-with-index" => Some(CollisionPolicy::Suffix),
+            "error" => Some(CollisionPolicy::Error),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CollisionPolicy::Overwrite => "overwrite",
+            CollisionPolicy::KeepFirst => "keep-first",
+            CollisionPolicy::Suffix => "suffix-with-index",
+            CollisionPolicy::Error => "error",
+        }
+    }
+}
+
+/// 解析 udev.conf 中的 symlink_collision=，如 "disk/by-label:suffix, disk/by-id:keep-first"
+///
+/// 目录相对于设备根目录；返回 (目录, 策略) 列表，格式错误时返回出错的那一项。
+pub fn parse_collision_policies(value: &str) -> Result<Vec<(PathBuf, CollisionPolicy)>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (dir, policy) = entry.rsplit_once(':').ok_or_else(|| entry.to_string())?;
+            let policy = CollisionPolicy::parse(policy).ok_or_else(|| entry.to_string())?;
+            Ok((PathBuf::from(dir.trim().trim_matches('/')), policy))
+        })
+        .collect()
+}
+
+/// 某个设备对一个符号链接的声明
+#[derive(Debug, Clone)]
+struct Claim {
+    devpath: PathBuf,
+    target: PathBuf,
+    priority: i32,
+    // 声明的先后顺序，优先级相同时后声明的设备胜出
+    order: u64,
+}
+
+/// 记录每个符号链接被哪些设备声明，用于按 link_priority 决定链接指向
+#[derive(Debug, Default)]
+pub struct SymlinkDb {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    claims: HashMap<PathBuf, Vec<Claim>>,
+    nodes: HashMap<PathBuf, PathBuf>,
+    next_order: u64,
+    // 按目录（绝对路径）配置的冲突策略
+    policies: HashMap<PathBuf, CollisionPolicy>,
+}
+
+impl Inner {
+    // 链接所在目录或最近的上级目录的策略
+    fn policy(&self, link: &Path) -> CollisionPolicy {
+        link.ancestors()
+            .skip(1)
+            .find_map(|dir| self.policies.get(dir))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn winner(&self, link: &Path) -> Option<&Claim> {
+        let claims = self.claims.get(link)?.iter();
+        match self.policy(link) {
+            CollisionPolicy::KeepFirst => {
+                claims.max_by_key(|claim| (claim.priority, std::cmp::Reverse(claim.order)))
+            }
+            _ => claims.max_by_key(|claim| (claim.priority, claim.order)),
+        }
+    }
+
+    // suffix 策略：设备已经持有的链接保持不变，否则取第一个没有被其它设备占用的 name、name_1、name_2 ...
+    fn suffixed_link(&self, link: &Path, devpath: &Path) -> PathBuf {
+        let candidate = |index: usize| match index {
+            0 => link.to_path_buf(),
+            n => {
+                let mut name = link.as_os_str().to_os_string();
+                name.push(format!("_{}", n));
+                PathBuf::from(name)
+            }
+        };
+        let owned = (0..)
+            .map(candidate)
+            .take_while(|candidate| self.claims.contains_key(candidate))
+            .find(|candidate| {
+                self.claims[candidate].iter().any(|claim| claim.devpath == devpath)
+            });
+
+        owned.unwrap_or_else(|| {
+            (0..)
+                .map(candidate)
+                .find(|candidate| !self.taken(candidate, devpath))
+                .unwrap_or_else(|| link.to_path_buf())
+        })
+    }
+
+    // 链接是否已被其它设备声明
+    fn taken(&self, link: &Path, devpath: &Path) -> bool {
+        self.claims
+            .get(link)
+            .is_some_and(|claims| claims.iter().any(|claim| claim.devpath != devpath))
+    }
+
+    fn snapshot(&self) -> BTreeMap<PathBuf, DeviceLinks> {
+        let mut devices: BTreeMap<PathBuf, DeviceLinks> = BTreeMap::new();
+
+        for (devpath, node) in &self.nodes {
+            devices.entry(devpath.clone()).or_default().node = Some(node.clone());
+        }
+        for (link, claims) in &self.claims {
+            let winner = self.winner(link).map(|claim| claim.order);
+            for claim in claims {
+                devices.entry(claim.devpath.clone()).or_default().links.push(LinkRecord {
+                    link: link.clone(),
+                    priority: claim.priority,
+                    active: Some(claim.order) == winner,
+                });
+            }
+        }
+        for device in devices.values_mut() {
+            device.links.sort_by(|a, b| a.link.cmp(&b.link));
+        }
+
+        devices
+    }
+}
+
+impl SymlinkDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置按目录的冲突策略，目录为绝对路径，取代之前的设置
+    pub fn set_policies(&self, policies: Vec<(PathBuf, CollisionPolicy)>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.policies = policies.into_iter().collect();
+    }
+
+    /// 记录设备对链接的声明（同一设备重复声明会更新目标和优先级）
+    ///
+    /// 返回实际声明的链接（suffix 策略下可能带序号）以及该链接应当指向的目标；
+    /// error 策略下链接已被其它设备占用时拒绝声明，返回 None。
+    pub fn claim(&self, link: &Path, devpath: &Path, target: &Path, priority: i32) -> Option<(PathBuf, PathBuf)> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let link = match inner.policy(link) {
+            CollisionPolicy::Error if inner.taken(link, devpath) => {
+                error!("Symlink {:?} is already claimed, refusing it for {:?}", link, devpath);
+                return None;
+            }
+            CollisionPolicy::Suffix => inner.suffixed_link(link, devpath),
+            _ => link.to_path_buf(),
+        };
+
+        let order = inner.next_order;
+        inner.next_order += 1;
+
+        let claims = inner.claims.entry(link.clone()).or_default();
+        claims.retain(|claim| claim.devpath != devpath);
+        claims.push(Claim {
+            devpath: devpath.to_path_buf(),
+            target: target.to_path_buf(),
+            priority,
+            order,
+        });
+
+        let winner = inner.winner(&link).map(|claim| claim.target.clone()).unwrap_or_default();
+        Some((link, winner))
+    }
+
+    /// 撤销设备对一个链接的声明，用于链接创建失败时
+    pub fn unclaim(&self, link: &Path, devpath: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(claims) = inner.claims.get_mut(link) {
+            claims.retain(|claim| claim.devpath != devpath);
+            if claims.is_empty() {
+                inner.claims.remove(link);
+            }
+        }
+    }
+
+    /// 记录设备创建的节点，设备 release 时一并清除
+    pub fn record_node(&self, devpath: &Path, node: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.nodes.insert(devpath.to_path_buf(), node.to_path_buf());
+    }
+
+    /// 设备创建时记录的节点，NAME= 改过名字时与 DEVNAME 不同
+    pub fn node(&self, devpath: &Path) -> Option<PathBuf> {
+        self.inner.lock().unwrap().nodes.get(devpath).cloned()
+    }
+
+    pub fn device_links(&self, devpath: &Path) -> DeviceLinks {
+        let inner = self.inner.lock().unwrap();
+        inner.snapshot().remove(devpath).unwrap_or_default()
+    }
+
+    /// 写出所有设备的节点和链接；持锁写入，并发的保存不会互相覆盖临时文件
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let inner = self.inner.lock().unwrap();
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut content = String::new();
+        for (devpath, device) in inner.snapshot() {
+            if let Some(node) = &device.node {
+                content.push_str(&format!("N\t{}\t{}\n", devpath.display(), node.display()));
+            }
+            for link in &device.links {
+                content.push_str(&format!(
+                    "S\t{}\t{}\t{}\t{}\n",
+                    devpath.display(),
+                    link.link.display(),
+                    link.priority,
+                    u8::from(link.active)
+                ));
+            }
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// 撤销设备的所有声明，返回受影响的链接以及剩余的最高优先级目标（没有则为 None）
+    pub fn release(&self, devpath: &Path) -> Vec<(PathBuf, Option<PathBuf>)> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut affected = Vec::new();
+        inner.nodes.remove(devpath);
+
+        inner.claims.retain(|link, claims| {
+            let before = claims.len();
+            claims.retain(|claim| claim.devpath != devpath);
+            if claims.len() != before {
+                affected.push(link.clone());
+            }
+            !claims.is_empty()
+        });
+
+        affected
+            .into_iter()
+            .map(|link| {
+                let target = inner.winner(&link).map(|claim| claim.target.clone());
+                (link, target)
+            })
+            .collect()
+    }
+}
+
+/// 读取守护进程写出的节点和链接记录，以 devpath 为键
+pub fn load_device_links<P: AsRef<Path>>(path: P) -> io::Result<BTreeMap<PathBuf, DeviceLinks>> {
+    let content = fs::read_to_string(path)?;
+    let mut devices: BTreeMap<PathBuf, DeviceLinks> = BTreeMap::new();
+
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields[..] {
+            ["N", devpath, node] => {
+                devices.entry(PathBuf::from(devpath)).or_default().node = Some(PathBuf::from(node));
+            }
+            ["S", devpath, link, priority, active] => {
+                let Ok(priority) = priority.parse() else {
+                    continue;
+                };
+                devices.entry(PathBuf::from(devpath)).or_default().links.push(LinkRecord {
+                    link: PathBuf::from(link),
+                    priority,
+                    active: active == "1",
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(devices)
+}
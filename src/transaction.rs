@@ -0,0 +1,292 @@
+// src/transaction.rs
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use crate::db;
+use crate::device::{DeviceAction, UEventDevice};
+
+/// 正在处理的事件留下的意图记录；正常结束时删除，启动时还存在说明上次在处理中途退出
+///
+/// 目录在 tmpfs 上，只用于守护进程崩溃或被杀后的恢复，重启后节点和记录本身也不复存在。
+pub const TRANSACTIONS_DIR: &str = "/run/rust_udev/transactions";
+
+/// 一个事件将要进行的文件系统和数据库修改：先写下意图，再创建节点和链接、写设备记录，最后提交
+///
+/// 每行一项："A:" 动作、"P:" devpath、"Q:" seqnum、"N:" 节点、"S:" 链接，路径都是完整路径。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub action: String,
+    pub devpath: PathBuf,
+    pub seqnum: u64,
+    /// 在数据库中的文件名，同时作为意图文件的文件名
+    pub id: String,
+    pub node: Option<PathBuf>,
+    pub links: Vec<PathBuf>,
+}
+
+/// 启动时如何处理遗留的事务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// 设备还在：让内核重新发出事件，由正常的处理流程完成
+    Replay,
+    /// 设备已经不在，或中断的是 remove：删除节点、指向节点的链接和设备记录
+    Cleanup,
+}
+
+impl Transaction {
+    /// 没有数据库 id 的设备无法记录，返回 None
+    pub fn new(device: &UEventDevice, node: Option<PathBuf>, links: Vec<PathBuf>) -> Option<Self> {
+        Some(Self {
+            action: device.action().as_str().to_string(),
+            devpath: device.devpath().to_path_buf(),
+            seqnum: device.seqnum(),
+            id: device.db_id()?,
+            node,
+            links,
+        })
+    }
+
+    /// 写入意图，之后才能开始修改文件系统；先写临时文件再改名，不会留下写了一半的意图
+    pub fn begin<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let mut content = format!("A:{}\nP:{}\nQ:{}\n", self.action, self.devpath.display(), self.seqnum);
+        if let Some(node) = &self.node {
+            content.push_str(&format!("N:{}\n", node.display()));
+        }
+        for link in &self.links {
+            content.push_str(&format!("S:{}\n", link.display()));
+        }
+
+        let path = dir.join(&self.id);
+        let tmp_path = dir.join(format!("{}.tmp", self.id));
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// 所有修改完成后删除意图
+    pub fn commit<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        match fs::remove_file(dir.as_ref().join(&self.id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse(id: &str, content: &str) -> Option<Self> {
+        let mut action = None;
+        let mut devpath = None;
+        let mut seqnum = 0;
+        let mut node = None;
+        let mut links = Vec::new();
+        for line in content.lines() {
+            let Some((kind, value)) = line.split_once(':') else {
+                continue;
+            };
+            match kind {
+                "A" => action = Some(value.to_string()),
+                "P" => devpath = Some(PathBuf::from(value)),
+                "Q" => seqnum = value.parse().unwrap_or(0),
+                "N" => node = Some(PathBuf::from(value)),
+                "S" => links.push(PathBuf::from(value)),
+                _ => {}
+            }
+        }
+        Some(Self {
+            action: action?,
+            devpath: devpath?,
+            seqnum,
+            id: id.to_string(),
+            node,
+            links,
+        })
+    }
+
+    /// sys_root 下设备还存在且不是 remove 时重放，否则清理
+    pub fn recovery<P: AsRef<Path>>(&self, sys_root: P) -> Recovery {
+        let syspath = sys_root.as_ref().join(self.devpath.strip_prefix("/").unwrap_or(&self.devpath));
+        if self.action != DeviceAction::Remove.as_str() && syspath.join("uevent").exists() {
+            Recovery::Replay
+        } else {
+            Recovery::Cleanup
+        }
+    }
+}
+
+/// 读取遗留的意图，写了一半的临时文件直接删除；目录不存在时为空
+pub fn pending<P: AsRef<Path>>(dir: P) -> io::Result<Vec<Transaction>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut transactions = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        // begin 没有完成，文件系统还没有被修改
+        if path.extension().is_some_and(|ext| ext == "tmp") {
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        let id = entry.file_name().to_string_lossy().into_owned();
+        match fs::read_to_string(&path).map(|content| Transaction::parse(&id, &content)) {
+            Ok(Some(transaction)) => transactions.push(transaction),
+            Ok(None) => {
+                warn!("Discarding malformed transaction {:?}", path);
+                let _ = fs::remove_file(&path);
+            }
+            Err(e) => warn!("Failed to read transaction {:?}: {}", path, e),
+        }
+    }
+    transactions.sort_by_key(|transaction| transaction.seqnum);
+    Ok(transactions)
+}
+
+/// 处理上次中断的事务：设备仍在时向 sysfs 写入 add 或 change 让内核重新发出事件，
+/// 否则删除节点、仍指向节点的链接和 data_dir 中的设备记录。处理完的意图被删除
+pub fn recover<P: AsRef<Path>>(dir: P, sys_root: &Path, data_dir: &Path) -> io::Result<Vec<(Transaction, Recovery)>> {
+    let dir = dir.as_ref();
+    let mut recovered = Vec::new();
+
+    for transaction in pending(dir)? {
+        let recovery = transaction.recovery(sys_root);
+        let result = match recovery {
+            Recovery::Replay => replay(&transaction, sys_root),
+            Recovery::Cleanup => cleanup(&transaction, data_dir),
+        };
+        match result {
+            Ok(()) => {
+                transaction.commit(dir)?;
+                recovered.push((transaction, recovery));
+            }
+            Err(e) => warn!("Failed to recover {:?} ({}): {}", transaction.devpath, transaction.action, e),
+        }
+    }
+    Ok(recovered)
+}
+
+fn replay(transaction: &Transaction, sys_root: &Path) -> io::Result<()> {
+    let syspath = sys_root.join(transaction.devpath.strip_prefix("/").unwrap_or(&transaction.devpath));
+    let action = if transaction.action == DeviceAction::Add.as_str() {
+        "add"
+    } else {
+        "change"
+    };
+    fs::write(syspath.join("uevent"), action)
+}
+
+fn cleanup(transaction: &Transaction, data_dir: &Path) -> io::Result<()> {
+    // 链接可能已经属于另一个设备，只删除指向本设备节点的
+    if let Some(node) = &transaction.node {
+        for link in &transaction.links {
+            if fs::read_link(link).is_ok_and(|target| &target == node) {
+                fs::remove_file(link)?;
+            }
+        }
+        match fs::symlink_metadata(node) {
+            Ok(metadata) if !metadata.is_dir() => fs::remove_file(node)?,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    db::remove_record(data_dir, &transaction.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("rust_udev-transaction-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn transaction(action: &str, node: &Path, links: Vec<PathBuf>) -> Transaction {
+        Transaction {
+            action: action.to_string(),
+            devpath: PathBuf::from("/devices/virtual/block/loop0"),
+            seqnum: 7,
+            id: "b7:0".to_string(),
+            node: Some(node.to_path_buf()),
+            links,
+        }
+    }
+
+    #[test]
+    fn begin_pending_commit_round_trip() {
+        let tmp = TempDir::new("cycle");
+        let dir = tmp.0.join("transactions");
+        let written = transaction("add", Path::new("/dev/loop0"), vec![PathBuf::from("/dev/disk/by-id/x")]);
+
+        written.begin(&dir).unwrap();
+        fs::write(dir.join("c1:3.tmp"), "A:add\n").unwrap();
+        assert_eq!(pending(&dir).unwrap(), vec![written.clone()]);
+        // 写了一半的临时文件在读取时被删除
+        assert!(!dir.join("c1:3.tmp").exists());
+
+        written.commit(&dir).unwrap();
+        assert!(pending(&dir).unwrap().is_empty());
+        written.commit(&dir).unwrap();
+    }
+
+    #[test]
+    fn recover_replays_present_devices() {
+        let tmp = TempDir::new("replay");
+        let dir = tmp.0.join("transactions");
+        let sys_root = tmp.0.join("sys");
+        let syspath = sys_root.join("devices/virtual/block/loop0");
+        fs::create_dir_all(&syspath).unwrap();
+        fs::write(syspath.join("uevent"), "").unwrap();
+
+        transaction("change", Path::new("/dev/loop0"), Vec::new()).begin(&dir).unwrap();
+        let recovered = recover(&dir, &sys_root, &tmp.0.join("data")).unwrap();
+
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].1, Recovery::Replay);
+        assert_eq!(fs::read_to_string(syspath.join("uevent")).unwrap(), "change");
+        assert!(pending(&dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn recover_cleans_up_vanished_devices() {
+        let tmp = TempDir::new("cleanup");
+        let dir = tmp.0.join("transactions");
+        let data_dir = tmp.0.join("data");
+        let node = tmp.0.join("loop0");
+        let own_link = tmp.0.join("own");
+        let foreign_link = tmp.0.join("foreign");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(data_dir.join("b7:0"), "E:ID_X=1\n").unwrap();
+        fs::write(&node, "").unwrap();
+        std::os::unix::fs::symlink(&node, &own_link).unwrap();
+        std::os::unix::fs::symlink(tmp.0.join("other"), &foreign_link).unwrap();
+
+        transaction("add", &node, vec![own_link.clone(), foreign_link.clone()]).begin(&dir).unwrap();
+        let recovered = recover(&dir, &tmp.0.join("sys"), &data_dir).unwrap();
+
+        assert_eq!(recovered[0].1, Recovery::Cleanup);
+        assert!(!node.exists());
+        assert!(fs::symlink_metadata(&own_link).is_err());
+        assert!(fs::symlink_metadata(&foreign_link).is_ok());
+        assert!(!data_dir.join("b7:0").exists());
+        assert!(pending(&dir).unwrap().is_empty());
+    }
+}
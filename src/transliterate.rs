@@ -0,0 +1,152 @@
+// src/transliterate.rs
+
+// 除 ASCII 字母数字之外可以出现在设备节点和链接名中的字符
+const SAFE_CHARS: &[u8] = b"#+-.:=@_";
+
+/// 厂商、型号、序列号等字符串用于 by-id 名字时，不安全字符的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transliteration {
+    /// 替换为 '_'，与 systemd-udevd 生成的名字逐字节相同
+    #[default]
+    Replace,
+    /// 直接去掉
+    Strip,
+    /// 像 ID_VENDOR_ENC 一样写成 \xNN，可以还原出原值
+    Escape,
+}
+
+impl Transliteration {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "replace" => Some(Transliteration::Replace),
+            "strip" => Some(Transliteration::Strip),
+            "escape" => Some(Transliteration::Escape),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transliteration::Replace => "replace",
+            Transliteration::Strip => "strip",
+            Transliteration::Escape => "escape",
+        }
+    }
+}
+
+// C 语言 isspace 的字符，比 char::is_ascii_whitespace 多了 '\v'
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r')
+}
+
+fn is_safe(b: u8, keep: &str) -> bool {
+    b.is_ascii_alphanumeric() || SAFE_CHARS.contains(&b) || keep.as_bytes().contains(&b)
+}
+
+// 开头是一个合法的多字节 UTF-8 字符时返回它的长度；与 udev 一样不接受 Unicode 非字符
+fn multibyte_len(bytes: &[u8]) -> Option<usize> {
+    let len = match bytes.first()? {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return None,
+    };
+    let c = std::str::from_utf8(bytes.get(..len)?).ok()?.chars().next()? as u32;
+    let noncharacter = (0xFDD0..=0xFDEF).contains(&c) || c & 0xFFFE == 0xFFFE;
+    (!noncharacter).then_some(len)
+}
+
+/// 与 udev 的 replace_whitespace 相同：去掉首尾空白，中间连续的空白换成一个 '_'
+pub fn replace_whitespace<S: AsRef<[u8]> + ?Sized>(value: &S) -> Vec<u8> {
+    value
+        .as_ref()
+        .split(|&b| is_space(b))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(&b'_')
+}
+
+// 逐字节检查：安全字符、keep 中的字符、已有的 \x 转义和合法的多字节 UTF-8 字符保留；
+// keep 含空格时空白换成空格；其它字节替换为 '_'，strip 为 true 时去掉
+fn filter_chars(bytes: &[u8], keep: &str, strip: bool) -> String {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if is_safe(b, keep) {
+            result.push(b);
+            i += 1;
+        } else if b == b'\\' && bytes.get(i + 1) == Some(&b'x') {
+            result.extend_from_slice(b"\\x");
+            i += 2;
+        } else if let Some(len) = multibyte_len(&bytes[i..]) {
+            result.extend_from_slice(&bytes[i..i + len]);
+            i += len;
+        } else {
+            if is_space(b) && keep.contains(' ') {
+                result.push(b' ');
+            } else if !strip {
+                result.push(b'_');
+            }
+            i += 1;
+        }
+    }
+    String::from_utf8(result).expect("only ASCII and complete UTF-8 sequences are kept")
+}
+
+/// 与 udev 的 replace_chars 相同：保留 ASCII 字母数字、"#+-.:=@_"、keep 中的字符、
+/// 合法的多字节 UTF-8 字符以及 encode_string 产生的 \x 转义，其它字节替换为 '_'
+pub fn replace_chars<S: AsRef<[u8]> + ?Sized>(value: &S, keep: &str) -> String {
+    filter_chars(value.as_ref(), keep, false)
+}
+
+/// 与 udev 的 encode_devnode_name 相同：多字节 UTF-8 字符和安全字符原样保留，
+/// 其它字节（包括空格和 '\\'）写成 \xNN，结果可以直接用作链接名，且能还原出原值
+pub fn encode_string<S: AsRef<[u8]> + ?Sized>(value: &S) -> String {
+    let bytes = value.as_ref();
+    let mut encoded = String::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(len) = multibyte_len(&bytes[i..]) {
+            encoded.push_str(std::str::from_utf8(&bytes[i..i + len]).expect("checked by multibyte_len"));
+            i += len;
+            continue;
+        }
+        let b = bytes[i];
+        if b != b'\\' && is_safe(b, "") {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("\\x{:02x}", b));
+        }
+        i += 1;
+    }
+    encoded
+}
+
+/// 把厂商、型号、序列号等字符串按 mode 转换为 by-id 名字的一部分；
+/// 默认与 systemd-udevd 相同：先 replace_whitespace 再 replace_chars
+pub fn sanitize_id<S: AsRef<[u8]> + ?Sized>(value: &S, mode: Transliteration) -> String {
+    match mode {
+        Transliteration::Replace => filter_chars(&replace_whitespace(value), "", false),
+        Transliteration::Strip => filter_chars(&replace_whitespace(value), "", true),
+        Transliteration::Escape => {
+            let bytes = value.as_ref();
+            let start = bytes.iter().position(|&b| !is_space(b)).unwrap_or(bytes.len());
+            let end = bytes.iter().rposition(|&b| !is_space(b)).map_or(start, |end| end + 1);
+            encode_string(&bytes[start..end])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_id_follows_the_given_mode() {
+        let value = b" ACME  Disk/2\xff ";
+        assert_eq!(sanitize_id(value, Transliteration::Replace), "ACME_Disk_2_");
+        assert_eq!(sanitize_id(value, Transliteration::Strip), "ACME_Disk2");
+        assert_eq!(sanitize_id(value, Transliteration::Escape), "ACME\\x20\\x20Disk\\x2f2\\xff");
+    }
+}
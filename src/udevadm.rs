@@ -1,13 +1,61 @@
 // src/udevadm.rs
 
-use crate::libudev::get_device_info;
-use log::{info, error};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags};
+
+use crate::builtins::{builtin_names, find_builtin, run_builtin, BuiltinOptions};
+use crate::control::{send_commands, ControlCommand, CONTROL_PATH};
+use crate::dashboard::Dashboard;
+use crate::db::{load_history, load_provenance, HISTORY_DIR, PROVENANCE_DIR};
+use crate::device::{devlink_name, synth_uuid, DeviceAction, UEventDevice};
+use crate::journal::{load_journal, RunRecord, JOURNAL_PATH};
+use crate::libudev::{device_descendants, get_device_info, resolve_device, resolve_syspath, Enumerator};
+use crate::monitor::{MonitorView, PropertySource, UEventMonitor};
+use crate::plan::plan_actions;
+use crate::stats::{
+    format_cache_usage, format_summary, load_cache_usage, load_counts, load_queue_depth, load_queue_state,
+    CACHES_PATH, QUEUE_PATH, STATS_PATH,
+};
+use crate::rules::metrics::{self, RULE_METRICS_PATH, TOP_OFFENDERS};
+use crate::rules::matcher::Rule;
+use crate::rules::parser::{
+    load_all_rules, parse_rules_file_with_errors, parse_rules_str_with_errors, parse_rules_with_errors,
+    rule_files,
+};
+use crate::rules::ruleset::{RuleSet, RuleSetOptions};
+use crate::rules::security::SecurityReport;
+use crate::symlink_db::{load_device_links, DeviceLinks, LINKS_PATH};
+use crate::xattr;
+use log::{info, error, warn};
+
+const DASHBOARD_REFRESH: Duration = Duration::from_secs(1);
+
+/// 内核已经发出的最后一个事件的 SEQNUM
+pub const KERNEL_SEQNUM_PATH: &str = "/sys/kernel/uevent_seqnum";
+
+// udevadm settle 检查队列的间隔
+const SETTLE_POLL: Duration = Duration::from_millis(50);
+
+// 队列已空且这么久没有变化时，还没收到的 SEQNUM 按属于其它网络命名空间处理
+const SETTLE_QUIET: Duration = Duration::from_millis(500);
 
 #[derive(Debug)]
 pub enum UdevadmError {
     DeviceNotFound(String),
     IoError(String, std::io::Error),
     SysfsError(String),
+    /// udevadm verify 发现的问题数
+    VerifyFailed(usize),
+    UnknownBuiltin(String),
+    /// 守护进程拒绝了控制命令
+    ControlFailed(String, String),
+    /// udevadm settle 超时，队列中还有这么多事件
+    SettleTimeout(usize),
 }
 
 impl std::fmt::Display for UdevadmError {
@@ -16,27 +64,757 @@ impl std::fmt::Display for UdevadmError {
             UdevadmError::DeviceNotFound(path) => write!(f, "Device not found: {}", path),
             UdevadmError::IoError(path, err) => write!(f, "IO Error on {}: {}", path, err),
             UdevadmError::SysfsError(path) => write!(f, "Error accessing sysfs for {}", path),
+            UdevadmError::UnknownBuiltin(name) => write!(f, "Unknown builtin: {}", name),
+            UdevadmError::VerifyFailed(count) => write!(f, "Rules verification found {} problem(s)", count),
+            UdevadmError::SettleTimeout(depth) => write!(f, "Timed out with {} event(s) still queued", depth),
+            UdevadmError::ControlFailed(command, reason) => write!(f, "Control command '{}' failed: {}", command, reason),
         }
     }
 }
 
-pub fn udevadm_info(device_path: &str) -> Result<(), UdevadmError> {
+/// 以 P:/N:/S:/E: 格式打印设备，节点和链接相对 dev_root 显示；verbose 时标注链接优先级
+pub fn udevadm_info(device_path: &str, dev_root: &Path, verbose: bool) -> Result<(), UdevadmError> {
     info!("udevadm device path: {}", device_path);
-    match get_device_info(device_path) {
-        Some(info) => {
-            for (key, value) in info {
-                info!("{}={}", key, value);
+    let (Some(syspath), Some(info)) = (resolve_syspath(device_path), get_device_info(device_path)) else {
+        error!("Device not found: {}", device_path);
+        return Err(UdevadmError::DeviceNotFound(device_path.to_string()));
+    };
+
+    let links = load_links();
+    print_device(&syspath, info, &links, dev_root, verbose);
+    Ok(())
+}
+
+// 守护进程没有运行或还没有创建过节点时没有记录文件
+fn load_links() -> BTreeMap<PathBuf, DeviceLinks> {
+    match load_device_links(LINKS_PATH) {
+        Ok(links) => links,
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to read {}: {}", LINKS_PATH, e);
+            }
+            BTreeMap::new()
+        }
+    }
+}
+
+fn print_device(
+    syspath: &Path,
+    info: HashMap<String, String>,
+    links: &BTreeMap<PathBuf, DeviceLinks>,
+    dev_root: &Path,
+    verbose: bool,
+) {
+    let devpath = Path::new("/").join(syspath.strip_prefix("/sys").unwrap_or(syspath));
+    println!("P: {}", devpath.display());
+
+    if let Some(device) = links.get(&devpath) {
+        if let Some(node) = &device.node {
+            println!("N: {}", node.strip_prefix(dev_root).unwrap_or(node).display());
+        }
+        for link in &device.links {
+            let name = link.link.strip_prefix(dev_root).unwrap_or(&link.link);
+            match (verbose, link.active) {
+                (false, _) => println!("S: {}", name.display()),
+                (true, true) => println!("S: {} (priority {})", name.display(), link.priority),
+                (true, false) => println!(
+                    "S: {} (priority {}, owned by another device)",
+                    name.display(),
+                    link.priority
+                ),
+            }
+        }
+    } else if let Some(devlinks) = info.get("DEVLINKS") {
+        // 链接状态中没有这个设备时使用数据库记录的链接
+        for link in devlinks.split_whitespace() {
+            println!("S: {}", devlink_name(link, dev_root));
+        }
+    }
+
+    if let Some(tags) = info.get("TAGS") {
+        for tag in tags.split(':').filter(|tag| !tag.is_empty()) {
+            println!("G: {}", tag);
+        }
+    }
+
+    // 规则用 XATTR{} 写在设备节点上的扩展属性
+    let node = links
+        .get(&devpath)
+        .and_then(|device| device.node.clone())
+        .or_else(|| info.get("DEVNAME").map(|name| dev_root.join(name)));
+    if let Some(node) = node {
+        match xattr::list_trusted(&node) {
+            Ok(attrs) => {
+                for (name, value) in attrs {
+                    println!("X: {}={}", name, value);
+                }
+            }
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                warn!("Failed to read xattrs of {}: {}", node.display(), e);
             }
+            Err(_) => {}
+        }
+    }
+
+    let mut properties: Vec<_> = info.into_iter().collect();
+    properties.sort();
+    for (key, value) in properties {
+        println!("E: {}={}", key, value);
+    }
+}
+
+/// 打印所有设备的属性；属性和标签取自守护进程保存的设备记录，没有记录的设备读取 sysfs。
+/// tags 不为空时只打印带有全部这些标签的设备
+pub fn udevadm_info_export_db(tags: &[String], dev_root: &Path) -> Result<(), UdevadmError> {
+    let mut enumerator = Enumerator::new();
+    enumerator.use_dev_root(dev_root);
+    for tag in tags {
+        enumerator.match_tag(tag);
+    }
+    let links = load_links();
+
+    for (i, device) in enumerator.scan_devices().into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_device(&device.syspath(), device.properties().clone(), &links, dev_root, false);
+    }
+    Ok(())
+}
+
+/// 打印设备及其每一级父设备的内核名、子系统、驱动和 sysfs 属性，格式可以直接用于规则：
+/// 设备本身用 KERNEL/ATTR{}，父设备用 KERNELS/ATTRS{}
+pub fn udevadm_info_attribute_walk(device_path: &str) -> Result<(), UdevadmError> {
+    let Some(mut device) = resolve_device(device_path) else {
+        error!("Device not found: {}", device_path);
+        return Err(UdevadmError::DeviceNotFound(device_path.to_string()));
+    };
+
+    println!("Udevadm info starts with the device specified by the devpath and then");
+    println!("walks up the chain of parent devices. It prints for every device");
+    println!("found, all possible attributes in the udev rules key format.");
+    println!("A rule to match, can be composed by the attributes of the device");
+    println!("and the attributes from one single parent device.");
+    println!();
+
+    print_attributes(&mut device, "looking at device", false);
+    let mut parent = device.parent();
+    while let Some(mut device) = parent {
+        print_attributes(&mut device, "looking at parent device", true);
+        parent = device.parent();
+    }
+    Ok(())
+}
+
+fn print_attributes(device: &mut UEventDevice, title: &str, parent: bool) {
+    println!("  {} '{}':", title, device.devpath().display());
+    for line in attribute_keys(device, parent) {
+        println!("    {}", line);
+    }
+    println!();
+}
+
+// 设备本身用 KERNEL、ATTR{} 等，父设备用 KERNELS、ATTRS{} 等，每一行都能直接粘贴进规则。
+// 二进制内容和无法写成规则字符串的属性（结尾是反斜杠）被跳过，值中的引号被转义
+fn attribute_keys(device: &mut UEventDevice, parent: bool) -> Vec<String> {
+    let suffix = if parent { "S" } else { "" };
+    let mut keys = vec![
+        format!("KERNEL{}==\"{}\"", suffix, device.kernel().unwrap_or_default()),
+        format!("SUBSYSTEM{}==\"{}\"", suffix, device.subsystem()),
+        format!("DRIVER{}==\"{}\"", suffix, device.driver().unwrap_or_default()),
+    ];
+    for name in device.sysattr_names() {
+        let Some(value) = device.sysattr(&name) else {
+            continue;
+        };
+        // ATTR{} 匹配时忽略开头的空白
+        let value = value.trim_start();
+        if value.chars().any(|c| c.is_control() && c != '\t') || value.ends_with('\\') {
+            continue;
+        }
+        keys.push(format!("ATTR{}{{{}}}==\"{}\"", suffix, name, value.replace('"', "\\\"")));
+    }
+    keys
+}
+
+/// 打印设备及其所有子孙设备（比如 USB hub 及其下的设备、磁盘及其分区）的属性
+pub fn udevadm_info_recursive(device_path: &str, dev_root: &Path, verbose: bool) -> Result<(), UdevadmError> {
+    let Some(syspath) = resolve_syspath(device_path) else {
+        error!("Device not found: {}", device_path);
+        return Err(UdevadmError::DeviceNotFound(device_path.to_string()));
+    };
+
+    let mut devices = vec![syspath.clone()];
+    devices.extend(device_descendants(&syspath));
+    let links = load_links();
+
+    for (i, device) in devices.iter().enumerate() {
+        let Some(info) = get_device_info(&device.to_string_lossy()) else {
+            return Err(UdevadmError::SysfsError(device.display().to_string()));
+        };
+
+        if i > 0 {
+            println!();
+        }
+        print_device(device, info, &links, dev_root, verbose);
+    }
+
+    Ok(())
+}
+
+// 设备可以用节点、sysfs 路径或 devpath 指定；已经移除的设备只能用 devpath 或 sysfs 路径
+fn resolve_devpath(device_path: &str) -> Result<PathBuf, UdevadmError> {
+    let to_devpath = |syspath: &Path| Path::new("/").join(syspath.strip_prefix("/sys").unwrap_or(syspath));
+    match resolve_syspath(device_path) {
+        Some(syspath) => Ok(to_devpath(&syspath)),
+        None if device_path.starts_with("/devices/") || device_path.starts_with("/sys/devices/") => {
+            Ok(to_devpath(Path::new(device_path)))
         }
         None => {
+            error!("Device not found: {}", device_path);
+            Err(UdevadmError::DeviceNotFound(device_path.to_string()))
+        }
+    }
+}
+
+/// 打印设备的节点名、每个符号链接和 OWNER/GROUP/MODE 分别由哪条规则设置
+pub fn udevadm_info_provenance(device_path: &str) -> Result<(), UdevadmError> {
+    let devpath = resolve_devpath(device_path)?;
+    let mut entries = match load_provenance(PROVENANCE_DIR, &devpath) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(UdevadmError::IoError(PROVENANCE_DIR.to_string(), e)),
+    };
+
+    println!("P: {}", devpath.display());
+    if entries.is_empty() {
+        println!("no rule assignments recorded");
+        return Ok(());
+    }
+    // 同一个键的链接保持规则顺序
+    let order = ["NAME", "SYMLINK", "OWNER", "GROUP", "MODE"];
+    entries.sort_by_key(|entry| order.iter().position(|key| *key == entry.key));
+    let width = entries.iter().map(|entry| entry.key.len() + entry.value.len() + 3).max().unwrap_or(0);
+    for entry in &entries {
+        let assignment = format!("{}=\"{}\"", entry.key, entry.value);
+        println!("{:<width$}  {}", assignment, entry.location, width = width);
+    }
+    Ok(())
+}
+
+/// 打印守护进程记录的设备最近事件；设备已被移除时可以直接给出 devpath
+pub fn udevadm_info_history(device_path: &str) -> Result<(), UdevadmError> {
+    let devpath = resolve_devpath(device_path)?;
+
+    let entries = match load_history(HISTORY_DIR, &devpath) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(UdevadmError::IoError(HISTORY_DIR.to_string(), e)),
+    };
+
+    println!("P: {}", devpath.display());
+    if entries.is_empty() {
+        println!("no events recorded");
+        return Ok(());
+    }
+
+    let runs: Vec<RunRecord> = read_journal()?
+        .into_iter()
+        .filter(|record| record.devpath == devpath)
+        .collect();
+
+    let mut previous: Option<u64> = None;
+    for entry in &entries {
+        let since = previous.map_or(String::new(), |last| format!("  (+{}s)", entry.timestamp - last));
+        println!(
+            "{}  {:<8} seqnum {}{}",
+            format_local_time(entry.timestamp),
+            entry.action.as_str(),
+            entry.seqnum,
+            since
+        );
+        for record in runs.iter().filter(|record| record.seqnum == entry.seqnum) {
+            println!("    RUN '{}' {}", record.command, record.status);
+        }
+        previous = Some(entry.timestamp);
+    }
+
+    Ok(())
+}
+
+/// 打印 RUN 日志中失败的命令，最早的在前
+pub fn udevadm_run_failures() -> Result<(), UdevadmError> {
+    let failures: Vec<RunRecord> = read_journal()?
+        .into_iter()
+        .filter(|record| !record.status.success())
+        .collect();
+
+    if failures.is_empty() {
+        println!("no failed RUN commands recorded");
+        return Ok(());
+    }
+    for record in &failures {
+        println!(
+            "{}  seqnum {}  {}",
+            format_local_time(record.timestamp),
+            record.seqnum,
+            record.devpath.display()
+        );
+        println!("    RUN '{}' (pid {}) {}", record.command, record.pid, record.status);
+    }
+
+    Ok(())
+}
+
+// 守护进程还没有执行过 RUN 时日志文件不存在
+fn read_journal() -> Result<Vec<RunRecord>, UdevadmError> {
+    match load_journal(JOURNAL_PATH) {
+        Ok(records) => Ok(records),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(UdevadmError::IoError(JOURNAL_PATH.to_string(), e)),
+    }
+}
+
+// 按本地时区格式化为 YYYY-MM-DD HH:MM:SS
+fn format_local_time(timestamp: u64) -> String {
+    let time = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return timestamp.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+pub fn udevadm_cli(device_path: &str, dev_root: &Path) -> Result<(), UdevadmError> {
+    udevadm_info(device_path, dev_root, false)
+}
+
+pub fn udevadm_stats(stats_path: &str) -> Result<(), UdevadmError> {
+    let counts = load_counts(stats_path).map_err(|e| {
+        error!("Failed to read stats from {}: {}", stats_path, e);
+        UdevadmError::IoError(stats_path.to_string(), e)
+    })?;
+
+    let total: usize = counts.values().sum();
+    println!("{}", format_summary(&counts));
+    println!("total: {}", total);
+
+    // 缓存占用是附加信息，旧版本守护进程不会写出这个文件
+    if let Ok(usage) = load_cache_usage(CACHES_PATH) {
+        if !usage.is_empty() {
+            println!("caches:");
+            for cache in &usage {
+                println!("  {}", format_cache_usage(cache));
+            }
+        }
+    }
+
+    // 还没有执行过程序或遇到慢属性时没有这个文件
+    if let Ok(timings) = metrics::load(RULE_METRICS_PATH) {
+        if !timings.is_empty() {
+            println!("slowest rules:");
+            for timing in timings.iter().take(TOP_OFFENDERS) {
+                println!("  {}", timing);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 对设备执行单个内置命令并打印它会设置的属性，不修改设备也不需要守护进程；
+/// command 是 "name [args]" 形式，与 IMPORT{builtin} 的取值相同
+pub fn udevadm_test_builtin(
+    command: &str,
+    device_path: &str,
+    action: &str,
+    dev_root: &Path,
+    options: &BuiltinOptions,
+) -> Result<(), UdevadmError> {
+    let name = command.split_whitespace().next().unwrap_or("");
+    if find_builtin(name).is_none() {
+        error!(
+            "Unknown builtin '{}', available: {}",
+            name,
+            builtin_names().join(", ")
+        );
+        return Err(UdevadmError::UnknownBuiltin(name.to_string()));
+    }
+
+    let Some(mut device) = resolve_device(device_path) else {
+        error!("Device not found: {}", device_path);
+        return Err(UdevadmError::DeviceNotFound(device_path.to_string()));
+    };
+    device.set_action(action.parse().unwrap_or(DeviceAction::Unknown(action.to_string())));
+    device.set_dev_root(dev_root);
+
+    let properties = run_builtin(command, &device, options)
+        .map_err(|e| UdevadmError::IoError(format!("builtin '{}'", command), e))?;
+    for (key, value) in properties {
+        println!("{}={}", key, value);
+    }
+
+    Ok(())
+}
+
+/// 用守护进程的规则模拟设备的一个事件，打印检查过的规则、最终的属性、节点、符号链接、权限和 RUN 命令；
+/// 不创建节点和链接、不写 ATTR{}、不执行 RUN，PROGRAM 和 IMPORT 照常执行
+pub fn udevadm_test(
+    device_path: &str,
+    action: &str,
+    rules_dirs: &[PathBuf],
+    embedded: Vec<Rule>,
+    rule_options: &RuleSetOptions,
+) -> Result<(), UdevadmError> {
+    let Some(mut device) = resolve_device(device_path) else {
+        error!("Device not found: {}", device_path);
+        return Err(UdevadmError::DeviceNotFound(device_path.to_string()));
+    };
+    device.set_action(action.parse().unwrap_or(DeviceAction::Unknown(action.to_string())));
+
+    let rules = load_all_rules(rules_dirs, &embedded, rule_options.max_file_size).map_err(|e| {
+        error!("Failed to load rules: {}", e);
+        UdevadmError::IoError("rules directories".to_string(), e)
+    })?;
+    let rules = RuleSet::with_options(rules, rule_options);
+    let plan = plan_actions(&device, &rules);
+
+    print!("{}", plan.trace);
+    println!();
+    println!("Properties:");
+    for (key, value) in &plan.properties {
+        println!("  {}={}", key, value);
+    }
+    if !plan.tags.is_empty() {
+        println!("Tags: {}", plan.tags.join(" "));
+    }
+
+    if plan.ignore_device {
+        println!("ignore_device: no node, symlinks or RUN");
+        return Ok(());
+    }
+    if plan.trace.matched() == 0 {
+        println!("No rules matched");
+        return Ok(());
+    }
+    if let Some(node) = &plan.node {
+        println!("Node: {}", node.display());
+    }
+    if let Some(name) = &plan.interface_name {
+        println!("Interface name: {}", name);
+    }
+    for link in &plan.symlinks {
+        println!("Symlink: {}", link.display());
+    }
+    let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "(unchanged)".to_string());
+    println!("OWNER={} GROUP={} MODE={}", show(&plan.owner), show(&plan.group), show(&plan.mode));
+    for (command, location) in &plan.run {
+        println!("RUN: {} ({})", command, location);
+    }
+
+    Ok(())
+}
+
+/// 请求内核为给出的设备重新发出事件；uuid 为 true 时为每个设备生成 SYNTH_UUID 并打印出来
+pub fn udevadm_trigger(device_paths: &[String], action: &str, uuid: bool) -> Result<(), UdevadmError> {
+    let action: DeviceAction = action.parse().unwrap_or(DeviceAction::Unknown(action.to_string()));
+    for device_path in device_paths {
+        let Some(device) = resolve_device(device_path) else {
             error!("Device not found: {}", device_path);
             return Err(UdevadmError::DeviceNotFound(device_path.to_string()));
+        };
+        let uuid = match uuid {
+            true => Some(synth_uuid().map_err(|e| UdevadmError::IoError("random uuid".to_string(), e))?),
+            false => None,
+        };
+        device
+            .trigger(&action, uuid.as_deref())
+            .map_err(|e| UdevadmError::IoError(device.syspath().display().to_string(), e))?;
+        match uuid {
+            Some(uuid) => println!("{} {}", device.syspath().display(), uuid),
+            None => info!("Triggered {} on {:?}", action.as_str(), device.syspath()),
+        }
+    }
+    Ok(())
+}
+
+// verify - 从标准输入读取的规则在输出中显示的文件名
+const STDIN_RULES: &str = "<stdin>";
+
+/// 不启动守护进程检查规则：path 可以是单个规则文件或目录，"-" 表示从标准输入读取，省略时检查 rules_dirs
+pub fn udevadm_verify(
+    path: Option<&str>,
+    rules_dirs: &[PathBuf],
+    security: bool,
+    max_file_size: u64,
+) -> Result<(), UdevadmError> {
+    let dirs = match path {
+        Some(path) => vec![PathBuf::from(path)],
+        None => rules_dirs.to_vec(),
+    };
+    let io_error = |e: io::Error| {
+        let shown = path.map_or_else(|| "rules directories".to_string(), str::to_string);
+        error!("Failed to read {}: {}", shown, e);
+        UdevadmError::IoError(shown, e)
+    };
+
+    let (files, report) = match path {
+        Some("-") => {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content).map_err(io_error)?;
+            (vec![PathBuf::from(STDIN_RULES)], parse_rules_str_with_errors(&content, Path::new(STDIN_RULES)))
+        }
+        Some(path) if Path::new(path).is_file() => {
+            (vec![PathBuf::from(path)], parse_rules_file_with_errors(path, max_file_size).map_err(io_error)?)
+        }
+        Some(path) if !Path::new(path).exists() => {
+            return Err(io_error(io::Error::new(io::ErrorKind::NotFound, "no such file or directory")));
+        }
+        _ => (
+            rule_files(&dirs).map_err(io_error)?,
+            parse_rules_with_errors(&dirs, max_file_size).map_err(io_error)?,
+        ),
+    };
+
+    for e in &report.diagnostics {
+        println!("{} [{}]", e, e.kind);
+    }
+
+    let mut problems = report.diagnostics.len();
+    if report.is_clean() {
+        println!("{} file(s), {} rule(s): OK", files.len(), report.rules.len());
+    } else {
+        println!("{} file(s) checked, {} problem(s)", files.len(), problems);
+    }
+
+    if security {
+        let security = SecurityReport::new(&files, &report.rules);
+        for (file, findings) in &security.files {
+            if findings.is_empty() {
+                println!("{}: clean", file.display());
+                continue;
+            }
+            println!("{}: {} finding(s)", file.display(), findings.len());
+            for finding in findings {
+                println!("  {}", finding);
+            }
+        }
+        println!(
+            "security: {} finding(s) in {} file(s)",
+            security.findings(),
+            security.files.values().filter(|findings| !findings.is_empty()).count()
+        );
+        problems += security.findings();
+    }
+
+    if problems == 0 {
+        Ok(())
+    } else {
+        Err(UdevadmError::VerifyFailed(problems))
+    }
+}
+
+/// 把命令依次发给运行中的守护进程，任何一条失败时停止
+pub fn udevadm_control(commands: &[ControlCommand], timeout: Duration) -> Result<(), UdevadmError> {
+    let results = send_commands(CONTROL_PATH, commands, timeout).map_err(|e| {
+        error!("Failed to send control commands to {}: {}", CONTROL_PATH, e);
+        UdevadmError::IoError(CONTROL_PATH.to_string(), e)
+    })?;
+
+    for (command, result) in commands.iter().zip(results) {
+        if let Err(reason) = result {
+            error!("Daemon rejected '{}': {}", command.to_line(), reason);
+            return Err(UdevadmError::ControlFailed(command.to_line(), reason));
+        }
+    }
+    Ok(())
+}
+
+/// 等待守护进程处理完队列中的事件，以及开始等待时内核已经发出的事件；timeout 为零时只检查一次
+///
+/// 守护进程没有运行时直接返回。其它网络命名空间的事件也占用 SEQNUM 但不会发给守护进程，
+/// 所以队列已空且一段时间没有变化时同样认为已经处理完。
+pub fn udevadm_settle(timeout: Duration) -> Result<(), UdevadmError> {
+    let start = Instant::now();
+    // 先 ping 确认守护进程在运行；连不上时队列文件可能是上次运行留下的
+    match send_commands(CONTROL_PATH, &[ControlCommand::Ping], timeout.max(SETTLE_POLL)) {
+        Ok(_) => {}
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => {
+            warn!("Daemon is not running ({}), nothing to wait for", e);
+            return Ok(());
+        }
+        Err(e) => return Err(UdevadmError::IoError(CONTROL_PATH.to_string(), e)),
+    }
+    let kernel_seqnum = std::fs::read_to_string(KERNEL_SEQNUM_PATH)
+        .ok()
+        .and_then(|content| content.trim().parse::<u64>().ok());
+
+    let mut last_state = None;
+    let mut unchanged_since = start;
+    loop {
+        let state = load_queue_state(QUEUE_PATH).map_err(|e| UdevadmError::IoError(QUEUE_PATH.to_string(), e))?;
+        if last_state != Some(state) {
+            last_state = Some(state);
+            unchanged_since = Instant::now();
+        }
+        if state.depth == 0 {
+            let caught_up = kernel_seqnum.is_none_or(|seqnum| state.last_seqnum >= seqnum);
+            if caught_up || unchanged_since.elapsed() >= SETTLE_QUIET {
+                info!("Event queue is empty, last seqnum {}", state.last_seqnum);
+                return Ok(());
+            }
+        }
+        if start.elapsed() >= timeout {
+            error!("Timed out after {:?} with {} event(s) queued", timeout, state.depth);
+            return Err(UdevadmError::SettleTimeout(state.depth));
+        }
+        std::thread::sleep(SETTLE_POLL);
+    }
+}
+
+/// 打印守护进程记录的缺少 SUBSYSTEM 或 ACTION 的事件
+pub fn udevadm_debug_dump(incomplete_path: &str) -> Result<(), UdevadmError> {
+    match std::fs::read_to_string(incomplete_path) {
+        Ok(content) if !content.is_empty() => print!("{}", content),
+        Ok(_) => println!("no incomplete events recorded"),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => println!("no incomplete events recorded"),
+        Err(e) => {
+            error!("Failed to read {}: {}", incomplete_path, e);
+            return Err(UdevadmError::IoError(incomplete_path.to_string(), e));
         }
     }
 
     Ok(())
 }
 
-pub fn udevadm_cli(device_path: &str) -> Result<(), UdevadmError> {
-    udevadm_info(device_path)
-}
\ No newline at end of file
+/// 监听事件；device_count 为真时以实时面板形式展示各子系统统计。
+/// view 为 Udev 时显示守护进程处理后的事件，properties 为真时列出属性，规则添加或修改的标为 (udev)
+pub fn udevadm_monitor(device_count: bool, view: MonitorView, properties: bool) -> Result<(), UdevadmError> {
+    let monitor = UEventMonitor::with_view(view)
+        .map_err(|e| UdevadmError::IoError("netlink socket".to_string(), e))?;
+    let poll_fd = PollFd::new(monitor.as_raw_fd(), PollFlags::POLLIN);
+
+    let mut dashboard = Dashboard::new();
+    let mut last_refresh: Option<Instant> = None;
+    let label = match view {
+        MonitorView::Kernel => "KERNEL",
+        MonitorView::Udev => "UDEV  ",
+    };
+
+    if !device_count {
+        println!("monitor will print the received events for:");
+        match view {
+            MonitorView::Kernel => println!("KERNEL - the kernel uevent\n"),
+            MonitorView::Udev => println!("UDEV - the event which udev sends out after rule processing\n"),
+        }
+    }
+
+    loop {
+        match poll(&mut [poll_fd], 200) {
+            Ok(0) => {}
+            Ok(_) => match monitor.receive_device() {
+                Ok(event) => {
+                    let device = &event.device;
+                    if device_count {
+                        dashboard.record(device);
+                    } else {
+                        // 与 udevadm 相同，显示事件到达时的 CLOCK_MONOTONIC 秒数
+                        let usec = device.received().monotonic_usec;
+                        println!(
+                            "{}[{}.{:06}] {:<8} {} ({})",
+                            label,
+                            usec / 1_000_000,
+                            usec % 1_000_000,
+                            device.action_label(),
+                            device.devpath().display(),
+                            device.subsystem_label()
+                        );
+                        if properties {
+                            let sorted: BTreeMap<_, _> = device.properties().iter().collect();
+                            for (key, value) in sorted {
+                                match event.source(key) {
+                                    Some(PropertySource::Udev) if view == MonitorView::Udev => {
+                                        println!("{}={} (udev)", key, value)
+                                    }
+                                    _ => println!("{}={}", key, value),
+                                }
+                            }
+                            println!();
+                        }
+                    }
+                }
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::InvalidData) => {}
+                Err(e) => return Err(UdevadmError::IoError("netlink socket".to_string(), e)),
+            },
+            Err(e) => {
+                return Err(UdevadmError::IoError("netlink socket".to_string(), e.into()));
+            }
+        }
+
+        if device_count && last_refresh.is_none_or(|t| t.elapsed() >= DASHBOARD_REFRESH) {
+            let counts = load_counts(STATS_PATH).unwrap_or_default();
+            let queue_depth = load_queue_depth(QUEUE_PATH).ok();
+
+            // 清屏并把光标移到左上角，原地刷新
+            print!("\x1b[2J\x1b[H{}", dashboard.render(&counts, queue_depth));
+            let _ = io::stdout().flush();
+            last_refresh = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::rules::parser::{parse_rules_str, parse_rules_str_with_errors};
+
+    #[test]
+    fn attribute_walk_keys_parse_and_match_the_device() {
+        let sys = std::env::temp_dir().join(format!("rust_udev-walk-{}", std::process::id()));
+        let usb = sys.join("devices/pci0/usb1");
+        // host0 没有 uevent 文件，不是设备
+        let disk = usb.join("host0/block/sda");
+        fs::create_dir_all(&disk).unwrap();
+        fs::create_dir_all(sys.join("bus/usb")).unwrap();
+        symlink(sys.join("bus/usb"), usb.join("subsystem")).unwrap();
+        fs::write(usb.join("uevent"), "").unwrap();
+        fs::write(usb.join("serial"), "AB \"1\"\n").unwrap();
+        fs::write(usb.join("path"), "C:\\\n").unwrap();
+        fs::write(disk.join("uevent"), "DEVNAME=sda\n").unwrap();
+        fs::write(disk.join("size"), "  42\n").unwrap();
+
+        let mut device = UEventDevice::from_syspath_in(&sys, &disk).unwrap();
+        let mut parent = device.parent().unwrap();
+        let mut keys = attribute_keys(&mut device, false);
+        let parent_keys = attribute_keys(&mut parent, true);
+        let grandparent = parent.parent();
+        keys.extend(parent_keys.clone());
+        let report = parse_rules_str_with_errors(&keys.join(", "), Path::new("walk.rules"));
+        let rules = RuleSet::new(report.rules.clone());
+        let symbols = rules.prepare(&device);
+        let matched = rules.matches(0, &symbols, &mut device);
+        // 父设备条件必须由同一级设备满足
+        let mixed = RuleSet::new(parse_rules_str(r#"KERNELS=="sda", ATTRS{serial}=="AB \"1\"""#));
+        let mixed_matched = mixed.matches(0, &mixed.prepare(&device), &mut device);
+        fs::remove_dir_all(&sys).unwrap();
+
+        assert_eq!(parent.kernel(), Some("usb1"));
+        assert!(grandparent.is_none());
+        assert!(parent_keys.contains(&r#"ATTRS{serial}=="AB \"1\"""#.to_string()));
+        assert!(!parent_keys.iter().any(|key| key.starts_with("ATTRS{path}")));
+        assert!(report.is_clean(), "{:?}", report.diagnostics);
+        assert!(matched);
+        assert!(!mixed_matched);
+    }
+}
@@ -1,83 +1,1207 @@
 // src/udevd.rs
 
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::io;
-use std::os::fd::AsRawFd;
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
+use crossbeam::channel::{bounded, Receiver};
+use nix::errno::Errno;
 use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
 use std::path::{Path, PathBuf};
 
 use crate::actions::*;
+use crate::builtins::{run_builtin, BuiltinOptions};
+use crate::builtins::security_token::security_token_rules;
+use crate::cancel::CancellationToken;
+use crate::clock::{system_clock, Clock};
+use crate::config::{Config, DEFAULT_EVENT_TIMEOUT};
+use crate::control::{ControlCommand, ControlServer, CONTROL_PATH};
+use crate::deferred::{DeferredAction, DeferredScheduler, Fired};
+use crate::db::{
+    load_record, record_id, remove_history, remove_record, save_history, save_provenance, save_record, ShardedDeviceDb,
+    DATA_DIR, DEFAULT_DB_CAPACITY, HISTORY_DIR, PROVENANCE_DIR,
+};
+use crate::dispatcher::{default_workers, EventDispatcher, DEPENDENCY_TIMEOUT};
+use crate::journal::JOURNAL_PATH;
 use crate::device::{DeviceAction, UEventDevice};
-use crate::monitor::UEventMonitor;
+use crate::filter::{NamespaceFilter, DEFAULT_IGNORED_INTERFACES};
+use crate::libudev::Enumerator;
+use crate::logging::{set_log_filter, set_log_level, EventContext};
+use crate::media::{MediaWatcher, MEDIA_POLL_INTERVAL};
+use crate::monitor::{MonitorView, UEventMonitor, UdevBroadcaster};
+use crate::net;
+use crate::plan::{evaluate_rules, ExecutionPlan};
+use crate::profile;
+use crate::reaper::Reaper;
+use crate::reprobe::ReprobeScheduler;
 use crate::rules::matcher::Rule;
-use crate::rules::parser::RuleManager;
+use crate::rules::metrics::{self, RULE_METRICS_PATH};
+use crate::rules::parser::{
+    default_rules_dirs, parse_inline_rules, parse_rules_str_with_errors, RuleManager,
+    DEFAULT_MAX_RULES_FILE_SIZE,
+};
+use crate::rules::cache::EVAL_CACHE_CAPACITY;
+use crate::rules::ruleset::{RuleSet, RuleSetOptions};
+use crate::rules::trace::{self, EventTrace};
+use crate::seqnum::SeqnumTracker;
+use crate::strict::check_startup;
+use crate::symlink_db::{load_device_links, CollisionPolicy, SymlinkDb, LINKS_PATH};
+use crate::transaction::{self, Recovery, Transaction, TRANSACTIONS_DIR};
+use crate::transliterate::Transliteration;
+use crate::stats::{
+    save_cache_usage, save_queue_state, CacheUsage, DeviceStats, IncompleteEvents, CACHES_PATH,
+    DEFAULT_STATS_CAPACITY, INCOMPLETE_PATH, QUEUE_PATH, STATS_PATH, QueueState,
+};
 use log::*;
 
 const POLL_TIMEOUT: i32 = 100;
 
-pub fn start_udevd() -> Result<(), Box<dyn std::error::Error>> {
+// 写出缓存占用的间隔
+const CACHE_USAGE_INTERVAL: Duration = Duration::from_secs(5);
+
+// 冷启动时队列中的事件少于这个数就送入下一批
+const COLDPLUG_BATCH: usize = 64;
+// 冷启动期间 poll 的超时（毫秒），工作线程处理完一批后尽快送入下一批
+const COLDPLUG_POLL_TIMEOUT: i32 = 10;
+
+// 内置安全令牌规则在日志中显示的文件名
+const SECURITY_TOKEN_RULES: &str = "<security-token>";
+
+/// 守护进程启动选项
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    /// 规则或设备目录有任何问题时直接拒绝启动
+    pub strict: bool,
+    /// 设备数据库最多保留历史的设备数，当前存在的设备不受限制
+    pub db_capacity: usize,
+    /// 统计最多跟踪的设备数
+    pub stats_capacity: usize,
+    /// 规则匹配结果缓存最多保存的设备数
+    pub eval_cache_capacity: usize,
+    /// 设置后启用内置的 FIDO 令牌和智能卡读卡器规则，设备节点属于该组
+    pub security_token_group: Option<String>,
+    /// OWNER/GROUP 名字的解析时机
+    pub resolve_names: ResolveNames,
+    /// 为每个事件记录规则匹配过程并写入日志
+    pub trace_rules: bool,
+    /// 厂商、型号等字符串用于 by-id 名字时不安全字符的处理方式
+    pub transliteration: Transliteration,
+    /// 发现 SEQNUM 缺号（丢失了内核事件）时重新同步相关子系统的设备
+    pub resync_on_gap: bool,
+    /// 名字匹配这些 glob 的网络接口的事件只计数，不处理；为空时处理所有接口
+    pub ignore_interfaces: Vec<String>,
+    /// 按目录（相对于设备根目录）的符号链接冲突策略
+    pub symlink_policies: Vec<(PathBuf, CollisionPolicy)>,
+    /// 为 sysfs 中已有的设备合成 add 事件，在监听循环中分批处理
+    pub coldplug: bool,
+    /// 把处理完的事件广播到 udev 多播组；systemd-udevd 同时运行时 libudev 的接收方会收到重复事件，默认关闭
+    pub broadcast_events: bool,
+    /// 创建设备节点和符号链接的目录
+    pub dev_root: PathBuf,
+    /// 规则目录，按优先级从低到高
+    pub rules_dirs: Vec<PathBuf>,
+    /// 超过这个大小的规则文件被跳过
+    pub max_rules_file_size: u64,
+    /// 同时处理事件的线程数，None 时与 CPU 数相同
+    pub children_max: Option<usize>,
+    /// 每个 RUN 命令启动前的等待时间
+    pub exec_delay: Duration,
+    /// PROGRAM、IMPORT{program} 和 RUN 命令最长的运行时间
+    pub event_timeout: Duration,
+    /// 配置文件 rule= 和命令行 --rule 给出的单行规则，与内置规则一样排在规则文件之前
+    pub inline_rules: Vec<String>,
+    /// 设置后在该回环地址上提供 HTTP 状态接口
+    #[cfg(feature = "http-status")]
+    pub http_status: Option<std::net::SocketAddr>,
+    /// 事件到达时间、超时、延迟动作和规则计时使用的时钟
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            db_capacity: DEFAULT_DB_CAPACITY,
+            stats_capacity: DEFAULT_STATS_CAPACITY,
+            eval_cache_capacity: EVAL_CACHE_CAPACITY,
+            security_token_group: None,
+            resolve_names: ResolveNames::default(),
+            trace_rules: false,
+            transliteration: Transliteration::default(),
+            resync_on_gap: false,
+            ignore_interfaces: DEFAULT_IGNORED_INTERFACES.iter().map(|pattern| pattern.to_string()).collect(),
+            symlink_policies: Vec::new(),
+            coldplug: true,
+            broadcast_events: false,
+            dev_root: PathBuf::from(DEV_ROOT),
+            rules_dirs: default_rules_dirs(),
+            max_rules_file_size: DEFAULT_MAX_RULES_FILE_SIZE,
+            children_max: None,
+            exec_delay: Duration::ZERO,
+            event_timeout: DEFAULT_EVENT_TIMEOUT,
+            inline_rules: Vec::new(),
+            #[cfg(feature = "http-status")]
+            http_status: None,
+            clock: system_clock(),
+        }
+    }
+}
+
+impl DaemonOptions {
+    /// 取配置文件中的设置，其余为默认值
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            resolve_names: config.resolve_names,
+            trace_rules: config.trace_rules,
+            transliteration: config.transliteration,
+            resync_on_gap: config.resync_on_gap,
+            broadcast_events: config.broadcast_events,
+            ignore_interfaces: config.ignore_interfaces.clone(),
+            symlink_policies: config.symlink_policies.clone(),
+            inline_rules: config.rules.clone(),
+            dev_root: config.dev_root.clone(),
+            rules_dirs: config.rules_dirs.clone(),
+            max_rules_file_size: config.max_rules_file_size,
+            children_max: config.children_max,
+            exec_delay: config.exec_delay,
+            event_timeout: config.event_timeout,
+            ..Self::default()
+        }
+    }
+
+    /// RuleManager 加载和编译规则时使用的选项
+    pub fn rule_options(&self) -> RuleSetOptions {
+        RuleSetOptions {
+            eval_cache_capacity: self.eval_cache_capacity,
+            max_file_size: self.max_rules_file_size,
+            clock: self.clock.clone(),
+            resolve_names: self.resolve_names,
+            builtins: BuiltinOptions {
+                transliteration: self.transliteration,
+            },
+            dev_root: self.dev_root.clone(),
+            event_timeout: self.event_timeout,
+        }
+    }
+}
+
+// 各符号链接的声明者及优先级
+static SYMLINKS: LazyLock<SymlinkDb> = LazyLock::new(SymlinkDb::new);
+
+// 处理完的事件广播到 udev 多播组，run_udevd 按 DaemonOptions::broadcast_events 设置；套接字打不开时只是不广播
+static BROADCASTER: RwLock<Option<UdevBroadcaster>> = RwLock::new(None);
+
+/// 一个守护进程实例的时钟和按时钟工作的调度器，run_udevd 按 DaemonOptions 创建，
+/// 同一进程中先后启动的守护进程各用各的时钟、exec_delay 和 event_timeout
+#[derive(Debug)]
+pub struct DaemonState {
+    clock: Arc<dyn Clock>,
+    // 规则通过 OPTIONS+="reprobe=..." 请求的延迟重新探测
+    reprobes: ReprobeScheduler,
+    // 规则通过 AT{delay}= 安排的延迟动作，设备 remove 时取消
+    deferred: DeferredScheduler,
+    // 后台执行的 RUN 子进程，退出状态写入 JOURNAL_PATH
+    reaper: Arc<Reaper>,
+}
+
+impl DaemonState {
+    pub fn new(options: &DaemonOptions) -> Self {
+        let clock = options.clock.clone();
+        let reaper = Reaper::with_clock(clock.clone(), JOURNAL_PATH)
+            .with_exec_delay(options.exec_delay)
+            .with_event_timeout(options.event_timeout);
+        Self {
+            reprobes: ReprobeScheduler::with_clock(clock.clone()),
+            deferred: DeferredScheduler::with_clock(clock.clone(), "/sys"),
+            reaper: Arc::new(reaper),
+            clock,
+        }
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+}
+
+// 已分发但尚未处理完成的事件数
+static PENDING_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn pending_events() -> usize {
+    PENDING_EVENTS.load(Ordering::SeqCst)
+}
+
+// 事件处理结束（包括提前返回或 panic）时减少计数
+struct PendingGuard;
+
+impl PendingGuard {
+    fn new() -> Self {
+        PENDING_EVENTS.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        PENDING_EVENTS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// 守护进程合成的事件使用的 SEQNUM，从 2^63 开始，不会与内核分配的重复
+static SYNTHETIC_SEQNUM: AtomicU64 = AtomicU64::new(1 << 63);
+
+// 为 devpath 合成一个 change 事件：属性从 sysfs 重新读取，而不是沿用上次规则处理后的属性，
+// 并分配新的 SEQNUM；设备已经不在时返回 None
+fn synthesize_change(devpath: &Path) -> Option<UEventDevice> {
+    let mut device = UEventDevice::from_syspath(Path::new("/sys").join(devpath.strip_prefix("/").unwrap_or(devpath)))?;
+    device.set_action(DeviceAction::Change);
+    device.set_seqnum(SYNTHETIC_SEQNUM.fetch_add(1, Ordering::Relaxed));
+    Some(device)
+}
+
+// 设置了 watch 选项的设备节点
+static DEVICE_WATCH: LazyLock<DeviceWatch> = LazyLock::new(DeviceWatch::new);
+
+/// 在设备节点上监听 IN_CLOSE_WRITE：以写方式打开节点的进程关闭它时（比如 mkfs 之后）
+/// 合成一个 change 事件，让规则重新读取文件系统等信息
+struct DeviceWatch {
+    inotify: Option<Inotify>,
+    watches: Mutex<HashMap<WatchDescriptor, WatchedDevice>>,
+}
+
+#[derive(Debug)]
+struct WatchedDevice {
+    devpath: PathBuf,
+    // 最近一次事件的属性，用于合成 change 事件
+    event: HashMap<String, String>,
+}
+
+impl DeviceWatch {
+    fn new() -> Self {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .map_err(|e| warn!("Failed to initialize inotify, watch option disabled: {}", e))
+            .ok();
+
+        Self {
+            inotify,
+            watches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        self.inotify.as_ref().map(|inotify| inotify.as_raw_fd())
+    }
+
+    fn watch(&self, node: &Path, device: &UEventDevice) {
+        let Some(inotify) = self.inotify else {
+            return;
+        };
+        self.unwatch(device.devpath());
+
+        match inotify.add_watch(node, AddWatchFlags::IN_CLOSE_WRITE) {
+            Ok(wd) => {
+                debug!("Watching {:?} for {:?}", node, device.devpath());
+                self.watches.lock().unwrap().insert(
+                    wd,
+                    WatchedDevice {
+                        devpath: device.devpath().to_path_buf(),
+                        event: device.properties().clone(),
+                    },
+                );
+            }
+            Err(e) => warn!("Failed to watch {:?}: {}", node, e),
+        }
+    }
+
+    fn unwatch(&self, devpath: &Path) {
+        let Some(inotify) = self.inotify else {
+            return;
+        };
+
+        let mut watches = self.watches.lock().unwrap();
+        let wds: Vec<_> = watches
+            .iter()
+            .filter(|(_, watched)| watched.devpath == devpath)
+            .map(|(wd, _)| *wd)
+            .collect();
+
+        for wd in wds {
+            watches.remove(&wd);
+            // 节点已被删除时内核会自动移除 watch，这里的错误可以忽略
+            let _ = inotify.rm_watch(wd);
+            debug!("Stopped watching {:?}", devpath);
+        }
+    }
+
+    /// 读取所有待处理的 inotify 事件，为被写入后关闭的节点合成 change 事件
+    fn changed_devices(&self, clock: &dyn Clock) -> Vec<UEventDevice> {
+        let Some(inotify) = self.inotify else {
+            return Vec::new();
+        };
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(Errno::EAGAIN) => return Vec::new(),
+            Err(e) => {
+                warn!("Failed to read inotify events: {}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut watches = self.watches.lock().unwrap();
+        let mut changed = Vec::new();
+        for event in events {
+            if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                watches.remove(&event.wd);
+                continue;
+            }
+            if !event.mask.contains(AddWatchFlags::IN_CLOSE_WRITE) {
+                continue;
+            }
+
+            let Some(watched) = watches.get(&event.wd) else {
+                continue;
+            };
+            let mut props = watched.event.clone();
+            props.insert("ACTION".to_string(), "change".to_string());
+            if let Some(device) = UEventDevice::from_event_with_clock(props, clock) {
+                changed.push(device);
+            }
+        }
+
+        changed
+    }
+}
+
+/// 守护进程返回的错误，可以在线程之间传递
+pub type DaemonError = Box<dyn std::error::Error + Send + Sync>;
+
+// SIGTERM/SIGINT 处理函数设置，主循环据此取消令牌
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_terminate(_signal: libc::c_int) {
+    TERMINATE.store(true, Ordering::Relaxed);
+}
+
+/// 作为独立进程运行守护进程，收到 SIGTERM 或 SIGINT 后停止所有线程并返回
+pub fn start_udevd(options: &DaemonOptions) -> Result<(), DaemonError> {
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        let ret = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_terminate as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if ret != 0 {
+            return Err(Box::new(io::Error::last_os_error()));
+        }
+    }
+
+    run_udevd(options, &CancellationToken::new())
+}
+
+/// 嵌入到其他程序中的守护进程，在后台线程中运行；drop 时取消令牌并等待主循环退出
+#[derive(Debug)]
+pub struct Udevd {
+    token: CancellationToken,
+    thread: Option<JoinHandle<Result<(), DaemonError>>>,
+}
+
+impl Udevd {
+    pub fn spawn(options: DaemonOptions) -> io::Result<Self> {
+        let token = CancellationToken::new();
+        let token_clone = token.clone();
+        let thread = thread::Builder::new()
+            .name("udevd".to_string())
+            .spawn(move || run_udevd(&options, &token_clone))?;
+
+        Ok(Self {
+            token,
+            thread: Some(thread),
+        })
+    }
+
+    /// 守护进程各线程共享的令牌，取消它与 drop 句柄效果相同
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// 停止守护进程，返回主循环的结果
+    pub fn stop(mut self) -> Result<(), DaemonError> {
+        self.token.cancel();
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err("udevd thread panicked".into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Udevd {
+    fn drop(&mut self) {
+        self.token.cancel();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// 守护进程在规则文件之前加载的内置规则：udev.conf 和 --rule 的内联规则、安全令牌规则，
+/// 以及 android、printer 特性的规则包
+pub fn embedded_rules(options: &DaemonOptions) -> Vec<Rule> {
+    let mut embedded = Vec::new();
+    if !options.inline_rules.is_empty() {
+        let report = parse_inline_rules(&options.inline_rules);
+        for e in &report.diagnostics {
+            warn!("{}", e);
+        }
+        info!("Loaded {} inline rule(s)", report.rules.len());
+        embedded.extend(report.rules);
+    }
+    let token_rules = options.security_token_group.as_ref().and_then(|group| {
+        let rules = security_token_rules(group);
+        if rules.is_none() {
+            error!("Invalid security token group '{}', built-in security token rules disabled", group);
+        }
+        rules
+    });
+    if let Some(rules) = token_rules {
+        embedded.extend(parse_embedded_rules(&rules, SECURITY_TOKEN_RULES));
+    }
+    for profile in profile::enabled() {
+        embedded.extend(parse_embedded_rules(profile.rules, profile.name));
+    }
+    embedded
+}
+
+// 解析一份内置规则文本，name 是日志中显示的文件名；有问题的行只记录警告
+fn parse_embedded_rules(text: &str, name: &str) -> Vec<Rule> {
+    let report = parse_rules_str_with_errors(text, Path::new(name));
+    for e in &report.diagnostics {
+        warn!("{}", e);
+    }
+    info!("Loaded {} built-in rule(s) from {}", report.rules.len(), name);
+    report.rules
+}
+
+/// 运行守护进程直到 token 被取消；返回时（包括出错时）同时停止它启动的所有后台线程
+pub fn run_udevd(options: &DaemonOptions, token: &CancellationToken) -> Result<(), DaemonError> {
     info!("Starting udevd daemon...");
+    let _stop_threads = token.drop_guard();
+    let clock = options.clock.clone();
+    let state = Arc::new(DaemonState::new(options));
+
+    let rule_paths = options.rules_dirs.clone();
+    info!("dev_root={}", options.dev_root.display());
+    *BROADCASTER.write().unwrap() = if options.broadcast_events {
+        info!("Broadcasting processed events to the udev multicast group");
+        UdevBroadcaster::new().map_err(|e| warn!("Cannot broadcast processed events: {}", e)).ok()
+    } else {
+        None
+    };
+    // 处理事件的工作线程池，同一设备及其父子设备的事件按分发顺序处理；返回前停止，出错返回时由 drop 停止
+    let dispatcher = EventDispatcher::with_clock(
+        options.children_max.unwrap_or_else(default_workers),
+        clock.clone(),
+        DEPENDENCY_TIMEOUT,
+    );
+    info!("children_max={}", dispatcher.max_workers());
+    info!("resolve_names={}", options.resolve_names.as_str());
+    info!("id_transliteration={}", options.transliteration.as_str());
+    info!("max_rules_file_size={}", options.max_rules_file_size);
+    trace::set_enabled(options.trace_rules);
+    if options.trace_rules {
+        info!("Rule match tracing enabled");
+    }
+
+    for (dir, policy) in &options.symlink_policies {
+        info!("Symlink collisions in {}/{} use {}", options.dev_root.display(), dir.display(), policy.as_str());
+    }
+    SYMLINKS.set_policies(
+        options
+            .symlink_policies
+            .iter()
+            .map(|(dir, policy)| (options.dev_root.join(dir), *policy))
+            .collect(),
+    );
+
+    if options.strict {
+        check_startup(&rule_paths, &options.inline_rules, &options.dev_root, options.max_rules_file_size)?;
+        info!("Strict startup checks passed");
+    }
+    let rule_manager = RuleManager::with_options(rule_paths, embedded_rules(options), options.rule_options());
+    create_static_nodes(&rule_manager.get_rules());
+
+    state.reaper.start(token.clone())?;
+
+    // 上次运行留下的节点和链接记录，第一次保存之前读取；用于清理守护进程没见过 add 的设备
+    let mut previous_links = match load_device_links(LINKS_PATH) {
+        Ok(devices) => devices,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => {
+            warn!("Failed to read {}: {}", LINKS_PATH, e);
+            BTreeMap::new()
+        }
+    };
+    let monitor = UEventMonitor::with_clock(MonitorView::Kernel, clock.clone())?;
+    // 在监听套接字打开之后重放，内核重新发出的事件才能被收到
+    recover_transactions();
+    // 事件处理闭包和定时写出缓存占用都要用到
+    let stats = RefCell::new(DeviceStats::with_capacity(options.stats_capacity));
+    // 统计只在内存中更新，随缓存占用定时写出
+    let stats_dirty = Cell::new(false);
+    let incomplete = RefCell::new(IncompleteEvents::new());
+    // 状态接口在另一个线程中读取设备数据库
+    let db = Arc::new(ShardedDeviceDb::with_capacity(options.db_capacity));
+    #[cfg(feature = "http-status")]
+    if let Some(addr) = options.http_status {
+        use crate::http_status::{serve, StatusSources};
+        let sources = StatusSources {
+            db: db.clone(),
+            rules: rule_manager.shared(),
+        };
+        // 状态接口不是必需的，失败时守护进程照常运行
+        if let Err(e) = serve(addr, sources, token.clone()) {
+            warn!("Failed to start HTTP status on {}: {}", addr, e);
+        }
+    }
+    let namespace_filter = NamespaceFilter::default().with_ignored_interfaces(&options.ignore_interfaces);
+    if !options.ignore_interfaces.is_empty() {
+        info!("Ignoring events for interfaces matching {}", options.ignore_interfaces.join(" "));
+    }
+    let media_watcher = MediaWatcher::start(MEDIA_POLL_INTERVAL, token.clone());
+    let mut coldplug = if options.coldplug {
+        coldplug_devices()
+    } else {
+        VecDeque::new()
+    };
+    let coldplug_total = coldplug.len();
+    // 没有控制套接字时守护进程照常运行，只是不能用 udevadm control 管理
+    let mut control = ControlServer::bind(CONTROL_PATH)
+        .inspect_err(|e| warn!("Failed to listen on {}: {}", CONTROL_PATH, e))
+        .ok();
+    let mut last_queue_state = None;
+    let mut last_cache_usage: Option<Vec<CacheUsage>> = None;
+    // 启动后第一轮循环就写出一次
+    let mut next_cache_usage_save = clock.now();
+    let mut last_seqnum = 0;
+    let mut last_metrics = metrics::generation();
+    let mut seqnums = SeqnumTracker::new();
+
+    // 收到的内核事件：统计、过滤、更新数据库后分发
+    let mut handle_event = |device: UEventDevice| {
+        let missing = device.missing_fields();
+        if !missing.is_empty() {
+            warn!(
+                "Event for {:?} is missing {}, see udevadm debug-dump",
+                device.devpath(),
+                missing.join(", ")
+            );
+            let mut incomplete = incomplete.borrow_mut();
+            incomplete.record(&device);
+            if let Err(e) = incomplete.save(INCOMPLETE_PATH) {
+                warn!("Failed to write {}: {}", INCOMPLETE_PATH, e);
+            }
+        }
+
+        if namespace_filter.should_ignore(&device) {
+            return;
+        }
+        stats.borrow_mut().record(&device);
+        stats_dirty.set(true);
+
+        // 没见过 add 的设备，按上次运行的记录清理残留的节点和链接
+        if *device.action() == DeviceAction::Remove && !db.contains(device.devpath()) {
+            if let Some(record) = previous_links.remove(device.devpath()) {
+                let removed = remove_stale_artifacts(&device, &record);
+                if removed > 0 {
+                    info!("Cleaned {} stale node(s) and link(s) for unknown device {:?}", removed, device.devpath());
+                }
+            }
+        }
+
+        // 桥接设备被移除时，子设备可能不会各自发出 remove；最深的设备先分发，
+        // 父设备的 remove 在下面最后分发，dispatcher 保证它在所有子孙的 remove 处理完之后才处理
+        if *device.action() == DeviceAction::Remove {
+            for orphan in db.orphan_removes(device.devpath()) {
+                info!(
+                    "Synthesizing remove for {:?}, parent {:?} is gone",
+                    orphan.devpath(),
+                    device.devpath()
+                );
+                update_db(&db, &orphan);
+                stats.borrow_mut().record(&orphan);
+                process_event(&state, &dispatcher, orphan, rule_manager.get_rules());
+            }
+        }
+        update_db(&db, &device);
+        media_watcher.update(&device);
+        state.reprobes.update(&device);
+        state.deferred.update(&device);
+
+        let rules = rule_manager.get_rules();
+        let handle = process_event(&state, &dispatcher, device, rules);
+        debug!("Dispatched event seqnum {}", handle.seqnum());
+    };
+
+    while !token.is_cancelled() {
+        if TERMINATE.swap(false, Ordering::Relaxed) {
+            info!("Received termination signal, shutting down...");
+            token.cancel();
+            break;
+        }
+
+        // 还没送入的冷启动事件也算在队列中，否则 udevadm settle 可能在它们处理之前返回
+        let queue_state = QueueState {
+            depth: pending_events() + coldplug.len(),
+            last_seqnum,
+        };
+        if last_queue_state != Some(queue_state) {
+            if let Err(e) = save_queue_state(QUEUE_PATH, queue_state) {
+                warn!("Failed to write queue state to {}: {}", QUEUE_PATH, e);
+            }
+            last_queue_state = Some(queue_state);
+        }
+
+        // 估算占用要遍历各个缓存，定时写出，没有变化时不写；统计同样定时写出
+        if clock.now() >= next_cache_usage_save {
+            if stats_dirty.replace(false) {
+                save_stats(&stats.borrow());
+            }
+            let rules = rule_manager.get_rules();
+            let [eval, interner] = rules.usage();
+            let usage = vec![
+                db.usage(),
+                stats.borrow().usage(),
+                incomplete.borrow().usage(),
+                eval,
+                interner,
+            ];
+            if last_cache_usage.as_ref() != Some(&usage) {
+                if let Err(e) = save_cache_usage(CACHES_PATH, &usage) {
+                    warn!("Failed to write cache usage to {}: {}", CACHES_PATH, e);
+                }
+                last_cache_usage = Some(usage);
+            }
+            next_cache_usage_save = clock.now() + CACHE_USAGE_INTERVAL;
+        }
+
+        // PROGRAM 在事件线程中、RUN 在回收线程中计时，这里统一写出
+        let generation = metrics::generation();
+        if generation != last_metrics {
+            if let Err(e) = metrics::save(RULE_METRICS_PATH, &metrics::snapshot()) {
+                warn!("Failed to write rule metrics to {}: {}", RULE_METRICS_PATH, e);
+            }
+            last_metrics = generation;
+        }
 
-    let rule_paths = vec![Path::new("/home/rust_udev/rust_udev/rules/").to_path_buf()];
-    let rule_manager = RuleManager::new(rule_paths); 
-
-    let monitor = UEventMonitor::new()?;
-    let poll_fd = PollFd::new(monitor.as_raw_fd(), PollFlags::POLLIN);
-
-    loop {
-        match poll(&mut [poll_fd], POLL_TIMEOUT) {
-            Ok(0) => continue,
-            Ok(_) => match monitor.receive_event() {
-                Ok(event_map) => {
-                    if let Some(device) = UEventDevice::from_event(event_map) {
-                        let rules = rule_manager.get_rules();
-                        process_event(device, rules);
-                    } else {
-                        warn!("Failed to parse event into UEventDevice");
+        for devpath in state.reprobes.due() {
+            match synthesize_change(&devpath) {
+                Some(device) => {
+                    info!("Re-probing {:?}, synthesizing change", devpath);
+                    handle_event(device);
+                }
+                None => debug!("Not re-probing {:?}, device is gone", devpath),
+            }
+        }
+        for fired in state.deferred.due() {
+            match fired {
+                Fired::Run {
+                    command,
+                    location,
+                    env,
+                    seqnum,
+                    devpath,
+                    received,
+                } => {
+                    info!("Running deferred command '{}' for {:?} ({})", command, devpath, location);
+                    state.reaper.run(vec![(command, location)], env, seqnum, &devpath, received);
+                }
+                Fired::Event(devpath) => match synthesize_change(&devpath) {
+                    Some(device) => {
+                        info!("Deferred re-evaluation of {:?}, synthesizing change", devpath);
+                        state.deferred.replay(&device);
+                        handle_event(device);
                     }
+                    None => debug!("Dropping deferred re-evaluation of {:?}, device is gone", devpath),
+                },
+            }
+        }
+
+        // 冷启动的设备分批送入，工作线程处理前一批时照常读取监听套接字
+        if !coldplug.is_empty() && pending_events() < COLDPLUG_BATCH {
+            let batch = COLDPLUG_BATCH.min(coldplug.len());
+            for device in coldplug.drain(..batch) {
+                handle_event(device);
+            }
+            if coldplug.is_empty() {
+                info!("Coldplugged {} existing device(s)", coldplug_total);
+            }
+        }
+
+        // 控制客户端随连接和断开变化，每轮重新构造
+        let control_fds = control.as_ref().map(ControlServer::fds).unwrap_or_default();
+        let mut poll_fds = vec![PollFd::new(monitor.as_raw_fd(), PollFlags::POLLIN)];
+        if let Some(fd) = DEVICE_WATCH.as_raw_fd() {
+            poll_fds.push(PollFd::new(fd, PollFlags::POLLIN));
+        }
+        poll_fds.extend(control_fds.iter().map(|&fd| PollFd::new(fd, PollFlags::POLLIN)));
+
+        // 控制命令每次醒来只执行一条，已经读到的其余命令不等待
+        let control_pending = control.as_ref().is_some_and(ControlServer::pending);
+        let timeout = if control_pending {
+            0
+        } else if coldplug.is_empty() {
+            POLL_TIMEOUT
+        } else {
+            COLDPLUG_POLL_TIMEOUT
+        };
+        match poll(&mut poll_fds, timeout) {
+            Ok(0) if !control_pending => {}
+            Ok(_) => {
+                // 套接字是阻塞的，只读有数据的那几个；客户端断开时只有 POLLHUP
+                let polled = |fd: RawFd, flags: PollFlags| {
+                    poll_fds
+                        .iter()
+                        .any(|pfd| pfd.as_raw_fd() == fd && pfd.revents().is_some_and(|events| events.intersects(flags)))
+                };
+                let readable = |fd: RawFd| polled(fd, PollFlags::POLLIN);
+                let control_ready =
+                    control_pending || control_fds.iter().any(|&fd| polled(fd, PollFlags::POLLIN | PollFlags::POLLHUP));
+                if let Some(control) = control.as_mut().filter(|_| control_ready) {
+                    control.handle(|command| execute_control(command, &rule_manager, &dispatcher));
                 }
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
-                Err(e) => return Err(Box::new(e)),
-            },
+
+                for device in DEVICE_WATCH.changed_devices(&*clock) {
+                    info!("{:?} was closed after writing, synthesizing change", device.devpath());
+                    update_db(&db, &device);
+                    process_event(&state, &dispatcher, device, rule_manager.get_rules());
+                }
+
+                // 也可能只是控制套接字或设备监视有数据
+                if readable(monitor.as_raw_fd()) {
+                    match monitor.receive_timed_event() {
+                        Ok((event_map, received)) => match UEventDevice::from_event_with_clock(event_map, &*clock) {
+                            Some(mut device) => {
+                                device.set_received(received);
+                                last_seqnum = last_seqnum.max(device.seqnum());
+                                seqnums.observe(&device);
+                                // 内核的事件比还没送入的冷启动事件新
+                                if !coldplug.is_empty() {
+                                    coldplug.retain(|queued| queued.devpath() != device.devpath());
+                                }
+                                handle_event(device);
+                            }
+                            None => warn!("Dropping event without DEVPATH"),
+                        },
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                        // 接收缓冲区满时内核丢弃了消息，缺号会在后面被发现
+                        Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                            warn!("Netlink receive buffer overflowed, some events were lost");
+                            seqnums.overflowed();
+                        }
+                        Err(e) => return Err(Box::new(e)),
+                    }
+                }
+            }
+            // 被 SIGTERM 等信号打断，回到循环开头检查
+            Err(Errno::EINTR) => continue,
             Err(e) => {
                 error!("Poll error: {}", e);
-                thread::sleep(Duration::from_millis(1000));
+                token.wait_timeout(Duration::from_millis(1000));
+            }
+        }
+
+        for gap in seqnums.take_gaps() {
+            if options.resync_on_gap {
+                resync(&gap.subsystems, &state, &db, &rule_manager, &dispatcher);
             }
         }
     }
+
+    dispatcher.shutdown();
+    // 最后一次定时写出之后的事件
+    if stats_dirty.get() {
+        save_stats(&stats.borrow());
+    }
+    info!("udevd stopped");
+    Ok(())
+}
+
+fn save_stats(stats: &DeviceStats) {
+    if let Err(e) = stats.save(STATS_PATH) {
+        warn!("Failed to write stats to {}: {}", STATS_PATH, e);
+    }
+}
+
+// 执行 udevadm control 发来的一条命令
+fn execute_control(
+    command: ControlCommand,
+    rule_manager: &RuleManager,
+    dispatcher: &EventDispatcher,
+) -> Result<(), String> {
+    match command {
+        ControlCommand::Reload => {
+            info!("Reloading rules on request");
+            rule_manager.reload();
+        }
+        ControlCommand::SetLogLevel(level) => {
+            set_log_level(level);
+            info!("Log level set to {}", level);
+        }
+        ControlCommand::SetLogFilter(directives) => {
+            let spec: Vec<String> = directives.iter().map(ToString::to_string).collect();
+            info!("Log filter set to '{}'", spec.join(","));
+            set_log_filter(directives);
+        }
+        ControlCommand::StopExecQueue => {
+            dispatcher.set_paused(true);
+            info!("Event processing stopped, new events are queued");
+        }
+        ControlCommand::StartExecQueue => {
+            dispatcher.set_paused(false);
+            info!("Event processing resumed, {} queued event(s)", dispatcher.queued());
+        }
+        ControlCommand::Ping => {}
+    }
+    Ok(())
 }
 
-fn process_event(device: UEventDevice, rules: Arc<Mutex<Vec<Rule>>>) {
-    rayon::spawn(move || {
-        let rules = rules.lock().unwrap();
-        let mut matched = false;
+// 丢失事件之后重新同步 subsystems 中的设备：数据库中 sysfs 已经不存在的设备合成 remove，
+// sysfs 中的设备在后台线程中写 uevent 让内核重新发出事件，数据库中没有的发 add，其余发 change
+fn resync(
+    subsystems: &BTreeSet<String>,
+    state: &Arc<DaemonState>,
+    db: &Arc<ShardedDeviceDb>,
+    rule_manager: &RuleManager,
+    dispatcher: &EventDispatcher,
+) {
+    // 数据库按 devpath 排序，父设备在子孙之前；已消失设备的子孙不论属于哪个子系统也一并移除
+    let mut gone: BTreeMap<PathBuf, HashMap<String, String>> = BTreeMap::new();
+    for (devpath, event) in db.devices() {
+        let missing = event.get("SUBSYSTEM").is_some_and(|subsystem| subsystems.contains(subsystem))
+            && !Path::new("/sys").join(devpath.strip_prefix("/").unwrap_or(&devpath)).join("uevent").exists();
+        if missing || devpath.ancestors().skip(1).any(|ancestor| gone.contains_key(ancestor)) {
+            gone.insert(devpath, event);
+        }
+    }
+    if !gone.is_empty() {
+        info!("Resynchronizing {:?}: {} remove(s) synthesized", subsystems, gone.len());
+    }
+    // 最深的设备先分发，dispatcher 按分发顺序先处理子设备的 remove，再处理父设备的
+    for (_, mut event) in gone.into_iter().rev() {
+        event.insert("ACTION".to_string(), "remove".to_string());
+        let Some(device) = UEventDevice::from_event_with_clock(event, state.clock()) else {
+            continue;
+        };
+        info!("{:?} disappeared while events were lost, synthesizing remove", device.devpath());
+        update_db(db, &device);
+        process_event(state, dispatcher, device, rule_manager.get_rules());
+    }
 
-        if !device.is_usb_device() { return; }
+    // 遍历 sysfs 和写 uevent 可能很慢，不能阻塞主循环；内核重新发出的事件照常从监听套接字收到
+    let subsystems = subsystems.clone();
+    let db = db.clone();
+    let spawned = thread::Builder::new().name("resync".to_string()).spawn(move || {
+        for subsystem in subsystems {
+            let mut triggered = 0;
+            for device in Enumerator::with_sys_root("/sys").match_subsystem(&subsystem).scan_devices() {
+                let action = if db.contains(device.devpath()) {
+                    DeviceAction::Change
+                } else {
+                    DeviceAction::Add
+                };
+                match device.trigger(&action, None) {
+                    Ok(()) => triggered += 1,
+                    Err(e) => warn!("Failed to re-trigger {:?}: {}", device.devpath(), e),
+                }
+            }
+            info!("Resynchronized {}: {} device(s) re-triggered", subsystem, triggered);
+        }
+    });
+    if let Err(e) = spawned {
+        warn!("Failed to start resync thread: {}", e);
+    }
+}
 
-        info!("Processing event: {}", device);
+// 启动前已经存在的设备不会再有 add 事件：为每个设备合成一个，按 DEVPATH 顺序（父设备在前），
+// 由监听循环分批送入正常的事件处理流程
+fn coldplug_devices() -> VecDeque<UEventDevice> {
+    Enumerator::with_sys_root("/sys")
+        .scan_devices()
+        .into_iter()
+        .map(|mut device| {
+            device.set_action(DeviceAction::Add);
+            device
+        })
+        .collect()
+}
 
-        for rule in &*rules {
-            debug!("Checking rule: {:?}", rule);
-            if rule.matches(&device) {
-                matched = true;
-                execute_rule_actions(rule, &device);
-                break;
+/// 启动时为带 static_node 选项的规则创建节点，此时相应模块可能尚未加载
+pub fn create_static_nodes(rules: &RuleSet) {
+    for rule in rules.rules() {
+        for name in &rule.static_node {
+            if let Err(e) = create_static_node(&rules.options().dev_root, name, rule, rules.options().resolve_names) {
+                warn!("Failed to set up static node {}: {}", name, e);
             }
         }
+    }
+}
+
+/// 单个事件的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventOutcome {
+    /// 有规则匹配并已执行
+    Matched,
+    /// 没有规则匹配
+    Unmatched,
+    /// 事件被过滤或被规则 ignore_device 忽略，未执行任何操作
+    Skipped,
+}
+
+/// process_event 返回的句柄，可用于等待事件处理完成
+#[derive(Debug)]
+pub struct EventHandle {
+    seqnum: u64,
+    receiver: Receiver<EventOutcome>,
+}
+
+impl EventHandle {
+    pub fn seqnum(&self) -> u64 {
+        self.seqnum
+    }
+
+    /// 阻塞直到事件处理完成；工作线程异常退出时返回 None
+    pub fn wait(self) -> Option<EventOutcome> {
+        self.receiver.recv().ok()
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<EventOutcome> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// 非阻塞地查询结果，尚未完成时返回 None
+    pub fn try_outcome(&self) -> Option<EventOutcome> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// 把事件交给 dispatcher 的工作线程池：同一设备的事件按分发顺序处理，父设备的事件处理完才处理子设备的，
+/// 不相关的设备并行处理。规则快照在分发时已经取得，排队期间重新加载不影响本事件
+pub fn process_event(
+    state: &Arc<DaemonState>,
+    dispatcher: &EventDispatcher,
+    mut device: UEventDevice,
+    rules: Arc<RuleSet>,
+) -> EventHandle {
+    let (tx, rx) = bounded(1);
+    let state = state.clone();
+    let seqnum = device.seqnum();
+    let pending = PendingGuard::new();
+    let devpath = device.devpath().to_path_buf();
+
+    dispatcher.dispatch(&devpath, move || {
+        let _pending = pending;
+        let _context = EventContext::enter(seqnum, device.devpath());
+
+        info!("Processing event: {}", device);
+
+        // 之前事件脱离执行的 RUN 命令结束之后再处理 remove，它们不会看到节点和链接已被删除
+        if *device.action() == DeviceAction::Remove {
+            state.reaper.wait_device(device.devpath(), rules.options().event_timeout);
+        }
+
+        let mut trace = trace::enabled().then(|| EventTrace::new(&device));
+        let plan = evaluate_rules(&mut device, &rules, false, trace.as_mut());
+        if let Some(trace) = trace {
+            info!("{}", trace.to_string().trim_end());
+        }
+
+        if let Some(delay) = plan.reprobe.filter(|_| *device.action() != DeviceAction::Remove) {
+            state.reprobes.schedule(&device, delay);
+        }
 
-        if !matched {
+        // 调用方可能已经丢弃了句柄，发送失败无需处理
+        let mut transaction = None;
+        let outcome = if plan.ignore_device {
+            // ignore_device：不创建节点也不执行 RUN
+            info!("Rule requested ignore_device, ignoring {:?}", device.devpath());
+            EventOutcome::Skipped
+        } else if plan.matched_rules == 0 {
             warn!("No rules matched for device: {}", device);
+            EventOutcome::Unmatched
+        } else {
+            if let Some(name) = &plan.interface_name {
+                rename_interface(&mut device, name);
+            }
+            transaction = begin_transaction(&device, &plan);
+            execute_plan(&plan, &mut device, rules.options(), &state.reaper);
+            schedule_deferred(&state.deferred, &plan, &device);
+            EventOutcome::Matched
+        };
+        if outcome == EventOutcome::Matched || *device.action() == DeviceAction::Remove {
+            record_provenance(&device, &plan);
+        }
+        if outcome != EventOutcome::Skipped {
+            if *device.action() != DeviceAction::Remove {
+                device.mark_initialized();
+            }
+            record_device(&mut device);
+            if let Some(broadcaster) = BROADCASTER.read().unwrap().as_ref() {
+                if let Err(e) = broadcaster.broadcast(&device, device.dev_root()) {
+                    warn!("Failed to broadcast {:?}: {}", device.devpath(), e);
+                }
+            }
+        }
+        if let Some(transaction) = transaction {
+            if let Err(e) = transaction.commit(TRANSACTIONS_DIR) {
+                warn!("Failed to commit transaction for {:?}: {}", device.devpath(), e);
+            }
         }
 
-        println!("---------------------------------------------------------------")
+        state.deferred.processed(&device);
+        debug!("Event seq {} handled {:?} after it was received", seqnum, device.received().elapsed(state.clock()));
+        let _ = tx.send(outcome);
     });
+
+    EventHandle { seqnum, receiver: rx }
+}
+
+// AT{} 的命令按规则处理完后的设备做变量替换；remove 事件之后设备已经不在，不再安排
+fn schedule_deferred(deferred: &DeferredScheduler, plan: &ExecutionPlan, device: &UEventDevice) {
+    if *device.action() == DeviceAction::Remove {
+        return;
+    }
+    for (delay, action, location) in &plan.deferred {
+        let action = match action {
+            DeferredAction::Run(command) => DeferredAction::Run(substitute_vars(command, device)),
+            DeferredAction::Event => DeferredAction::Event,
+        };
+        deferred.schedule(device, *delay, &action, location);
+    }
 }
 
-pub fn execute_rule_actions(rule: &Rule, device: &UEventDevice) {
-    info!("Executing rule actions for rule: {:?}", rule);
+// 更新设备数据库，并写出该设备的事件历史供 udevadm info --history 读取
+fn update_db(db: &ShardedDeviceDb, device: &UEventDevice) {
+    // 内存中淘汰的历史在磁盘上也删除，历史文件的数量随上限一起受限
+    for devpath in db.update(device) {
+        if let Err(e) = remove_history(HISTORY_DIR, &devpath) {
+            warn!("Failed to remove history of {:?}: {}", devpath, e);
+        }
+    }
+
+    if *device.action() == DeviceAction::Move {
+        if let Some(old) = device.property_path("DEVPATH_OLD") {
+            if let Err(e) = remove_history(HISTORY_DIR, old) {
+                warn!("Failed to remove history of {:?}: {}", old, e);
+            }
+            if let Err(e) = remove_history(PROVENANCE_DIR, old) {
+                warn!("Failed to remove provenance of {:?}: {}", old, e);
+            }
+            // 设备号和 ifindex 不随改名变化，只有 +subsystem:sysname 形式的文件名需要删除
+            let old_id = record_id(device.subsystem(), old, device.major().zip(device.minor()), device.property("IFINDEX"));
+            if let Some(old_id) = old_id.filter(|old_id| Some(old_id) != device.db_id().as_ref()) {
+                if let Err(e) = remove_record(DATA_DIR, &old_id) {
+                    warn!("Failed to remove record of {:?}: {}", old, e);
+                }
+            }
+        }
+    }
+    if let Some(entries) = db.history(device.devpath()) {
+        if let Err(e) = save_history(HISTORY_DIR, device.devpath(), &entries) {
+            warn!("Failed to write history of {:?}: {}", device.devpath(), e);
+        }
+    }
+}
+
+// 修改节点和链接之前记下意图，记录失败时照常处理，只是中断后无法恢复
+fn begin_transaction(device: &UEventDevice, plan: &ExecutionPlan) -> Option<Transaction> {
+    let root = device.dev_root();
+    let node = plan.name.as_ref().map(|name| root.join(name));
+    let links = device.devlinks().iter().map(|link| root.join(link)).collect();
+    let transaction = Transaction::new(device, node, links)?;
+    match transaction.begin(TRANSACTIONS_DIR) {
+        Ok(()) => Some(transaction),
+        Err(e) => {
+            warn!("Failed to record transaction for {:?}: {}", device.devpath(), e);
+            None
+        }
+    }
+}
+
+// 上次退出时处理到一半的事件：设备还在的重新触发，不在的清理残留的节点、链接和记录
+fn recover_transactions() {
+    match transaction::recover(TRANSACTIONS_DIR, Path::new("/sys"), Path::new(DATA_DIR)) {
+        Ok(recovered) => {
+            for (transaction, recovery) in recovered {
+                match recovery {
+                    Recovery::Replay => info!(
+                        "Replaying interrupted {} of {:?}",
+                        transaction.action, transaction.devpath
+                    ),
+                    Recovery::Cleanup => info!(
+                        "Cleaned up interrupted {} of {:?}",
+                        transaction.action, transaction.devpath
+                    ),
+                }
+            }
+        }
+        Err(e) => warn!("Failed to recover transactions from {}: {}", TRANSACTIONS_DIR, e),
+    }
+}
+
+// 保存规则处理后的属性、标签和符号链接，udevadm info 和枚举设备时读取；设备移除时删除。
+// change 和 bind 不删除已有的链接，规则这次没有声明的链接从原有记录中保留
+fn record_device(device: &mut UEventDevice) {
+    let result = match device.db_id() {
+        Some(id) if *device.action() == DeviceAction::Remove => remove_record(DATA_DIR, &id),
+        Some(id) if matches!(device.action(), DeviceAction::Change | DeviceAction::Bind) => {
+            if let Ok(previous) = load_record(DATA_DIR, &id) {
+                for link in &previous.links {
+                    device.add_devlink(link);
+                }
+            }
+            save_record(DATA_DIR, device)
+        }
+        _ => save_record(DATA_DIR, device),
+    };
+    if let Err(e) = result {
+        warn!("Failed to write record of {:?}: {}", device.devpath(), e);
+    }
+}
+
+// 写出节点名、链接和权限分别来自哪条规则，供 udevadm info --provenance 读取；设备移除后删除。
+// 没有任何赋值的事件（比如只有 add 规则时的 change）不改变节点和链接，保留之前的记录
+fn record_provenance(device: &UEventDevice, plan: &ExecutionPlan) {
+    let result = match device.action() {
+        DeviceAction::Remove => remove_history(PROVENANCE_DIR, device.devpath()),
+        DeviceAction::Add if plan.provenance.is_empty() => remove_history(PROVENANCE_DIR, device.devpath()),
+        _ if plan.provenance.is_empty() => return,
+        _ => save_provenance(PROVENANCE_DIR, device.devpath(), &plan.provenance),
+    };
+    if let Err(e) = result {
+        warn!("Failed to write provenance of {:?}: {}", device.devpath(), e);
+    }
+}
+
+// 写出节点和链接记录供 udevadm info 读取
+fn save_links() {
+    if let Err(e) = SYMLINKS.save(LINKS_PATH) {
+        warn!("Failed to write links to {}: {}", LINKS_PATH, e);
+    }
+}
+
+/// 执行合并后的计划；device 的链接更新为符号链接数据库实际分配给它的链接
+///
+/// add 时的顺序：创建节点 → 设置 MODE/OWNER/GROUP/XATTR/SECLABEL → 改名到最终名字并同步目录 →
+/// 符号链接 → RUN。能通过符号链接找到节点时权限已经就绪，RUN 看到的是完整的节点和链接；
+/// remove 时反过来，先删除链接再删除节点。options 是生成计划的规则集合的选项，RUN 命令交给 reaper 执行
+pub fn execute_plan(plan: &ExecutionPlan, device: &mut UEventDevice, options: &RuleSetOptions, reaper: &Reaper) {
+    info!("Executing plan: {:?}", plan);
 
     let action = match device.action() {
         DeviceAction::Add => Some("add"),
@@ -88,72 +1212,85 @@ pub fn execute_rule_actions(rule: &Rule, device: &UEventDevice) {
         _ => None,
     };
 
-    if let Some(devname) = device.devnode() {
-        let dev_path = PathBuf::from("/home/rust_udev/testdev").join(devname);
+    if action == Some("add") {
+        instantiate_i2c_devices(plan, device, &options.builtins);
+    }
+
+    // remove 等规则通常没有 NAME=，要处理的是 add 时实际创建的节点
+    let recorded = match action {
+        Some("add") => None,
+        _ => SYMLINKS.node(device.devpath()),
+    };
+    let root = &options.dev_root;
+    let dev_path = recorded.or_else(|| plan.name.as_deref().map(|name| root.join(name)));
+
+    if let Some(dev_path) = dev_path {
+        let devname = dev_path.strip_prefix(root).unwrap_or(&dev_path).display().to_string();
+
+        // 处理期间不监听节点，避免 RUN 写入设备时再次触发 change
+        DEVICE_WATCH.unwatch(device.devpath());
 
         match action {
             Some("add") => {
-                if let Err(e) = create_device_node(devname, device, rule) {
+                if let Err(e) = create_device_node(root, &devname, device, plan) {
                     error!("Failed to create device node {}: {}", devname, e);
+                    // 没有节点也就不创建链接，记录中不应出现它们
+                    device.clear_devlinks();
                     return;
                 }
-                if let Err(e) = create_symlinks(&dev_path, &rule.symlink, device) {
+                SYMLINKS.record_node(device.devpath(), &dev_path);
+                if let Err(e) = create_symlinks(&dev_path, root, device, plan.link_priority, &SYMLINKS) {
                     warn!("Failed to create symlink(s): {}", e);
                 }
-                if let Some(cmds) = rule.run.get("add") {
-                    if let Err(e) = run_commands(cmds, device) {
-                        warn!("Failed to execute add run commands: {}", e);
-                    }
+                if let Err(e) = apply_seclabel(&dev_path, root, device.devlinks(), &plan.seclabel) {
+                    warn!("Failed to apply SELinux label: {}", e);
                 }
+                save_links();
             }
             Some("remove") => {
-                let symlink_dir = Path::new("/home/rust_udev/testdev");
+                // remove 事件的 RUN 看到设备之前拥有的链接
+                for link in SYMLINKS.device_links(device.devpath()).links {
+                    if let Ok(name) = link.link.strip_prefix(root) {
+                        device.add_devlink(&name.to_string_lossy());
+                    }
+                }
 
-                if let Err(e) = remove_symlinks(&dev_path, symlink_dir) {
+                if let Err(e) = remove_symlinks(&dev_path, root, device, &SYMLINKS) {
                     warn!("Failed to remove symlinks: {}", e);
                 }
+                save_links();
 
                 if let Err(e) = remove_device_node(&dev_path) {
                     warn!("Failed to remove device node {}: {}", devname, e);
                 }
-
-                if let Some(cmds) = rule.run.get("remove") {
-                    if let Err(e) = run_commands(cmds, device) {
-                        warn!("Failed to execute remove run commands: {}", e);
-                    }
-                }
             }
             Some("change") | Some("bind") => {
-                if let Err(e) = apply_mode(&dev_path, &rule.mode) {
+                if let Err(e) = apply_mode(&dev_path, &plan.mode) {
                     warn!("Failed to re-apply mode: {}", e);
                 }
-                if let Err(e) = apply_owner(&dev_path, &rule.owner) {
+                if let Err(e) = apply_owner(&dev_path, &plan.owner, plan.resolve_names) {
                     warn!("Failed to re-apply owner: {}", e);
                 }
-                if let Err(e) = apply_group(&dev_path, &rule.group) {
+                if let Err(e) = apply_group(&dev_path, &plan.group, plan.resolve_names) {
                     warn!("Failed to re-apply group: {}", e);
                 }
+                if let Err(e) = apply_xattrs(&dev_path, &plan.xattrs) {
+                    warn!("Failed to re-apply xattrs: {}", e);
+                }
+                if let Err(e) = apply_seclabel(&dev_path, root, device.devlinks(), &plan.seclabel) {
+                    warn!("Failed to re-apply SELinux label: {}", e);
+                }
                 if action == Some("bind") {
-                    if let Err(e) = create_symlinks(&dev_path, &rule.symlink, device) {
+                    if let Err(e) = create_symlinks(&dev_path, root, device, plan.link_priority, &SYMLINKS) {
                         warn!("Failed to create symlink(s): {}", e);
                     }
-                    if let Some(cmds) = rule.run.get("bind") {
-                        if let Err(e) = run_commands(cmds, device) {
-                            warn!("Failed to execute bind run commands: {}", e);
-                        }
-                    }
                 }
             }
             Some("unbind") => {
-                let symlink_dir = Path::new("/home/rust_udev/testdev");
-                if let Err(e) = remove_symlinks(&dev_path, symlink_dir) {
+                if let Err(e) = remove_symlinks(&dev_path, root, device, &SYMLINKS) {
                     warn!("Failed to remove symlinks: {}", e);
                 }
-                if let Some(cmds) = rule.run.get("unbind") {
-                    if let Err(e) = run_commands(cmds, device) {
-                        warn!("Failed to execute unbind run commands: {}", e);
-                    }
-                }
+                save_links();
             }
             Some(other) => {
                 warn!("Unsupported ACTION '{}'", other);
@@ -162,7 +1299,100 @@ pub fn execute_rule_actions(rule: &Rule, device: &UEventDevice) {
                 warn!("No supported ACTION in device, skipping rule execution.");
             }
         }
+
+        execute_run(plan, device, reaper);
+
+        if plan.watch && action != Some("remove") {
+            DEVICE_WATCH.watch(&dev_path, device);
+        }
     } else {
-        warn!("No DEVNAME in device, cannot execute rule actions.");
+        // 网卡等没有设备节点的设备仍然执行 RUN
+        debug!("No DEVNAME in device, only running RUN commands");
+        execute_run(plan, device, reaper);
+    }
+}
+
+// I2C_NEW_DEVICE= 交给 i2c_new_device 内置命令，已经存在的设备不会重复创建
+fn instantiate_i2c_devices(plan: &ExecutionPlan, device: &UEventDevice, options: &BuiltinOptions) {
+    for spec in &plan.i2c_new_devices {
+        if let Err(e) = run_builtin(&format!("i2c_new_device {}", spec), device, options) {
+            warn!("Failed to instantiate I2C device '{}' on {:?}: {}", spec, device.devpath(), e);
+        }
+    }
+}
+
+// NAME= 重命名网卡，只在 add 时进行；成功后 RUN 看到的 INTERFACE 是新名字，旧名字在 INTERFACE_OLD
+fn rename_interface(device: &mut UEventDevice, name: &str) {
+    if *device.action() != DeviceAction::Add {
+        return;
+    }
+    let Some(current) = device.property("INTERFACE").map(str::to_string) else {
+        warn!("NAME=\"{}\" for {:?}, but the device has no INTERFACE", name, device.devpath());
+        return;
+    };
+    if current == name {
+        return;
+    }
+
+    let ifindex = match device.property_u64("IFINDEX") {
+        Some(ifindex) => Ok(ifindex as u32),
+        None => net::ifindex(&current),
+    };
+    match ifindex.and_then(|ifindex| net::rename(ifindex, name)) {
+        Ok(()) => {
+            info!("Renamed network interface {} to {}", current, name);
+            device.set_property("INTERFACE_OLD", &current);
+            device.set_property("INTERFACE", name);
+        }
+        Err(e) => error!("Failed to rename network interface {} to {}: {}", current, name, e),
+    }
+}
+
+// RUN 命令在规则匹配时已按 ACTION== 筛选，这里只排除无法识别的动作
+fn execute_run(plan: &ExecutionPlan, device: &UEventDevice, reaper: &Reaper) {
+    if plan.run.is_empty() {
+        return;
+    }
+    if let DeviceAction::Unknown(action) = device.action() {
+        warn!("Not running RUN commands for unknown ACTION '{}'", action);
+        return;
+    }
+    // 命令脱离事件处理在后台执行，退出状态由 reaper 回收并记入 RUN 日志
+    let commands = plan
+        .run
+        .iter()
+        .zip(&plan.run_locations)
+        .map(|(command, location)| (substitute_vars(command, device), location.clone()))
+        .collect();
+    reaper.run(commands, device.properties().clone(), device.seqnum(), device.devpath(), device.received());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn each_daemon_state_uses_its_own_clock() {
+        let device = UEventDevice::from_event(HashMap::from([
+            ("ACTION".to_string(), "add".to_string()),
+            ("DEVPATH".to_string(), "/devices/virtual/net/eth9".to_string()),
+            ("SUBSYSTEM".to_string(), "net".to_string()),
+        ]))
+        .unwrap();
+        let first = Arc::new(ManualClock::new());
+        let second = Arc::new(ManualClock::new());
+        let states = [first.clone(), second.clone()]
+            .map(|clock| DaemonState::new(&DaemonOptions { clock, ..DaemonOptions::default() }));
+        for state in &states {
+            assert!(state.reprobes.schedule(&device, Duration::from_secs(5)));
+        }
+
+        first.advance(Duration::from_secs(5));
+        assert_eq!(states[0].reprobes.due().len(), 1);
+        assert!(states[1].reprobes.due().is_empty());
+        second.advance(Duration::from_secs(5));
+        assert_eq!(states[1].reprobes.due().len(), 1);
     }
 }
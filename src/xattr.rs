@@ -0,0 +1,91 @@
+// src/xattr.rs
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// 规则只允许写入的扩展属性命名空间
+///
+/// 内核只允许在普通文件和目录上设置 user.*，设备节点上会返回 EPERM；
+/// trusted.* 需要 CAP_SYS_ADMIN，守护进程以 root 运行，devtmpfs/tmpfs 都支持。
+pub const TRUSTED_NAMESPACE: &str = "trusted.";
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// 设置文件的扩展属性，不跟随符号链接，因此也可以用于符号链接本身
+pub fn set(path: &Path, name: &str, value: &str) -> io::Result<()> {
+    let path = c_path(path)?;
+    let name = c_name(name)?;
+    let ret = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 列出文件上 trusted.* 命名空间的扩展属性及其值，按名字排序
+pub fn list_trusted(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let c_path = c_path(path)?;
+
+    let size = unsafe { libc::llistxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut names = vec![0u8; size as usize];
+    let size = unsafe {
+        libc::llistxattr(c_path.as_ptr(), names.as_mut_ptr() as *mut libc::c_char, names.len())
+    };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(size as usize);
+
+    let mut attrs = Vec::new();
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let name = String::from_utf8_lossy(name).into_owned();
+        if !name.starts_with(TRUSTED_NAMESPACE) {
+            continue;
+        }
+        attrs.push((name.clone(), get(&c_path, &name)?));
+    }
+    attrs.sort();
+    Ok(attrs)
+}
+
+fn get(path: &CString, name: &str) -> io::Result<String> {
+    let c_name = c_name(name)?;
+    let size = unsafe { libc::lgetxattr(path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut value = vec![0u8; size as usize];
+    let size = unsafe {
+        libc::lgetxattr(
+            path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_mut_ptr() as *mut libc::c_void,
+            value.len(),
+        )
+    };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    value.truncate(size as usize);
+    Ok(String::from_utf8_lossy(&value).into_owned())
+}
@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rust_udev::cancel::CancellationToken;
+use rust_udev::clock::{monotonic_usec, Clock, ManualClock, ReceiveTime, SystemClock};
+use rust_udev::dashboard::Dashboard;
+use rust_udev::device::UEventDevice;
+
+fn event(devpath: &str) -> UEventDevice {
+    let mut event = HashMap::new();
+    event.insert("ACTION".to_string(), "add".to_string());
+    event.insert("DEVPATH".to_string(), devpath.to_string());
+    event.insert("SUBSYSTEM".to_string(), "tty".to_string());
+    UEventDevice::from_event(event).unwrap()
+}
+
+fn tty_rate(dashboard: &mut Dashboard) -> String {
+    let out = dashboard.render(&BTreeMap::new(), None);
+    let line = out.lines().find(|line| line.starts_with("tty")).unwrap();
+    line.split_whitespace().last().unwrap().to_string()
+}
+
+#[test]
+fn manual_clock_only_moves_when_advanced() {
+    let clock = ManualClock::new();
+    let start = clock.now();
+    thread::sleep(Duration::from_millis(5));
+    assert_eq!(clock.now(), start);
+
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(clock.now() - start, Duration::from_secs(3));
+}
+
+#[test]
+fn event_timestamp_comes_from_clock() {
+    let clock = ManualClock::starting_at(UNIX_EPOCH + Duration::from_secs(1_000));
+    clock.advance(Duration::from_secs(42));
+
+    let mut event = HashMap::new();
+    event.insert("DEVPATH".to_string(), "/devices/virtual/tty/tty0".to_string());
+    let device = UEventDevice::from_event_with_clock(event, &clock).unwrap();
+    assert_eq!(device.timestamp(), 1_042);
+}
+
+#[test]
+fn kernel_receive_time_keeps_realtime_without_deriving_monotonic_from_it() {
+    // 墙上时间被往回调过一小时，内核时间戳看起来很旧
+    let realtime = SystemTime::now() - Duration::from_secs(3600);
+    let clock = ManualClock::new();
+    let received = ReceiveTime::from_kernel(realtime, &clock);
+
+    assert_eq!(received.realtime, realtime);
+    assert_eq!(received.monotonic_usec, clock.monotonic_usec());
+    clock.advance(Duration::from_millis(250));
+    assert_eq!(received.elapsed(&clock), Duration::from_millis(250));
+}
+
+#[test]
+fn system_clock_reads_clock_monotonic() {
+    let before = monotonic_usec();
+    assert!(SystemClock.monotonic_usec() >= before);
+}
+
+#[test]
+fn cancellation_wait_follows_the_injected_clock() {
+    let clock = Arc::new(ManualClock::new());
+    let token = CancellationToken::with_clock(clock.clone());
+    let waiter = {
+        let token = token.clone();
+        thread::spawn(move || token.wait_timeout(Duration::from_secs(3600)))
+    };
+
+    thread::sleep(Duration::from_millis(50));
+    assert!(!waiter.is_finished());
+    clock.advance(Duration::from_secs(3600));
+    // 每隔 CHECK_INTERVAL 重新读一次时钟
+    assert!(!waiter.join().unwrap());
+}
+
+#[test]
+fn usec_initialized_is_the_arrival_time() {
+    let mut event = HashMap::new();
+    event.insert("ACTION".to_string(), "add".to_string());
+    event.insert("DEVPATH".to_string(), "/devices/virtual/clock-test/arrival0".to_string());
+    event.insert("SUBSYSTEM".to_string(), "clock-test".to_string());
+    let mut device = UEventDevice::from_event(event).unwrap();
+    device.set_received(ReceiveTime {
+        realtime: UNIX_EPOCH + Duration::from_secs(1_000),
+        monotonic_usec: 1_234,
+    });
+
+    device.mark_initialized();
+    assert_eq!(device.usec_initialized(), Some(1_234));
+    assert_eq!(device.timestamp(), 1_000);
+}
+
+#[test]
+fn dashboard_rate_window_expires_after_ten_seconds() {
+    let clock = Arc::new(ManualClock::new());
+    let mut dashboard = Dashboard::with_clock(clock.clone());
+
+    for i in 0..5 {
+        dashboard.record(&event(&format!("/devices/virtual/tty/tty{}", i)));
+    }
+    // 启动不足 1 秒时按 1 秒计算
+    assert_eq!(tty_rate(&mut dashboard), "5.00");
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(tty_rate(&mut dashboard), "0.50");
+
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(tty_rate(&mut dashboard), "0.00");
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use rust_udev::db::{ShardedDeviceDb, DB_SHARDS};
+use rust_udev::device::{DeviceAction, UEventDevice};
+
+const THREADS: usize = 8;
+const DEVICES_PER_THREAD: usize = 500;
+
+fn properties(action: &str, devpath: &str, seqnum: u64) -> HashMap<String, String> {
+    let mut event = HashMap::new();
+    event.insert("ACTION".to_string(), action.to_string());
+    event.insert("DEVPATH".to_string(), devpath.to_string());
+    event.insert("SUBSYSTEM".to_string(), "tty".to_string());
+    event.insert("SEQNUM".to_string(), seqnum.to_string());
+    event
+}
+
+fn event(action: &str, devpath: &str, seqnum: u64) -> UEventDevice {
+    UEventDevice::from_event(properties(action, devpath, seqnum)).unwrap()
+}
+
+fn move_event(devpath: &str, old: &str, seqnum: u64) -> UEventDevice {
+    let mut event = properties("move", devpath, seqnum);
+    event.insert("DEVPATH_OLD".to_string(), old.to_string());
+    UEventDevice::from_event(event).unwrap()
+}
+
+fn devpath(thread: usize, device: usize) -> String {
+    format!("/devices/virtual/stress{}/tty{}", thread, device)
+}
+
+#[test]
+fn concurrent_add_remove_keeps_db_consistent() {
+    let db = Arc::new(ShardedDeviceDb::new());
+
+    // 每个线程负责自己的一组设备：全部 add，再 remove 其中的奇数号，偶数号收到 change
+    let writers: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let db = db.clone();
+            thread::spawn(move || {
+                let mut seqnum = (t * DEVICES_PER_THREAD * 3) as u64;
+                for d in 0..DEVICES_PER_THREAD {
+                    seqnum += 1;
+                    db.update(&event("add", &devpath(t, d), seqnum));
+                }
+                for d in 0..DEVICES_PER_THREAD {
+                    seqnum += 1;
+                    let action = if d % 2 == 1 { "remove" } else { "change" };
+                    db.update(&event(action, &devpath(t, d), seqnum));
+                }
+            })
+        })
+        .collect();
+
+    // 同时不断做跨分片的查询，不能死锁或看到半个条目
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    for (devpath, properties) in db.devices() {
+                        assert_eq!(properties.get("DEVPATH").map(PathBuf::from), Some(devpath));
+                    }
+                    assert!(db.len() <= THREADS * DEVICES_PER_THREAD);
+                    let _ = db.orphan_removes(Path::new("/devices/virtual"));
+                }
+            })
+        })
+        .collect();
+
+    for handle in writers.into_iter().chain(readers) {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(db.len(), THREADS * DEVICES_PER_THREAD / 2);
+    assert_eq!(db.usage().evicted, 0);
+    for t in 0..THREADS {
+        for d in 0..DEVICES_PER_THREAD {
+            let path = devpath(t, d);
+            let path = Path::new(&path);
+            assert_eq!(db.contains(path), d % 2 == 0, "{:?}", path);
+            if d % 2 == 0 {
+                assert_eq!(db.get(path).unwrap().get("ACTION").map(String::as_str), Some("change"));
+            }
+            let history = db.history(path).unwrap();
+            assert_eq!(history.len(), 2);
+            assert_eq!(history[0].action, DeviceAction::Add);
+            assert!(history[0].seqnum < history[1].seqnum);
+        }
+    }
+
+    // 子孙查询跨越全部分片，结果按路径排序
+    let children = db.devices_under(Path::new("/devices/virtual/stress3"));
+    assert_eq!(children.len(), DEVICES_PER_THREAD / 2);
+    assert!(children.windows(2).all(|pair| pair[0] < pair[1]));
+    let orphans = db.orphan_removes(Path::new("/devices/virtual/stress3"));
+    assert_eq!(orphans.len(), DEVICES_PER_THREAD / 2);
+    assert!(orphans.iter().all(|orphan| *orphan.action() == DeviceAction::Remove));
+}
+
+#[test]
+fn concurrent_moves_carry_history_across_shards() {
+    let db = Arc::new(ShardedDeviceDb::new());
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let db = db.clone();
+            thread::spawn(move || {
+                let mut seqnum = (t * DEVICES_PER_THREAD * 2) as u64;
+                for d in 0..DEVICES_PER_THREAD / 2 {
+                    let old = devpath(t, d);
+                    let new = format!("/devices/virtual/renamed{}/net{}", t, d);
+                    seqnum += 1;
+                    db.update(&event("add", &old, seqnum));
+                    seqnum += 1;
+                    db.update(&move_event(&new, &old, seqnum));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(db.len(), THREADS * DEVICES_PER_THREAD / 2);
+    for t in 0..THREADS {
+        for d in 0..DEVICES_PER_THREAD / 2 {
+            let old = devpath(t, d);
+            let new = format!("/devices/virtual/renamed{}/net{}", t, d);
+            assert!(!db.contains(Path::new(&old)));
+            assert!(db.history(Path::new(&old)).is_none());
+            let history = db.history(Path::new(&new)).unwrap();
+            let actions: Vec<_> = history.iter().map(|entry| entry.action.clone()).collect();
+            assert_eq!(actions, [DeviceAction::Add, DeviceAction::Move]);
+        }
+    }
+}
+
+#[test]
+fn capacity_bounds_history_but_never_evicts_live_devices() {
+    let db = ShardedDeviceDb::with_capacity(DB_SHARDS * 4);
+    assert_eq!(db.capacity(), DB_SHARDS * 4);
+
+    let total = DB_SHARDS * 40;
+    let mut evicted = Vec::new();
+    for d in 0..total {
+        evicted.extend(db.update(&event("add", &devpath(0, d), d as u64 + 1)));
+    }
+    let usage = db.usage();
+    assert_eq!(usage.entries, total);
+    assert!((0..total).all(|d| db.contains(Path::new(&devpath(0, d)))));
+
+    // 上限针对所有分片，保留的正好是最近的那些设备
+    let with_history: Vec<_> = (0..total).filter(|&d| db.history(Path::new(&devpath(0, d))).is_some()).collect();
+    assert_eq!(with_history, (total - DB_SHARDS * 4..total).collect::<Vec<_>>());
+    assert_eq!(usage.evicted, (total - DB_SHARDS * 4) as u64);
+    // 淘汰的设备按淘汰顺序返回，调用方据此删除磁盘上的历史
+    let expected: Vec<_> = (0..total - DB_SHARDS * 4).map(|d| PathBuf::from(devpath(0, d))).collect();
+    assert_eq!(evicted, expected);
+
+    // 父设备移除时仍能找到全部子设备
+    assert_eq!(db.orphan_removes(Path::new("/devices/virtual/stress0")).len(), total);
+}
+
+#[test]
+fn orphan_removes_list_the_deepest_devices_first() {
+    let db = ShardedDeviceDb::new();
+    let parent = "/devices/virtual/bridge0";
+    for (seqnum, devpath) in [parent, "/devices/virtual/bridge0/dev1", "/devices/virtual/bridge0/dev1/port0"]
+        .into_iter()
+        .enumerate()
+    {
+        db.update(&event("add", devpath, seqnum as u64 + 1));
+    }
+    db.update(&event("add", "/devices/virtual/bridge0/dev2", 4));
+
+    let orphans: Vec<_> =
+        db.orphan_removes(Path::new(parent)).iter().map(|device| device.devpath().to_path_buf()).collect();
+    assert_eq!(
+        orphans,
+        vec![
+            PathBuf::from("/devices/virtual/bridge0/dev2"),
+            PathBuf::from("/devices/virtual/bridge0/dev1/port0"),
+            PathBuf::from("/devices/virtual/bridge0/dev1"),
+        ]
+    );
+    assert!(orphans.iter().all(|devpath| devpath != Path::new(parent)));
+}
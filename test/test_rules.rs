@@ -1,31 +1,88 @@
 use std::collections::HashMap;
+
+use rust_udev::device::UEventDevice;
+use rust_udev::plan::plan_actions;
 use rust_udev::rules::parser::parse_rules_file;
-use rust_udev::rules::matcher::Rule;
-
-pub fn test_rule_match() {
-    // 模拟一个 uevent 事件
-    let mut event = HashMap::new();
-    event.insert("SUBSYSTEM".into(), "tty".into());
-    event.insert("KERNEL".into(), "ttyUSB0".into());
-    event.insert("DRIVER".into(), "usbserial".into());
-    event.insert("ACTION".into(), "add".into());
-
-    // 解析规则文件
-    match parse_rules_file("rules/99-custom.rules") {
-        Ok(rules) => {
-            let mut matched = false;
-            for rule in &rules {
-                if rule.matches(&event) {
-                    println!("✅ Rule matched: {:?}", rule);
-                    matched = true;
-                }
-            }
-            if !matched {
-                println!("❌ No rules matched the test event.");
-            }
-        }
-        Err(e) => {
-            eprintln!("⚠️ Failed to parse rules file: {}", e);
-        }
+use rust_udev::rules::ruleset::RuleSet;
+
+// 仓库自带的示例规则，cargo test 在包的根目录下运行
+const CUSTOM_RULES: &str = "rules/99-custom.rules";
+const ANDROID_RULES: &str = "rules/51-android.rules";
+
+fn event(action: &str, subsystem: &str, devtype: &str) -> UEventDevice {
+    event_with(action, subsystem, devtype, &[])
+}
+
+fn event_with(action: &str, subsystem: &str, devtype: &str, extra: &[(&str, &str)]) -> UEventDevice {
+    let mut properties = HashMap::from([
+        ("ACTION".to_string(), action.to_string()),
+        ("DEVPATH".to_string(), "/devices/pci0000:00/0000:00:14.0/usb1/1-1".to_string()),
+        ("SUBSYSTEM".to_string(), subsystem.to_string()),
+        ("DEVTYPE".to_string(), devtype.to_string()),
+        ("DEVNAME".to_string(), "bus/usb/001/003".to_string()),
+        ("DEVNUM".to_string(), "3".to_string()),
+        ("SEQNUM".to_string(), "1".to_string()),
+    ]);
+    properties.extend(extra.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+    UEventDevice::from_event(properties).unwrap()
+}
+
+fn custom_rules() -> RuleSet {
+    RuleSet::new(parse_rules_file(CUSTOM_RULES).unwrap())
+}
+
+fn run_commands(action: &str, subsystem: &str, devtype: &str) -> Vec<String> {
+    plan_actions(&event(action, subsystem, devtype), &custom_rules())
+        .run
+        .into_iter()
+        .map(|(command, _)| command)
+        .collect()
+}
+
+#[test]
+fn usb_add_gets_permissions_link_and_run() {
+    let rules = custom_rules();
+    let plan = plan_actions(&event("add", "usb", "usb_device"), &rules);
+
+    assert_eq!(plan.mode.as_deref(), Some("0606"));
+    // 默认在加载规则时把名字解析成 ID
+    assert!(matches!(plan.owner.as_deref(), Some("root" | "0")));
+    assert!(matches!(plan.group.as_deref(), Some("root" | "0")));
+    let root = &rules.options().dev_root;
+    assert_eq!(plan.symlinks, vec![root.join("usb-3")]);
+    assert_eq!(plan.node, Some(root.join("bus/usb/001/003")));
+    let run: Vec<_> = plan.run.iter().map(|(command, _)| command.as_str()).collect();
+    assert_eq!(run, vec!["echo /usr/bin/logger USB add"]);
+}
+
+#[test]
+fn each_action_runs_its_own_rule() {
+    for action in ["bind", "unbind", "remove"] {
+        assert_eq!(
+            run_commands(action, "usb", "usb_device"),
+            vec![format!("echo /usr/bin/logger USB {}", action)]
+        );
     }
 }
+
+#[test]
+fn other_devices_match_no_rule() {
+    assert!(run_commands("add", "usb", "usb_interface").is_empty());
+    assert!(run_commands("add", "tty", "").is_empty());
+    assert!(run_commands("change", "usb", "usb_device").is_empty());
+}
+
+#[test]
+fn android_devices_get_a_serial_link() {
+    let rules = RuleSet::new(parse_rules_file(ANDROID_RULES).unwrap());
+    // hwdb 与 usb_id 在测试环境中查不到这台设备，直接带上它们本应导入的属性
+    let android = [("ID_ANDROID", "1"), ("ID_SERIAL_SHORT", "ABC123")];
+
+    let plan = plan_actions(&event_with("add", "usb", "usb_device", &android), &rules);
+    assert_eq!(plan.symlinks, vec![rules.options().dev_root.join("android/ABC123")]);
+    assert_eq!(plan.mode.as_deref(), Some("0660"));
+
+    // 没有序列号时不创建链接
+    let plan = plan_actions(&event_with("add", "usb", "usb_device", &android[..1]), &rules);
+    assert!(plan.symlinks.is_empty());
+}
@@ -0,0 +1,192 @@
+// test/vm.rs
+//
+// 端到端测试：在 QEMU 虚拟机中运行 rust_udev，通过 QMP 热插拔 virtio 磁盘和网卡，
+// 在串口 shell 中检查符号链接和网卡改名。需要 QEMU、busybox 和合适的内核（见 test/vm/run.sh），
+// 默认跳过；make vm-test 或 RUST_UDEV_VM=1 cargo test --test vm 时运行。
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rust_udev::device::UEventDevice;
+use rust_udev::plan::plan_actions;
+use rust_udev::rules::parser::parse_rules_file;
+use rust_udev::rules::ruleset::RuleSet;
+
+const BOOT_TIMEOUT: Duration = Duration::from_secs(120);
+const HOTPLUG_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// 网卡规则按这个 MAC 地址改名为 vmtest0
+const NIC_MAC: &str = "52:54:00:12:34:99";
+
+struct Vm {
+    qemu: Child,
+    dir: PathBuf,
+    qmp: BufReader<UnixStream>,
+    console: UnixStream,
+}
+
+impl Vm {
+    fn boot() -> Self {
+        let dir = std::env::temp_dir().join(format!("rust_udev-vm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let qemu = Command::new("sh")
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("test/vm/run.sh"))
+            .env("VM_DIR", &dir)
+            .env("RUST_UDEV_BIN", env!("CARGO_BIN_EXE_rust_udev"))
+            .spawn()
+            .expect("failed to run test/vm/run.sh");
+
+        let console = connect(&dir.join("console.sock"));
+        let qmp = BufReader::new(connect(&dir.join("qmp.sock")));
+        let mut vm = Self { qemu, dir, qmp, console };
+
+        vm.read_console_until("VM-READY", BOOT_TIMEOUT);
+        // 问候消息之后必须先协商能力
+        vm.qmp_line();
+        vm.qmp(r#"{"execute":"qmp_capabilities"}"#);
+        vm
+    }
+
+    // 发送一条 QMP 命令，跳过异步事件，直到收到结果；命令失败时测试失败
+    fn qmp(&mut self, command: &str) {
+        writeln!(self.qmp.get_mut(), "{}", command).unwrap();
+        loop {
+            let line = self.qmp_line();
+            if line.contains("\"return\"") {
+                return;
+            }
+            assert!(!line.contains("\"error\""), "QMP command {} failed: {}", command, line);
+        }
+    }
+
+    fn qmp_line(&mut self) -> String {
+        let mut line = String::new();
+        self.qmp.read_line(&mut line).unwrap();
+        assert!(!line.is_empty(), "QMP connection closed");
+        line
+    }
+
+    // 在串口 shell 中执行命令，返回输出；结束标记由 shell 计算，不会与回显混淆
+    fn shell(&mut self, command: &str) -> String {
+        writeln!(self.console, "{}; echo __END$((1+1))__", command).unwrap();
+        self.read_console_until("__END2__", HOTPLUG_TIMEOUT)
+    }
+
+    // 反复检查 test 表达式，直到成立或超时
+    fn wait_until(&mut self, condition: &str) -> bool {
+        let deadline = Instant::now() + HOTPLUG_TIMEOUT;
+        while Instant::now() < deadline {
+            if self.shell(&format!("[ {} ] && echo __YES$((0+1))__", condition)).contains("__YES1__") {
+                return true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        false
+    }
+
+    fn read_console_until(&mut self, marker: &str, timeout: Duration) -> String {
+        let deadline = Instant::now() + timeout;
+        let mut output = String::new();
+        let mut buf = [0u8; 4096];
+        self.console.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+
+        while !output.contains(marker) {
+            assert!(Instant::now() < deadline, "timed out waiting for {:?}, console:\n{}", marker, output);
+            match self.console.read(&mut buf) {
+                Ok(0) => panic!("console closed, output:\n{}", output),
+                Ok(n) => output.push_str(&String::from_utf8_lossy(&buf[..n])),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                Err(e) => panic!("failed to read console: {}", e),
+            }
+        }
+        output
+    }
+
+    fn daemon_log(&mut self) -> String {
+        self.shell("cat /run/rust_udev.log")
+    }
+}
+
+impl Drop for Vm {
+    fn drop(&mut self) {
+        let _ = self.qemu.kill();
+        let _ = self.qemu.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// QEMU 启动后才创建套接字
+fn connect(path: &Path) -> UnixStream {
+    let deadline = Instant::now() + BOOT_TIMEOUT;
+    loop {
+        match UnixStream::connect(path) {
+            Ok(stream) => return stream,
+            Err(e) if Instant::now() >= deadline => panic!("cannot connect to {}: {}", path.display(), e),
+            Err(_) => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+// 不需要虚拟机：确认虚拟机中加载的规则会为热插拔的磁盘规划链接
+#[test]
+fn vm_rules_plan_the_disk_link() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/vm/99-vm-test.rules");
+    let rules = RuleSet::new(parse_rules_file(path).unwrap());
+    let device = UEventDevice::from_event(HashMap::from([
+        ("ACTION".to_string(), "add".to_string()),
+        ("DEVPATH".to_string(), "/devices/pci0000:00/0000:00:04.0/virtio1/block/vda".to_string()),
+        ("SUBSYSTEM".to_string(), "block".to_string()),
+        ("DEVTYPE".to_string(), "disk".to_string()),
+        ("DEVNAME".to_string(), "vda".to_string()),
+        ("SEQNUM".to_string(), "1".to_string()),
+    ]))
+    .unwrap();
+
+    assert_eq!(plan_actions(&device, &rules).symlinks, vec![rules.options().dev_root.join("vmtest/disk-vda")]);
+}
+
+#[test]
+fn hotplugged_devices_get_links_and_names() {
+    if std::env::var_os("RUST_UDEV_VM").is_none() {
+        eprintln!("skipping VM test, run make vm-test or set RUST_UDEV_VM=1");
+        return;
+    }
+
+    let mut vm = Vm::boot();
+
+    // 磁盘：添加后出现规则中的链接，移除后链接被删除
+    let image = vm.dir.join("disk1.img");
+    vm.qmp(&format!(
+        r#"{{"execute":"blockdev-add","arguments":{{"driver":"raw","node-name":"disk1","file":{{"driver":"file","filename":"{}"}}}}}}"#,
+        image.display()
+    ));
+    vm.qmp(r#"{"execute":"device_add","arguments":{"driver":"virtio-blk-pci","id":"vdisk1","drive":"disk1"}}"#);
+    if !vm.wait_until("-L /dev/vmtest/disk-vda") {
+        panic!("/dev/vmtest/disk-vda was not created, daemon log:\n{}", vm.daemon_log());
+    }
+    assert!(vm.shell("readlink /dev/vmtest/disk-vda").contains("vda"));
+
+    vm.qmp(r#"{"execute":"device_del","arguments":{"id":"vdisk1"}}"#);
+    if !vm.wait_until("! -e /dev/vmtest/disk-vda") {
+        panic!("/dev/vmtest/disk-vda was not removed, daemon log:\n{}", vm.daemon_log());
+    }
+
+    // 网卡：按 MAC 地址匹配的 NAME= 规则改名
+    vm.qmp(r#"{"execute":"netdev_add","arguments":{"type":"user","id":"net1"}}"#);
+    vm.qmp(&format!(
+        r#"{{"execute":"device_add","arguments":{{"driver":"virtio-net-pci","id":"vnic1","netdev":"net1","mac":"{}"}}}}"#,
+        NIC_MAC
+    ));
+    if !vm.wait_until("-e /sys/class/net/vmtest0") {
+        panic!("interface was not renamed to vmtest0, daemon log:\n{}", vm.daemon_log());
+    }
+
+    vm.qmp(r#"{"execute":"quit"}"#);
+}
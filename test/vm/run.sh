@@ -0,0 +1,50 @@
+#!/bin/sh
+# test/vm/run.sh
+#
+# 打包 initramfs 并启动最小的 QEMU 虚拟机，由 test/vm.rs 通过 QMP 和串口套接字驱动
+#
+# VM_DIR         工作目录，存放 initramfs、磁盘镜像和套接字（必需）
+# RUST_UDEV_BIN  要测试的守护进程，默认 target/release/rust_udev
+# VM_KERNEL      内核镜像，需内置 virtio-pci/blk/net、devtmpfs 和 ACPI PCI 热插拔
+# VM_BUSYBOX     busybox，默认取 PATH 中的
+# VM_QEMU        默认 qemu-system-x86_64
+set -eu
+
+: "${VM_DIR:?VM_DIR must be set}"
+BIN=${RUST_UDEV_BIN:-target/release/rust_udev}
+KERNEL=${VM_KERNEL:-/boot/vmlinuz-$(uname -r)}
+BUSYBOX=${VM_BUSYBOX:-$(command -v busybox)}
+QEMU=${VM_QEMU:-qemu-system-x86_64}
+HERE=$(cd "$(dirname "$0")" && pwd)
+
+# 管道中 cpio 失败不会让 set -e 退出，先检查
+for tool in cpio gzip "$QEMU"; do
+    command -v "$tool" > /dev/null || { echo "run.sh: $tool not found" >&2; exit 1; }
+done
+
+root=$VM_DIR/root
+rm -rf "$root"
+mkdir -p "$root/bin" "$root/etc/udev/rules.d" "$root/proc" "$root/sys" "$root/dev" "$root/run" "$root/tmp"
+cp "$BUSYBOX" "$root/bin/busybox"
+cp "$BIN" "$root/bin/rust_udev"
+cp "$HERE/init" "$root/init"
+chmod +x "$root/init"
+cp "$HERE/99-vm-test.rules" "$root/etc/udev/rules.d/"
+
+# 动态链接时把依赖的库一起放进去
+for exe in "$BIN" "$BUSYBOX"; do
+    ldd "$exe" 2>/dev/null | grep -o '/[^ ]*' | while read -r lib; do
+        mkdir -p "$root$(dirname "$lib")"
+        cp -L "$lib" "$root$lib"
+    done
+done
+
+(cd "$root" && find . | cpio -o -H newc --quiet | gzip) > "$VM_DIR/initramfs.gz"
+truncate -s 16M "$VM_DIR/disk1.img"
+
+exec "$QEMU" -machine pc,accel=kvm:tcg -m 512 -display none -no-reboot \
+    -kernel "$KERNEL" -initrd "$VM_DIR/initramfs.gz" \
+    -append "console=ttyS0 panic=-1 quiet" \
+    -serial "unix:$VM_DIR/console.sock,server=on,wait=off" \
+    -qmp "unix:$VM_DIR/qmp.sock,server=on,wait=off" \
+    -monitor none